@@ -0,0 +1,160 @@
+//! Interrupting hostcalls which block, see `WasiCtx::cancel_handle`.
+use crate::{Error, Result};
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[cfg(unix)]
+use std::{fs::File, io::Write, os::unix::prelude::*};
+
+/// Wakes a hostcall blocked on something other than a host descriptor, such as a virtual pipe.
+pub(crate) type Waker = Arc<dyn Fn() + Send + Sync>;
+
+thread_local! {
+    /// The canceller of the context whose hostcall is running on this thread, if any.
+    static CURRENT: RefCell<Option<Arc<Canceller>>> = RefCell::new(None);
+}
+
+/// Interrupts the blocking hostcalls of a `WasiCtx` from any thread, as returned by
+/// `WasiCtx::cancel_handle`.
+#[derive(Clone, Debug)]
+pub struct CancelHandle(Arc<Canceller>);
+
+impl CancelHandle {
+    /// Make the hostcalls of the context which are blocked, such as `fd_read` on a pipe or
+    /// `poll_oneoff` without a timeout, return `ECANCELED`, along with all those which would block
+    /// from now on. This can't be undone.
+    pub fn cancel(&self) {
+        self.0.cancel()
+    }
+
+    /// Whether `cancel` was called, which tells hostcalls failing with `ECANCELED` because of it
+    /// apart from others.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// The cancellation state of a `WasiCtx`.
+#[derive(Default)]
+pub(crate) struct Canceller {
+    cancelled: AtomicBool,
+    /// Whether a `CancelHandle` was taken, before which nothing can be cancelled.
+    taken: AtomicBool,
+    wakers: Mutex<Vec<(usize, Waker)>>,
+    next_waker: AtomicUsize,
+    /// A pipe whose reading end becomes readable once cancelled, so that it can be polled along
+    /// with host descriptors. It's only created when the first handle is taken.
+    #[cfg(unix)]
+    pipe: Mutex<Option<(File, File)>>,
+}
+
+impl fmt::Debug for Canceller {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Canceller")
+            .field("cancelled", &self.cancelled)
+            .field("taken", &self.taken)
+            .finish()
+    }
+}
+
+impl Canceller {
+    pub(crate) fn handle(canceller: &Arc<Self>) -> Result<CancelHandle> {
+        #[cfg(unix)]
+        {
+            let mut pipe = canceller.pipe.lock().unwrap();
+            if pipe.is_none() {
+                *pipe = Some(crate::sys::hostcalls_impl::wake_pipe()?);
+            }
+        }
+        canceller.taken.store(true, Ordering::SeqCst);
+        Ok(CancelHandle(Arc::clone(canceller)))
+    }
+
+    fn cancel(&self) {
+        if self.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        #[cfg(unix)]
+        {
+            if let Some((_, writer)) = &*self.pipe.lock().unwrap() {
+                // the pipe is never drained, so this only fails if it can't be written at all
+                let _ = (&*writer).write(&[0]);
+            }
+        }
+        // the wakers lock what the hostcalls wait on, which may be held while registering them
+        let wakers: Vec<Waker> = self
+            .wakers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, wake)| Arc::clone(wake))
+            .collect();
+        for wake in wakers {
+            wake();
+        }
+    }
+
+    /// Fails with `ECANCELED` if cancelled.
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            Err(Error::ECANCELED)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The reading end of the pipe which becomes readable once cancelled.
+    #[cfg(unix)]
+    pub(crate) fn wake_fd(&self) -> RawFd {
+        let pipe = self.pipe.lock().unwrap();
+        let (reader, _) = pipe.as_ref().expect("a handle was taken");
+        reader.as_raw_fd()
+    }
+
+    /// Runs `wait`, which blocks until something happens or `wake` is called, failing with
+    /// `ECANCELED` instead if cancelled before.
+    ///
+    /// `wake` must be able to make `wait` return even if it's called before `wait` starts to
+    /// block, e.g. by locking the mutex `wait` waits on a condition variable with, which must be
+    /// held while calling this.
+    pub(crate) fn wait<T>(&self, wake: Waker, wait: impl FnOnce() -> T) -> Result<T> {
+        let id = self.next_waker.fetch_add(1, Ordering::SeqCst);
+        self.wakers.lock().unwrap().push((id, wake));
+        let result = self.check().map(|()| wait());
+        self.wakers
+            .lock()
+            .unwrap()
+            .retain(|(other, _)| *other != id);
+        result
+    }
+}
+
+/// Makes `canceller` the one of the hostcall running on this thread, until the returned guard is
+/// dropped.
+pub(crate) fn enter(canceller: &Arc<Canceller>) -> Entered {
+    let previous = CURRENT.with(|current| current.replace(Some(Arc::clone(canceller))));
+    Entered(previous)
+}
+
+/// Restores the canceller of the enclosing hostcall, if any, when dropped.
+pub(crate) struct Entered(Option<Arc<Canceller>>);
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// The canceller of the hostcall running on this thread, if a handle to it was taken.
+pub(crate) fn current() -> Option<Arc<Canceller>> {
+    CURRENT.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .filter(|canceller| canceller.taken.load(Ordering::SeqCst))
+            .cloned()
+    })
+}
@@ -0,0 +1,101 @@
+//! Categories of hostcalls which can be disabled for a `WasiCtx` as a whole.
+use crate::{Error, Result};
+
+/// A category of hostcalls, see `WasiCtxBuilder::disable`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Capability {
+    /// Creating, renaming, linking and removing files and directories, opening files for writing,
+    /// and changing their size or timestamps. Writing to descriptors provided by the embedder,
+    /// such as stdout, remains possible.
+    FsMutation,
+    /// `clock_res_get` and `clock_time_get`.
+    Clocks,
+    /// `random_get`.
+    Random,
+    /// `args_get`, `args_sizes_get`, `environ_get` and `environ_sizes_get`.
+    ArgsEnv,
+    /// `poll_oneoff`.
+    Poll,
+}
+
+impl Capability {
+    const ALL: [Self; 5] = [
+        Self::FsMutation,
+        Self::Clocks,
+        Self::Random,
+        Self::ArgsEnv,
+        Self::Poll,
+    ];
+
+    fn bit(self) -> u8 {
+        match self {
+            Self::FsMutation => 1,
+            Self::Clocks => 1 << 1,
+            Self::Random => 1 << 2,
+            Self::ArgsEnv => 1 << 3,
+            Self::Poll => 1 << 4,
+        }
+    }
+}
+
+/// The error returned by hostcalls whose capability has been disabled.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DisabledError {
+    /// `ENOSYS`, as if the hostcall wasn't implemented at all.
+    NoSys,
+    /// `ENOTCAPABLE`, as for operations the guest lacks the rights for.
+    NotCapable,
+}
+
+/// The set of capabilities disabled for a `WasiCtx`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Capabilities {
+    disabled: u8,
+    error: DisabledError,
+}
+
+impl Capabilities {
+    pub(crate) fn new() -> Self {
+        Self {
+            disabled: 0,
+            error: DisabledError::NoSys,
+        }
+    }
+
+    pub(crate) fn disable(&mut self, capability: Capability) {
+        self.disabled |= capability.bit();
+    }
+
+    pub(crate) fn set_error(&mut self, error: DisabledError) {
+        self.error = error;
+    }
+
+    pub(crate) fn disabled(self) -> Vec<Capability> {
+        Capability::ALL
+            .iter()
+            .cloned()
+            .filter(|capability| self.disabled & capability.bit() != 0)
+            .collect()
+    }
+
+    pub(crate) fn error(self) -> DisabledError {
+        self.error
+    }
+
+    /// Fails unless `capability` is enabled.
+    pub(crate) fn check(self, capability: Capability) -> Result<()> {
+        if self.disabled & capability.bit() == 0 {
+            return Ok(());
+        }
+
+        log::debug!("Capabilities denied {:?}", capability);
+        match self.error {
+            DisabledError::NoSys => Err(Error::ENOSYS),
+            DisabledError::NotCapable => Err(Error::ENOTCAPABLE),
+        }
+    }
+}
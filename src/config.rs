@@ -0,0 +1,509 @@
+//! Declarative configuration of a `WasiCtxBuilder`, loaded from TOML or JSON.
+//!
+//! A configuration in TOML looks like this, where every key is optional:
+//!
+//! ```toml
+//! args = ["app.wasm", "--verbose"]
+//! disable = ["clocks", "random"]
+//! path_encoding = "lossless"
+//! devices = ["/dev/null", "/dev/urandom"]
+//! cross_device_rename = true
+//!
+//! [metadata]
+//! clamp_timestamps = 1577836800
+//!
+//! [env]
+//! allow = ["HOME", "LANG"]
+//! vars = { RUST_LOG = "debug" }
+//!
+//! [stdio]
+//! stdin = "null"
+//! stdout = "inherit"
+//! stderr = { append = "/var/log/app.log" }
+//!
+//! [[preopen]]
+//! guest = "/data"
+//! host = "/srv/data"
+//! rights = ["fd_read", "fd_readdir", "fd_seek", "path_open", "path_filestat_get"]
+//! policy = { deny = [{ pattern = "*.key" }] }
+//!
+//! [[preopen]]
+//! guest = "/shared"
+//! host = "/srv/shared"
+//! creation = { file_mode = 0o664, dir_mode = 0o2775, group = 100 }
+//!
+//! [[preopen]]
+//! guest = "/input"
+//! archive = "/srv/input.tar"
+//! sorted_readdir = true
+//!
+//! [[preopen]]
+//! guest = "/dev"
+//! devices = true
+//! ```
+use crate::capabilities::{Capability, DisabledError};
+use crate::ctx::WasiCtxBuilder;
+use crate::policy::{Denial, PathOp, PathPolicy};
+use crate::{wasm32, CreationMode, DevicePolicy, OverlayUpper, PathEncoding, VirtualMetadata};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The contents of a configuration file, see the module documentation for an example.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Command-line arguments, including the program name.
+    pub args: Vec<String>,
+    /// Inherit the command-line arguments of the host process instead.
+    pub inherit_args: bool,
+    pub env: EnvConfig,
+    pub stdio: StdioConfig,
+    #[serde(rename = "preopen")]
+    pub preopens: Vec<PreopenConfig>,
+    /// Categories of hostcalls to disable.
+    pub disable: Vec<Capability>,
+    pub disabled_error: Option<DisabledError>,
+    pub path_encoding: Option<PathEncoding>,
+    /// Host paths of the devices the guest may open, see `DevicePolicy`.
+    pub devices: Vec<PathBuf>,
+    /// Present virtual file metadata to the guest, see `VirtualMetadata`.
+    pub metadata: Option<MetadataConfig>,
+    /// Move files between host file systems by copying them, see
+    /// `WasiCtxBuilder::cross_device_rename`.
+    pub cross_device_rename: bool,
+}
+
+/// The environment variables of the guest.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnvConfig {
+    /// Inherit all environment variables of the host process.
+    pub inherit: bool,
+    /// Inherit only the given environment variables of the host process, if they're set.
+    pub allow: Vec<String>,
+    /// Explicit variables, which take precedence over inherited ones.
+    pub vars: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StdioConfig {
+    /// The null device if missing, as for `Stdio::Null`.
+    pub stdin: Option<Stdio>,
+    pub stdout: Option<Stdio>,
+    pub stderr: Option<Stdio>,
+}
+
+/// Where a standard stream of the guest reads from or writes to.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stdio {
+    /// The null device.
+    Null,
+    /// The corresponding stream of the host process.
+    Inherit,
+    /// A file, which is truncated, or created, if used for output.
+    File(PathBuf),
+    /// A file, which is appended to, or created. Only valid for output.
+    Append(PathBuf),
+}
+
+/// The settings of `VirtualMetadata`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetadataConfig {
+    /// The epoch to clamp timestamps to, in seconds since the Unix epoch.
+    pub clamp_timestamps: Option<u64>,
+}
+
+/// A preopened directory, which is either a host directory, an archive, an overlay or the
+/// in-process devices.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PreopenConfig {
+    /// The path of the directory in the guest.
+    pub guest: PathBuf,
+    pub host: Option<PathBuf>,
+    pub archive: Option<PathBuf>,
+    pub overlay: Option<OverlayConfig>,
+    /// Preopen the devices of `WasiCtxBuilder::preopened_devices` instead of a directory.
+    #[serde(default)]
+    pub devices: bool,
+    /// Names of the rights of the directory, such as `path_open`, which also limit the rights of
+    /// everything opened from it. All rights are granted if missing.
+    pub rights: Option<Vec<String>>,
+    pub policy: Option<PolicyConfig>,
+    /// The permissions and group of new files and directories, see `CreationMode`.
+    pub creation: Option<CreationMode>,
+    /// Return directory entries in sorted order, see `WasiCtxBuilder::sorted_readdir`.
+    #[serde(default)]
+    pub sorted_readdir: bool,
+}
+
+/// A host directory layered over a read-only one, see `WasiCtxBuilder::preopened_overlay`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OverlayConfig {
+    pub lower: PathBuf,
+    /// The directory receiving modifications, which are kept in memory if missing.
+    pub upper: Option<PathBuf>,
+}
+
+/// A `PathPolicy` for a preopen.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyConfig {
+    pub allow: Vec<RuleConfig>,
+    pub deny: Vec<RuleConfig>,
+    pub denial: Option<Denial>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    /// The operations the rule applies to, which are all of them if missing.
+    pub ops: Option<Vec<PathOp>>,
+    pub pattern: String,
+}
+
+/// An invalid configuration, along with the key it was found at.
+#[derive(Debug)]
+pub struct ConfigError {
+    key: String,
+    message: String,
+}
+
+impl ConfigError {
+    fn new<K: Into<String>, M: fmt::Display>(key: K, message: M) -> Self {
+        Self {
+            key: key.into(),
+            message: message.to_string(),
+        }
+    }
+
+    /// The key of the offending value, such as `preopen[1].rights[0]`, or an empty string if
+    /// the error isn't specific to a key.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.key.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.key, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn path_error<E: fmt::Display>(err: serde_path_to_error::Error<E>) -> ConfigError {
+    let key = err.path().to_string();
+    // `serde_path_to_error` denotes the root as `.`
+    let key = if key == "." { String::new() } else { key };
+    ConfigError::new(key, err.into_inner())
+}
+
+impl Config {
+    pub fn from_toml(s: &str) -> Result<Self, ConfigError> {
+        let mut deserializer = toml::Deserializer::new(s);
+        serde_path_to_error::deserialize(&mut deserializer).map_err(path_error)
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, ConfigError> {
+        let mut deserializer = serde_json::Deserializer::from_str(s);
+        let config = serde_path_to_error::deserialize(&mut deserializer).map_err(path_error)?;
+        deserializer.end().map_err(|e| ConfigError::new("", e))?;
+        Ok(config)
+    }
+
+    /// Loads a configuration file, whose format is determined by its `.toml` or `.json`
+    /// extension.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| ConfigError::new("", format!("{}: {}", path.display(), e)))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml(&contents),
+            Some("json") => Self::from_json(&contents),
+            _ => Err(ConfigError::new(
+                "",
+                format!("{}: expected a .toml or .json file", path.display()),
+            )),
+        }
+    }
+
+    /// Validates the configuration, and creates a builder from it, which may be customized
+    /// further before building the `WasiCtx`.
+    pub fn into_builder(self) -> Result<WasiCtxBuilder, ConfigError> {
+        let mut builder = WasiCtxBuilder::new().map_err(|e| ConfigError::new("", e))?;
+
+        if self.inherit_args {
+            if !self.args.is_empty() {
+                return Err(ConfigError::new(
+                    "inherit_args",
+                    "can't be combined with `args`",
+                ));
+            }
+            builder = builder
+                .inherit_args()
+                .map_err(|e| ConfigError::new("inherit_args", e))?;
+        } else {
+            builder = builder
+                .args(self.args.iter())
+                .map_err(|e| ConfigError::new("args", e))?;
+        }
+
+        builder = self.env.apply(builder)?;
+        builder = self.stdio.apply(builder)?;
+
+        for (i, preopen) in self.preopens.into_iter().enumerate() {
+            builder = preopen.apply(builder, &format!("preopen[{}]", i))?;
+        }
+
+        for capability in self.disable {
+            builder = builder.disable(capability);
+        }
+        if let Some(error) = self.disabled_error {
+            builder = builder.disabled_error(error);
+        }
+        if let Some(path_encoding) = self.path_encoding {
+            builder = builder.path_encoding(path_encoding);
+        }
+        if !self.devices.is_empty() {
+            let policy = self
+                .devices
+                .iter()
+                .fold(DevicePolicy::new(), |policy, path| policy.allow(path));
+            builder = builder.device_policy(policy);
+        }
+        if let Some(metadata) = self.metadata {
+            let mut virtual_metadata = VirtualMetadata::new();
+            if let Some(secs) = metadata.clamp_timestamps {
+                virtual_metadata =
+                    virtual_metadata.clamp_timestamps(UNIX_EPOCH + Duration::from_secs(secs));
+            }
+            builder = builder.virtual_metadata(virtual_metadata);
+        }
+        if self.cross_device_rename {
+            builder = builder.cross_device_rename();
+        }
+
+        Ok(builder)
+    }
+}
+
+impl EnvConfig {
+    fn apply(self, builder: WasiCtxBuilder) -> Result<WasiCtxBuilder, ConfigError> {
+        if self.inherit && !self.allow.is_empty() {
+            return Err(ConfigError::new(
+                "env.allow",
+                "can't be combined with `env.inherit`",
+            ));
+        }
+
+        let mut vars: BTreeMap<String, String> = BTreeMap::new();
+        if self.inherit {
+            vars.extend(std::env::vars());
+        }
+        for (i, name) in self.allow.into_iter().enumerate() {
+            match std::env::var(&name) {
+                Ok(value) => {
+                    vars.insert(name, value);
+                }
+                Err(std::env::VarError::NotPresent) => {}
+                Err(e) => return Err(ConfigError::new(format!("env.allow[{}]", i), e)),
+            }
+        }
+        vars.extend(self.vars);
+
+        builder
+            .envs(vars.into_iter())
+            .map_err(|_| ConfigError::new("env", "variables must not contain NUL bytes"))
+    }
+}
+
+impl StdioConfig {
+    fn apply(self, builder: WasiCtxBuilder) -> Result<WasiCtxBuilder, ConfigError> {
+        let builder = match self.stdin.unwrap_or(Stdio::Null) {
+            Stdio::Null => Ok(builder),
+            Stdio::Inherit => builder.inherit_stdin(),
+            Stdio::File(path) => File::open(&path)
+                .map_err(Into::into)
+                .and_then(|file| builder.stdin(file)),
+            Stdio::Append(_) => {
+                return Err(ConfigError::new(
+                    "stdio.stdin",
+                    "can't append to an input stream",
+                ))
+            }
+        }
+        .map_err(|e| ConfigError::new("stdio.stdin", e))?;
+
+        let builder = match self.stdout.unwrap_or(Stdio::Null) {
+            Stdio::Null => Ok(builder),
+            Stdio::Inherit => builder.inherit_stdout(),
+            stdout => open_output(&stdout).and_then(|file| builder.stdout(file)),
+        }
+        .map_err(|e| ConfigError::new("stdio.stdout", e))?;
+
+        match self.stderr.unwrap_or(Stdio::Null) {
+            Stdio::Null => Ok(builder),
+            Stdio::Inherit => builder.inherit_stderr(),
+            stderr => open_output(&stderr).and_then(|file| builder.stderr(file)),
+        }
+        .map_err(|e| ConfigError::new("stdio.stderr", e))
+    }
+}
+
+fn open_output(stdio: &Stdio) -> crate::Result<File> {
+    let file = match stdio {
+        Stdio::File(path) => File::create(path)?,
+        Stdio::Append(path) => OpenOptions::new().append(true).create(true).open(path)?,
+        Stdio::Null | Stdio::Inherit => unreachable!("not backed by a file"),
+    };
+    Ok(file)
+}
+
+impl PreopenConfig {
+    fn apply(self, builder: WasiCtxBuilder, key: &str) -> Result<WasiCtxBuilder, ConfigError> {
+        let guest = self.guest;
+        let mut builder = match (self.host, self.archive, self.overlay, self.devices) {
+            (Some(host), None, None, false) => {
+                let dir = crate::preopen_dir(&host)
+                    .map_err(|e| ConfigError::new(format!("{}.host", key), e))?;
+                builder.preopened_dir(dir, &guest)
+            }
+            #[cfg(feature = "archive")]
+            (None, Some(archive), None, false) => builder
+                .preopened_archive(&archive, &guest)
+                .map_err(|e| ConfigError::new(format!("{}.archive", key), e))?,
+            #[cfg(not(feature = "archive"))]
+            (None, Some(_), None, false) => {
+                return Err(ConfigError::new(
+                    format!("{}.archive", key),
+                    "archives require the `archive` feature of wasi-common",
+                ))
+            }
+            (None, None, Some(overlay), false) => {
+                let upper = match overlay.upper {
+                    Some(upper) => OverlayUpper::Host(upper),
+                    None => OverlayUpper::Memory,
+                };
+                builder
+                    .preopened_overlay(&overlay.lower, upper, &guest)
+                    .map_err(|e| ConfigError::new(format!("{}.overlay", key), e))?
+            }
+            (None, None, None, true) => builder.preopened_devices(&guest),
+            _ => {
+                return Err(ConfigError::new(
+                    key,
+                    "expected exactly one of `host`, `archive`, `overlay` or `devices`",
+                ))
+            }
+        };
+
+        if let Some(names) = self.rights {
+            let mut rights = 0;
+            for (i, name) in names.iter().enumerate() {
+                rights |= right_from_name(name).ok_or_else(|| {
+                    ConfigError::new(
+                        format!("{}.rights[{}]", key, i),
+                        format!("unknown right `{}`", name),
+                    )
+                })?;
+            }
+            builder = builder.preopen_rights(&guest, rights, rights);
+        }
+
+        if let Some(policy) = self.policy {
+            builder = builder.path_policy(&guest, policy.into_policy());
+        }
+
+        if let Some(creation) = self.creation {
+            let mode = CreationMode {
+                group: creation.group,
+                ..CreationMode::new(creation.file_mode, creation.dir_mode)
+            };
+            builder = builder.creation_mode(&guest, mode);
+        }
+
+        if self.sorted_readdir {
+            builder = builder.sorted_readdir(&guest);
+        }
+
+        Ok(builder)
+    }
+}
+
+impl PolicyConfig {
+    fn into_policy(self) -> PathPolicy {
+        let mut policy = PathPolicy::new();
+        for rule in self.allow {
+            policy = policy.allow(rule.ops(), &rule.pattern);
+        }
+        for rule in self.deny {
+            policy = policy.deny(rule.ops(), &rule.pattern);
+        }
+        if let Some(denial) = self.denial {
+            policy = policy.denial(denial);
+        }
+        policy
+    }
+}
+
+impl RuleConfig {
+    fn ops(&self) -> &[PathOp] {
+        match &self.ops {
+            Some(ops) => ops,
+            None => PathOp::ALL,
+        }
+    }
+}
+
+fn right_from_name(name: &str) -> Option<wasm32::__wasi_rights_t> {
+    let right = match name {
+        "fd_datasync" => wasm32::__WASI_RIGHT_FD_DATASYNC,
+        "fd_read" => wasm32::__WASI_RIGHT_FD_READ,
+        "fd_seek" => wasm32::__WASI_RIGHT_FD_SEEK,
+        "fd_fdstat_set_flags" => wasm32::__WASI_RIGHT_FD_FDSTAT_SET_FLAGS,
+        "fd_sync" => wasm32::__WASI_RIGHT_FD_SYNC,
+        "fd_tell" => wasm32::__WASI_RIGHT_FD_TELL,
+        "fd_write" => wasm32::__WASI_RIGHT_FD_WRITE,
+        "fd_advise" => wasm32::__WASI_RIGHT_FD_ADVISE,
+        "fd_allocate" => wasm32::__WASI_RIGHT_FD_ALLOCATE,
+        "path_create_directory" => wasm32::__WASI_RIGHT_PATH_CREATE_DIRECTORY,
+        "path_create_file" => wasm32::__WASI_RIGHT_PATH_CREATE_FILE,
+        "path_link_source" => wasm32::__WASI_RIGHT_PATH_LINK_SOURCE,
+        "path_link_target" => wasm32::__WASI_RIGHT_PATH_LINK_TARGET,
+        "path_open" => wasm32::__WASI_RIGHT_PATH_OPEN,
+        "fd_readdir" => wasm32::__WASI_RIGHT_FD_READDIR,
+        "path_readlink" => wasm32::__WASI_RIGHT_PATH_READLINK,
+        "path_rename_source" => wasm32::__WASI_RIGHT_PATH_RENAME_SOURCE,
+        "path_rename_target" => wasm32::__WASI_RIGHT_PATH_RENAME_TARGET,
+        "path_filestat_get" => wasm32::__WASI_RIGHT_PATH_FILESTAT_GET,
+        "path_filestat_set_size" => wasm32::__WASI_RIGHT_PATH_FILESTAT_SET_SIZE,
+        "path_filestat_set_times" => wasm32::__WASI_RIGHT_PATH_FILESTAT_SET_TIMES,
+        "fd_filestat_get" => wasm32::__WASI_RIGHT_FD_FILESTAT_GET,
+        "fd_filestat_set_size" => wasm32::__WASI_RIGHT_FD_FILESTAT_SET_SIZE,
+        "fd_filestat_set_times" => wasm32::__WASI_RIGHT_FD_FILESTAT_SET_TIMES,
+        "path_symlink" => wasm32::__WASI_RIGHT_PATH_SYMLINK,
+        "path_remove_directory" => wasm32::__WASI_RIGHT_PATH_REMOVE_DIRECTORY,
+        "path_unlink_file" => wasm32::__WASI_RIGHT_PATH_UNLINK_FILE,
+        "poll_fd_readwrite" => wasm32::__WASI_RIGHT_POLL_FD_READWRITE,
+        "sock_shutdown" => wasm32::__WASI_RIGHT_SOCK_SHUTDOWN,
+        _ => return None,
+    };
+    Some(right)
+}
@@ -0,0 +1,52 @@
+//! Permissions and ownership of files and directories created by the guest.
+use crate::fdentry::{Descriptor, FdEntry};
+use crate::{Error, Result};
+
+/// How files and directories created by the guest below a preopened directory are set up, see
+/// `WasiCtxBuilder::creation_mode`.
+///
+/// Without one, new files get mode `0o666` and new directories `0o777`, as filtered by the umask
+/// of the host process. With one, the modes are applied exactly, regardless of the umask, so
+/// that for example shared volumes end up with group-writable files however the host process
+/// was started. Only files and directories which didn't exist before are affected.
+///
+/// Creation modes are only supported for host directories on unix. Building a context with one
+/// for any other preopen, such as an overlay or an archive, or on other platforms, fails with
+/// `ENOTSUP`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CreationMode {
+    /// The permission bits of new files, of which only the lowest 9 are used.
+    pub file_mode: u32,
+    /// The permission bits of new directories, which may also include the setgid and sticky
+    /// bits.
+    pub dir_mode: u32,
+    /// The group id new files and directories are given, rather than that of the host process,
+    /// or of the parent directory if it has the setgid bit set.
+    pub group: Option<u32>,
+}
+
+impl CreationMode {
+    pub fn new(file_mode: u32, dir_mode: u32) -> Self {
+        Self {
+            file_mode: file_mode & 0o777,
+            dir_mode: dir_mode & 0o3777,
+            group: None,
+        }
+    }
+
+    /// Give new files and directories the group `gid`.
+    pub fn group(mut self, gid: u32) -> Self {
+        self.group = Some(gid);
+        self
+    }
+
+    /// Fails with `ENOTSUP` unless new files and directories below the preopen `fe` can be set
+    /// up with a creation mode.
+    pub(crate) fn check_supported(fe: &FdEntry) -> Result<()> {
+        match fe.as_descriptor(0, 0)? {
+            Descriptor::OsFile(_) if cfg!(unix) => Ok(()),
+            _ => Err(Error::ENOTSUP),
+        }
+    }
+}
@@ -1,17 +1,55 @@
-use crate::fdentry::FdEntry;
+use crate::cancel::{CancelHandle, Canceller};
+use crate::capabilities::{Capabilities, Capability, DisabledError};
+use crate::creation::CreationMode;
+use crate::device::{DevicePolicy, Devices};
+use crate::encoding::PathEncoding;
+use crate::fdentry::{Descriptor, FdEntry, FdInfo};
+use crate::journal::{GuestPath, Journal, JournalEntry};
+use crate::metadata::{MetadataMap, VirtualMetadata};
+use crate::metrics::{Metrics, MetricsReport};
+use crate::policy::{PathPolicy, PolicyScope};
+use crate::snapshot::{self, PolicySnapshot, Snapshot};
 use crate::sys::dev_null;
-use crate::{host, Error, Result};
+#[cfg(feature = "archive")]
+use crate::virtfs::ArchiveLayer;
+use crate::virtfs::{
+    DevDir, FsTable, HostLayer, Layer, MemoryLayer, OverlayFs, OverlayUpper, PipeEnd, PipeReader,
+    PipeWriter, VirtualFile,
+};
+use crate::{host, wasm32, Error, Result};
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::ffi::CString;
 use std::fs::File;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A directory to be preopened by `WasiCtxBuilder::build`.
+enum Preopen {
+    Dir(File),
+    Virtual(Box<dyn VirtualFile>),
+    /// A `DevDir`, which depends on the capabilities of the context.
+    Devices,
+}
 
 /// A builder allowing customizable construction of `WasiCtx` instances.
 pub struct WasiCtxBuilder {
     fds: HashMap<host::__wasi_fd_t, FdEntry>,
-    preopens: Vec<(PathBuf, File)>,
+    preopens: Vec<(PathBuf, Preopen)>,
+    policies: HashMap<PathBuf, PathPolicy>,
+    rights: HashMap<PathBuf, (host::__wasi_rights_t, host::__wasi_rights_t)>,
+    creation_modes: HashMap<PathBuf, CreationMode>,
+    sorted_readdirs: HashSet<PathBuf>,
+    /// Policy scopes of descriptors restored by `WasiCtxBuilder::from_snapshot`.
+    scopes: HashMap<host::__wasi_fd_t, PolicySnapshot>,
+    capabilities: Capabilities,
+    metrics: bool,
+    journal: bool,
+    cross_device_rename: bool,
+    path_encoding: PathEncoding,
+    device_policy: DevicePolicy,
+    virtual_metadata: Option<VirtualMetadata>,
     args: Vec<CString>,
     env: HashMap<CString, CString>,
 }
@@ -22,6 +60,18 @@ impl WasiCtxBuilder {
         let mut builder = Self {
             fds: HashMap::new(),
             preopens: Vec::new(),
+            policies: HashMap::new(),
+            rights: HashMap::new(),
+            creation_modes: HashMap::new(),
+            sorted_readdirs: HashSet::new(),
+            scopes: HashMap::new(),
+            capabilities: Capabilities::new(),
+            metrics: false,
+            journal: false,
+            cross_device_rename: false,
+            path_encoding: PathEncoding::default(),
+            device_policy: DevicePolicy::new(),
+            virtual_metadata: None,
             args: vec![],
             env: HashMap::new(),
         };
@@ -33,6 +83,42 @@ impl WasiCtxBuilder {
         Ok(builder)
     }
 
+    /// Builder for a `WasiCtx` equivalent to the one `snapshot` was taken of, see
+    /// `WasiCtx::snapshot`.
+    ///
+    /// Descriptors which were subject to a path policy need it to be given again with
+    /// `WasiCtxBuilder::path_policy`, or building the context fails with `EACCES`.
+    pub fn from_snapshot(snapshot: &Snapshot) -> Result<Self> {
+        let filesystems: Vec<Arc<OverlayFs>> = snapshot
+            .filesystems
+            .iter()
+            .map(|fs| OverlayFs::from_snapshot(fs).map(Arc::new))
+            .collect::<Result<_>>()?;
+
+        let mut builder = Self::new()?
+            .args(snapshot.args.iter())?
+            .envs(snapshot.env.iter())?;
+        for capability in &snapshot.disabled {
+            builder.capabilities.disable(*capability);
+        }
+        builder.capabilities.set_error(snapshot.disabled_error);
+        builder.metrics = snapshot.metrics;
+        builder.journal = snapshot.journal;
+        builder.cross_device_rename = snapshot.cross_device_rename;
+        builder.path_encoding = snapshot.path_encoding;
+        builder.device_policy = snapshot.device_policy.clone();
+        builder.virtual_metadata = snapshot.virtual_metadata;
+        builder.fds.clear();
+        for fd in &snapshot.fds {
+            let fe = snapshot::restore_fd(fd, &filesystems, builder.capabilities)?;
+            builder.fds.insert(fd.fd, fe);
+            if let Some(policy) = &fd.policy {
+                builder.scopes.insert(fd.fd, policy.clone());
+            }
+        }
+        Ok(builder)
+    }
+
     /// Add arguments to the command-line arguments list.
     pub fn args<S: AsRef<str>>(mut self, args: impl Iterator<Item = S>) -> Result<Self> {
         let args: Result<Vec<CString>> = args
@@ -55,9 +141,26 @@ impl WasiCtxBuilder {
     }
 
     /// Inherit the stdin, stdout, and stderr streams from the host process.
-    pub fn inherit_stdio(mut self) -> Result<Self> {
+    pub fn inherit_stdio(self) -> Result<Self> {
+        self.inherit_stdin()
+            .and_then(Self::inherit_stdout)
+            .and_then(Self::inherit_stderr)
+    }
+
+    /// Inherit the stdin stream from the host process.
+    pub fn inherit_stdin(mut self) -> Result<Self> {
         self.fds.insert(0, FdEntry::duplicate_stdin()?);
+        Ok(self)
+    }
+
+    /// Inherit the stdout stream from the host process.
+    pub fn inherit_stdout(mut self) -> Result<Self> {
         self.fds.insert(1, FdEntry::duplicate_stdout()?);
+        Ok(self)
+    }
+
+    /// Inherit the stderr stream from the host process.
+    pub fn inherit_stderr(mut self) -> Result<Self> {
         self.fds.insert(2, FdEntry::duplicate_stderr()?);
         Ok(self)
     }
@@ -114,9 +217,189 @@ impl WasiCtxBuilder {
         Ok(self)
     }
 
+    /// Use the reading end of a pipe created by `pipe` as stdin.
+    pub fn stdin_pipe(mut self, reader: PipeReader) -> Result<Self> {
+        self.fds.insert(0, pipe_fd_entry(reader.into())?);
+        Ok(self)
+    }
+
+    /// Use the writing end of a pipe created by `pipe` as stdout.
+    pub fn stdout_pipe(mut self, writer: PipeWriter) -> Result<Self> {
+        self.fds.insert(1, pipe_fd_entry(writer.into())?);
+        Ok(self)
+    }
+
+    /// Use the writing end of a pipe created by `pipe` as stderr.
+    pub fn stderr_pipe(mut self, writer: PipeWriter) -> Result<Self> {
+        self.fds.insert(2, pipe_fd_entry(writer.into())?);
+        Ok(self)
+    }
+
     /// Add a preopened directory.
     pub fn preopened_dir<P: AsRef<Path>>(mut self, dir: File, guest_path: P) -> Self {
-        self.preopens.push((guest_path.as_ref().to_owned(), dir));
+        self.preopens
+            .push((guest_path.as_ref().to_owned(), Preopen::Dir(dir)));
+        self
+    }
+
+    /// Add a preopened directory layering the writable `upper` directory over the `lower` host
+    /// directory, which is never modified.
+    ///
+    /// Files created, modified, renamed or removed by the guest end up in `upper`, where
+    /// deletions of lower entries are recorded as whiteouts. Reading directories and metadata
+    /// shows the merged view of both.
+    pub fn preopened_overlay<P: AsRef<Path>, Q: AsRef<Path>>(
+        mut self,
+        lower: P,
+        upper: OverlayUpper,
+        guest_path: Q,
+    ) -> Result<Self> {
+        let lower = Box::new(HostLayer::new(lower, true)?);
+        let upper: Box<dyn Layer> = match upper {
+            OverlayUpper::Host(upper) => Box::new(HostLayer::new(upper, false)?),
+            OverlayUpper::Memory => Box::new(MemoryLayer::new()),
+        };
+        let root = OverlayFs::new(lower, Some(upper)).root();
+        self.preopens
+            .push((guest_path.as_ref().to_owned(), Preopen::Virtual(root)));
+        Ok(self)
+    }
+
+    /// Add a preopened directory serving the contents of the tar or zip `archive`, which is
+    /// read-only to the guest.
+    ///
+    /// The archive is indexed up front; file contents are read from it as the guest opens them.
+    /// Requires the `archive` feature.
+    #[cfg(feature = "archive")]
+    pub fn preopened_archive<P: AsRef<Path>, Q: AsRef<Path>>(
+        mut self,
+        archive: P,
+        guest_path: Q,
+    ) -> Result<Self> {
+        let archive = Box::new(ArchiveLayer::open(archive)?);
+        let root = OverlayFs::new(archive, None).root();
+        self.preopens
+            .push((guest_path.as_ref().to_owned(), Preopen::Virtual(root)));
+        Ok(self)
+    }
+
+    /// Add a preopened directory of devices implemented in-process, for guests expecting those of
+    /// `/dev`: `null`, `zero`, `random` and `urandom`, along with `stdin`, `stdout` and `stderr`,
+    /// which open the descriptors 0, 1 and 2 of the guest again.
+    ///
+    /// The directory is read-only, and its devices aren't subject to the `DevicePolicy`. The
+    /// random devices read from the same source as `random_get`, and are missing if
+    /// `Capability::Random` is disabled.
+    pub fn preopened_devices<P: AsRef<Path>>(mut self, guest_path: P) -> Self {
+        self.preopens
+            .push((guest_path.as_ref().to_owned(), Preopen::Devices));
+        self
+    }
+
+    /// Restrict the operations on paths below the preopened directory at `guest_path`.
+    ///
+    /// The policy also applies to all directories the guest opens from the preopen. Building the
+    /// context fails with `ENOENT` if there's no preopen at `guest_path`.
+    pub fn path_policy<P: AsRef<Path>>(mut self, guest_path: P, policy: PathPolicy) -> Self {
+        self.policies.insert(guest_path.as_ref().to_owned(), policy);
+        self
+    }
+
+    /// Restrict the rights of the preopened directory at `guest_path`, and of everything opened
+    /// from it, to `rights_base` and `rights_inheriting`.
+    ///
+    /// Building the context fails with `ENOENT` if there's no preopen at `guest_path`.
+    pub fn preopen_rights<P: AsRef<Path>>(
+        mut self,
+        guest_path: P,
+        rights_base: wasm32::__wasi_rights_t,
+        rights_inheriting: wasm32::__wasi_rights_t,
+    ) -> Self {
+        self.rights.insert(
+            guest_path.as_ref().to_owned(),
+            (rights_base, rights_inheriting),
+        );
+        self
+    }
+
+    /// Set the permissions and ownership of files and directories the guest creates below the
+    /// preopened host directory at `guest_path`, including through directories opened from it.
+    ///
+    /// Building the context fails with `ENOENT` if there's no preopen at `guest_path`, and with
+    /// `ENOTSUP` if it isn't a host directory, see `CreationMode`.
+    pub fn creation_mode<P: AsRef<Path>>(mut self, guest_path: P, mode: CreationMode) -> Self {
+        self.creation_modes
+            .insert(guest_path.as_ref().to_owned(), mode);
+        self
+    }
+
+    /// Make `fd_readdir` return the entries of the preopened directory at `guest_path`, and of
+    /// the directories opened from it, sorted by their names, rather than in the order of the
+    /// host file system, so that guests listing directories behave the same on every host.
+    ///
+    /// The entries are listed whenever the guest reads a directory from the start, and its
+    /// cookies refer to that listing until it does so again.
+    ///
+    /// Building the context fails with `ENOENT` if there's no preopen at `guest_path`.
+    pub fn sorted_readdir<P: AsRef<Path>>(mut self, guest_path: P) -> Self {
+        self.sorted_readdirs.insert(guest_path.as_ref().to_owned());
+        self
+    }
+
+    /// Disable a category of hostcalls, which then fail without doing anything.
+    pub fn disable(mut self, capability: Capability) -> Self {
+        self.capabilities.disable(capability);
+        self
+    }
+
+    /// Set the error returned by disabled hostcalls, `DisabledError::NoSys` by default.
+    pub fn disabled_error(mut self, error: DisabledError) -> Self {
+        self.capabilities.set_error(error);
+        self
+    }
+
+    /// Keep counters of the I/O performed by the guest, which can be read with `WasiCtx::metrics`.
+    pub fn metrics(mut self) -> Self {
+        self.metrics = true;
+        self
+    }
+
+    /// Keep a journal of the modifications the guest makes to files, which can be read with
+    /// `WasiCtx::journal`.
+    pub fn journal(mut self) -> Self {
+        self.journal = true;
+        self
+    }
+
+    /// Make `path_rename` move regular files, symbolic links and directory trees between host
+    /// file systems by copying them and removing the originals, rather than failing with `EXDEV`.
+    ///
+    /// The copy is renamed into place once it's complete, so the new path never refers to a
+    /// partial copy, but unlike a rename, the move as a whole isn't atomic: if removing the
+    /// original fails, both are left behind. Timestamps and permission bits are preserved, while
+    /// ownership and hard links aren't. Moves between virtual file systems, or between them and
+    /// host directories, still fail with `EXDEV`.
+    pub fn cross_device_rename(mut self) -> Self {
+        self.cross_device_rename = true;
+        self
+    }
+
+    /// Set how paths are passed between the guest and the host, `PathEncoding::Utf8` by default.
+    pub fn path_encoding(mut self, path_encoding: PathEncoding) -> Self {
+        self.path_encoding = path_encoding;
+        self
+    }
+
+    /// Set which block and character devices the guest may open, none by default.
+    pub fn device_policy(mut self, device_policy: DevicePolicy) -> Self {
+        self.device_policy = device_policy;
+        self
+    }
+
+    /// Present the guest with virtual device and inode numbers, and optionally timestamps,
+    /// rather than those of the host, see `VirtualMetadata`.
+    pub fn virtual_metadata(mut self, virtual_metadata: VirtualMetadata) -> Self {
+        self.virtual_metadata = Some(virtual_metadata);
         self
     }
 
@@ -124,15 +407,95 @@ impl WasiCtxBuilder {
     pub fn build(mut self) -> Result<WasiCtx> {
         // startup code starts looking at fd 3 for preopens
         let mut preopen_fd = 3;
-        for (guest_path, dir) in self.preopens {
-            if !dir.metadata()?.is_dir() {
-                return Err(Error::EBADF);
+        let policies: HashMap<PathBuf, Arc<PathPolicy>> = self
+            .policies
+            .into_iter()
+            .map(|(guest_path, policy)| (guest_path, Arc::new(policy)))
+            .collect();
+        for guest_path in policies
+            .keys()
+            .chain(self.rights.keys())
+            .chain(self.creation_modes.keys())
+            .chain(self.sorted_readdirs.iter())
+        {
+            let restored = self
+                .fds
+                .values()
+                .any(|fe| fe.preopen_path.as_ref() == Some(guest_path));
+            if !restored && !self.preopens.iter().any(|(path, _)| path == guest_path) {
+                return Err(Error::ENOENT);
+            }
+        }
+
+        // descriptors restored from a snapshot, including preopens
+        for (fd, fe) in &mut self.fds {
+            let scope = match (self.scopes.get(fd), &fe.preopen_path) {
+                (Some(scope), _) => Some((scope.preopen.clone(), scope.dir.clone())),
+                (None, Some(guest_path)) => Some((guest_path.clone(), PathBuf::new())),
+                (None, None) => None,
+            };
+            if let Some((preopen, dir)) = scope {
+                match policies.get(&preopen) {
+                    Some(policy) => {
+                        fe.policy = Some(PolicyScope::new(Arc::clone(policy), preopen, dir))
+                    }
+                    None if self.scopes.contains_key(fd) => return Err(Error::EACCES),
+                    None => {}
+                }
+            }
+            if let Some(guest_path) = &fe.preopen_path {
+                if let Some((rights_base, rights_inheriting)) = self.rights.get(guest_path) {
+                    fe.rights_base &= rights_base;
+                    fe.rights_inheriting &= rights_inheriting;
+                }
+                if let Some(mode) = self.creation_modes.get(guest_path) {
+                    CreationMode::check_supported(fe)?;
+                    fe.creation_mode = Some(*mode);
+                }
+                if self.sorted_readdirs.contains(guest_path) {
+                    fe.sorted_readdir = Some(Vec::new());
+                }
             }
+        }
+
+        for (guest_path, preopen) in self.preopens {
+            let mut fe = match preopen {
+                Preopen::Dir(dir) => {
+                    if !dir.metadata()?.is_dir() {
+                        return Err(Error::EBADF);
+                    }
+                    FdEntry::from(dir)?
+                }
+                Preopen::Virtual(dir) => FdEntry::from_descriptor(Descriptor::VirtualFile(dir))?,
+                Preopen::Devices => {
+                    let random = self.capabilities.check(Capability::Random).is_ok();
+                    let dir = Box::new(DevDir::new(random));
+                    FdEntry::from_descriptor(Descriptor::VirtualFile(dir))?
+                }
+            };
 
             while self.fds.contains_key(&preopen_fd) {
                 preopen_fd = preopen_fd.checked_add(1).ok_or(Error::ENFILE)?;
             }
-            let mut fe = FdEntry::from(dir)?;
+            fe.policy = policies.get(&guest_path).map(|policy| {
+                PolicyScope::new(Arc::clone(policy), guest_path.clone(), PathBuf::new())
+            });
+            if let Some((rights_base, rights_inheriting)) = self.rights.get(&guest_path) {
+                fe.rights_base &= rights_base;
+                fe.rights_inheriting &= rights_inheriting;
+            }
+            if let Some(mode) = self.creation_modes.get(&guest_path) {
+                CreationMode::check_supported(&fe)?;
+                fe.creation_mode = Some(*mode);
+            }
+            if self.sorted_readdirs.contains(&guest_path) {
+                fe.sorted_readdir = Some(Vec::new());
+            }
+            fe.virtual_dev = preopen_fd.into();
+            fe.origin = Some(GuestPath {
+                preopen: guest_path.clone(),
+                path: PathBuf::new(),
+            });
             fe.preopen_path = Some(guest_path);
             log::debug!("WasiCtx inserting ({:?}, {:?})", preopen_fd, fe);
             self.fds.insert(preopen_fd, fe);
@@ -140,31 +503,133 @@ impl WasiCtxBuilder {
             preopen_fd = preopen_fd.checked_add(1).ok_or(Error::ENFILE)?;
         }
 
-        let env = self
-            .env
-            .into_iter()
-            .map(|(k, v)| {
-                let mut pair = k.into_bytes();
-                pair.push(b'=');
-                pair.extend_from_slice(v.to_bytes_with_nul());
-                // constructing a new CString from existing CStrings is safe
-                unsafe { CString::from_vec_unchecked(pair) }
-            })
-            .collect();
+        let env = self.env.into_iter().map(|(k, v)| env_pair(k, v)).collect();
 
         Ok(WasiCtx {
             fds: self.fds,
             args: self.args,
             env,
+            capabilities: self.capabilities,
+            metrics: if self.metrics {
+                Some(Metrics::default())
+            } else {
+                None
+            },
+            journal: if self.journal {
+                Some(Journal::default())
+            } else {
+                None
+            },
+            cross_device_rename: self.cross_device_rename,
+            canceller: Arc::default(),
+            path_encoding: self.path_encoding,
+            devices: self.device_policy.resolve()?,
+            metadata: self
+                .virtual_metadata
+                .map(|metadata| metadata.resolve())
+                .transpose()?,
+            last_error: Mutex::new(None),
         })
     }
 }
 
+fn env_pair(k: CString, v: CString) -> CString {
+    let mut pair = k.into_bytes();
+    pair.push(b'=');
+    pair.extend_from_slice(v.to_bytes_with_nul());
+    // constructing a new CString from existing CStrings is safe
+    unsafe { CString::from_vec_unchecked(pair) }
+}
+
+/// Options for deriving a child context from a template with `WasiCtx::fork`.
+#[derive(Debug, Default)]
+pub struct ForkOptions {
+    rights: Option<(host::__wasi_rights_t, host::__wasi_rights_t)>,
+    fd_rights: HashMap<host::__wasi_fd_t, (host::__wasi_rights_t, host::__wasi_rights_t)>,
+    closed: HashSet<host::__wasi_fd_t>,
+    clear_env: bool,
+    env: HashMap<CString, CString>,
+    share_offsets: bool,
+}
+
+impl ForkOptions {
+    /// Options duplicating everything as is.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restrict the rights of all descriptors of the child to `rights_base` and
+    /// `rights_inheriting`.
+    pub fn rights(
+        mut self,
+        rights_base: wasm32::__wasi_rights_t,
+        rights_inheriting: wasm32::__wasi_rights_t,
+    ) -> Self {
+        let (base, inheriting) = self.rights.get_or_insert((!0, !0));
+        *base &= rights_base;
+        *inheriting &= rights_inheriting;
+        self
+    }
+
+    /// Restrict the rights of the descriptor `fd` of the child to `rights_base` and
+    /// `rights_inheriting`.
+    pub fn fd_rights(
+        mut self,
+        fd: wasm32::__wasi_fd_t,
+        rights_base: wasm32::__wasi_rights_t,
+        rights_inheriting: wasm32::__wasi_rights_t,
+    ) -> Self {
+        let (base, inheriting) = self.fd_rights.entry(fd).or_insert((!0, !0));
+        *base &= rights_base;
+        *inheriting &= rights_inheriting;
+        self
+    }
+
+    /// Leave the descriptor `fd` out of the child.
+    pub fn close_fd(mut self, fd: wasm32::__wasi_fd_t) -> Self {
+        self.closed.insert(fd);
+        self
+    }
+
+    /// Don't inherit the environment of the parent, only the entries added with
+    /// `ForkOptions::env`.
+    pub fn clear_env(mut self) -> Self {
+        self.clear_env = true;
+        self
+    }
+
+    /// Add an entry to the environment of the child, replacing the parent's one of the same name.
+    pub fn env<S: AsRef<str>>(mut self, k: S, v: S) -> Result<Self> {
+        self.env.insert(
+            CString::new(k.as_ref()).map_err(|_| Error::ENOTCAPABLE)?,
+            CString::new(v.as_ref()).map_err(|_| Error::ENOTCAPABLE)?,
+        );
+        Ok(self)
+    }
+
+    /// Let host files and directories share their offsets between parent and child, as if
+    /// duplicated with `dup`. By default, they're opened again for the child, which gets its own
+    /// offsets.
+    pub fn share_offsets(mut self, share_offsets: bool) -> Self {
+        self.share_offsets = share_offsets;
+        self
+    }
+}
+
 #[derive(Debug)]
 pub struct WasiCtx {
     fds: HashMap<host::__wasi_fd_t, FdEntry>,
     pub(crate) args: Vec<CString>,
     pub(crate) env: Vec<CString>,
+    pub(crate) capabilities: Capabilities,
+    pub(crate) metrics: Option<Metrics>,
+    pub(crate) journal: Option<Journal>,
+    pub(crate) cross_device_rename: bool,
+    pub(crate) canceller: Arc<Canceller>,
+    pub(crate) path_encoding: PathEncoding,
+    pub(crate) devices: Devices,
+    pub(crate) metadata: Option<MetadataMap>,
+    last_error: Mutex<Option<Error>>,
 }
 
 impl WasiCtx {
@@ -183,6 +648,275 @@ impl WasiCtx {
             .and_then(|ctx| ctx.build())
     }
 
+    /// Describe all descriptors currently open, ordered by their numbers.
+    pub fn fds(&self) -> Vec<FdInfo> {
+        let mut fds: Vec<FdInfo> = self.fds.iter().map(|(fd, fe)| fe.info(*fd)).collect();
+        fds.sort_by_key(FdInfo::fd);
+        fds
+    }
+
+    /// Describe the descriptor `fd`, if it's open.
+    pub fn fd_info(&self, fd: wasm32::__wasi_fd_t) -> Option<FdInfo> {
+        self.fds.get(&fd).map(|fe| fe.info(fd))
+    }
+
+    /// The descriptors open for the host file or directory at `path`, ordered by their numbers.
+    pub fn fds_by_host_path<P: AsRef<Path>>(&self, path: P) -> Vec<wasm32::__wasi_fd_t> {
+        let path = match path.as_ref().canonicalize() {
+            Ok(path) => path,
+            Err(_) => return Vec::new(),
+        };
+        self.fds()
+            .into_iter()
+            .filter(|info| match info.host_path() {
+                Some(host_path) => host_path.canonicalize().ok().as_ref() == Some(&path),
+                None => false,
+            })
+            .map(|info| info.fd())
+            .collect()
+    }
+
+    /// Hand the host `file` to the guest under the lowest free descriptor number from 3, with at
+    /// most the given rights, and return the number.
+    ///
+    /// The file may be a device which the `DevicePolicy` doesn't allow, as it's the embedder's
+    /// choice to hand it over.
+    pub fn insert_file(
+        &mut self,
+        file: File,
+        rights_base: wasm32::__wasi_rights_t,
+        rights_inheriting: wasm32::__wasi_rights_t,
+    ) -> Result<wasm32::__wasi_fd_t> {
+        let mut fe = FdEntry::from(file)?;
+        fe.rights_base &= rights_base;
+        fe.rights_inheriting &= rights_inheriting;
+        self.insert_fd_entry(fe)
+    }
+
+    /// Hand the host `file` to the guest as the descriptor `fd`, with at most the given rights.
+    ///
+    /// Fails with `EEXIST` if `fd` is already open, rather than closing it behind the guest's back.
+    pub fn insert_file_at(
+        &mut self,
+        fd: wasm32::__wasi_fd_t,
+        file: File,
+        rights_base: wasm32::__wasi_rights_t,
+        rights_inheriting: wasm32::__wasi_rights_t,
+    ) -> Result<()> {
+        if self.fds.contains_key(&fd) {
+            return Err(Error::EEXIST);
+        }
+        let mut fe = FdEntry::from(file)?;
+        fe.rights_base &= rights_base;
+        fe.rights_inheriting &= rights_inheriting;
+        self.insert_fd_entry_at(fd, fe);
+        Ok(())
+    }
+
+    /// Hand either end of a pipe created by `pipe` to the guest under the lowest free descriptor
+    /// number from 3, and return the number.
+    pub fn insert_pipe<E: Into<PipeEnd>>(&mut self, end: E) -> Result<wasm32::__wasi_fd_t> {
+        let fe = pipe_fd_entry(end.into())?;
+        self.insert_fd_entry(fe)
+    }
+
+    /// Hand either end of a pipe created by `pipe` to the guest as the descriptor `fd`.
+    ///
+    /// Fails with `EEXIST` if `fd` is already open, like `insert_file_at`.
+    pub fn insert_pipe_at<E: Into<PipeEnd>>(
+        &mut self,
+        fd: wasm32::__wasi_fd_t,
+        end: E,
+    ) -> Result<()> {
+        if self.fds.contains_key(&fd) {
+            return Err(Error::EEXIST);
+        }
+        let fe = pipe_fd_entry(end.into())?;
+        self.insert_fd_entry_at(fd, fe);
+        Ok(())
+    }
+
+    /// Close the descriptor `fd` for the guest and take ownership of the host file it refers to,
+    /// at its current offset.
+    ///
+    /// Fails with `EBADF` if `fd` isn't open, and with `ENOTSUP` if it doesn't refer to a host
+    /// file, such as stdio or a file of a virtual file system, in which case it stays open.
+    pub fn take_file(&mut self, fd: wasm32::__wasi_fd_t) -> Result<File> {
+        let fe = self.fds.get(&fd).ok_or(Error::EBADF)?;
+        if fe.as_descriptor(0, 0)?.as_file().is_err() {
+            return Err(Error::ENOTSUP);
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.closed(fd);
+        }
+        self.remove_fd_entry(fd)?.into_file()
+    }
+
+    /// Derive an independent child context from this one, as configured by `options`.
+    ///
+    /// Every descriptor is duplicated into the child under the same number, along with its rights
+    /// and path policy, and so are the arguments and the disabled capabilities. Virtual files
+    /// always get their own offsets, and virtual file systems are shared like host ones. If this
+    /// context keeps metrics or a journal, the child keeps its own, starting out empty, while the
+    /// inode numbers of `VirtualMetadata` are shared. The child can't be cancelled through the
+    /// `CancelHandle`s of this context, only through its own.
+    ///
+    /// Fails with `EBADF` if `options` refer to a descriptor which isn't open, and with `ENOTSUP`
+    /// if a host file can't be opened again, which may happen on hosts other than Linux when it
+    /// was removed.
+    pub fn fork(&self, options: &ForkOptions) -> Result<Self> {
+        for fd in options.fd_rights.keys().chain(options.closed.iter()) {
+            if !self.fds.contains_key(fd) {
+                return Err(Error::EBADF);
+            }
+        }
+
+        let mut fds = HashMap::new();
+        for (fd, fe) in &self.fds {
+            if options.closed.contains(fd) {
+                continue;
+            }
+            let mut fe = fe.duplicate(options.share_offsets)?;
+            if let Some((rights_base, rights_inheriting)) = options.rights {
+                fe.rights_base &= rights_base;
+                fe.rights_inheriting &= rights_inheriting;
+            }
+            if let Some((rights_base, rights_inheriting)) = options.fd_rights.get(fd) {
+                fe.rights_base &= rights_base;
+                fe.rights_inheriting &= rights_inheriting;
+            }
+            fds.insert(*fd, fe);
+        }
+
+        let mut env: Vec<CString> = if options.clear_env {
+            Vec::new()
+        } else {
+            self.env
+                .iter()
+                .filter(|pair| {
+                    let name = pair.to_bytes().split(|b| *b == b'=').next();
+                    !options.env.keys().any(|k| Some(k.to_bytes()) == name)
+                })
+                .cloned()
+                .collect()
+        };
+        env.extend(
+            options
+                .env
+                .iter()
+                .map(|(k, v)| env_pair(k.clone(), v.clone())),
+        );
+
+        Ok(Self {
+            fds,
+            args: self.args.clone(),
+            env,
+            capabilities: self.capabilities,
+            metrics: self.metrics.as_ref().map(|_| Metrics::default()),
+            journal: self.journal.as_ref().map(|_| Journal::default()),
+            cross_device_rename: self.cross_device_rename,
+            canceller: Arc::default(),
+            path_encoding: self.path_encoding,
+            devices: self.devices.clone(),
+            metadata: self.metadata.clone(),
+            last_error: Mutex::new(None),
+        })
+    }
+
+    /// Take the error of the most recent hostcall which failed, along with the hostcall, the
+    /// descriptor and the path it failed for. The guest only sees its errno.
+    pub fn take_last_error(&self) -> Option<Error> {
+        self.last_error.lock().unwrap().take()
+    }
+
+    pub(crate) fn set_last_error(&self, error: Error) {
+        *self.last_error.lock().unwrap() = Some(error);
+    }
+
+    /// The counters kept since this context was built, if it was built with
+    /// `WasiCtxBuilder::metrics`.
+    pub fn metrics(&self) -> Option<MetricsReport> {
+        self.metrics.as_ref().map(|metrics| metrics.report())
+    }
+
+    /// The modifications the guest made to files so far, oldest first, if this context was built
+    /// with `WasiCtxBuilder::journal`.
+    pub fn journal(&self) -> Option<Vec<JournalEntry>> {
+        self.journal.as_ref().map(|journal| journal.entries())
+    }
+
+    /// A handle which interrupts the hostcalls of this context which block, making them return
+    /// `ECANCELED`, and can be sent to another thread, e.g. to tear down a guest which doesn't
+    /// respond in time.
+    ///
+    /// The hostcalls which can be interrupted are `poll_oneoff`, including when it only sleeps,
+    /// and `fd_read` on host pipes, sockets, terminals, the standard input and virtual pipes. On
+    /// Windows, only reading virtual pipes can be interrupted once blocked, the others fail with
+    /// `ECANCELED` when called after cancelling. Other hostcalls, such as `fd_write` on a full
+    /// virtual pipe, aren't affected.
+    ///
+    /// Once a handle was taken, the standard input is read without going through the buffer of
+    /// `io::stdin`, so data the embedder left in it isn't seen by the guest.
+    ///
+    /// Fails if the host can't set up what blocking hostcalls are woken through, e.g. because it
+    /// ran out of descriptors.
+    pub fn cancel_handle(&self) -> Result<CancelHandle> {
+        Canceller::handle(&self.canceller)
+    }
+
+    /// Take a portable snapshot of the state of this context, from which an equivalent one can be
+    /// built with `WasiCtxBuilder::from_snapshot`.
+    ///
+    /// Fails with `ENOTSUP` if a descriptor can't be recorded, such as a pipe, a host file which
+    /// was removed, or a virtual file whose file system has no directory open anymore.
+    pub fn snapshot(&mut self) -> Result<Snapshot> {
+        let mut fds: Vec<_> = self.fds.iter_mut().collect();
+        fds.sort_by_key(|(fd, _)| **fd);
+
+        let mut filesystems = FsTable::default();
+        for (_, fe) in &fds {
+            if let Descriptor::VirtualFile(file) = fe.as_descriptor(0, 0)? {
+                filesystems.add(file.as_ref());
+            }
+        }
+        let fds = fds
+            .into_iter()
+            .map(|(fd, fe)| snapshot::snapshot_fd(*fd, fe, &filesystems))
+            .collect::<Result<_>>()?;
+
+        let args = self
+            .args
+            .iter()
+            .map(|arg| arg.to_str().map(str::to_owned).map_err(|_| Error::EILSEQ))
+            .collect::<Result<_>>()?;
+        let env = self
+            .env
+            .iter()
+            .map(|pair| {
+                let pair = pair.to_str().map_err(|_| Error::EILSEQ)?;
+                let mut parts = pair.trim_end_matches('\0').splitn(2, '=');
+                let name = parts.next().unwrap_or_default().to_owned();
+                let value = parts.next().unwrap_or_default().to_owned();
+                Ok((name, value))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Snapshot {
+            args,
+            env,
+            fds,
+            filesystems: filesystems.snapshot()?,
+            disabled: self.capabilities.disabled(),
+            disabled_error: self.capabilities.error(),
+            metrics: self.metrics.is_some(),
+            journal: self.journal.is_some(),
+            cross_device_rename: self.cross_device_rename,
+            path_encoding: self.path_encoding,
+            device_policy: self.devices.policy.clone(),
+            virtual_metadata: self.metadata.as_ref().map(|metadata| metadata.settings),
+        })
+    }
+
     /// Check if `WasiCtx` contains the specified raw WASI `fd`.
     pub(crate) unsafe fn contains_fd_entry(&self, fd: host::__wasi_fd_t) -> bool {
         self.fds.contains_key(&fd)
@@ -234,3 +968,7 @@ impl WasiCtx {
         self.fds.remove(&fd).ok_or(Error::EBADF)
     }
 }
+
+fn pipe_fd_entry(end: PipeEnd) -> Result<FdEntry> {
+    FdEntry::from_descriptor(Descriptor::VirtualFile(end.into_virtual_file()))
+}
@@ -0,0 +1,107 @@
+//! Restrictions on the block and character devices the guest may open.
+use crate::fdentry::Descriptor;
+use crate::hostcalls_impl::PathGet;
+use crate::sys::{fdentry_impl, hostcalls_impl};
+use crate::{Error, Result};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// The block and character devices the guest may open with `path_open`, see
+/// `WasiCtxBuilder::device_policy`.
+///
+/// No device may be opened by default, so that device nodes reachable from a preopened directory
+/// are off limits; opening one fails with `EACCES`. Devices are told apart by their device
+/// number, so an allowed device may be opened through any node or link referring to it. Nodes are
+/// checked before they're opened, as opening some devices has side effects. Devices handed to the
+/// guest by the embedder, such as stdio or with `WasiCtx::insert_file`, aren't subject to the
+/// policy.
+///
+/// On Windows, character devices can't be told apart, so allowing any of them allows all of them.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DevicePolicy {
+    allowed: Vec<PathBuf>,
+}
+
+impl DevicePolicy {
+    /// A policy which doesn't allow any device.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A policy allowing the devices which are harmless to share with any guest: `/dev/null`,
+    /// `/dev/zero`, `/dev/random` and `/dev/urandom`, or `NUL` on Windows.
+    pub fn standard() -> Self {
+        if cfg!(windows) {
+            Self::new().allow("NUL")
+        } else {
+            Self::new()
+                .allow("/dev/null")
+                .allow("/dev/zero")
+                .allow("/dev/random")
+                .allow("/dev/urandom")
+        }
+    }
+
+    /// Allow the device at the host path `path`, such as `/dev/null`.
+    ///
+    /// Building the context fails with `ENODEV` if `path` isn't a device.
+    pub fn allow<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.allowed.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Looks up the device numbers of the allowed devices.
+    pub(crate) fn resolve(&self) -> Result<Devices> {
+        let mut allowed = Vec::with_capacity(self.allowed.len());
+        for path in &self.allowed {
+            let file = File::open(path)?;
+            allowed.push(fdentry_impl::device_id(&file)?.ok_or(Error::ENODEV)?);
+        }
+        Ok(Devices {
+            policy: self.clone(),
+            allowed,
+        })
+    }
+}
+
+/// The device numbers of the devices allowed by a `DevicePolicy`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Devices {
+    /// The policy, which is recorded by snapshots.
+    pub(crate) policy: DevicePolicy,
+    allowed: Vec<u64>,
+}
+
+impl Devices {
+    /// Fails with `EACCES` if the path resolved by `path_open` is a device which isn't allowed,
+    /// before it's opened.
+    pub(crate) fn check_path(&self, resolved: &PathGet) -> Result<()> {
+        self.check_id(hostcalls_impl::path_device_id(resolved)?)
+    }
+
+    /// Fails with `EACCES` if `file` is a device which isn't allowed.
+    pub(crate) fn check_file(&self, file: &File) -> Result<()> {
+        self.check_id(fdentry_impl::device_id(file)?)
+    }
+
+    fn check_id(&self, id: Option<u64>) -> Result<()> {
+        match id {
+            Some(id) if !self.allowed.contains(&id) => Err(Error::EACCES),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails with `EACCES` if `descriptor` is a device which isn't allowed.
+    pub(crate) fn check(&self, descriptor: &Descriptor) -> Result<()> {
+        match descriptor {
+            Descriptor::OsFile(file) => self.check_file(file),
+            // files of virtual directories may be backed by host files, such as in overlays
+            Descriptor::VirtualFile(file) => match file.host_file() {
+                Some(file) => self.check_file(file),
+                None => Ok(()),
+            },
+            Descriptor::Stdin | Descriptor::Stdout | Descriptor::Stderr => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! How paths are passed between the guest and the host, see `WasiCtxBuilder::path_encoding`.
+//!
+//! Whatever the encoding, paths are carried through `path_get`, virtual file systems and the
+//! `sys` layer as UTF-8 strings in which host names that aren't valid UTF-8 are escaped: every
+//! byte which isn't part of a valid UTF-8 sequence is replaced by one of the last 128 code points
+//! of the supplementary private use area B. A name which contains one of those code points has
+//! its bytes escaped the same way, so that the mapping is lossless. The `sys` layer undoes the
+//! escaping when handing paths to the host.
+use crate::{Error, Result};
+use std::borrow::Cow;
+use std::str;
+
+/// The code point which byte `0x80` is escaped to; the others follow in order, up to `U+10FFFF`.
+const ESCAPE_BASE: u32 = 0x10_ff80;
+
+/// How paths given by the guest, and names returned to it, are encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PathEncoding {
+    /// Paths given by the guest must be valid UTF-8, as WASI requires, or the hostcall fails
+    /// with `EILSEQ`. Likewise, host names which aren't valid UTF-8 are skipped by `fd_readdir`,
+    /// and symlinks to them make `path_readlink` fail with `EILSEQ`.
+    Utf8,
+    /// Paths are passed through as bytes, so that any host name can be listed, opened and
+    /// linked to. On unix, those are the bytes of the host name. On Windows, whose names are
+    /// UTF-16 but may contain unpaired surrogates, they're the WTF-8 encoding of the name, which
+    /// is its UTF-8 encoding unless it contains an unpaired surrogate.
+    Lossless,
+}
+
+impl Default for PathEncoding {
+    fn default() -> Self {
+        PathEncoding::Utf8
+    }
+}
+
+impl PathEncoding {
+    /// Converts a path given by the guest to the escaped form used within the crate.
+    pub(crate) fn path_from_guest(self, path: &[u8]) -> Result<Cow<'_, str>> {
+        if self == PathEncoding::Utf8 {
+            str::from_utf8(path).map_err(|_| Error::EILSEQ)?;
+        }
+        Ok(path_from_bytes(path))
+    }
+
+    /// Checks a name returned to the guest by `fd_readdir` or `path_readlink`, failing with
+    /// `EILSEQ` if it isn't valid UTF-8 in `PathEncoding::Utf8` mode. A `truncated` name may end
+    /// in a sequence which was cut off.
+    pub(crate) fn check_name(self, name: &[u8], truncated: bool) -> Result<()> {
+        if self == PathEncoding::Lossless {
+            return Ok(());
+        }
+        match str::from_utf8(name) {
+            Ok(_) => Ok(()),
+            Err(e) if truncated && e.error_len().is_none() => Ok(()),
+            Err(_) => Err(Error::EILSEQ),
+        }
+    }
+}
+
+/// Escapes the bytes of a host name, or of a path given by the guest.
+pub(crate) fn path_from_bytes(mut bytes: &[u8]) -> Cow<'_, str> {
+    if let Ok(path) = str::from_utf8(bytes) {
+        if !path.chars().any(is_escape) {
+            return Cow::Borrowed(path);
+        }
+    }
+
+    let mut path = String::with_capacity(bytes.len());
+    loop {
+        match str::from_utf8(bytes) {
+            Ok(valid) => {
+                push_escaped(&mut path, valid);
+                return Cow::Owned(path);
+            }
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                push_escaped(&mut path, unsafe { str::from_utf8_unchecked(valid) });
+                // a sequence cut off at the end is invalid as a whole
+                let (invalid, rest) = match e.error_len() {
+                    Some(len) => rest.split_at(len),
+                    None => (rest, &[][..]),
+                };
+                for &byte in invalid {
+                    path.push(escape(byte));
+                }
+                bytes = rest;
+            }
+        }
+    }
+}
+
+/// Undoes `path_from_bytes`, giving the bytes of the host name.
+pub(crate) fn path_to_bytes(path: &str) -> Cow<'_, [u8]> {
+    if !path.chars().any(is_escape) {
+        return Cow::Borrowed(path.as_bytes());
+    }
+
+    let mut bytes = Vec::with_capacity(path.len());
+    for c in path.chars() {
+        if is_escape(c) {
+            bytes.push((c as u32 - ESCAPE_BASE) as u8 | 0x80);
+        } else {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+    }
+    bytes.into()
+}
+
+fn push_escaped(path: &mut String, valid: &str) {
+    for c in valid.chars() {
+        if is_escape(c) {
+            let mut buf = [0; 4];
+            for &byte in c.encode_utf8(&mut buf).as_bytes() {
+                path.push(escape(byte));
+            }
+        } else {
+            path.push(c);
+        }
+    }
+}
+
+fn is_escape(c: char) -> bool {
+    c as u32 >= ESCAPE_BASE
+}
+
+/// Escapes a byte which can't be passed as it is, which is never ASCII.
+fn escape(byte: u8) -> char {
+    std::char::from_u32(ESCAPE_BASE + u32::from(byte & 0x7f)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bytes: &[u8]) -> String {
+        let path = path_from_bytes(bytes);
+        assert_eq!(&*path_to_bytes(&path), bytes, "escaped as {:?}", path);
+        path.into_owned()
+    }
+
+    #[test]
+    fn valid_names_are_passed_through() {
+        assert!(match path_from_bytes(b"dir/file.txt") {
+            Cow::Borrowed(path) => path == "dir/file.txt",
+            Cow::Owned(_) => false,
+        });
+        assert_eq!(round_trip("caf\u{e9}".as_bytes()), "caf\u{e9}");
+    }
+
+    #[test]
+    fn invalid_bytes_are_escaped() {
+        assert_eq!(round_trip(b"caf\xe9"), "caf\u{10ffe9}");
+        // a truncated sequence at the end, and an unexpected continuation byte
+        assert_eq!(round_trip(b"\xe2\x82"), "\u{10ffe2}\u{10ff82}");
+        assert_eq!(round_trip(b"a\x80b"), "a\u{10ff80}b");
+    }
+
+    #[test]
+    fn escape_code_points_are_escaped_themselves() {
+        let name = "\u{10ffe9}";
+        let escaped = round_trip(name.as_bytes());
+        assert_ne!(escaped, name);
+        assert_eq!(escaped.chars().count(), name.len());
+    }
+
+    #[test]
+    fn all_short_names_round_trip() {
+        for first in 0..=255u8 {
+            round_trip(&[first]);
+            for &second in &[0x41, 0x80, 0xbf, 0xc3, 0xff] {
+                round_trip(&[first, second]);
+            }
+        }
+    }
+
+    #[test]
+    fn utf8_paths_must_be_valid() {
+        assert_eq!(
+            PathEncoding::Utf8
+                .path_from_guest(b"caf\xe9")
+                .err()
+                .map(|e| e.as_wasi_errno()),
+            Some(crate::host::__WASI_EILSEQ)
+        );
+        assert_eq!(
+            PathEncoding::Lossless.path_from_guest(b"caf\xe9").unwrap(),
+            "caf\u{10ffe9}"
+        );
+    }
+
+    #[test]
+    fn utf8_names_must_be_valid() {
+        let utf8 = PathEncoding::Utf8;
+        assert!(utf8.check_name("caf\u{e9}".as_bytes(), false).is_ok());
+        assert!(utf8.check_name(b"caf\xe9", false).is_err());
+        // a sequence cut off at the end is fine only if the name was truncated
+        assert!(utf8.check_name(b"caf\xc3", true).is_ok());
+        assert!(utf8.check_name(b"caf\xc3", false).is_err());
+        assert!(utf8.check_name(b"caf\xe9!", true).is_err());
+        assert!(PathEncoding::Lossless.check_name(b"caf\xe9", false).is_ok());
+    }
+}
@@ -0,0 +1,192 @@
+//! Conversions between WASI errnos, host error codes and `io::Error`.
+//!
+//! Every WASI errno survives the round trip through `io::Error`: errnos with a host counterpart
+//! become OS errors with that code, and the others carry the errno along, so that
+//! `from_io_error(&to_io_error(errno))` always gives back `errno`.
+use crate::sys::{self, host_impl};
+use crate::{host, wasm32};
+use std::{error, fmt, io};
+
+/// The `io::ErrorKind` of WASI errnos which aren't `ErrorKind::Other`, matching the kinds `std`
+/// gives to the corresponding OS errors. Kinds corresponding to multiple errnos are converted
+/// back to the first one.
+const KINDS: &[(wasm32::__wasi_errno_t, io::ErrorKind)] = &[
+    (host::__WASI_ENOENT, io::ErrorKind::NotFound),
+    (host::__WASI_EACCES, io::ErrorKind::PermissionDenied),
+    (host::__WASI_EPERM, io::ErrorKind::PermissionDenied),
+    (host::__WASI_ENOTCAPABLE, io::ErrorKind::PermissionDenied),
+    (host::__WASI_ECONNREFUSED, io::ErrorKind::ConnectionRefused),
+    (host::__WASI_ECONNRESET, io::ErrorKind::ConnectionReset),
+    (host::__WASI_ECONNABORTED, io::ErrorKind::ConnectionAborted),
+    (host::__WASI_ENOTCONN, io::ErrorKind::NotConnected),
+    (host::__WASI_EADDRINUSE, io::ErrorKind::AddrInUse),
+    (host::__WASI_EADDRNOTAVAIL, io::ErrorKind::AddrNotAvailable),
+    (host::__WASI_EPIPE, io::ErrorKind::BrokenPipe),
+    (host::__WASI_EEXIST, io::ErrorKind::AlreadyExists),
+    (host::__WASI_EAGAIN, io::ErrorKind::WouldBlock),
+    (host::__WASI_EINVAL, io::ErrorKind::InvalidInput),
+    (host::__WASI_ETIMEDOUT, io::ErrorKind::TimedOut),
+    (host::__WASI_EINTR, io::ErrorKind::Interrupted),
+];
+
+/// The payload of `io::Error`s for WASI errnos without a host counterpart.
+#[derive(Debug)]
+struct WasiErrno(wasm32::__wasi_errno_t);
+
+impl fmt::Display for WasiErrno {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(description(self.0))
+    }
+}
+
+impl error::Error for WasiErrno {}
+
+/// Convert a WASI errno into an `io::Error`, see the module documentation.
+///
+/// Errnos which aren't defined by WASI are reported as `ErrorKind::Other`, and converted back
+/// as they are.
+pub fn to_io_error(errno: wasm32::__wasi_errno_t) -> io::Error {
+    match host_impl::errno_to_host(errno) {
+        Some(code) => io::Error::from_raw_os_error(code),
+        None => io::Error::new(to_io_error_kind(errno), WasiErrno(errno)),
+    }
+}
+
+/// Convert the result of a hostcall into an `io::Result`, which is `Ok` for `__WASI_ESUCCESS`.
+pub fn to_io_result(errno: wasm32::__wasi_errno_t) -> io::Result<()> {
+    if errno == host::__WASI_ESUCCESS {
+        Ok(())
+    } else {
+        Err(to_io_error(errno))
+    }
+}
+
+/// Convert an `io::Error` into a WASI errno, by its OS error code if it has one, and otherwise
+/// by its kind. Errors returned by `to_io_error` are converted back to their errno.
+pub fn from_io_error(err: &io::Error) -> wasm32::__wasi_errno_t {
+    if let Some(code) = err.raw_os_error() {
+        return from_host(code);
+    }
+    match err
+        .get_ref()
+        .and_then(|err| err.downcast_ref::<WasiErrno>())
+    {
+        Some(WasiErrno(errno)) => *errno,
+        None => from_io_error_kind(err.kind()),
+    }
+}
+
+/// The `io::ErrorKind` best describing a WASI errno, `ErrorKind::Other` for most of them.
+pub fn to_io_error_kind(errno: wasm32::__wasi_errno_t) -> io::ErrorKind {
+    KINDS
+        .iter()
+        .find(|(e, _)| *e == errno)
+        .map_or(io::ErrorKind::Other, |(_, kind)| *kind)
+}
+
+/// The WASI errno best describing an `io::ErrorKind`, `__WASI_EIO` for kinds without a more
+/// specific one, such as `ErrorKind::Other`, `ErrorKind::InvalidData` and
+/// `ErrorKind::UnexpectedEof`.
+pub fn from_io_error_kind(kind: io::ErrorKind) -> wasm32::__wasi_errno_t {
+    KINDS
+        .iter()
+        .find(|(_, k)| *k == kind)
+        .map_or(host::__WASI_EIO, |(errno, _)| *errno)
+}
+
+/// Convert a host error code, an errno on Unix and a `GetLastError` code on Windows, into a WASI
+/// errno.
+pub fn from_host(code: i32) -> wasm32::__wasi_errno_t {
+    (1..=host::__WASI_ENOTCAPABLE)
+        .find(|errno| host_impl::errno_to_host(*errno) == Some(code))
+        .unwrap_or_else(|| sys::errno_from_host(code))
+}
+
+/// Convert a WASI errno into a host error code, if the host has a counterpart for it.
+pub fn to_host(errno: wasm32::__wasi_errno_t) -> Option<i32> {
+    host_impl::errno_to_host(errno)
+}
+
+/// A description of a WASI errno, as given by `strerror` on POSIX hosts.
+pub fn description(errno: wasm32::__wasi_errno_t) -> &'static str {
+    match errno {
+        host::__WASI_ESUCCESS => "Success",
+        host::__WASI_E2BIG => "Argument list too long",
+        host::__WASI_EACCES => "Permission denied",
+        host::__WASI_EADDRINUSE => "Address in use",
+        host::__WASI_EADDRNOTAVAIL => "Address not available",
+        host::__WASI_EAFNOSUPPORT => "Address family not supported by protocol",
+        host::__WASI_EAGAIN => "Resource temporarily unavailable",
+        host::__WASI_EALREADY => "Operation already in progress",
+        host::__WASI_EBADF => "Bad file descriptor",
+        host::__WASI_EBADMSG => "Bad message",
+        host::__WASI_EBUSY => "Resource busy",
+        host::__WASI_ECANCELED => "Operation canceled",
+        host::__WASI_ECHILD => "No child process",
+        host::__WASI_ECONNABORTED => "Connection aborted",
+        host::__WASI_ECONNREFUSED => "Connection refused",
+        host::__WASI_ECONNRESET => "Connection reset by peer",
+        host::__WASI_EDEADLK => "Resource deadlock would occur",
+        host::__WASI_EDESTADDRREQ => "Destination address required",
+        host::__WASI_EDOM => "Domain error",
+        host::__WASI_EDQUOT => "Quota exceeded",
+        host::__WASI_EEXIST => "File exists",
+        host::__WASI_EFAULT => "Bad address",
+        host::__WASI_EFBIG => "File too large",
+        host::__WASI_EHOSTUNREACH => "Host is unreachable",
+        host::__WASI_EIDRM => "Identifier removed",
+        host::__WASI_EILSEQ => "Illegal byte sequence",
+        host::__WASI_EINPROGRESS => "Operation in progress",
+        host::__WASI_EINTR => "Interrupted system call",
+        host::__WASI_EINVAL => "Invalid argument",
+        host::__WASI_EIO => "I/O error",
+        host::__WASI_EISCONN => "Socket is connected",
+        host::__WASI_EISDIR => "Is a directory",
+        host::__WASI_ELOOP => "Symbolic link loop",
+        host::__WASI_EMFILE => "No file descriptors available",
+        host::__WASI_EMLINK => "Too many links",
+        host::__WASI_EMSGSIZE => "Message too large",
+        host::__WASI_EMULTIHOP => "Multihop attempted",
+        host::__WASI_ENAMETOOLONG => "Filename too long",
+        host::__WASI_ENETDOWN => "Network is down",
+        host::__WASI_ENETRESET => "Connection reset by network",
+        host::__WASI_ENETUNREACH => "Network unreachable",
+        host::__WASI_ENFILE => "Too many open files in system",
+        host::__WASI_ENOBUFS => "No buffer space available",
+        host::__WASI_ENODEV => "No such device",
+        host::__WASI_ENOENT => "No such file or directory",
+        host::__WASI_ENOEXEC => "Exec format error",
+        host::__WASI_ENOLCK => "No locks available",
+        host::__WASI_ENOLINK => "Link has been severed",
+        host::__WASI_ENOMEM => "Out of memory",
+        host::__WASI_ENOMSG => "No message of desired type",
+        host::__WASI_ENOPROTOOPT => "Protocol not available",
+        host::__WASI_ENOSPC => "No space left on device",
+        host::__WASI_ENOSYS => "Function not implemented",
+        host::__WASI_ENOTCONN => "Socket not connected",
+        host::__WASI_ENOTDIR => "Not a directory",
+        host::__WASI_ENOTEMPTY => "Directory not empty",
+        host::__WASI_ENOTRECOVERABLE => "State not recoverable",
+        host::__WASI_ENOTSOCK => "Not a socket",
+        host::__WASI_ENOTSUP => "Not supported",
+        host::__WASI_ENOTTY => "Not a tty",
+        host::__WASI_ENXIO => "No such device or address",
+        host::__WASI_EOVERFLOW => "Value too large for data type",
+        host::__WASI_EOWNERDEAD => "Previous owner died",
+        host::__WASI_EPERM => "Operation not permitted",
+        host::__WASI_EPIPE => "Broken pipe",
+        host::__WASI_EPROTO => "Protocol error",
+        host::__WASI_EPROTONOSUPPORT => "Protocol not supported",
+        host::__WASI_EPROTOTYPE => "Protocol wrong type for socket",
+        host::__WASI_ERANGE => "Result not representable",
+        host::__WASI_EROFS => "Read-only file system",
+        host::__WASI_ESPIPE => "Invalid seek",
+        host::__WASI_ESRCH => "No such process",
+        host::__WASI_ESTALE => "Stale file handle",
+        host::__WASI_ETIMEDOUT => "Operation timed out",
+        host::__WASI_ETXTBSY => "Text file busy",
+        host::__WASI_EXDEV => "Cross-device link",
+        host::__WASI_ENOTCAPABLE => "Capabilities insufficient",
+        _ => "Unknown error",
+    }
+}
@@ -1,12 +1,13 @@
 // Due to https://github.com/rust-lang/rust/issues/64247
 #![allow(clippy::use_self)]
-use crate::host;
-use failure::Fail;
+use crate::{host, wasm32};
 use std::convert::Infallible;
 use std::fmt;
+use std::io;
 use std::num::TryFromIntError;
+use std::path::{Path, PathBuf};
 
-#[derive(Clone, Copy, Debug, Fail, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[repr(u16)]
 pub enum WasiError {
     ESUCCESS = host::__WASI_ESUCCESS,
@@ -102,14 +103,28 @@ impl fmt::Display for WasiError {
     }
 }
 
-#[derive(Debug, Fail)]
+impl std::error::Error for WasiError {}
+
+#[derive(Debug)]
 pub enum Error {
     Wasi(WasiError),
-    Io(std::io::Error),
+    Io(io::Error),
     #[cfg(unix)]
     Nix(nix::Error),
     #[cfg(windows)]
     Win(winx::winerror::WinError),
+    /// An error returned by a hostcall, along with what it was operating on.
+    Context(Box<ErrorContext>),
+}
+
+/// The hostcall an `Error` was returned by, see `Error::hostcall`.
+#[derive(Debug)]
+pub struct ErrorContext {
+    /// Unset until the error leaves the hostcall, when it only records the path.
+    hostcall: Option<&'static str>,
+    fd: Option<host::__wasi_fd_t>,
+    path: Option<PathBuf>,
+    error: Error,
 }
 
 impl From<WasiError> for Error {
@@ -125,8 +140,8 @@ impl From<nix::Error> for Error {
     }
 }
 
-impl From<std::io::Error> for Error {
-    fn from(err: std::io::Error) -> Self {
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
         Self::Io(err)
     }
 }
@@ -151,25 +166,99 @@ impl From<winx::winerror::WinError> for Error {
 }
 
 impl Error {
-    pub(crate) fn as_wasi_errno(&self) -> host::__wasi_errno_t {
+    /// The errno returned to the guest for this error.
+    pub fn as_wasi_errno(&self) -> host::__wasi_errno_t {
         match self {
             Self::Wasi(no) => no.as_raw_errno(),
-            Self::Io(e) => errno_from_ioerror(e.to_owned()),
+            Self::Io(e) => crate::errno::from_io_error(e),
             #[cfg(unix)]
-            Self::Nix(err) => err
-                .as_errno()
-                .map_or_else(
-                    || {
-                        log::debug!("Unknown nix errno: {}", err);
-                        Self::ENOSYS
-                    },
-                    crate::sys::host_impl::errno_from_nix,
-                )
-                .as_wasi_errno(),
+            Self::Nix(err) => match err {
+                nix::Error::Sys(errno) => crate::sys::host_impl::errno_from_nix(*errno),
+                nix::Error::InvalidPath => Self::EINVAL,
+                nix::Error::InvalidUtf8 => Self::EILSEQ,
+                nix::Error::UnsupportedOperation => Self::ENOTSUP,
+            }
+            .as_wasi_errno(),
             #[cfg(windows)]
             Self::Win(err) => crate::sys::host_impl::errno_from_win(*err),
+            Self::Context(context) => context.error.as_wasi_errno(),
+        }
+    }
+
+    /// Record that this error was returned by `hostcall`, for the guest descriptor `fd`, keeping
+    /// the path recorded by `Error::at_path`, if any.
+    pub(crate) fn in_hostcall(
+        mut self,
+        hostcall: &'static str,
+        fd: Option<host::__wasi_fd_t>,
+    ) -> Self {
+        if let Self::Context(context) = &mut self {
+            if context.hostcall.is_none() {
+                context.hostcall = Some(hostcall);
+                context.fd = fd;
+                return self;
+            }
+        }
+        Self::Context(Box::new(ErrorContext {
+            hostcall: Some(hostcall),
+            fd,
+            path: None,
+            error: self,
+        }))
+    }
+
+    /// Record that this error occurred while resolving or operating on `path`, unless it already
+    /// has a path.
+    pub(crate) fn at_path<P: Into<PathBuf>>(self, path: P) -> Self {
+        match self {
+            Self::Context(_) => self,
+            error => Self::Context(Box::new(ErrorContext {
+                hostcall: None,
+                fd: None,
+                path: Some(path.into()),
+                error,
+            })),
+        }
+    }
+
+    /// The name of the hostcall which returned this error, for errors taken with
+    /// `WasiCtx::take_last_error`.
+    pub fn hostcall(&self) -> Option<&'static str> {
+        match self {
+            Self::Context(context) => context.hostcall,
+            _ => None,
+        }
+    }
+
+    /// The guest descriptor the hostcall operated on. For hostcalls taking two descriptors, such
+    /// as `path_rename`, this is the first one.
+    pub fn fd(&self) -> Option<host::__wasi_fd_t> {
+        match self {
+            Self::Context(context) => context.fd,
+            _ => None,
+        }
+    }
+
+    /// The path the hostcall failed to resolve, as given by the guest, or otherwise the resolved
+    /// path it failed to operate on, relative to the descriptor it was resolved from and after
+    /// following any symlinks. For hostcalls taking two paths, such as `path_rename`, this is the
+    /// first one, unless only the second one failed to resolve.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Context(context) => context.path.as_ref().map(AsRef::as_ref),
+            _ => None,
+        }
+    }
+
+    /// The I/O error reported by the host, if the error originated from one.
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Context(context) => context.error.io_error(),
+            _ => None,
         }
     }
+
     pub const ESUCCESS: Self = Error::Wasi(WasiError::ESUCCESS);
     pub const E2BIG: Self = Error::Wasi(WasiError::E2BIG);
     pub const EACCES: Self = Error::Wasi(WasiError::EACCES);
@@ -252,22 +341,38 @@ impl Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Self::Io(e) => e.fmt(f),
             Self::Wasi(e) => e.fmt(f),
-            #[cfg(unix)]
-            Self::Nix(e) => e.fmt(f),
-            #[cfg(windows)]
-            Self::Win(e) => e.fmt(f),
+            Self::Context(context) => {
+                f.write_str(context.hostcall.unwrap_or("hostcall"))?;
+                f.write_str(" failed")?;
+                if let Some(fd) = context.fd {
+                    write!(f, " on fd {}", fd)?;
+                }
+                if let Some(path) = &context.path {
+                    write!(f, " at {:?}", path)?;
+                }
+                write!(f, ": {}", context.error)
+            }
+            // the host error itself is the source
+            _ => {
+                let errno = wasm32::strerror(self.as_wasi_errno());
+                f.write_str(errno.trim_start_matches("__WASI_"))
+            }
         }
     }
 }
 
-fn errno_from_ioerror(e: &std::io::Error) -> host::__wasi_errno_t {
-    match e.raw_os_error() {
-        Some(code) => crate::sys::errno_from_host(code),
-        None => {
-            log::debug!("Inconvertible OS error: {}", e);
-            host::__WASI_EIO
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Wasi(_) => None,
+            Self::Io(e) => Some(e),
+            #[cfg(unix)]
+            Self::Nix(e) => Some(e),
+            #[cfg(windows)]
+            Self::Win(e) => Some(e),
+            // the error itself is part of the message
+            Self::Context(context) => context.error.source(),
         }
     }
 }
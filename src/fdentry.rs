@@ -1,6 +1,14 @@
-use crate::sys::fdentry_impl::{determine_type_and_access_rights, OsFile};
-use crate::{host, Error, Result};
-use std::path::PathBuf;
+use crate::creation::CreationMode;
+use crate::helpers::stream_position;
+use crate::hostcalls_impl::RawDirent;
+use crate::journal::GuestPath;
+use crate::policy::PolicyScope;
+use crate::sys::fdentry_impl::{self, determine_type_and_access_rights, file_path, OsFile};
+use crate::sys::hostcalls_impl::{self, fs_helpers};
+use crate::virtfs::VirtualFile;
+use crate::{host, wasm32, Error, Result};
+use std::io::{Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::{fs, io};
 
 #[derive(Debug)]
@@ -9,6 +17,7 @@ pub(crate) enum Descriptor {
     Stdin,
     Stdout,
     Stderr,
+    VirtualFile(Box<dyn VirtualFile>),
 }
 
 impl Descriptor {
@@ -26,10 +35,16 @@ impl Descriptor {
         }
     }
 
-    pub(crate) fn is_file(&self) -> bool {
+    /// Whether this is a pipe or a socket, which has no offset, so that seeking it fails with
+    /// `ESPIPE` whatever its rights. Host files, including stdio, are told apart by `file_type`,
+    /// their type when they were opened.
+    fn is_stream(&self, file_type: host::__wasi_filetype_t) -> bool {
         match self {
-            Self::OsFile(_) => true,
-            _ => false,
+            Self::VirtualFile(file) => file.is_stream(),
+            _ => {
+                file_type == host::__WASI_FILETYPE_SOCKET_STREAM
+                    || file_type == host::__WASI_FILETYPE_SOCKET_DGRAM
+            }
         }
     }
 
@@ -58,6 +73,57 @@ impl Descriptor {
     }
 }
 
+/// A description of a descriptor open in a `WasiCtx`, as returned by `WasiCtx::fd_info`.
+#[derive(Clone, Debug)]
+pub struct FdInfo {
+    fd: wasm32::__wasi_fd_t,
+    filetype: wasm32::__wasi_filetype_t,
+    rights_base: wasm32::__wasi_rights_t,
+    rights_inheriting: wasm32::__wasi_rights_t,
+    fdflags: wasm32::__wasi_fdflags_t,
+    preopen_path: Option<PathBuf>,
+    host_path: Option<PathBuf>,
+    offset: Option<wasm32::__wasi_filesize_t>,
+}
+
+impl FdInfo {
+    pub fn fd(&self) -> wasm32::__wasi_fd_t {
+        self.fd
+    }
+
+    pub fn filetype(&self) -> wasm32::__wasi_filetype_t {
+        self.filetype
+    }
+
+    pub fn rights_base(&self) -> wasm32::__wasi_rights_t {
+        self.rights_base
+    }
+
+    pub fn rights_inheriting(&self) -> wasm32::__wasi_rights_t {
+        self.rights_inheriting
+    }
+
+    /// The flags as reported by `fd_fdstat_get`, which are 0 for the stdio of the host process.
+    pub fn fdflags(&self) -> wasm32::__wasi_fdflags_t {
+        self.fdflags
+    }
+
+    /// The guest path of preopened directories.
+    pub fn preopen_path(&self) -> Option<&Path> {
+        self.preopen_path.as_ref().map(AsRef::as_ref)
+    }
+
+    /// The path of the host file or directory, if it's backed by one that can still be found.
+    pub fn host_path(&self) -> Option<&Path> {
+        self.host_path.as_ref().map(AsRef::as_ref)
+    }
+
+    /// The current offset of regular files.
+    pub fn offset(&self) -> Option<wasm32::__wasi_filesize_t> {
+        self.offset
+    }
+}
+
 /// An abstraction struct serving as a wrapper for a host `Descriptor` object which requires
 /// certain base rights `rights_base` and inheriting rights `rights_inheriting` in order to be
 /// accessed correctly.
@@ -73,24 +139,69 @@ pub(crate) struct FdEntry {
     pub(crate) rights_base: host::__wasi_rights_t,
     pub(crate) rights_inheriting: host::__wasi_rights_t,
     pub(crate) preopen_path: Option<PathBuf>,
+    /// The path policy of the preopen this directory was opened from.
+    pub(crate) policy: Option<PolicyScope>,
+    /// The creation mode of the preopen this directory was opened from.
+    pub(crate) creation_mode: Option<CreationMode>,
+    /// The device number of files reached through this descriptor under `VirtualMetadata`, which
+    /// is the number of the preopen it was opened from, or 0.
+    pub(crate) virtual_dev: host::__wasi_device_t,
+    /// The entries of this directory as of the last time `fd_readdir` read it from the start, if
+    /// it returns them in sorted order.
+    pub(crate) sorted_readdir: Option<Vec<RawDirent>>,
+    /// Where this descriptor was opened from, if it was reached through a preopen.
+    pub(crate) origin: Option<GuestPath>,
+    /// Whether the descriptor is a stream, see `Descriptor::is_stream`.
+    stream: bool,
     // TODO: directories
 }
 
 impl FdEntry {
     pub(crate) fn from(file: fs::File) -> Result<Self> {
         unsafe { determine_type_and_access_rights(&file) }.map(
-            |(file_type, rights_base, rights_inheriting)| Self {
-                file_type,
-                descriptor: Descriptor::OsFile(OsFile::from(file)),
-                rights_base,
-                rights_inheriting,
-                preopen_path: None,
+            |(file_type, rights_base, rights_inheriting)| {
+                let descriptor = Descriptor::OsFile(OsFile::from(file));
+                Self {
+                    file_type,
+                    stream: descriptor.is_stream(file_type),
+                    descriptor,
+                    rights_base,
+                    rights_inheriting,
+                    preopen_path: None,
+                    policy: None,
+                    creation_mode: None,
+                    virtual_dev: 0,
+                    sorted_readdir: None,
+                    origin: None,
+                }
             },
         )
     }
 
-    pub(crate) fn duplicate(file: &fs::File) -> Result<Self> {
-        Self::from(file.try_clone()?)
+    /// Create an `FdEntry` for a descriptor returned by `VirtualFile::openat`, which may refer to
+    /// either a host file or another virtual file.
+    pub(crate) fn from_descriptor(descriptor: Descriptor) -> Result<Self> {
+        let (file_type, rights_base, rights_inheriting) = match &descriptor {
+            Descriptor::VirtualFile(file) => (
+                file.filetype(),
+                file.rights_base(),
+                file.rights_inheriting(),
+            ),
+            descriptor => unsafe { determine_type_and_access_rights(descriptor) }?,
+        };
+        Ok(Self {
+            file_type,
+            stream: descriptor.is_stream(file_type),
+            descriptor,
+            rights_base,
+            rights_inheriting,
+            preopen_path: None,
+            policy: None,
+            creation_mode: None,
+            virtual_dev: 0,
+            sorted_readdir: None,
+            origin: None,
+        })
     }
 
     pub(crate) fn duplicate_stdin() -> Result<Self> {
@@ -101,6 +212,12 @@ impl FdEntry {
                 rights_base,
                 rights_inheriting,
                 preopen_path: None,
+                policy: None,
+                creation_mode: None,
+                virtual_dev: 0,
+                sorted_readdir: None,
+                origin: None,
+                stream: Descriptor::Stdin.is_stream(file_type),
             },
         )
     }
@@ -113,6 +230,12 @@ impl FdEntry {
                 rights_base,
                 rights_inheriting,
                 preopen_path: None,
+                policy: None,
+                creation_mode: None,
+                virtual_dev: 0,
+                sorted_readdir: None,
+                origin: None,
+                stream: Descriptor::Stdout.is_stream(file_type),
             },
         )
     }
@@ -125,10 +248,98 @@ impl FdEntry {
                 rights_base,
                 rights_inheriting,
                 preopen_path: None,
+                policy: None,
+                creation_mode: None,
+                virtual_dev: 0,
+                sorted_readdir: None,
+                origin: None,
+                stream: Descriptor::Stderr.is_stream(file_type),
             },
         )
     }
 
+    /// Describe this entry, which is open as `fd`, for `WasiCtx::fd_info`.
+    pub(crate) fn info(&self, fd: host::__wasi_fd_t) -> FdInfo {
+        let is_file = self.file_type == host::__WASI_FILETYPE_REGULAR_FILE;
+        let (fdflags, host_path, offset) = match &self.descriptor {
+            Descriptor::OsFile(file) => (
+                hostcalls_impl::fd_fdstat_get(file).unwrap_or(0),
+                file_path(file).ok(),
+                if is_file {
+                    stream_position(&**file).ok()
+                } else {
+                    None
+                },
+            ),
+            Descriptor::VirtualFile(file) => (file.fdstat_get(), file.host_path(), file.offset()),
+            Descriptor::Stdin | Descriptor::Stdout | Descriptor::Stderr => (0, None, None),
+        };
+        FdInfo {
+            fd,
+            filetype: self.file_type,
+            rights_base: self.rights_base,
+            rights_inheriting: self.rights_inheriting,
+            fdflags,
+            preopen_path: self.preopen_path.clone(),
+            host_path,
+            offset,
+        }
+    }
+
+    /// Duplicate this entry for `WasiCtx::fork`.
+    ///
+    /// Host files and directories are opened again, so that the duplicate has its own offset,
+    /// unless `share_offset` is set. Pipes and sockets, which have no offset, are shared. Virtual
+    /// files always get their own offset.
+    pub(crate) fn duplicate(&self, share_offset: bool) -> Result<Self> {
+        let descriptor = match &self.descriptor {
+            Descriptor::OsFile(file) if !share_offset && !self.stream => {
+                let path = fdentry_impl::reopen_path(file)?;
+                let file = if self.file_type == host::__WASI_FILETYPE_DIRECTORY {
+                    crate::sys::preopen_dir(&path)?
+                } else {
+                    let offset = if self.file_type == host::__WASI_FILETYPE_REGULAR_FILE {
+                        stream_position(&**file)?
+                    } else {
+                        0
+                    };
+                    let fdflags = hostcalls_impl::fd_fdstat_get(file)?;
+                    // `/proc/self/fd` has symlinks to the files themselves
+                    open_host_file(&path, true, self.rights_base, fdflags, offset)?
+                };
+                Descriptor::OsFile(OsFile::from(file))
+            }
+            Descriptor::OsFile(file) => Descriptor::OsFile(OsFile::from(file.try_clone()?)),
+            Descriptor::Stdin => Descriptor::Stdin,
+            Descriptor::Stdout => Descriptor::Stdout,
+            Descriptor::Stderr => Descriptor::Stderr,
+            Descriptor::VirtualFile(file) => Descriptor::VirtualFile(file.try_clone()?),
+        };
+        Ok(Self {
+            file_type: self.file_type,
+            descriptor,
+            rights_base: self.rights_base,
+            rights_inheriting: self.rights_inheriting,
+            preopen_path: self.preopen_path.clone(),
+            policy: self.policy.clone(),
+            creation_mode: self.creation_mode,
+            virtual_dev: self.virtual_dev,
+            sorted_readdir: self.sorted_readdir.as_ref().map(|_| Vec::new()),
+            origin: self.origin.clone(),
+            stream: self.stream,
+        })
+    }
+
+    /// Convert this `FdEntry` into the host file it refers to.
+    ///
+    /// Fails with `ENOTSUP` for stdio and virtual files.
+    pub(crate) fn into_file(self) -> Result<fs::File> {
+        match self.descriptor {
+            Descriptor::OsFile(file) => Ok(file.into_file()),
+            _ => Err(Error::ENOTSUP),
+        }
+    }
+
     /// Convert this `FdEntry` into a host `Descriptor` object provided the specified
     /// `rights_base` and `rights_inheriting` rights are set on this `FdEntry` object.
     ///
@@ -161,6 +372,30 @@ impl FdEntry {
         Ok(&mut self.descriptor)
     }
 
+    /// Convert this `FdEntry` into a mutable host `Descriptor` object for `fd_readdir`, along with
+    /// the entries it returns if they're sorted, provided the `FD_READDIR` right is set.
+    pub(crate) fn as_readdir_mut(
+        &mut self,
+    ) -> Result<(&mut Descriptor, Option<&mut Vec<RawDirent>>)> {
+        self.validate_rights(host::__WASI_RIGHT_FD_READDIR, 0)?;
+        Ok((&mut self.descriptor, self.sorted_readdir.as_mut()))
+    }
+
+    /// Whether the descriptor is a stream without an offset, see `Descriptor::is_stream`.
+    pub(crate) fn is_stream(&self) -> bool {
+        self.stream
+    }
+
+    /// The path modifications of the contents of this file are journaled under, if it's a
+    /// regular file reached through a preopen.
+    pub(crate) fn journal_path(&self) -> Option<GuestPath> {
+        if self.file_type == host::__WASI_FILETYPE_REGULAR_FILE {
+            self.origin.clone()
+        } else {
+            None
+        }
+    }
+
     /// Check if this `FdEntry` object satisfies the specified base rights `rights_base`, and
     /// inheriting rights `rights_inheriting`; i.e., if rights attached to this `FdEntry` object
     /// are a superset.
@@ -179,3 +414,27 @@ impl FdEntry {
         }
     }
 }
+
+/// Opens the host file at `path` for reading and writing as far as `rights_base` allows, with the
+/// given flags, and seeks to `offset` unless it's 0. A symlink at `path` is only followed if
+/// `follow` is set.
+pub(crate) fn open_host_file(
+    path: &Path,
+    follow: bool,
+    rights_base: host::__wasi_rights_t,
+    fdflags: host::__wasi_fdflags_t,
+    offset: host::__wasi_filesize_t,
+) -> Result<fs::File> {
+    let read = rights_base & host::__WASI_RIGHT_FD_READ != 0;
+    let write = rights_base & host::__WASI_RIGHT_FD_WRITE != 0;
+    let mut open = fs::OpenOptions::new();
+    open.read(read || !write)
+        .write(write)
+        .append(fdflags & host::__WASI_FDFLAG_APPEND != 0);
+    fs_helpers::open_options_ext(&mut open, fdflags, follow);
+    let mut file = open.open(path)?;
+    if offset != 0 {
+        file.seek(SeekFrom::Start(offset))?;
+    }
+    Ok(file)
+}
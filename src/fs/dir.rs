@@ -1,4 +1,5 @@
-use crate::fs::{error::wasi_errno_to_io_error, File, OpenOptions, ReadDir};
+use crate::errno::to_io_result;
+use crate::fs::{File, OpenOptions, ReadDir};
 use crate::{host, hostcalls, WasiCtx};
 #[cfg(unix)]
 use std::os::unix::ffi::OsStrExt;
@@ -49,7 +50,7 @@ impl<'ctx> Dir<'ctx> {
         // on `OsStrExt`.
         unimplemented!("Dir::open_file");
         /*
-        wasi_errno_to_io_error(hostcalls::path_open(
+        to_io_result(hostcalls::path_open(
             self.ctx,
             self.fd,
             host::__WASI_LOOKUP_SYMLINK_FOLLOW,
@@ -95,7 +96,7 @@ impl<'ctx> Dir<'ctx> {
         // TODO: See the comment in `open_file`.
         unimplemented!("Dir::open_dir");
         /*
-        wasi_errno_to_io_error(hostcalls::path_open(
+        to_io_result(hostcalls::path_open(
             self.ctx,
             self.fd,
             host::__WASI_LOOKUP_SYMLINK_FOLLOW,
@@ -129,7 +130,7 @@ impl<'ctx> Dir<'ctx> {
         // TODO: Set the requested rights to be read+write.
         unimplemented!("Dir::create_file");
         /*
-        wasi_errno_to_io_error(hostcalls::path_open(
+        to_io_result(hostcalls::path_open(
             self.ctx,
             self.fd,
             host::__WASI_LOOKUP_SYMLINK_FOLLOW,
@@ -1,4 +1,5 @@
-use crate::fs::{error::wasi_errno_to_io_error, Metadata};
+use crate::errno::to_io_result;
+use crate::fs::Metadata;
 use crate::{host, hostcalls, WasiCtx};
 use std::io;
 
@@ -35,7 +36,7 @@ impl<'ctx> File<'ctx> {
     ///
     /// [`std::fs::File::sync_all`]: https://doc.rust-lang.org/std/fs/struct.File.html#method.sync_all
     pub fn sync_all(&self) -> io::Result<()> {
-        wasi_errno_to_io_error(unsafe { hostcalls::fd_sync(self.ctx, self.fd) })
+        to_io_result(unsafe { hostcalls::fd_sync(self.ctx, self.fd) })
     }
 
     /// This function is similar to `sync_all`, except that it may not synchronize
@@ -45,7 +46,7 @@ impl<'ctx> File<'ctx> {
     ///
     /// [`std::fs::File::sync_data`]: https://doc.rust-lang.org/std/fs/struct.File.html#method.sync_data
     pub fn sync_data(&self) -> io::Result<()> {
-        wasi_errno_to_io_error(unsafe { hostcalls::fd_datasync(self.ctx, self.fd) })
+        to_io_result(unsafe { hostcalls::fd_datasync(self.ctx, self.fd) })
     }
 
     /// Truncates or extends the underlying file, updating the size of this file
@@ -55,7 +56,7 @@ impl<'ctx> File<'ctx> {
     ///
     /// [`std::fs::File::set_len`]: https://doc.rust-lang.org/std/fs/struct.File.html#method.set_len
     pub fn set_len(&self, size: u64) -> io::Result<()> {
-        wasi_errno_to_io_error(unsafe { hostcalls::fd_filestat_set_size(self.ctx, self.fd, size) })
+        to_io_result(unsafe { hostcalls::fd_filestat_set_size(self.ctx, self.fd, size) })
     }
 
     /// Queries metadata about the underlying file.
@@ -91,7 +92,7 @@ impl<'ctx> io::Read for File<'ctx> {
         // TODO: See the comment in `Dir::open_file`.
         unimplemented!("File::read");
         /*
-        wasi_errno_to_io_error(unsafe {
+        to_io_result(unsafe {
             hostcalls::fd_read(self.ctx, self.fd, &iov, 1, &mut nread)
         })?;
         */
@@ -9,6 +9,7 @@
 #[derive(Copy, Clone, Eq, PartialEq, Hash)]
 pub struct FileType {}
 
+#[allow(clippy::trivially_copy_pass_by_ref)]
 impl FileType {
     /// Tests whether this file type represents a directory.
     ///
@@ -11,6 +11,7 @@ use std::{io, time::SystemTime};
 #[derive(Clone)]
 pub struct Metadata {}
 
+#[allow(clippy::len_without_is_empty)]
 impl Metadata {
     /// Returns the file type for this metadata.
     ///
@@ -1,6 +1,7 @@
 use crate::{Error, Result};
 use std::convert::TryInto;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::io::{self, Seek, SeekFrom};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub(crate) fn systemtime_to_timestamp(st: SystemTime) -> Result<u64> {
     st.duration_since(UNIX_EPOCH)
@@ -9,3 +10,15 @@ pub(crate) fn systemtime_to_timestamp(st: SystemTime) -> Result<u64> {
         .try_into()
         .map_err(Into::into) // u128 doesn't fit into u64
 }
+
+pub(crate) fn timestamp_to_systemtime(timestamp: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(timestamp)
+}
+
+/// The current offset of `seek`, as `Seek::stream_position` returns in newer versions of Rust.
+// newer versions of clippy suggest `stream_position` through `clippy::seek_from_current`, which
+// older versions don't know
+#[allow(clippy::complexity)]
+pub(crate) fn stream_position<S: Seek>(mut seek: S) -> io::Result<u64> {
+    seek.seek(SeekFrom::Current(0))
+}
@@ -3,8 +3,8 @@
 //! still largely reflects that.
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
-use crate::{Error, Result};
-use std::{io, slice, str};
+#![allow(clippy::unreadable_literal)]
+use std::{io, slice};
 
 pub(crate) type void = ::std::os::raw::c_void;
 
@@ -224,17 +224,25 @@ pub(crate) const RIGHTS_ALL: __wasi_rights_t = __WASI_RIGHT_FD_DATASYNC
     | __WASI_RIGHT_POLL_FD_READWRITE
     | __WASI_RIGHT_SOCK_SHUTDOWN;
 
-// Block and character device interaction is outside the scope of
-// WASI. Simply allow everything.
+// Block and character device interaction is outside the scope of WASI, so devices may only be
+// read from, written to and synced, and block devices seeked. Which devices may be opened at all
+// is up to the `DevicePolicy`.
 // Those constants are unused on Windows
 #[allow(unused)]
-pub(crate) const RIGHTS_BLOCK_DEVICE_BASE: __wasi_rights_t = RIGHTS_ALL;
+pub(crate) const RIGHTS_BLOCK_DEVICE_BASE: __wasi_rights_t =
+    RIGHTS_CHARACTER_DEVICE_BASE | __WASI_RIGHT_FD_SEEK | __WASI_RIGHT_FD_TELL;
 #[allow(unused)]
-pub(crate) const RIGHTS_BLOCK_DEVICE_INHERITING: __wasi_rights_t = RIGHTS_ALL;
+pub(crate) const RIGHTS_BLOCK_DEVICE_INHERITING: __wasi_rights_t = 0;
 #[allow(unused)]
-pub(crate) const RIGHTS_CHARACTER_DEVICE_BASE: __wasi_rights_t = RIGHTS_ALL;
+pub(crate) const RIGHTS_CHARACTER_DEVICE_BASE: __wasi_rights_t = __WASI_RIGHT_FD_DATASYNC
+    | __WASI_RIGHT_FD_READ
+    | __WASI_RIGHT_FD_FDSTAT_SET_FLAGS
+    | __WASI_RIGHT_FD_SYNC
+    | __WASI_RIGHT_FD_WRITE
+    | __WASI_RIGHT_FD_FILESTAT_GET
+    | __WASI_RIGHT_POLL_FD_READWRITE;
 #[allow(unused)]
-pub(crate) const RIGHTS_CHARACTER_DEVICE_INHERITING: __wasi_rights_t = RIGHTS_ALL;
+pub(crate) const RIGHTS_CHARACTER_DEVICE_INHERITING: __wasi_rights_t = 0;
 
 // Only allow directory operations on directories. Directories can only
 // yield file descriptors to other directories and files.
@@ -287,6 +295,14 @@ pub(crate) const RIGHTS_SHARED_MEMORY_BASE: __wasi_rights_t = __WASI_RIGHT_FD_RE
 #[allow(unused)]
 pub(crate) const RIGHTS_SHARED_MEMORY_INHERITING: __wasi_rights_t = 0;
 
+// Operations that apply to pipes and FIFOs, which are streams without an offset.
+pub(crate) const RIGHTS_PIPE_BASE: __wasi_rights_t = __WASI_RIGHT_FD_READ
+    | __WASI_RIGHT_FD_FDSTAT_SET_FLAGS
+    | __WASI_RIGHT_FD_WRITE
+    | __WASI_RIGHT_FD_FILESTAT_GET
+    | __WASI_RIGHT_POLL_FD_READWRITE;
+pub(crate) const RIGHTS_PIPE_INHERITING: __wasi_rights_t = 0;
+
 // Operations that apply to sockets and socket pairs.
 pub(crate) const RIGHTS_SOCKET_BASE: __wasi_rights_t = __WASI_RIGHT_FD_READ
     | __WASI_RIGHT_FD_FDSTAT_SET_FLAGS
@@ -505,14 +521,6 @@ pub(crate) unsafe fn iovec_to_host_mut(iovec: &mut __wasi_iovec_t) -> io::IoSlic
     io::IoSliceMut::new(slice)
 }
 
-/// Creates not-owned WASI path from byte slice.
-///
-/// NB WASI spec requires bytes to be valid UTF-8. Otherwise,
-/// `__WASI_EILSEQ` error is returned.
-pub(crate) fn path_from_slice<'a>(s: &'a [u8]) -> Result<&'a str> {
-    str::from_utf8(s).map_err(|_| Error::EILSEQ)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,12 +1,16 @@
 #![allow(non_camel_case_types)]
+#![allow(clippy::too_many_arguments)]
 use crate::ctx::WasiCtx;
 use crate::wasm32;
 
 hostcalls! {
+    #[fd(fd)]
     pub unsafe fn fd_close(wasi_ctx: &mut WasiCtx, fd: wasm32::__wasi_fd_t,) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_datasync(wasi_ctx: &WasiCtx, fd: wasm32::__wasi_fd_t,) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_pread(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
@@ -17,6 +21,7 @@ hostcalls! {
         nread: wasm32::uintptr_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_pwrite(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
@@ -27,6 +32,7 @@ hostcalls! {
         nwritten: wasm32::uintptr_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_read(
         wasi_ctx: &mut WasiCtx,
         memory: &mut [u8],
@@ -36,12 +42,14 @@ hostcalls! {
         nread: wasm32::uintptr_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(from)]
     pub unsafe fn fd_renumber(
         wasi_ctx: &mut WasiCtx,
         from: wasm32::__wasi_fd_t,
         to: wasm32::__wasi_fd_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_seek(
         wasi_ctx: &mut WasiCtx,
         memory: &mut [u8],
@@ -51,6 +59,7 @@ hostcalls! {
         newoffset: wasm32::uintptr_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_tell(
         wasi_ctx: &mut WasiCtx,
         memory: &mut [u8],
@@ -58,6 +67,7 @@ hostcalls! {
         newoffset: wasm32::uintptr_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_fdstat_get(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
@@ -65,12 +75,14 @@ hostcalls! {
         fdstat_ptr: wasm32::uintptr_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_fdstat_set_flags(
         wasi_ctx: &WasiCtx,
         fd: wasm32::__wasi_fd_t,
         fdflags: wasm32::__wasi_fdflags_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_fdstat_set_rights(
         wasi_ctx: &mut WasiCtx,
         fd: wasm32::__wasi_fd_t,
@@ -78,8 +90,10 @@ hostcalls! {
         fs_rights_inheriting: wasm32::__wasi_rights_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_sync(wasi_ctx: &WasiCtx, fd: wasm32::__wasi_fd_t,) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_write(
         wasi_ctx: &mut WasiCtx,
         memory: &mut [u8],
@@ -89,6 +103,7 @@ hostcalls! {
         nwritten: wasm32::uintptr_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_advise(
         wasi_ctx: &WasiCtx,
         fd: wasm32::__wasi_fd_t,
@@ -97,6 +112,7 @@ hostcalls! {
         advice: wasm32::__wasi_advice_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_allocate(
         wasi_ctx: &WasiCtx,
         fd: wasm32::__wasi_fd_t,
@@ -104,6 +120,7 @@ hostcalls! {
         len: wasm32::__wasi_filesize_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(dirfd)]
     pub unsafe fn path_create_directory(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
@@ -112,6 +129,7 @@ hostcalls! {
         path_len: wasm32::size_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(old_dirfd)]
     pub unsafe fn path_link(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
@@ -124,6 +142,7 @@ hostcalls! {
         new_path_len: wasm32::size_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(dirfd)]
     pub unsafe fn path_open(
         wasi_ctx: &mut WasiCtx,
         memory: &mut [u8],
@@ -138,6 +157,7 @@ hostcalls! {
         fd_out_ptr: wasm32::uintptr_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_readdir(
         wasi_ctx: &mut WasiCtx,
         memory: &mut [u8],
@@ -148,6 +168,7 @@ hostcalls! {
         buf_used: wasm32::uintptr_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(dirfd)]
     pub unsafe fn path_readlink(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
@@ -159,6 +180,7 @@ hostcalls! {
         buf_used: wasm32::uintptr_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(old_dirfd)]
     pub unsafe fn path_rename(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
@@ -170,6 +192,7 @@ hostcalls! {
         new_path_len: wasm32::size_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_filestat_get(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
@@ -177,6 +200,7 @@ hostcalls! {
         filestat_ptr: wasm32::uintptr_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_filestat_set_times(
         wasi_ctx: &WasiCtx,
         fd: wasm32::__wasi_fd_t,
@@ -185,12 +209,14 @@ hostcalls! {
         fst_flags: wasm32::__wasi_fstflags_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_filestat_set_size(
         wasi_ctx: &WasiCtx,
         fd: wasm32::__wasi_fd_t,
         st_size: wasm32::__wasi_filesize_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(dirfd)]
     pub unsafe fn path_filestat_get(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
@@ -201,6 +227,7 @@ hostcalls! {
         filestat_ptr: wasm32::uintptr_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(dirfd)]
     pub unsafe fn path_filestat_set_times(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
@@ -213,6 +240,7 @@ hostcalls! {
         fst_flags: wasm32::__wasi_fstflags_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(dirfd)]
     pub unsafe fn path_symlink(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
@@ -223,6 +251,7 @@ hostcalls! {
         new_path_len: wasm32::size_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(dirfd)]
     pub unsafe fn path_unlink_file(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
@@ -231,6 +260,7 @@ hostcalls! {
         path_len: wasm32::size_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(dirfd)]
     pub unsafe fn path_remove_directory(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
@@ -239,6 +269,7 @@ hostcalls! {
         path_len: wasm32::size_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_prestat_get(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
@@ -246,6 +277,7 @@ hostcalls! {
         prestat_ptr: wasm32::uintptr_t,
     ) -> wasm32::__wasi_errno_t;
 
+    #[fd(fd)]
     pub unsafe fn fd_prestat_dir_name(
         wasi_ctx: &WasiCtx,
         memory: &mut [u8],
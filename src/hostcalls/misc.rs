@@ -53,18 +53,21 @@ hostcalls! {
     ) -> wasm32::__wasi_errno_t;
 
     pub unsafe fn random_get(
+        wasi_ctx: &WasiCtx,
         memory: &mut [u8],
         buf_ptr: wasm32::uintptr_t,
         buf_len: wasm32::size_t,
     ) -> wasm32::__wasi_errno_t;
 
     pub unsafe fn clock_res_get(
+        wasi_ctx: &WasiCtx,
         memory: &mut [u8],
         clock_id: wasm32::__wasi_clockid_t,
         resolution_ptr: wasm32::uintptr_t,
     ) -> wasm32::__wasi_errno_t;
 
     pub unsafe fn clock_time_get(
+        wasi_ctx: &WasiCtx,
         memory: &mut [u8],
         clock_id: wasm32::__wasi_clockid_t,
         precision: wasm32::__wasi_timestamp_t,
@@ -1,6 +1,7 @@
 mod fs;
 mod misc;
 mod sock;
+pub mod wasm64;
 
 pub use self::fs::*;
 pub use self::misc::*;
@@ -2,10 +2,11 @@
 #![allow(unused_unsafe)]
 #![allow(unused)]
 use crate::ctx::WasiCtx;
-use crate::wasm32;
+use crate::{host, wasm32};
 use wasi_common_cbindgen::wasi_common_cbindgen;
 
 #[wasi_common_cbindgen]
+#[allow(clippy::too_many_arguments)]
 pub unsafe fn sock_recv(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
@@ -16,7 +17,8 @@ pub unsafe fn sock_recv(
     ro_datalen: wasm32::uintptr_t,
     ro_flags: wasm32::uintptr_t,
 ) -> wasm32::__wasi_errno_t {
-    unimplemented!("sock_recv")
+    // sockets aren't supported yet
+    super::return_enc_errno(host::__WASI_ENOSYS)
 }
 
 #[wasi_common_cbindgen]
@@ -29,7 +31,8 @@ pub unsafe fn sock_send(
     si_flags: wasm32::__wasi_siflags_t,
     so_datalen: wasm32::uintptr_t,
 ) -> wasm32::__wasi_errno_t {
-    unimplemented!("sock_send")
+    // sockets aren't supported yet
+    super::return_enc_errno(host::__WASI_ENOSYS)
 }
 
 #[wasi_common_cbindgen]
@@ -39,5 +42,6 @@ pub unsafe fn sock_shutdown(
     sock: wasm32::__wasi_fd_t,
     how: wasm32::__wasi_sdflags_t,
 ) -> wasm32::__wasi_errno_t {
-    unimplemented!("sock_shutdown")
+    // sockets aren't supported yet
+    super::return_enc_errno(host::__WASI_ENOSYS)
 }
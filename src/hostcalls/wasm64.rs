@@ -0,0 +1,397 @@
+//! Hostcalls for guests using 64-bit linear memories. These are the same as the ones exported
+//! by the parent module, except that pointers and sizes are `wasm64::uintptr_t` and
+//! `wasm64::size_t`, and that no C bindings are generated for them.
+#![allow(non_camel_case_types)]
+#![allow(clippy::too_many_arguments)]
+#![allow(unused_unsafe)]
+use crate::ctx::WasiCtx;
+use crate::{host, wasm64};
+
+pub use super::misc::{proc_exit, proc_raise};
+
+hostcalls_wasm64! {
+    #[fd(fd)]
+    pub unsafe fn fd_close(wasi_ctx: &mut WasiCtx, fd: wasm64::__wasi_fd_t,) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_datasync(wasi_ctx: &WasiCtx, fd: wasm64::__wasi_fd_t,) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_pread(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        fd: wasm64::__wasi_fd_t,
+        iovs_ptr: wasm64::uintptr_t,
+        iovs_len: wasm64::size_t,
+        offset: wasm64::__wasi_filesize_t,
+        nread: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_pwrite(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        fd: wasm64::__wasi_fd_t,
+        iovs_ptr: wasm64::uintptr_t,
+        iovs_len: wasm64::size_t,
+        offset: wasm64::__wasi_filesize_t,
+        nwritten: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_read(
+        wasi_ctx: &mut WasiCtx,
+        memory: &mut [u8],
+        fd: wasm64::__wasi_fd_t,
+        iovs_ptr: wasm64::uintptr_t,
+        iovs_len: wasm64::size_t,
+        nread: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(from)]
+    pub unsafe fn fd_renumber(
+        wasi_ctx: &mut WasiCtx,
+        from: wasm64::__wasi_fd_t,
+        to: wasm64::__wasi_fd_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_seek(
+        wasi_ctx: &mut WasiCtx,
+        memory: &mut [u8],
+        fd: wasm64::__wasi_fd_t,
+        offset: wasm64::__wasi_filedelta_t,
+        whence: wasm64::__wasi_whence_t,
+        newoffset: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_tell(
+        wasi_ctx: &mut WasiCtx,
+        memory: &mut [u8],
+        fd: wasm64::__wasi_fd_t,
+        newoffset: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_fdstat_get(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        fd: wasm64::__wasi_fd_t,
+        fdstat_ptr: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_fdstat_set_flags(
+        wasi_ctx: &WasiCtx,
+        fd: wasm64::__wasi_fd_t,
+        fdflags: wasm64::__wasi_fdflags_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_fdstat_set_rights(
+        wasi_ctx: &mut WasiCtx,
+        fd: wasm64::__wasi_fd_t,
+        fs_rights_base: wasm64::__wasi_rights_t,
+        fs_rights_inheriting: wasm64::__wasi_rights_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_sync(wasi_ctx: &WasiCtx, fd: wasm64::__wasi_fd_t,) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_write(
+        wasi_ctx: &mut WasiCtx,
+        memory: &mut [u8],
+        fd: wasm64::__wasi_fd_t,
+        iovs_ptr: wasm64::uintptr_t,
+        iovs_len: wasm64::size_t,
+        nwritten: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_advise(
+        wasi_ctx: &WasiCtx,
+        fd: wasm64::__wasi_fd_t,
+        offset: wasm64::__wasi_filesize_t,
+        len: wasm64::__wasi_filesize_t,
+        advice: wasm64::__wasi_advice_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_allocate(
+        wasi_ctx: &WasiCtx,
+        fd: wasm64::__wasi_fd_t,
+        offset: wasm64::__wasi_filesize_t,
+        len: wasm64::__wasi_filesize_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(dirfd)]
+    pub unsafe fn path_create_directory(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        dirfd: wasm64::__wasi_fd_t,
+        path_ptr: wasm64::uintptr_t,
+        path_len: wasm64::size_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(old_dirfd)]
+    pub unsafe fn path_link(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        old_dirfd: wasm64::__wasi_fd_t,
+        old_flags: wasm64::__wasi_lookupflags_t,
+        old_path_ptr: wasm64::uintptr_t,
+        old_path_len: wasm64::size_t,
+        new_dirfd: wasm64::__wasi_fd_t,
+        new_path_ptr: wasm64::uintptr_t,
+        new_path_len: wasm64::size_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(dirfd)]
+    pub unsafe fn path_open(
+        wasi_ctx: &mut WasiCtx,
+        memory: &mut [u8],
+        dirfd: wasm64::__wasi_fd_t,
+        dirflags: wasm64::__wasi_lookupflags_t,
+        path_ptr: wasm64::uintptr_t,
+        path_len: wasm64::size_t,
+        oflags: wasm64::__wasi_oflags_t,
+        fs_rights_base: wasm64::__wasi_rights_t,
+        fs_rights_inheriting: wasm64::__wasi_rights_t,
+        fs_flags: wasm64::__wasi_fdflags_t,
+        fd_out_ptr: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_readdir(
+        wasi_ctx: &mut WasiCtx,
+        memory: &mut [u8],
+        fd: wasm64::__wasi_fd_t,
+        buf: wasm64::uintptr_t,
+        buf_len: wasm64::size_t,
+        cookie: wasm64::__wasi_dircookie_t,
+        buf_used: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(dirfd)]
+    pub unsafe fn path_readlink(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        dirfd: wasm64::__wasi_fd_t,
+        path_ptr: wasm64::uintptr_t,
+        path_len: wasm64::size_t,
+        buf_ptr: wasm64::uintptr_t,
+        buf_len: wasm64::size_t,
+        buf_used: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(old_dirfd)]
+    pub unsafe fn path_rename(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        old_dirfd: wasm64::__wasi_fd_t,
+        old_path_ptr: wasm64::uintptr_t,
+        old_path_len: wasm64::size_t,
+        new_dirfd: wasm64::__wasi_fd_t,
+        new_path_ptr: wasm64::uintptr_t,
+        new_path_len: wasm64::size_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_filestat_get(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        fd: wasm64::__wasi_fd_t,
+        filestat_ptr: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_filestat_set_times(
+        wasi_ctx: &WasiCtx,
+        fd: wasm64::__wasi_fd_t,
+        st_atim: wasm64::__wasi_timestamp_t,
+        st_mtim: wasm64::__wasi_timestamp_t,
+        fst_flags: wasm64::__wasi_fstflags_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_filestat_set_size(
+        wasi_ctx: &WasiCtx,
+        fd: wasm64::__wasi_fd_t,
+        st_size: wasm64::__wasi_filesize_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(dirfd)]
+    pub unsafe fn path_filestat_get(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        dirfd: wasm64::__wasi_fd_t,
+        dirflags: wasm64::__wasi_lookupflags_t,
+        path_ptr: wasm64::uintptr_t,
+        path_len: wasm64::size_t,
+        filestat_ptr: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(dirfd)]
+    pub unsafe fn path_filestat_set_times(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        dirfd: wasm64::__wasi_fd_t,
+        dirflags: wasm64::__wasi_lookupflags_t,
+        path_ptr: wasm64::uintptr_t,
+        path_len: wasm64::size_t,
+        st_atim: wasm64::__wasi_timestamp_t,
+        st_mtim: wasm64::__wasi_timestamp_t,
+        fst_flags: wasm64::__wasi_fstflags_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(dirfd)]
+    pub unsafe fn path_symlink(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        old_path_ptr: wasm64::uintptr_t,
+        old_path_len: wasm64::size_t,
+        dirfd: wasm64::__wasi_fd_t,
+        new_path_ptr: wasm64::uintptr_t,
+        new_path_len: wasm64::size_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(dirfd)]
+    pub unsafe fn path_unlink_file(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        dirfd: wasm64::__wasi_fd_t,
+        path_ptr: wasm64::uintptr_t,
+        path_len: wasm64::size_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(dirfd)]
+    pub unsafe fn path_remove_directory(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        dirfd: wasm64::__wasi_fd_t,
+        path_ptr: wasm64::uintptr_t,
+        path_len: wasm64::size_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_prestat_get(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        fd: wasm64::__wasi_fd_t,
+        prestat_ptr: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    #[fd(fd)]
+    pub unsafe fn fd_prestat_dir_name(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        fd: wasm64::__wasi_fd_t,
+        path_ptr: wasm64::uintptr_t,
+        path_len: wasm64::size_t,
+    ) -> wasm64::__wasi_errno_t;
+}
+
+hostcalls_wasm64! {
+    pub unsafe fn args_get(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        argv_ptr: wasm64::uintptr_t,
+        argv_buf: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    pub unsafe fn args_sizes_get(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        argc_ptr: wasm64::uintptr_t,
+        argv_buf_size_ptr: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    pub unsafe fn environ_get(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        environ_ptr: wasm64::uintptr_t,
+        environ_buf: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    pub unsafe fn environ_sizes_get(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        environ_count_ptr: wasm64::uintptr_t,
+        environ_size_ptr: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    pub unsafe fn random_get(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        buf_ptr: wasm64::uintptr_t,
+        buf_len: wasm64::size_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    pub unsafe fn clock_res_get(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        clock_id: wasm64::__wasi_clockid_t,
+        resolution_ptr: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    pub unsafe fn clock_time_get(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        clock_id: wasm64::__wasi_clockid_t,
+        precision: wasm64::__wasi_timestamp_t,
+        time_ptr: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    pub unsafe fn poll_oneoff(
+        wasi_ctx: &WasiCtx,
+        memory: &mut [u8],
+        input: wasm64::uintptr_t,
+        output: wasm64::uintptr_t,
+        nsubscriptions: wasm64::size_t,
+        nevents: wasm64::uintptr_t,
+    ) -> wasm64::__wasi_errno_t;
+
+    pub unsafe fn sched_yield() -> wasm64::__wasi_errno_t;
+}
+
+#[allow(unused)]
+pub unsafe fn sock_recv(
+    wasi_ctx: &WasiCtx,
+    memory: &mut [u8],
+    sock: wasm64::__wasi_fd_t,
+    ri_data: wasm64::uintptr_t,
+    ri_data_len: wasm64::size_t,
+    ri_flags: wasm64::__wasi_riflags_t,
+    ro_datalen: wasm64::uintptr_t,
+    ro_flags: wasm64::uintptr_t,
+) -> wasm64::__wasi_errno_t {
+    // sockets aren't supported yet
+    super::return_enc_errno(host::__WASI_ENOSYS)
+}
+
+#[allow(unused)]
+pub unsafe fn sock_send(
+    wasi_ctx: &WasiCtx,
+    memory: &mut [u8],
+    sock: wasm64::__wasi_fd_t,
+    si_data: wasm64::uintptr_t,
+    si_data_len: wasm64::size_t,
+    si_flags: wasm64::__wasi_siflags_t,
+    so_datalen: wasm64::uintptr_t,
+) -> wasm64::__wasi_errno_t {
+    // sockets aren't supported yet
+    super::return_enc_errno(host::__WASI_ENOSYS)
+}
+
+#[allow(unused)]
+pub unsafe fn sock_shutdown(
+    wasi_ctx: &WasiCtx,
+    memory: &mut [u8],
+    sock: wasm64::__wasi_fd_t,
+    how: wasm64::__wasi_sdflags_t,
+) -> wasm64::__wasi_errno_t {
+    // sockets aren't supported yet
+    super::return_enc_errno(host::__WASI_ENOSYS)
+}
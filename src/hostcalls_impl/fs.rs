@@ -1,17 +1,26 @@
 #![allow(non_camel_case_types)]
-use super::fs_helpers::path_get;
+#![allow(clippy::too_many_arguments)]
+use super::fs_helpers::{
+    fd_readdir_raw, fd_readdir_virtual, filestat_set_times_decode, path_get, readdir_sorted,
+    PathGet,
+};
+use crate::cancel;
+use crate::capabilities::Capability;
 use crate::ctx::WasiCtx;
 use crate::fdentry::{Descriptor, FdEntry};
+use crate::helpers::systemtime_to_timestamp;
+use crate::journal::{GuestPath, JournalEntry};
 use crate::memory::*;
+use crate::policy::PathOp;
 use crate::sys::fdentry_impl::determine_type_rights;
 use crate::sys::hostcalls_impl::fs_helpers::path_open_rights;
 use crate::sys::{host_impl, hostcalls_impl};
-use crate::{host, wasm32, Error, Result};
+use crate::{encoding, host, wasm32, Error, Result};
 use filetime::{set_file_handle_times, FileTime};
 use log::trace;
 use std::fs::File;
 use std::io::{self, Read, Seek, SeekFrom, Write};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::path::PathBuf;
 
 pub(crate) unsafe fn fd_close(wasi_ctx: &mut WasiCtx, fd: wasm32::__wasi_fd_t) -> Result<()> {
     trace!("fd_close(fd={:?})", fd);
@@ -25,6 +34,9 @@ pub(crate) unsafe fn fd_close(wasi_ctx: &mut WasiCtx, fd: wasm32::__wasi_fd_t) -
     }
 
     wasi_ctx.remove_fd_entry(fd)?;
+    if let Some(metrics) = &wasi_ctx.metrics {
+        metrics.closed(fd);
+    }
     Ok(())
 }
 
@@ -32,22 +44,25 @@ pub(crate) unsafe fn fd_datasync(wasi_ctx: &WasiCtx, fd: wasm32::__wasi_fd_t) ->
     trace!("fd_datasync(fd={:?})", fd);
 
     let fd = dec_fd(fd);
-    let fd = wasi_ctx
+    let fd = match wasi_ctx
         .get_fd_entry(fd)?
         .as_descriptor(host::__WASI_RIGHT_FD_DATASYNC, 0)?
-        .as_file()?;
+    {
+        Descriptor::VirtualFile(file) => return file.datasync(),
+        descriptor => descriptor.as_file()?,
+    };
 
     fd.sync_data().map_err(Into::into)
 }
 
-pub(crate) unsafe fn fd_pread(
+pub(crate) unsafe fn fd_pread<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     fd: wasm32::__wasi_fd_t,
-    iovs_ptr: wasm32::uintptr_t,
-    iovs_len: wasm32::size_t,
+    iovs_ptr: P,
+    iovs_len: P,
     offset: wasm32::__wasi_filesize_t,
-    nread: wasm32::uintptr_t,
+    nread: P,
 ) -> Result<()> {
     trace!(
         "fd_pread(fd={:?}, iovs_ptr={:#x?}, iovs_len={:?}, offset={}, nread={:#x?})",
@@ -59,10 +74,9 @@ pub(crate) unsafe fn fd_pread(
     );
 
     let fd = dec_fd(fd);
-    let fd = wasi_ctx
+    let descriptor = wasi_ctx
         .get_fd_entry(fd)?
-        .as_descriptor(host::__WASI_RIGHT_FD_READ, 0)?
-        .as_file()?;
+        .as_descriptor(host::__WASI_RIGHT_FD_READ, 0)?;
 
     let iovs = dec_iovec_slice(memory, iovs_ptr, iovs_len)?;
 
@@ -72,7 +86,10 @@ pub(crate) unsafe fn fd_pread(
     }
     let buf_size = iovs.iter().map(|v| v.buf_len).sum();
     let mut buf = vec![0; buf_size];
-    let host_nread = hostcalls_impl::fd_pread(fd, &mut buf, offset)?;
+    let host_nread = match descriptor {
+        Descriptor::VirtualFile(file) => file.pread(&mut buf, offset)?,
+        descriptor => hostcalls_impl::fd_pread(descriptor.as_file()?, &mut buf, offset)?,
+    };
     let mut buf_offset = 0;
     let mut left = host_nread;
     for iov in &iovs {
@@ -88,17 +105,22 @@ pub(crate) unsafe fn fd_pread(
 
     trace!("     | *nread={:?}", host_nread);
 
-    enc_usize_byref(memory, nread, host_nread)
+    enc_usize_byref(memory, nread, host_nread)?;
+
+    if let Some(metrics) = &wasi_ctx.metrics {
+        metrics.read(fd, host_nread);
+    }
+    Ok(())
 }
 
-pub(crate) unsafe fn fd_pwrite(
+pub(crate) unsafe fn fd_pwrite<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     fd: wasm32::__wasi_fd_t,
-    iovs_ptr: wasm32::uintptr_t,
-    iovs_len: wasm32::size_t,
+    iovs_ptr: P,
+    iovs_len: P,
     offset: wasm32::__wasi_filesize_t,
-    nwritten: wasm32::uintptr_t,
+    nwritten: P,
 ) -> Result<()> {
     trace!(
         "fd_pwrite(fd={:?}, iovs_ptr={:#x?}, iovs_len={:?}, offset={}, nwritten={:#x?})",
@@ -110,10 +132,9 @@ pub(crate) unsafe fn fd_pwrite(
     );
 
     let fd = dec_fd(fd);
-    let fd = wasi_ctx
-        .get_fd_entry(fd)?
-        .as_descriptor(host::__WASI_RIGHT_FD_READ, 0)?
-        .as_file()?;
+    let fe = wasi_ctx.get_fd_entry(fd)?;
+    let journal_path = wasi_ctx.journal.as_ref().and_then(|_| fe.journal_path());
+    let descriptor = fe.as_descriptor(host::__WASI_RIGHT_FD_READ, 0)?;
     let iovs = dec_iovec_slice(memory, iovs_ptr, iovs_len)?;
 
     let offset = dec_filesize(offset);
@@ -128,20 +149,32 @@ pub(crate) unsafe fn fd_pwrite(
             iov.buf_len,
         ));
     }
-    let host_nwritten = hostcalls_impl::fd_pwrite(fd, &buf, offset)?;
+    let host_nwritten = match descriptor {
+        Descriptor::VirtualFile(file) => file.pwrite(&buf, offset)?,
+        descriptor => hostcalls_impl::fd_pwrite(descriptor.as_file()?, &buf, offset)?,
+    };
 
     trace!("     | *nwritten={:?}", host_nwritten);
 
-    enc_usize_byref(memory, nwritten, host_nwritten)
+    if let (Some(journal), Some(path)) = (wasi_ctx.journal.as_ref(), journal_path) {
+        journal.written(path, offset, host_nwritten as u64);
+    }
+
+    enc_usize_byref(memory, nwritten, host_nwritten)?;
+
+    if let Some(metrics) = &wasi_ctx.metrics {
+        metrics.written(fd, host_nwritten);
+    }
+    Ok(())
 }
 
-pub(crate) unsafe fn fd_read(
+pub(crate) unsafe fn fd_read<P: WasmPtr>(
     wasi_ctx: &mut WasiCtx,
     memory: &mut [u8],
     fd: wasm32::__wasi_fd_t,
-    iovs_ptr: wasm32::uintptr_t,
-    iovs_len: wasm32::size_t,
-    nread: wasm32::uintptr_t,
+    iovs_ptr: P,
+    iovs_len: P,
+    nread: P,
 ) -> Result<()> {
     trace!(
         "fd_read(fd={:?}, iovs_ptr={:#x?}, iovs_len={:?}, nread={:#x?})",
@@ -158,20 +191,32 @@ pub(crate) unsafe fn fd_read(
         .collect();
     let fd = dec_fd(fd);
 
-    let maybe_host_nread = match wasi_ctx
-        .get_fd_entry_mut(fd)?
-        .as_descriptor_mut(host::__WASI_RIGHT_FD_READ, 0)?
-    {
-        Descriptor::OsFile(file) => file.read_vectored(&mut iovs),
-        Descriptor::Stdin => io::stdin().lock().read_vectored(&mut iovs),
+    let _cancel = cancel::enter(&wasi_ctx.canceller);
+    let canceller = cancel::current();
+    let fe = wasi_ctx.get_fd_entry_mut(fd)?;
+    let stream = fe.file_type != host::__WASI_FILETYPE_REGULAR_FILE
+        && fe.file_type != host::__WASI_FILETYPE_DIRECTORY;
+    let host_nread = match fe.as_descriptor_mut(host::__WASI_RIGHT_FD_READ, 0)? {
+        Descriptor::OsFile(file) => match &canceller {
+            Some(canceller) if stream => hostcalls_impl::read_stream(file, &mut iovs, canceller)?,
+            _ => file.read_vectored(&mut iovs)?,
+        },
+        Descriptor::Stdin => match &canceller {
+            Some(canceller) => hostcalls_impl::read_stdin(&mut iovs, canceller)?,
+            None => io::stdin().lock().read_vectored(&mut iovs)?,
+        },
+        Descriptor::VirtualFile(file) => file.read_vectored(&mut iovs)?,
         _ => return Err(Error::EBADF),
     };
 
-    let host_nread = maybe_host_nread?;
-
     trace!("     | *nread={:?}", host_nread);
 
-    enc_usize_byref(memory, nread, host_nread)
+    enc_usize_byref(memory, nread, host_nread)?;
+
+    if let Some(metrics) = &wasi_ctx.metrics {
+        metrics.read(fd, host_nread);
+    }
+    Ok(())
 }
 
 pub(crate) unsafe fn fd_renumber(
@@ -188,40 +233,29 @@ pub(crate) unsafe fn fd_renumber(
         return Err(Error::EBADF);
     }
 
-    let from_fe = wasi_ctx.get_fd_entry(from)?;
-    let to_fe = wasi_ctx.get_fd_entry(to)?;
-
-    // Don't allow renumbering over a pre-opened resource.
-    // TODO: Eventually, we do want to permit this, once libpreopen in
-    // userspace is capable of removing entries from its tables as well.
-    if from_fe.preopen_path.is_some() || to_fe.preopen_path.is_some() {
-        return Err(Error::ENOTSUP);
+    // Renumbering an fd onto itself is a no-op.
+    if from == to {
+        return Ok(());
     }
 
-    // check if stdio fds
-    // TODO should we renumber stdio fds?
-    if !from_fe.as_descriptor(0, 0)?.is_file() || !to_fe.as_descriptor(0, 0)?.is_file() {
-        return Err(Error::EBADF);
+    // Move the entry as a whole, so that stdio descriptors, rights and preopen metadata all
+    // carry over. Whatever was previously at `to` is dropped, which closes its host handle.
+    let fe = wasi_ctx.remove_fd_entry(from)?;
+    wasi_ctx.insert_fd_entry_at(to, fe);
+    if let Some(metrics) = &wasi_ctx.metrics {
+        metrics.renumbered(from, to);
     }
 
-    let fe_from_dup = from_fe
-        .as_descriptor(0, 0)?
-        .as_file()
-        .and_then(|file| FdEntry::duplicate(file))?;
-
-    wasi_ctx.insert_fd_entry_at(to, fe_from_dup);
-    wasi_ctx.remove_fd_entry(from)?;
-
     Ok(())
 }
 
-pub(crate) unsafe fn fd_seek(
+pub(crate) unsafe fn fd_seek<P: WasmPtr>(
     wasi_ctx: &mut WasiCtx,
     memory: &mut [u8],
     fd: wasm32::__wasi_fd_t,
     offset: wasm32::__wasi_filedelta_t,
     whence: wasm32::__wasi_whence_t,
-    newoffset: wasm32::uintptr_t,
+    newoffset: P,
 ) -> Result<()> {
     trace!(
         "fd_seek(fd={:?}, offset={:?}, whence={}, newoffset={:#x?})",
@@ -240,10 +274,12 @@ pub(crate) unsafe fn fd_seek(
     } else {
         host::__WASI_RIGHT_FD_SEEK | host::__WASI_RIGHT_FD_TELL
     };
-    let fd = wasi_ctx
-        .get_fd_entry_mut(fd)?
-        .as_descriptor_mut(rights, 0)?
-        .as_file_mut()?;
+    let fe = wasi_ctx.get_fd_entry_mut(fd)?;
+    if fe.is_stream() {
+        // streams lack the rights to seek, but can't be seeked in the first place
+        return Err(Error::ESPIPE);
+    }
+    let descriptor = fe.as_descriptor_mut(rights, 0)?;
 
     let pos = match whence {
         host::__WASI_WHENCE_CUR => SeekFrom::Current(offset),
@@ -251,47 +287,53 @@ pub(crate) unsafe fn fd_seek(
         host::__WASI_WHENCE_SET => SeekFrom::Start(offset as u64),
         _ => return Err(Error::EINVAL),
     };
-    let host_newoffset = fd.seek(pos)?;
+    let host_newoffset = match descriptor {
+        Descriptor::VirtualFile(file) => file.seek(pos)?,
+        descriptor => descriptor.as_file_mut()?.seek(pos)?,
+    };
 
     trace!("     | *newoffset={:?}", host_newoffset);
 
     enc_filesize_byref(memory, newoffset, host_newoffset)
 }
 
-pub(crate) unsafe fn fd_tell(
+pub(crate) unsafe fn fd_tell<P: WasmPtr>(
     wasi_ctx: &mut WasiCtx,
     memory: &mut [u8],
     fd: wasm32::__wasi_fd_t,
-    newoffset: wasm32::uintptr_t,
+    newoffset: P,
 ) -> Result<()> {
     trace!("fd_tell(fd={:?}, newoffset={:#x?})", fd, newoffset);
 
     let fd = dec_fd(fd);
-    let fd = wasi_ctx
-        .get_fd_entry_mut(fd)?
-        .as_descriptor_mut(host::__WASI_RIGHT_FD_TELL, 0)?
-        .as_file_mut()?;
-
-    let host_offset = fd.seek(SeekFrom::Current(0))?;
+    let fe = wasi_ctx.get_fd_entry_mut(fd)?;
+    if fe.is_stream() {
+        return Err(Error::ESPIPE);
+    }
+    let host_offset = match fe.as_descriptor_mut(host::__WASI_RIGHT_FD_TELL, 0)? {
+        Descriptor::VirtualFile(file) => file.seek(SeekFrom::Current(0))?,
+        descriptor => descriptor.as_file_mut()?.seek(SeekFrom::Current(0))?,
+    };
 
     trace!("     | *newoffset={:?}", host_offset);
 
     enc_filesize_byref(memory, newoffset, host_offset)
 }
 
-pub(crate) unsafe fn fd_fdstat_get(
+pub(crate) unsafe fn fd_fdstat_get<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     fd: wasm32::__wasi_fd_t,
-    fdstat_ptr: wasm32::uintptr_t, // *mut wasm32::__wasi_fdstat_t
+    fdstat_ptr: P, // *mut wasm32::__wasi_fdstat_t
 ) -> Result<()> {
     trace!("fd_fdstat_get(fd={:?}, fdstat_ptr={:#x?})", fd, fdstat_ptr);
 
     let mut fdstat = dec_fdstat_byref(memory, fdstat_ptr)?;
     let fd = dec_fd(fd);
-    let wasi_fd = wasi_ctx.get_fd_entry(fd)?.as_descriptor(0, 0)?.as_file()?;
-
-    let fs_flags = hostcalls_impl::fd_fdstat_get(wasi_fd)?;
+    let fs_flags = match wasi_ctx.get_fd_entry(fd)?.as_descriptor(0, 0)? {
+        Descriptor::VirtualFile(file) => file.fdstat_get(),
+        descriptor => hostcalls_impl::fd_fdstat_get(descriptor.as_file()?)?,
+    };
 
     let fe = wasi_ctx.get_fd_entry(fd)?;
     fdstat.fs_filetype = fe.file_type;
@@ -313,7 +355,10 @@ pub(crate) unsafe fn fd_fdstat_set_flags(
 
     let fdflags = dec_fdflags(fdflags);
     let fd = dec_fd(fd);
-    let fd = wasi_ctx.get_fd_entry(fd)?.as_descriptor(0, 0)?.as_file()?;
+    let fd = match wasi_ctx.get_fd_entry(fd)?.as_descriptor(0, 0)? {
+        Descriptor::VirtualFile(file) => return file.fdstat_set_flags(fdflags),
+        descriptor => descriptor.as_file()?,
+    };
 
     hostcalls_impl::fd_fdstat_set_flags(fd, fdflags)
 }
@@ -332,7 +377,7 @@ pub(crate) unsafe fn fd_fdstat_set_rights(
     );
 
     let fd = dec_fd(fd);
-    let fe = &mut wasi_ctx.get_fd_entry_mut(fd)?;
+    let fe = wasi_ctx.get_fd_entry_mut(fd)?;
 
     if fe.rights_base & fs_rights_base != fs_rights_base
         || fe.rights_inheriting & fs_rights_inheriting != fs_rights_inheriting
@@ -349,20 +394,23 @@ pub(crate) unsafe fn fd_sync(wasi_ctx: &WasiCtx, fd: wasm32::__wasi_fd_t) -> Res
     trace!("fd_sync(fd={:?})", fd);
 
     let fd = dec_fd(fd);
-    let fd = wasi_ctx
+    let fd = match wasi_ctx
         .get_fd_entry(fd)?
         .as_descriptor(host::__WASI_RIGHT_FD_SYNC, 0)?
-        .as_file()?;
+    {
+        Descriptor::VirtualFile(file) => return file.sync(),
+        descriptor => descriptor.as_file()?,
+    };
     fd.sync_all().map_err(Into::into)
 }
 
-pub(crate) unsafe fn fd_write(
+pub(crate) unsafe fn fd_write<P: WasmPtr>(
     wasi_ctx: &mut WasiCtx,
     memory: &mut [u8],
     fd: wasm32::__wasi_fd_t,
-    iovs_ptr: wasm32::uintptr_t,
-    iovs_len: wasm32::size_t,
-    nwritten: wasm32::uintptr_t,
+    iovs_ptr: P,
+    iovs_len: P,
+    nwritten: P,
 ) -> Result<()> {
     trace!(
         "fd_write(fd={:?}, iovs_ptr={:#x?}, iovs_len={:?}, nwritten={:#x?})",
@@ -376,11 +424,13 @@ pub(crate) unsafe fn fd_write(
     let iovs = dec_iovec_slice(memory, iovs_ptr, iovs_len)?;
     let iovs: Vec<io::IoSlice> = iovs.iter().map(|vec| host::iovec_to_host(vec)).collect();
 
+    let journaling = wasi_ctx.journal.is_some();
+    let fe = wasi_ctx.get_fd_entry_mut(fd)?;
+    let journal_path = if journaling { fe.journal_path() } else { None };
+    let descriptor = fe.as_descriptor_mut(host::__WASI_RIGHT_FD_WRITE, 0)?;
+
     // perform unbuffered writes
-    let host_nwritten = match wasi_ctx
-        .get_fd_entry_mut(fd)?
-        .as_descriptor_mut(host::__WASI_RIGHT_FD_WRITE, 0)?
-    {
+    let host_nwritten = match descriptor {
         Descriptor::OsFile(file) => file.write_vectored(&iovs)?,
         Descriptor::Stdin => return Err(Error::EBADF),
         Descriptor::Stdout => {
@@ -392,11 +442,34 @@ pub(crate) unsafe fn fd_write(
             nwritten
         }
         Descriptor::Stderr => io::stderr().lock().write_vectored(&iovs)?,
+        Descriptor::VirtualFile(file) => file.write_vectored(&iovs)?,
+    };
+    // the offset written at is only known after the fact in append mode, so it's taken from
+    // where the write left off
+    let journal_offset = match (&journal_path, descriptor) {
+        (None, _) => None,
+        (Some(_), Descriptor::VirtualFile(file)) => file.seek(SeekFrom::Current(0)).ok(),
+        (Some(_), descriptor) => descriptor
+            .as_file_mut()
+            .ok()
+            .and_then(|file| file.seek(SeekFrom::Current(0)).ok()),
     };
 
     trace!("     | *nwritten={:?}", host_nwritten);
 
-    enc_usize_byref(memory, nwritten, host_nwritten)
+    if let (Some(journal), Some(path), Some(end)) =
+        (wasi_ctx.journal.as_ref(), journal_path, journal_offset)
+    {
+        let len = host_nwritten as u64;
+        journal.written(path, end.saturating_sub(len), len);
+    }
+
+    enc_usize_byref(memory, nwritten, host_nwritten)?;
+
+    if let Some(metrics) = &wasi_ctx.metrics {
+        metrics.written(fd, host_nwritten);
+    }
+    Ok(())
 }
 
 pub(crate) unsafe fn fd_advise(
@@ -418,10 +491,13 @@ pub(crate) unsafe fn fd_advise(
     let advice = dec_advice(advice);
     let offset = dec_filesize(offset);
     let len = dec_filesize(len);
-    let fd = wasi_ctx
+    let fd = match wasi_ctx
         .get_fd_entry(fd)?
         .as_descriptor(host::__WASI_RIGHT_FD_ADVISE, 0)?
-        .as_file()?;
+    {
+        Descriptor::VirtualFile(file) => return file.advise(advice, offset, len),
+        descriptor => descriptor.as_file()?,
+    };
 
     hostcalls_impl::fd_advise(fd, advice, offset, len)
 }
@@ -434,13 +510,18 @@ pub(crate) unsafe fn fd_allocate(
 ) -> Result<()> {
     trace!("fd_allocate(fd={:?}, offset={}, len={})", fd, offset, len);
 
+    wasi_ctx.capabilities.check(Capability::FsMutation)?;
+
     let fd = dec_fd(fd);
     let offset = dec_filesize(offset);
     let len = dec_filesize(len);
-    let fd = wasi_ctx
+    let fd = match wasi_ctx
         .get_fd_entry(fd)?
         .as_descriptor(host::__WASI_RIGHT_FD_ALLOCATE, 0)?
-        .as_file()?;
+    {
+        Descriptor::VirtualFile(file) => return file.allocate(offset, len),
+        descriptor => descriptor.as_file()?,
+    };
 
     let metadata = fd.metadata()?;
 
@@ -458,12 +539,12 @@ pub(crate) unsafe fn fd_allocate(
     }
 }
 
-pub(crate) unsafe fn path_create_directory(
+pub(crate) unsafe fn path_create_directory<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     dirfd: wasm32::__wasi_fd_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
+    path_ptr: P,
+    path_len: P,
 ) -> Result<()> {
     trace!(
         "path_create_directory(dirfd={:?}, path_ptr={:#x?}, path_len={})",
@@ -472,28 +553,45 @@ pub(crate) unsafe fn path_create_directory(
         path_len,
     );
 
+    wasi_ctx.capabilities.check(Capability::FsMutation)?;
+
     let dirfd = dec_fd(dirfd);
-    let path = dec_slice_of::<u8>(memory, path_ptr, path_len).and_then(host::path_from_slice)?;
+    let path = dec_slice_of::<u8, _>(memory, path_ptr, path_len)
+        .and_then(|path| wasi_ctx.path_encoding.path_from_guest(path))?;
 
     trace!("     | (path_ptr,path_len)='{}'", path);
 
     let rights = host::__WASI_RIGHT_PATH_OPEN | host::__WASI_RIGHT_PATH_CREATE_DIRECTORY;
     let fe = &wasi_ctx.get_fd_entry(dirfd)?;
-    let resolved = path_get(fe, rights, 0, 0, path, false)?;
-
-    hostcalls_impl::path_create_directory(resolved)
+    let resolved = path_get(fe, rights, 0, 0, &path, false)?;
+    resolved.check_policy(PathOp::Create)?;
+    let journal_path = wasi_ctx
+        .journal
+        .as_ref()
+        .and_then(|_| resolved.guest_path());
+
+    let at = resolved.resolved().to_owned();
+    match resolved.virtual_dirfd() {
+        Some(dir) => dir.create_directory(resolved.path()),
+        None => hostcalls_impl::path_create_directory(resolved),
+    }
+    .map_err(|e| e.at_path(at))?;
+    if let (Some(journal), Some(path)) = (wasi_ctx.journal.as_ref(), journal_path) {
+        journal.record(JournalEntry::CreateDirectory(path));
+    }
+    Ok(())
 }
 
-pub(crate) unsafe fn path_link(
+pub(crate) unsafe fn path_link<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     old_dirfd: wasm32::__wasi_fd_t,
     old_flags: wasm32::__wasi_lookupflags_t,
-    old_path_ptr: wasm32::uintptr_t,
-    old_path_len: wasm32::size_t,
+    old_path_ptr: P,
+    old_path_len: P,
     new_dirfd: wasm32::__wasi_fd_t,
-    new_path_ptr: wasm32::uintptr_t,
-    new_path_len: wasm32::size_t,
+    new_path_ptr: P,
+    new_path_len: P,
 ) -> Result<()> {
     trace!(
         "path_link(old_dirfd={:?}, old_flags={:?}, old_path_ptr={:#x?}, old_path_len={}, new_dirfd={:?}, new_path_ptr={:#x?}, new_path_len={})",
@@ -506,12 +604,14 @@ pub(crate) unsafe fn path_link(
         new_path_len,
     );
 
+    wasi_ctx.capabilities.check(Capability::FsMutation)?;
+
     let old_dirfd = dec_fd(old_dirfd);
     let new_dirfd = dec_fd(new_dirfd);
-    let old_path =
-        dec_slice_of::<u8>(memory, old_path_ptr, old_path_len).and_then(host::path_from_slice)?;
-    let new_path =
-        dec_slice_of::<u8>(memory, new_path_ptr, new_path_len).and_then(host::path_from_slice)?;
+    let old_path = dec_slice_of::<u8, _>(memory, old_path_ptr, old_path_len)
+        .and_then(|path| wasi_ctx.path_encoding.path_from_guest(path))?;
+    let new_path = dec_slice_of::<u8, _>(memory, new_path_ptr, new_path_len)
+        .and_then(|path| wasi_ctx.path_encoding.path_from_guest(path))?;
 
     trace!("     | (old_path_ptr,old_path_len)='{}'", old_path);
     trace!("     | (new_path_ptr,new_path_len)='{}'", new_path);
@@ -523,7 +623,7 @@ pub(crate) unsafe fn path_link(
         host::__WASI_RIGHT_PATH_LINK_SOURCE,
         0,
         0,
-        old_path,
+        &old_path,
         false,
     )?;
     let resolved_new = path_get(
@@ -531,25 +631,43 @@ pub(crate) unsafe fn path_link(
         host::__WASI_RIGHT_PATH_LINK_TARGET,
         0,
         0,
-        new_path,
+        &new_path,
         false,
     )?;
-
-    hostcalls_impl::path_link(resolved_old, resolved_new)
+    resolved_old.check_policy(PathOp::Link)?;
+    resolved_new.check_policy(PathOp::Link)?;
+    let journal_paths = wasi_ctx
+        .journal
+        .as_ref()
+        .and_then(|_| Some((resolved_old.guest_path()?, resolved_new.guest_path()?)));
+
+    let at = resolved_old.resolved().to_owned();
+    match (resolved_old.virtual_dirfd(), resolved_new.virtual_dirfd()) {
+        (Some(old_dir), Some(new_dir)) => {
+            old_dir.link(resolved_old.path(), new_dir, resolved_new.path())
+        }
+        (None, None) => hostcalls_impl::path_link(resolved_old, resolved_new),
+        _ => Err(Error::EXDEV),
+    }
+    .map_err(|e| e.at_path(at))?;
+    if let (Some(journal), Some((from, to))) = (wasi_ctx.journal.as_ref(), journal_paths) {
+        journal.record(JournalEntry::Link { from, to });
+    }
+    Ok(())
 }
 
-pub(crate) unsafe fn path_open(
+pub(crate) unsafe fn path_open<P: WasmPtr>(
     wasi_ctx: &mut WasiCtx,
     memory: &mut [u8],
     dirfd: wasm32::__wasi_fd_t,
     dirflags: wasm32::__wasi_lookupflags_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
+    path_ptr: P,
+    path_len: P,
     oflags: wasm32::__wasi_oflags_t,
     fs_rights_base: wasm32::__wasi_rights_t,
     fs_rights_inheriting: wasm32::__wasi_rights_t,
     fs_flags: wasm32::__wasi_fdflags_t,
-    fd_out_ptr: wasm32::uintptr_t,
+    fd_out_ptr: P,
 ) -> Result<()> {
     trace!(
         "path_open(dirfd={:?}, dirflags={:?}, path_ptr={:#x?}, path_len={:?}, oflags={:#x?}, fs_rights_base={:#x?}, fs_rights_inheriting={:#x?}, fs_flags={:#x?}, fd_out_ptr={:#x?})",
@@ -574,53 +692,139 @@ pub(crate) unsafe fn path_open(
     let fs_rights_inheriting = dec_rights(fs_rights_inheriting);
     let fs_flags = dec_fdflags(fs_flags);
 
-    let path = dec_slice_of::<u8>(memory, path_ptr, path_len).and_then(host::path_from_slice)?;
+    let path = dec_slice_of::<u8, _>(memory, path_ptr, path_len)
+        .and_then(|path| wasi_ctx.path_encoding.path_from_guest(path))?;
 
     trace!("     | (path_ptr,path_len)='{}'", path);
 
+    // which open mode do we need?
+    let read = fs_rights_base & (host::__WASI_RIGHT_FD_READ | host::__WASI_RIGHT_FD_READDIR) != 0;
+    let write = fs_rights_base
+        & (host::__WASI_RIGHT_FD_DATASYNC
+            | host::__WASI_RIGHT_FD_WRITE
+            | host::__WASI_RIGHT_FD_ALLOCATE
+            | host::__WASI_RIGHT_FD_FILESTAT_SET_SIZE)
+        != 0;
+
+    if write || oflags & (host::__WASI_O_CREAT | host::__WASI_O_TRUNC) != 0 {
+        wasi_ctx.capabilities.check(Capability::FsMutation)?;
+    }
+
     let (needed_base, needed_inheriting) =
         path_open_rights(fs_rights_base, fs_rights_inheriting, oflags, fs_flags);
     let fe = &wasi_ctx.get_fd_entry(dirfd)?;
+    let virtual_dev = fe.virtual_dev;
+    let sorted_readdir = fe.sorted_readdir.is_some();
+    let opened = host_impl::path_to_host(&path)?;
+    let opened = match &fe.preopen_path {
+        Some(preopen_path) => preopen_path.join(opened),
+        None => PathBuf::from(opened.into_owned()),
+    };
     let resolved = path_get(
         fe,
         needed_base,
         needed_inheriting,
         dirflags,
-        path,
+        &path,
         oflags & host::__WASI_O_CREAT != 0,
     )?;
 
-    // which open mode do we need?
-    let read = fs_rights_base & (host::__WASI_RIGHT_FD_READ | host::__WASI_RIGHT_FD_READDIR) != 0;
-    let write = fs_rights_base
-        & (host::__WASI_RIGHT_FD_DATASYNC
-            | host::__WASI_RIGHT_FD_WRITE
-            | host::__WASI_RIGHT_FD_ALLOCATE
-            | host::__WASI_RIGHT_FD_FILESTAT_SET_SIZE)
-        != 0;
+    if read || !write {
+        resolved.check_policy(PathOp::OpenRead)?;
+    }
+    if write || oflags & host::__WASI_O_TRUNC != 0 {
+        resolved.check_policy(PathOp::OpenWrite)?;
+    }
+    if oflags & host::__WASI_O_CREAT != 0 {
+        resolved.check_policy(PathOp::Create)?;
+    }
+    let policy = resolved.policy_scope();
+    let creation_mode = resolved.creation_mode();
+    let origin = resolved.guest_path();
+    // whether the file is created can only be told by looking for it beforehand
+    let existed = if wasi_ctx.journal.is_some()
+        && origin.is_some()
+        && oflags & (host::__WASI_O_CREAT | host::__WASI_O_EXCL) == host::__WASI_O_CREAT
+    {
+        match resolved.virtual_dirfd() {
+            Some(dir) => dir.filestat_get_at(resolved.path()).is_ok(),
+            None => hostcalls_impl::path_exists(&resolved)
+                .map_err(|e| e.at_path(resolved.resolved()))?,
+        }
+    } else {
+        oflags & host::__WASI_O_EXCL == 0
+    };
+
+    let at = resolved.resolved().to_owned();
+    let mut fe = path_open_resolved(wasi_ctx, resolved, read, write, oflags, fs_flags)
+        .map_err(|e| e.at_path(at))?;
+    if fe.file_type == host::__WASI_FILETYPE_DIRECTORY {
+        fe.policy = policy;
+        fe.creation_mode = creation_mode;
+        if sorted_readdir {
+            fe.sorted_readdir = Some(Vec::new());
+        }
+    }
+    fe.virtual_dev = virtual_dev;
+    fe.origin = origin.clone();
+    let guest_fd = wasi_ctx.insert_fd_entry(fe)?;
+    if let Some(metrics) = &wasi_ctx.metrics {
+        metrics.opened(opened);
+    }
+    if let (Some(journal), Some(path)) = (wasi_ctx.journal.as_ref(), origin) {
+        if oflags & host::__WASI_O_CREAT != 0 && !existed {
+            journal.record(JournalEntry::Create(path));
+        } else if oflags & host::__WASI_O_TRUNC != 0 {
+            journal.record(JournalEntry::Truncate(path));
+        }
+    }
+
+    trace!("     | *fd={:?}", guest_fd);
+
+    enc_fd_byref(memory, fd_out_ptr, guest_fd)
+}
+
+/// Opens the path resolved by `path_open`.
+unsafe fn path_open_resolved(
+    wasi_ctx: &WasiCtx,
+    resolved: PathGet,
+    read: bool,
+    write: bool,
+    oflags: host::__wasi_oflags_t,
+    fs_flags: host::__wasi_fdflags_t,
+) -> Result<FdEntry> {
+    if let Some(dir) = resolved.virtual_dirfd() {
+        if let Some(fd) = dir.stdio_alias(resolved.path()) {
+            // like `/proc/self/fd/1`, which is gone if the guest closed the descriptor
+            let fe = wasi_ctx.get_fd_entry(fd).map_err(|_| Error::ENOENT)?;
+            return fe.duplicate(true);
+        }
+        let descriptor = dir.openat(resolved.path(), read, write, oflags, fs_flags)?;
+        wasi_ctx.devices.check(&descriptor)?;
+        return FdEntry::from_descriptor(descriptor);
+    }
 
+    wasi_ctx.devices.check_path(&resolved)?;
     let fd = hostcalls_impl::path_open(resolved, read, write, oflags, fs_flags)?;
+    // the node may have been replaced since it was checked
+    wasi_ctx.devices.check_file(&fd)?;
 
     // Determine the type of the new file descriptor and which rights contradict with this type
     let (_ty, max_base, max_inheriting) = determine_type_rights(&fd)?;
     let mut fe = FdEntry::from(fd)?;
     fe.rights_base &= max_base;
     fe.rights_inheriting &= max_inheriting;
-    let guest_fd = wasi_ctx.insert_fd_entry(fe)?;
-
-    trace!("     | *fd={:?}", guest_fd);
-
-    enc_fd_byref(memory, fd_out_ptr, guest_fd)
+    Ok(fe)
 }
 
-pub(crate) unsafe fn fd_readdir(
+pub(crate) unsafe fn fd_readdir<P: WasmPtr>(
     wasi_ctx: &mut WasiCtx,
     memory: &mut [u8],
     fd: wasm32::__wasi_fd_t,
-    buf: wasm32::uintptr_t,
-    buf_len: wasm32::size_t,
+    buf: P,
+    buf_len: P,
     cookie: wasm32::__wasi_dircookie_t,
-    buf_used: wasm32::uintptr_t,
+    buf_used: P,
 ) -> Result<()> {
     trace!(
         "fd_readdir(fd={:?}, buf={:#x?}, buf_len={}, cookie={:#x?}, buf_used={:#x?})",
@@ -634,32 +838,57 @@ pub(crate) unsafe fn fd_readdir(
     enc_usize_byref(memory, buf_used, 0)?;
 
     let fd = dec_fd(fd);
-    let file = wasi_ctx
-        .get_fd_entry_mut(fd)?
-        .as_descriptor_mut(host::__WASI_RIGHT_FD_READDIR, 0)?
-        .as_file_mut()?;
-    let host_buf = dec_slice_of_mut::<u8>(memory, buf, buf_len)?;
+    let encoding = wasi_ctx.path_encoding;
+    let virtualize = wasi_ctx.metadata.is_some();
+    let fe = wasi_ctx.get_fd_entry_mut(fd)?;
+    let dev = fe.virtual_dev;
+    let (descriptor, sorted) = fe.as_readdir_mut()?;
+    let host_buf = dec_slice_of_mut::<u8, _>(memory, buf, buf_len)?;
 
     trace!("     | (buf,buf_len)={:?}", host_buf);
 
     let cookie = dec_dircookie(cookie);
 
-    let host_bufused = hostcalls_impl::fd_readdir(file, host_buf, cookie)?;
+    let host_dev = match &*descriptor {
+        Descriptor::VirtualFile(_) => 0,
+        descriptor if virtualize => {
+            hostcalls_impl::fd_filestat_get_impl(descriptor.as_file()?)?.st_dev
+        }
+        _ => 0,
+    };
+    let host_bufused = match (sorted, descriptor) {
+        (Some(entries), descriptor) => {
+            // cookies refer to the listing taken when reading from the start
+            if cookie == host::__WASI_DIRCOOKIE_START || entries.is_empty() {
+                *entries = readdir_sorted(descriptor, encoding)?;
+            }
+            fd_readdir_raw(entries, host_buf, cookie)?
+        }
+        (None, Descriptor::VirtualFile(dir)) => {
+            fd_readdir_virtual(&dir.readdir()?, host_buf, cookie, encoding)?
+        }
+        (None, descriptor) => {
+            hostcalls_impl::fd_readdir(descriptor.as_file_mut()?, host_buf, cookie, encoding)?
+        }
+    };
+    if let Some(metadata) = &wasi_ctx.metadata {
+        metadata.readdir(dev, host_dev, &mut host_buf[..host_bufused]);
+    }
 
     trace!("     | *buf_used={:?}", host_bufused);
 
     enc_usize_byref(memory, buf_used, host_bufused)
 }
 
-pub(crate) unsafe fn path_readlink(
+pub(crate) unsafe fn path_readlink<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     dirfd: wasm32::__wasi_fd_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
-    buf_ptr: wasm32::uintptr_t,
-    buf_len: wasm32::size_t,
-    buf_used: wasm32::uintptr_t,
+    path_ptr: P,
+    path_len: P,
+    buf_ptr: P,
+    buf_len: P,
+    buf_used: P,
 ) -> Result<()> {
     trace!(
         "path_readlink(dirfd={:?}, path_ptr={:#x?}, path_len={:?}, buf_ptr={:#x?}, buf_len={}, buf_used={:#x?})",
@@ -674,16 +903,33 @@ pub(crate) unsafe fn path_readlink(
     enc_usize_byref(memory, buf_used, 0)?;
 
     let dirfd = dec_fd(dirfd);
-    let path = dec_slice_of::<u8>(memory, path_ptr, path_len).and_then(host::path_from_slice)?;
+    let path = dec_slice_of::<u8, _>(memory, path_ptr, path_len)
+        .and_then(|path| wasi_ctx.path_encoding.path_from_guest(path))?;
 
     trace!("     | (path_ptr,path_len)='{}'", &path);
 
     let fe = &wasi_ctx.get_fd_entry(dirfd)?;
     let resolved = path_get(fe, host::__WASI_RIGHT_PATH_READLINK, 0, 0, &path, false)?;
-
-    let mut buf = dec_slice_of_mut::<u8>(memory, buf_ptr, buf_len)?;
-
-    let host_bufused = hostcalls_impl::path_readlink(resolved, &mut buf)?;
+    resolved.check_policy(PathOp::Readlink)?;
+
+    let mut buf = dec_slice_of_mut::<u8, _>(memory, buf_ptr, buf_len)?;
+
+    let at = resolved.resolved().to_owned();
+    let host_bufused = match resolved.virtual_dirfd() {
+        Some(dir) => dir.readlinkat(resolved.path()).map(|link| {
+            let link = encoding::path_to_bytes(&link);
+            let len = std::cmp::min(link.len(), buf.len());
+            buf[..len].copy_from_slice(&link[..len]);
+            len
+        }),
+        None => hostcalls_impl::path_readlink(resolved, &mut buf),
+    }
+    .and_then(|len| {
+        let truncated = len == buf.len();
+        wasi_ctx.path_encoding.check_name(&buf[..len], truncated)?;
+        Ok(len)
+    })
+    .map_err(|e| e.at_path(at))?;
 
     trace!("     | (buf_ptr,*buf_used)={:?}", buf);
     trace!("     | *buf_used={:?}", host_bufused);
@@ -691,15 +937,15 @@ pub(crate) unsafe fn path_readlink(
     enc_usize_byref(memory, buf_used, host_bufused)
 }
 
-pub(crate) unsafe fn path_rename(
+pub(crate) unsafe fn path_rename<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     old_dirfd: wasm32::__wasi_fd_t,
-    old_path_ptr: wasm32::uintptr_t,
-    old_path_len: wasm32::size_t,
+    old_path_ptr: P,
+    old_path_len: P,
     new_dirfd: wasm32::__wasi_fd_t,
-    new_path_ptr: wasm32::uintptr_t,
-    new_path_len: wasm32::size_t,
+    new_path_ptr: P,
+    new_path_len: P,
 ) -> Result<()> {
     trace!(
         "path_rename(old_dirfd={:?}, old_path_ptr={:#x?}, old_path_len={:?}, new_dirfd={:?}, new_path_ptr={:#x?}, new_path_len={:?})",
@@ -711,12 +957,14 @@ pub(crate) unsafe fn path_rename(
         new_path_len,
     );
 
+    wasi_ctx.capabilities.check(Capability::FsMutation)?;
+
     let old_dirfd = dec_fd(old_dirfd);
     let new_dirfd = dec_fd(new_dirfd);
-    let old_path =
-        dec_slice_of::<u8>(memory, old_path_ptr, old_path_len).and_then(host::path_from_slice)?;
-    let new_path =
-        dec_slice_of::<u8>(memory, new_path_ptr, new_path_len).and_then(host::path_from_slice)?;
+    let old_path = dec_slice_of::<u8, _>(memory, old_path_ptr, old_path_len)
+        .and_then(|path| wasi_ctx.path_encoding.path_from_guest(path))?;
+    let new_path = dec_slice_of::<u8, _>(memory, new_path_ptr, new_path_len)
+        .and_then(|path| wasi_ctx.path_encoding.path_from_guest(path))?;
 
     trace!("     | (old_path_ptr,old_path_len)='{}'", old_path);
     trace!("     | (new_path_ptr,new_path_len)='{}'", new_path);
@@ -728,7 +976,7 @@ pub(crate) unsafe fn path_rename(
         host::__WASI_RIGHT_PATH_RENAME_SOURCE,
         0,
         0,
-        old_path,
+        &old_path,
         true,
     )?;
     let resolved_new = path_get(
@@ -736,21 +984,46 @@ pub(crate) unsafe fn path_rename(
         host::__WASI_RIGHT_PATH_RENAME_TARGET,
         0,
         0,
-        new_path,
+        &new_path,
         true,
     )?;
+    resolved_old.check_policy(PathOp::Rename)?;
+    resolved_new.check_policy(PathOp::Rename)?;
 
     log::debug!("path_rename resolved_old={:?}", resolved_old);
     log::debug!("path_rename resolved_new={:?}", resolved_new);
 
-    hostcalls_impl::path_rename(resolved_old, resolved_new)
+    let journal_paths = wasi_ctx
+        .journal
+        .as_ref()
+        .and_then(|_| Some((resolved_old.guest_path()?, resolved_new.guest_path()?)));
+
+    match (resolved_old.virtual_dirfd(), resolved_new.virtual_dirfd()) {
+        (Some(old_dir), Some(new_dir)) => {
+            old_dir.rename(resolved_old.path(), new_dir, resolved_new.path())
+        }
+        (None, None) => match hostcalls_impl::path_rename(&resolved_old, &resolved_new) {
+            Err(ref e)
+                if e.as_wasi_errno() == host::__WASI_EXDEV && wasi_ctx.cross_device_rename =>
+            {
+                hostcalls_impl::path_rename_copy(&resolved_old, &resolved_new)
+            }
+            res => res,
+        },
+        _ => Err(Error::EXDEV),
+    }
+    .map_err(|e| e.at_path(resolved_old.resolved()))?;
+    if let (Some(journal), Some((from, to))) = (wasi_ctx.journal.as_ref(), journal_paths) {
+        journal.record(JournalEntry::Rename { from, to });
+    }
+    Ok(())
 }
 
-pub(crate) unsafe fn fd_filestat_get(
+pub(crate) unsafe fn fd_filestat_get<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     fd: wasm32::__wasi_fd_t,
-    filestat_ptr: wasm32::uintptr_t,
+    filestat_ptr: P,
 ) -> Result<()> {
     trace!(
         "fd_filestat_get(fd={:?}, filestat_ptr={:#x?})",
@@ -759,9 +1032,18 @@ pub(crate) unsafe fn fd_filestat_get(
     );
 
     let fd = dec_fd(fd);
-    let fd = wasi_ctx.get_fd_entry(fd)?.as_descriptor(0, 0)?.as_file()?;
-
-    let host_filestat = hostcalls_impl::fd_filestat_get_impl(fd)?;
+    let fe = wasi_ctx.get_fd_entry(fd)?;
+    let (host_filestat, is_virtual) = match fe.as_descriptor(0, 0)? {
+        Descriptor::VirtualFile(file) => (file.filestat_get()?, true),
+        descriptor => (
+            hostcalls_impl::fd_filestat_get_impl(descriptor.as_file()?)?,
+            false,
+        ),
+    };
+    let host_filestat = match &wasi_ctx.metadata {
+        Some(metadata) => metadata.filestat(fe.virtual_dev, is_virtual, host_filestat),
+        None => host_filestat,
+    };
 
     trace!("     | *filestat_ptr={:?}", host_filestat);
 
@@ -783,17 +1065,45 @@ pub(crate) unsafe fn fd_filestat_set_times(
         fst_flags
     );
 
+    wasi_ctx.capabilities.check(Capability::FsMutation)?;
+
     let fd = dec_fd(fd);
-    let fd = wasi_ctx
-        .get_fd_entry(fd)?
-        .as_descriptor(host::__WASI_RIGHT_FD_FILESTAT_SET_TIMES, 0)?
-        .as_file()?;
+    let fe = wasi_ctx.get_fd_entry(fd)?;
+    let descriptor = fe.as_descriptor(host::__WASI_RIGHT_FD_FILESTAT_SET_TIMES, 0)?;
 
     let st_atim = dec_timestamp(st_atim);
     let st_mtim = dec_timestamp(st_mtim);
     let fst_flags = dec_fstflags(fst_flags);
 
-    fd_filestat_set_times_impl(fd, st_atim, st_mtim, fst_flags)
+    match descriptor {
+        Descriptor::VirtualFile(file) => {
+            let (atim, mtim) = filestat_set_times_decode(st_atim, st_mtim, fst_flags)?;
+            file.filestat_set_times(atim, mtim)?
+        }
+        descriptor => {
+            fd_filestat_set_times_impl(descriptor.as_file()?, st_atim, st_mtim, fst_flags)?
+        }
+    }
+    if let (Some(journal), Some(path)) = (wasi_ctx.journal.as_ref(), fe.origin.as_ref()) {
+        journal.record(set_times_entry(path.clone(), st_atim, st_mtim, fst_flags)?);
+    }
+    Ok(())
+}
+
+/// The journal entry for setting the timestamps of `path`, with those set to the current time
+/// resolved.
+fn set_times_entry(
+    path: GuestPath,
+    st_atim: host::__wasi_timestamp_t,
+    st_mtim: host::__wasi_timestamp_t,
+    fst_flags: host::__wasi_fstflags_t,
+) -> Result<JournalEntry> {
+    let (atim, mtim) = filestat_set_times_decode(st_atim, st_mtim, fst_flags)?;
+    Ok(JournalEntry::SetTimes {
+        path,
+        atim: atim.map(systemtime_to_timestamp).transpose()?,
+        mtim: mtim.map(systemtime_to_timestamp).transpose()?,
+    })
 }
 
 pub(crate) fn fd_filestat_set_times_impl(
@@ -802,34 +1112,13 @@ pub(crate) fn fd_filestat_set_times_impl(
     st_mtim: wasm32::__wasi_timestamp_t,
     fst_flags: wasm32::__wasi_fstflags_t,
 ) -> Result<()> {
-    let set_atim = fst_flags & host::__WASI_FILESTAT_SET_ATIM != 0;
-    let set_atim_now = fst_flags & host::__WASI_FILESTAT_SET_ATIM_NOW != 0;
-    let set_mtim = fst_flags & host::__WASI_FILESTAT_SET_MTIM != 0;
-    let set_mtim_now = fst_flags & host::__WASI_FILESTAT_SET_MTIM_NOW != 0;
-
-    if (set_atim && set_atim_now) || (set_mtim && set_mtim_now) {
-        return Err(Error::EINVAL);
-    }
-    let atim = if set_atim {
-        let time = UNIX_EPOCH + Duration::from_nanos(st_atim);
-        Some(FileTime::from_system_time(time))
-    } else if set_atim_now {
-        let time = SystemTime::now();
-        Some(FileTime::from_system_time(time))
-    } else {
-        None
-    };
-
-    let mtim = if set_mtim {
-        let time = UNIX_EPOCH + Duration::from_nanos(st_mtim);
-        Some(FileTime::from_system_time(time))
-    } else if set_mtim_now {
-        let time = SystemTime::now();
-        Some(FileTime::from_system_time(time))
-    } else {
-        None
-    };
-    set_file_handle_times(fd, atim, mtim).map_err(Into::into)
+    let (atim, mtim) = filestat_set_times_decode(st_atim, st_mtim, fst_flags)?;
+    set_file_handle_times(
+        fd,
+        atim.map(FileTime::from_system_time),
+        mtim.map(FileTime::from_system_time),
+    )
+    .map_err(Into::into)
 }
 
 pub(crate) unsafe fn fd_filestat_set_size(
@@ -839,28 +1128,40 @@ pub(crate) unsafe fn fd_filestat_set_size(
 ) -> Result<()> {
     trace!("fd_filestat_set_size(fd={:?}, st_size={})", fd, st_size);
 
+    wasi_ctx.capabilities.check(Capability::FsMutation)?;
+
     let fd = dec_fd(fd);
-    let fd = wasi_ctx
-        .get_fd_entry(fd)?
-        .as_descriptor(host::__WASI_RIGHT_FD_FILESTAT_SET_SIZE, 0)?
-        .as_file()?;
+    let fe = wasi_ctx.get_fd_entry(fd)?;
+    let descriptor = fe.as_descriptor(host::__WASI_RIGHT_FD_FILESTAT_SET_SIZE, 0)?;
 
     let st_size = dec_filesize(st_size);
     // This check will be unnecessary when rust-lang/rust#63326 is fixed
     if st_size > i64::max_value() as u64 {
         return Err(Error::E2BIG);
     }
-    fd.set_len(st_size).map_err(Into::into)
+    match descriptor {
+        Descriptor::VirtualFile(file) => file.filestat_set_size(st_size)?,
+        descriptor => descriptor.as_file()?.set_len(st_size)?,
+    }
+    if let Some(journal) = &wasi_ctx.journal {
+        if let Some(path) = fe.journal_path() {
+            journal.record(JournalEntry::SetSize {
+                path,
+                size: st_size,
+            });
+        }
+    }
+    Ok(())
 }
 
-pub(crate) unsafe fn path_filestat_get(
+pub(crate) unsafe fn path_filestat_get<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     dirfd: wasm32::__wasi_fd_t,
     dirflags: wasm32::__wasi_lookupflags_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
-    filestat_ptr: wasm32::uintptr_t,
+    path_ptr: P,
+    path_len: P,
+    filestat_ptr: P,
 ) -> Result<()> {
     trace!(
         "path_filestat_get(dirfd={:?}, dirflags={:?}, path_ptr={:#x?}, path_len={}, filestat_ptr={:#x?})",
@@ -873,7 +1174,8 @@ pub(crate) unsafe fn path_filestat_get(
 
     let dirfd = dec_fd(dirfd);
     let dirflags = dec_lookupflags(dirflags);
-    let path = dec_slice_of::<u8>(memory, path_ptr, path_len).and_then(host::path_from_slice)?;
+    let path = dec_slice_of::<u8, _>(memory, path_ptr, path_len)
+        .and_then(|path| wasi_ctx.path_encoding.path_from_guest(path))?;
 
     trace!("     | (path_ptr,path_len)='{}'", path);
 
@@ -883,23 +1185,33 @@ pub(crate) unsafe fn path_filestat_get(
         host::__WASI_RIGHT_PATH_FILESTAT_GET,
         0,
         dirflags,
-        path,
+        &path,
         false,
     )?;
-    let host_filestat = hostcalls_impl::path_filestat_get(resolved, dirflags)?;
+    let is_virtual = resolved.virtual_dirfd().is_some();
+    let at = resolved.resolved().to_owned();
+    let host_filestat = match resolved.virtual_dirfd() {
+        Some(dir) => dir.filestat_get_at(resolved.path()),
+        None => hostcalls_impl::path_filestat_get(resolved, dirflags),
+    }
+    .map_err(|e| e.at_path(at))?;
+    let host_filestat = match &wasi_ctx.metadata {
+        Some(metadata) => metadata.filestat(fe.virtual_dev, is_virtual, host_filestat),
+        None => host_filestat,
+    };
 
     trace!("     | *filestat_ptr={:?}", host_filestat);
 
     enc_filestat_byref(memory, filestat_ptr, host_filestat)
 }
 
-pub(crate) unsafe fn path_filestat_set_times(
+pub(crate) unsafe fn path_filestat_set_times<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     dirfd: wasm32::__wasi_fd_t,
     dirflags: wasm32::__wasi_lookupflags_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
+    path_ptr: P,
+    path_len: P,
     st_atim: wasm32::__wasi_timestamp_t,
     st_mtim: wasm32::__wasi_timestamp_t,
     fst_flags: wasm32::__wasi_fstflags_t,
@@ -914,9 +1226,12 @@ pub(crate) unsafe fn path_filestat_set_times(
         fst_flags
     );
 
+    wasi_ctx.capabilities.check(Capability::FsMutation)?;
+
     let dirfd = dec_fd(dirfd);
     let dirflags = dec_lookupflags(dirflags);
-    let path = dec_slice_of::<u8>(memory, path_ptr, path_len).and_then(host::path_from_slice)?;
+    let path = dec_slice_of::<u8, _>(memory, path_ptr, path_len)
+        .and_then(|path| wasi_ctx.path_encoding.path_from_guest(path))?;
 
     trace!("     | (path_ptr,path_len)='{}'", path);
 
@@ -930,21 +1245,38 @@ pub(crate) unsafe fn path_filestat_set_times(
         host::__WASI_RIGHT_PATH_FILESTAT_SET_TIMES,
         0,
         dirflags,
-        path,
+        &path,
         false,
     )?;
 
-    hostcalls_impl::path_filestat_set_times(resolved, dirflags, st_atim, st_mtim, fst_flags)
+    let journal_path = wasi_ctx
+        .journal
+        .as_ref()
+        .and_then(|_| resolved.guest_path());
+
+    let at = resolved.resolved().to_owned();
+    match resolved.virtual_dirfd() {
+        Some(dir) => filestat_set_times_decode(st_atim, st_mtim, fst_flags)
+            .and_then(|(atim, mtim)| dir.filestat_set_times_at(resolved.path(), atim, mtim)),
+        None => {
+            hostcalls_impl::path_filestat_set_times(resolved, dirflags, st_atim, st_mtim, fst_flags)
+        }
+    }
+    .map_err(|e| e.at_path(at))?;
+    if let (Some(journal), Some(path)) = (wasi_ctx.journal.as_ref(), journal_path) {
+        journal.record(set_times_entry(path, st_atim, st_mtim, fst_flags)?);
+    }
+    Ok(())
 }
 
-pub(crate) unsafe fn path_symlink(
+pub(crate) unsafe fn path_symlink<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
-    old_path_ptr: wasm32::uintptr_t,
-    old_path_len: wasm32::size_t,
+    old_path_ptr: P,
+    old_path_len: P,
     dirfd: wasm32::__wasi_fd_t,
-    new_path_ptr: wasm32::uintptr_t,
-    new_path_len: wasm32::size_t,
+    new_path_ptr: P,
+    new_path_len: P,
 ) -> Result<()> {
     trace!(
         "path_symlink(old_path_ptr={:#x?}, old_path_len={}, dirfd={:?}, new_path_ptr={:#x?}, new_path_len={})",
@@ -955,27 +1287,46 @@ pub(crate) unsafe fn path_symlink(
         new_path_len
     );
 
+    wasi_ctx.capabilities.check(Capability::FsMutation)?;
+
     let dirfd = dec_fd(dirfd);
-    let old_path =
-        dec_slice_of::<u8>(memory, old_path_ptr, old_path_len).and_then(host::path_from_slice)?;
-    let new_path =
-        dec_slice_of::<u8>(memory, new_path_ptr, new_path_len).and_then(host::path_from_slice)?;
+    let old_path = dec_slice_of::<u8, _>(memory, old_path_ptr, old_path_len)
+        .and_then(|path| wasi_ctx.path_encoding.path_from_guest(path))?;
+    let new_path = dec_slice_of::<u8, _>(memory, new_path_ptr, new_path_len)
+        .and_then(|path| wasi_ctx.path_encoding.path_from_guest(path))?;
 
     trace!("     | (old_path_ptr,old_path_len)='{}'", old_path);
     trace!("     | (new_path_ptr,new_path_len)='{}'", new_path);
 
     let fe = &wasi_ctx.get_fd_entry(dirfd)?;
-    let resolved_new = path_get(fe, host::__WASI_RIGHT_PATH_SYMLINK, 0, 0, new_path, true)?;
-
-    hostcalls_impl::path_symlink(old_path, resolved_new)
+    let resolved_new = path_get(fe, host::__WASI_RIGHT_PATH_SYMLINK, 0, 0, &new_path, true)?;
+    resolved_new.check_policy(PathOp::Symlink)?;
+    let journal_path = wasi_ctx
+        .journal
+        .as_ref()
+        .and_then(|_| resolved_new.guest_path());
+
+    let at = resolved_new.resolved().to_owned();
+    match resolved_new.virtual_dirfd() {
+        Some(dir) => dir.symlink(&old_path, resolved_new.path()),
+        None => hostcalls_impl::path_symlink(&old_path, resolved_new),
+    }
+    .map_err(|e| e.at_path(at))?;
+    if let (Some(journal), Some(path)) = (wasi_ctx.journal.as_ref(), journal_path) {
+        journal.record(JournalEntry::Symlink {
+            target: old_path.into_owned(),
+            path,
+        });
+    }
+    Ok(())
 }
 
-pub(crate) unsafe fn path_unlink_file(
+pub(crate) unsafe fn path_unlink_file<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     dirfd: wasm32::__wasi_fd_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
+    path_ptr: P,
+    path_len: P,
 ) -> Result<()> {
     trace!(
         "path_unlink_file(dirfd={:?}, path_ptr={:#x?}, path_len={})",
@@ -984,23 +1335,40 @@ pub(crate) unsafe fn path_unlink_file(
         path_len
     );
 
+    wasi_ctx.capabilities.check(Capability::FsMutation)?;
+
     let dirfd = dec_fd(dirfd);
-    let path = dec_slice_of::<u8>(memory, path_ptr, path_len).and_then(host::path_from_slice)?;
+    let path = dec_slice_of::<u8, _>(memory, path_ptr, path_len)
+        .and_then(|path| wasi_ctx.path_encoding.path_from_guest(path))?;
 
     trace!("     | (path_ptr,path_len)='{}'", path);
 
     let fe = &wasi_ctx.get_fd_entry(dirfd)?;
-    let resolved = path_get(fe, host::__WASI_RIGHT_PATH_UNLINK_FILE, 0, 0, path, false)?;
-
-    hostcalls_impl::path_unlink_file(resolved)
+    let resolved = path_get(fe, host::__WASI_RIGHT_PATH_UNLINK_FILE, 0, 0, &path, false)?;
+    resolved.check_policy(PathOp::Unlink)?;
+    let journal_path = wasi_ctx
+        .journal
+        .as_ref()
+        .and_then(|_| resolved.guest_path());
+
+    let at = resolved.resolved().to_owned();
+    match resolved.virtual_dirfd() {
+        Some(dir) => dir.unlink_file(resolved.path()),
+        None => hostcalls_impl::path_unlink_file(resolved),
+    }
+    .map_err(|e| e.at_path(at))?;
+    if let (Some(journal), Some(path)) = (wasi_ctx.journal.as_ref(), journal_path) {
+        journal.record(JournalEntry::Unlink(path));
+    }
+    Ok(())
 }
 
-pub(crate) unsafe fn path_remove_directory(
+pub(crate) unsafe fn path_remove_directory<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     dirfd: wasm32::__wasi_fd_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
+    path_ptr: P,
+    path_len: P,
 ) -> Result<()> {
     trace!(
         "path_remove_directory(dirfd={:?}, path_ptr={:#x?}, path_len={})",
@@ -1009,8 +1377,11 @@ pub(crate) unsafe fn path_remove_directory(
         path_len
     );
 
+    wasi_ctx.capabilities.check(Capability::FsMutation)?;
+
     let dirfd = dec_fd(dirfd);
-    let path = dec_slice_of::<u8>(memory, path_ptr, path_len).and_then(host::path_from_slice)?;
+    let path = dec_slice_of::<u8, _>(memory, path_ptr, path_len)
+        .and_then(|path| wasi_ctx.path_encoding.path_from_guest(path))?;
 
     trace!("     | (path_ptr,path_len)='{}'", path);
 
@@ -1020,20 +1391,34 @@ pub(crate) unsafe fn path_remove_directory(
         host::__WASI_RIGHT_PATH_REMOVE_DIRECTORY,
         0,
         0,
-        path,
+        &path,
         true,
     )?;
+    resolved.check_policy(PathOp::Unlink)?;
 
     log::debug!("path_remove_directory resolved={:?}", resolved);
 
-    hostcalls_impl::path_remove_directory(resolved)
+    let journal_path = wasi_ctx
+        .journal
+        .as_ref()
+        .and_then(|_| resolved.guest_path());
+    let at = resolved.resolved().to_owned();
+    match resolved.virtual_dirfd() {
+        Some(dir) => dir.remove_directory(resolved.path()),
+        None => hostcalls_impl::path_remove_directory(resolved),
+    }
+    .map_err(|e| e.at_path(at))?;
+    if let (Some(journal), Some(path)) = (wasi_ctx.journal.as_ref(), journal_path) {
+        journal.record(JournalEntry::RemoveDirectory(path));
+    }
+    Ok(())
 }
 
-pub(crate) unsafe fn fd_prestat_get(
+pub(crate) unsafe fn fd_prestat_get<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     fd: wasm32::__wasi_fd_t,
-    prestat_ptr: wasm32::uintptr_t,
+    prestat_ptr: P,
 ) -> Result<()> {
     trace!(
         "fd_prestat_get(fd={:?}, prestat_ptr={:#x?})",
@@ -1050,6 +1435,7 @@ pub(crate) unsafe fn fd_prestat_get(
     }
 
     let path = host_impl::path_from_host(po_path.as_os_str())?;
+    let path = encoding::path_to_bytes(&path);
 
     enc_prestat_byref(
         memory,
@@ -1065,12 +1451,12 @@ pub(crate) unsafe fn fd_prestat_get(
     )
 }
 
-pub(crate) unsafe fn fd_prestat_dir_name(
+pub(crate) unsafe fn fd_prestat_dir_name<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     fd: wasm32::__wasi_fd_t,
-    path_ptr: wasm32::uintptr_t,
-    path_len: wasm32::size_t,
+    path_ptr: P,
+    path_len: P,
 ) -> Result<()> {
     trace!(
         "fd_prestat_dir_name(fd={:?}, path_ptr={:#x?}, path_len={})",
@@ -1089,13 +1475,14 @@ pub(crate) unsafe fn fd_prestat_dir_name(
 
     let path = host_impl::path_from_host(po_path.as_os_str())?;
 
+    trace!("     | (path_ptr,path_len)='{}'", path);
+
+    let path = encoding::path_to_bytes(&path);
     if path.len() > dec_usize(path_len) {
         return Err(Error::ENAMETOOLONG);
     }
 
-    trace!("     | (path_ptr,path_len)='{}'", path);
-
-    enc_slice_of(memory, path.as_bytes(), path_ptr)
+    enc_slice_of(memory, &path, path_ptr)
 }
 
 #[allow(dead_code)] // trouble with sockets
@@ -1113,7 +1500,7 @@ pub(crate) enum FileType {
 }
 
 impl FileType {
-    pub(crate) fn to_wasi(&self) -> host::__wasi_filetype_t {
-        *self as host::__wasi_filetype_t
+    pub(crate) fn to_wasi(self) -> host::__wasi_filetype_t {
+        self as host::__wasi_filetype_t
     }
 }
@@ -1,24 +1,103 @@
 #![allow(non_camel_case_types)]
-use crate::sys::host_impl;
+use crate::creation::CreationMode;
+use crate::encoding::PathEncoding;
+use crate::fdentry::{Descriptor, FdEntry};
+use crate::journal::GuestPath;
+use crate::policy::{PathOp, PolicyScope};
+use crate::sys::fdentry_impl::OsFile;
+use crate::sys::hostcalls_impl;
 use crate::sys::hostcalls_impl::fs_helpers::*;
-use crate::{fdentry::FdEntry, host, Error, Result};
+use crate::virtfs::{Dirent, VirtualFile};
+use crate::{encoding, host, memory, Error, Result};
+use std::cmp;
+use std::convert::TryInto;
 use std::fs::File;
-use std::path::{Component, Path};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug)]
 pub(crate) struct PathGet {
-    dirfd: File,
+    dirfd: Descriptor,
     path: String,
+    /// The resolved path, relative to the directory `path_get` started from.
+    resolved: PathBuf,
+    scope: Option<PolicyScope>,
+    creation_mode: Option<CreationMode>,
+    origin: Option<GuestPath>,
 }
 
 impl PathGet {
+    /// `path` relative to the host directory `dirfd`, without any policy or creation mode, as
+    /// resolved by `virtfs::HostLayer` rather than by `path_get`.
+    pub(crate) fn host(dirfd: File, path: String) -> Self {
+        Self {
+            dirfd: Descriptor::OsFile(OsFile::from(dirfd)),
+            resolved: PathBuf::from(&path),
+            path,
+            scope: None,
+            creation_mode: None,
+            origin: None,
+        }
+    }
+
+    /// The host directory containing the path, which must only be called once
+    /// `PathGet::virtual_dirfd` has been checked.
     pub(crate) fn dirfd(&self) -> &File {
-        &self.dirfd
+        match &self.dirfd {
+            Descriptor::OsFile(file) => file,
+            _ => unreachable!("virtual directories are handled before calling into the host"),
+        }
+    }
+
+    /// The virtual directory containing the path, if it isn't on the host.
+    pub(crate) fn virtual_dirfd(&self) -> Option<&dyn VirtualFile> {
+        match &self.dirfd {
+            Descriptor::VirtualFile(dir) => Some(dir.as_ref()),
+            _ => None,
+        }
     }
 
     pub(crate) fn path(&self) -> &str {
         &self.path
     }
+
+    /// Checks the resolved path against the path policy of the directory, if it has one.
+    pub(crate) fn check_policy(&self, op: PathOp) -> Result<()> {
+        match &self.scope {
+            Some(scope) => scope
+                .check(op, &self.resolved)
+                .map_err(|e| e.at_path(&self.resolved)),
+            None => Ok(()),
+        }
+    }
+
+    /// The resolved path, relative to the directory `path_get` started from, which errors of the
+    /// operations on it are recorded at, see `Error::path`.
+    pub(crate) fn resolved(&self) -> &Path {
+        &self.resolved
+    }
+
+    /// How a file or directory created at the path is set up, if the preopen has a creation mode.
+    pub(crate) fn creation_mode(&self) -> Option<CreationMode> {
+        self.creation_mode
+    }
+
+    /// The resolved path relative to the preopen it was reached through, if it's known.
+    pub(crate) fn guest_path(&self) -> Option<GuestPath> {
+        self.origin.as_ref().map(|origin| {
+            let mut path = origin.path.clone();
+            path.extend(self.resolved.iter());
+            GuestPath {
+                preopen: origin.preopen.clone(),
+                path,
+            }
+        })
+    }
+
+    /// The policy scope of a directory opened at the resolved path.
+    pub(crate) fn policy_scope(&self) -> Option<PolicyScope> {
+        self.scope.as_ref().map(|scope| scope.join(&self.resolved))
+    }
 }
 
 /// Normalizes a path to ensure that the target path is located under the directory provided.
@@ -31,6 +110,25 @@ pub(crate) fn path_get(
     dirflags: host::__wasi_lookupflags_t,
     path: &str,
     needs_final_component: bool,
+) -> Result<PathGet> {
+    path_get_impl(
+        fe,
+        rights_base,
+        rights_inheriting,
+        dirflags,
+        path,
+        needs_final_component,
+    )
+    .map_err(|e| e.at_path(path))
+}
+
+fn path_get_impl(
+    fe: &FdEntry,
+    rights_base: host::__wasi_rights_t,
+    rights_inheriting: host::__wasi_rights_t,
+    dirflags: host::__wasi_lookupflags_t,
+    path: &str,
+    needs_final_component: bool,
 ) -> Result<PathGet> {
     const MAX_SYMLINK_EXPANSIONS: usize = 128;
 
@@ -44,10 +142,10 @@ pub(crate) fn path_get(
         return Err(Error::ENOTDIR);
     }
 
-    let dirfd = fe
-        .as_descriptor(rights_base, rights_inheriting)?
-        .as_file()?
-        .try_clone()?;
+    let dirfd = match fe.as_descriptor(rights_base, rights_inheriting)? {
+        Descriptor::VirtualFile(dir) => Descriptor::VirtualFile(dir.try_clone()?),
+        descriptor => Descriptor::OsFile(OsFile::from(descriptor.as_file()?.try_clone()?)),
+    };
 
     // Stack of directory file descriptors. Index 0 always corresponds with the directory provided
     // to this function. Entering a directory causes a file descriptor to be pushed, while handling
@@ -55,6 +153,10 @@ pub(crate) fn path_get(
     // escaping the base directory.
     let mut dir_stack = vec![dirfd];
 
+    // Names of the directories entered on top of the base directory, which make up the resolved
+    // path checked by path policies.
+    let mut name_stack: Vec<String> = Vec::new();
+
     // Stack of paths left to process. This is initially the `path` argument to this function, but
     // any symlinks we encounter are processed by pushing them on the stack.
     let mut path_stack = vec![path.to_owned()];
@@ -78,7 +180,7 @@ pub(crate) fn path_get(
                 let tail = components.as_path();
 
                 if tail.components().next().is_some() {
-                    let mut tail = host_impl::path_from_host(tail.as_os_str())?;
+                    let mut tail = tail.to_str().ok_or(Error::EILSEQ)?.to_owned();
                     if ends_with_slash {
                         tail.push('/');
                     }
@@ -98,6 +200,7 @@ pub(crate) fn path_get(
                     Component::ParentDir => {
                         // ".." so pop a dir
                         let _ = dir_stack.pop().ok_or(Error::ENOTCAPABLE)?;
+                        name_stack.pop();
 
                         // we're not allowed to pop past the original directory
                         if dir_stack.is_empty() {
@@ -105,16 +208,20 @@ pub(crate) fn path_get(
                         }
                     }
                     Component::Normal(head) => {
-                        let mut head = host_impl::path_from_host(head)?;
+                        let mut head = head.to_str().ok_or(Error::EILSEQ)?.to_owned();
                         if ends_with_slash {
                             // preserve trailing slash
                             head.push('/');
                         }
 
                         if !path_stack.is_empty() || (ends_with_slash && !needs_final_component) {
-                            match openat(dir_stack.last().ok_or(Error::ENOTCAPABLE)?, &head) {
+                            match descriptor_openat(
+                                dir_stack.last().ok_or(Error::ENOTCAPABLE)?,
+                                &head,
+                            ) {
                                 Ok(new_dir) => {
                                     dir_stack.push(new_dir);
+                                    name_stack.push(head.trim_end_matches('/').to_owned());
                                 }
                                 Err(e) => {
                                     match e.as_wasi_errno() {
@@ -125,7 +232,7 @@ pub(crate) fn path_get(
                                         // this with ENOTDIR because of the O_DIRECTORY flag.
                                         {
                                             // attempt symlink expansion
-                                            let mut link_path = readlinkat(
+                                            let mut link_path = descriptor_readlinkat(
                                                 dir_stack.last().ok_or(Error::ENOTCAPABLE)?,
                                                 &head,
                                             )?;
@@ -159,7 +266,10 @@ pub(crate) fn path_get(
                         {
                             // if there's a trailing slash, or if `LOOKUP_SYMLINK_FOLLOW` is set, attempt
                             // symlink expansion
-                            match readlinkat(dir_stack.last().ok_or(Error::ENOTCAPABLE)?, &head) {
+                            match descriptor_readlinkat(
+                                dir_stack.last().ok_or(Error::ENOTCAPABLE)?,
+                                &head,
+                            ) {
                                 Ok(mut link_path) => {
                                     symlink_expansions += 1;
                                     if symlink_expansions > MAX_SYMLINK_EXPANSIONS {
@@ -193,9 +303,14 @@ pub(crate) fn path_get(
                         }
 
                         // not a symlink, so we're done;
+                        name_stack.push(head.trim_end_matches('/').to_owned());
                         return Ok(PathGet {
                             dirfd: dir_stack.pop().ok_or(Error::ENOTCAPABLE)?,
                             path: head,
+                            resolved: name_stack.iter().collect(),
+                            scope: fe.policy.clone(),
+                            creation_mode: fe.creation_mode,
+                            origin: fe.origin.clone(),
                         });
                     }
                 }
@@ -206,8 +321,192 @@ pub(crate) fn path_get(
                 return Ok(PathGet {
                     dirfd: dir_stack.pop().ok_or(Error::ENOTCAPABLE)?,
                     path: String::from("."),
+                    resolved: name_stack.iter().collect(),
+                    scope: fe.policy.clone(),
+                    creation_mode: fe.creation_mode,
+                    origin: fe.origin.clone(),
                 });
             }
         }
     }
 }
+
+fn descriptor_openat(dirfd: &Descriptor, path: &str) -> Result<Descriptor> {
+    match dirfd {
+        Descriptor::VirtualFile(dir) => dir.openat(path, false, false, host::__WASI_O_DIRECTORY, 0),
+        dirfd => openat(dirfd.as_file()?, path).map(|dir| Descriptor::OsFile(OsFile::from(dir))),
+    }
+}
+
+fn descriptor_readlinkat(dirfd: &Descriptor, path: &str) -> Result<String> {
+    match dirfd {
+        Descriptor::VirtualFile(dir) => dir.readlinkat(path),
+        dirfd => readlinkat(dirfd.as_file()?, path),
+    }
+}
+
+/// Decodes the timestamps to be set by `fd_filestat_set_times` and `path_filestat_set_times`,
+/// where `None` leaves the corresponding timestamp unchanged.
+pub(crate) fn filestat_set_times_decode(
+    st_atim: host::__wasi_timestamp_t,
+    st_mtim: host::__wasi_timestamp_t,
+    fst_flags: host::__wasi_fstflags_t,
+) -> Result<(Option<SystemTime>, Option<SystemTime>)> {
+    let set_atim = fst_flags & host::__WASI_FILESTAT_SET_ATIM != 0;
+    let set_atim_now = fst_flags & host::__WASI_FILESTAT_SET_ATIM_NOW != 0;
+    let set_mtim = fst_flags & host::__WASI_FILESTAT_SET_MTIM != 0;
+    let set_mtim_now = fst_flags & host::__WASI_FILESTAT_SET_MTIM_NOW != 0;
+
+    if (set_atim && set_atim_now) || (set_mtim && set_mtim_now) {
+        return Err(Error::EINVAL);
+    }
+    let atim = if set_atim {
+        Some(UNIX_EPOCH + Duration::from_nanos(st_atim))
+    } else if set_atim_now {
+        Some(SystemTime::now())
+    } else {
+        None
+    };
+
+    let mtim = if set_mtim {
+        Some(UNIX_EPOCH + Duration::from_nanos(st_mtim))
+    } else if set_mtim_now {
+        Some(SystemTime::now())
+    } else {
+        None
+    };
+    Ok((atim, mtim))
+}
+
+/// An entry of a directory, with its name as it's passed to the guest.
+#[derive(Clone, Debug)]
+pub(crate) struct RawDirent {
+    name: Vec<u8>,
+    ino: host::__wasi_inode_t,
+    filetype: host::__wasi_filetype_t,
+}
+
+impl RawDirent {
+    fn sort_key(&self) -> (bool, bool, &[u8]) {
+        let name = self.name.as_slice();
+        (name != b".", name != b"..", name)
+    }
+}
+
+impl From<&Dirent> for RawDirent {
+    fn from(entry: &Dirent) -> Self {
+        Self {
+            name: encoding::path_to_bytes(&entry.name).into_owned(),
+            ino: entry.ino,
+            filetype: entry.filetype,
+        }
+    }
+}
+
+/// Converts the entries of a virtual directory, skipping those whose names can't be passed to the
+/// guest in `encoding`.
+fn raw_dirents(entries: &[Dirent], encoding: PathEncoding) -> Vec<RawDirent> {
+    entries
+        .iter()
+        .map(RawDirent::from)
+        .filter(|entry| encoding.check_name(&entry.name, false).is_ok())
+        .collect()
+}
+
+/// Serializes the entries of a virtual directory into `host_buf`, which is the equivalent of the
+/// `sys` implementation of `fd_readdir`. Cookies are indices into `entries`.
+pub(crate) fn fd_readdir_virtual(
+    entries: &[Dirent],
+    host_buf: &mut [u8],
+    cookie: host::__wasi_dircookie_t,
+    encoding: PathEncoding,
+) -> Result<usize> {
+    let entries = raw_dirents(entries, encoding);
+    fd_readdir_raw(&entries, host_buf, cookie)
+}
+
+/// Serializes `entries` into `host_buf` like `fd_readdir_virtual`.
+pub(crate) fn fd_readdir_raw(
+    entries: &[RawDirent],
+    host_buf: &mut [u8],
+    cookie: host::__wasi_dircookie_t,
+) -> Result<usize> {
+    let skip = cookie.try_into().unwrap_or(usize::max_value());
+    let mut host_buf_offset = 0;
+    for (index, entry) in entries.iter().enumerate().skip(skip) {
+        let dirent = host::__wasi_dirent_t {
+            d_next: memory::enc_dircookie((index + 1).try_into()?),
+            d_ino: memory::enc_inode(entry.ino),
+            d_namlen: memory::enc_u32(entry.name.len().try_into()?),
+            d_type: memory::enc_filetype(entry.filetype),
+        };
+
+        log::debug!("fd_readdir entry = {:?}", dirent);
+
+        let dirent_size = std::mem::size_of_val(&dirent);
+        let dirent_bytes =
+            unsafe { std::slice::from_raw_parts(&dirent as *const _ as *const u8, dirent_size) };
+        // an entry which doesn't fit is truncated, filling the buffer to tell the guest to retry
+        // with a larger one
+        for bytes in &[dirent_bytes, entry.name.as_slice()] {
+            let len = cmp::min(bytes.len(), host_buf.len() - host_buf_offset);
+            host_buf[host_buf_offset..host_buf_offset + len].copy_from_slice(&bytes[..len]);
+            host_buf_offset += len;
+        }
+        if host_buf_offset == host_buf.len() {
+            break;
+        }
+    }
+
+    Ok(host_buf_offset)
+}
+
+/// Lists all entries of the directory `descriptor`, sorted by their names, except for `.` and
+/// `..`, which come first.
+pub(crate) fn readdir_sorted(
+    descriptor: &mut Descriptor,
+    encoding: PathEncoding,
+) -> Result<Vec<RawDirent>> {
+    let mut entries = match descriptor {
+        Descriptor::VirtualFile(dir) => raw_dirents(&dir.readdir()?, encoding),
+        descriptor => readdir_host(descriptor.as_file_mut()?, encoding)?,
+    };
+    entries.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+    Ok(entries)
+}
+
+/// Lists all entries of the host directory `file`, in the order of the `sys` implementation of
+/// `fd_readdir`.
+fn readdir_host(file: &mut OsFile, encoding: PathEncoding) -> Result<Vec<RawDirent>> {
+    let dirent_size = std::mem::size_of::<host::__wasi_dirent_t>();
+    let mut buf = vec![0; 64 * 1024];
+    let mut cookie = host::__WASI_DIRCOOKIE_START;
+    let mut entries = Vec::new();
+    loop {
+        let used = hostcalls_impl::fd_readdir(file, &mut buf, cookie, encoding)?;
+        let mut offset = 0;
+        while offset + dirent_size <= used {
+            // entries aren't aligned in the buffer, which `read_unaligned` allows for
+            #[allow(clippy::cast_ptr_alignment)]
+            let dirent = unsafe {
+                std::ptr::read_unaligned(buf[offset..].as_ptr() as *const host::__wasi_dirent_t)
+            };
+            let name_start = offset + dirent_size;
+            let name_end = name_start + memory::dec_u32(dirent.d_namlen) as usize;
+            if name_end > used {
+                break;
+            }
+            entries.push(RawDirent {
+                name: buf[name_start..name_end].to_vec(),
+                ino: memory::dec_inode(dirent.d_ino),
+                filetype: memory::dec_filetype(dirent.d_type),
+            });
+            cookie = memory::dec_dircookie(dirent.d_next);
+            offset = name_end;
+        }
+        // the buffer is large enough for any entry, so the end is reached once nothing fits
+        if offset == 0 {
+            return Ok(entries);
+        }
+    }
+}
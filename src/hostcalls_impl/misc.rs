@@ -1,17 +1,23 @@
 #![allow(non_camel_case_types)]
+use crate::cancel;
+use crate::capabilities::Capability;
 use crate::ctx::WasiCtx;
 use crate::fdentry::Descriptor;
 use crate::memory::*;
 use crate::sys::hostcalls_impl;
+use crate::virtfs::VirtualFile;
 use crate::{host, wasm32, Error, Result};
 use log::trace;
-use std::convert::TryFrom;
+use std::convert::TryInto;
+use std::fs::File;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
-pub(crate) fn args_get(
+pub(crate) fn args_get<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
-    argv_ptr: wasm32::uintptr_t,
-    argv_buf: wasm32::uintptr_t,
+    argv_ptr: P,
+    argv_buf: P,
 ) -> Result<()> {
     trace!(
         "args_get(argv_ptr={:#x?}, argv_buf={:#x?})",
@@ -19,29 +25,34 @@ pub(crate) fn args_get(
         argv_buf,
     );
 
-    let mut argv_buf_offset = 0;
+    wasi_ctx.capabilities.check(Capability::ArgsEnv)?;
+
+    let mut argv_buf_offset = 0usize;
     let mut argv = vec![];
 
     for arg in wasi_ctx.args.iter() {
         let arg_bytes = arg.as_bytes_with_nul();
-        let arg_ptr = argv_buf + argv_buf_offset;
+        let arg_ptr = argv_buf
+            .add_offset(argv_buf_offset)
+            .ok_or(Error::EOVERFLOW)?;
 
         enc_slice_of(memory, arg_bytes, arg_ptr)?;
 
         argv.push(arg_ptr);
 
-        let len = wasm32::uintptr_t::try_from(arg_bytes.len())?;
-        argv_buf_offset = argv_buf_offset.checked_add(len).ok_or(Error::EOVERFLOW)?;
+        argv_buf_offset = argv_buf_offset
+            .checked_add(arg_bytes.len())
+            .ok_or(Error::EOVERFLOW)?;
     }
 
     enc_slice_of(memory, argv.as_slice(), argv_ptr)
 }
 
-pub(crate) fn args_sizes_get(
+pub(crate) fn args_sizes_get<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
-    argc_ptr: wasm32::uintptr_t,
-    argv_buf_size_ptr: wasm32::uintptr_t,
+    argc_ptr: P,
+    argv_buf_size_ptr: P,
 ) -> Result<()> {
     trace!(
         "args_sizes_get(argc_ptr={:#x?}, argv_buf_size_ptr={:#x?})",
@@ -49,6 +60,8 @@ pub(crate) fn args_sizes_get(
         argv_buf_size_ptr,
     );
 
+    wasi_ctx.capabilities.check(Capability::ArgsEnv)?;
+
     let argc = wasi_ctx.args.len();
     let argv_size = wasi_ctx
         .args
@@ -65,11 +78,11 @@ pub(crate) fn args_sizes_get(
     enc_usize_byref(memory, argv_buf_size_ptr, argv_size)
 }
 
-pub(crate) fn environ_get(
+pub(crate) fn environ_get<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
-    environ_ptr: wasm32::uintptr_t,
-    environ_buf: wasm32::uintptr_t,
+    environ_ptr: P,
+    environ_buf: P,
 ) -> Result<()> {
     trace!(
         "environ_get(environ_ptr={:#x?}, environ_buf={:#x?})",
@@ -77,31 +90,34 @@ pub(crate) fn environ_get(
         environ_buf,
     );
 
-    let mut environ_buf_offset = 0;
+    wasi_ctx.capabilities.check(Capability::ArgsEnv)?;
+
+    let mut environ_buf_offset = 0usize;
     let mut environ = vec![];
 
     for pair in wasi_ctx.env.iter() {
         let env_bytes = pair.as_bytes_with_nul();
-        let env_ptr = environ_buf + environ_buf_offset;
+        let env_ptr = environ_buf
+            .add_offset(environ_buf_offset)
+            .ok_or(Error::EOVERFLOW)?;
 
         enc_slice_of(memory, env_bytes, env_ptr)?;
 
         environ.push(env_ptr);
 
-        let len = wasm32::uintptr_t::try_from(env_bytes.len())?;
         environ_buf_offset = environ_buf_offset
-            .checked_add(len)
+            .checked_add(env_bytes.len())
             .ok_or(Error::EOVERFLOW)?;
     }
 
     enc_slice_of(memory, environ.as_slice(), environ_ptr)
 }
 
-pub(crate) fn environ_sizes_get(
+pub(crate) fn environ_sizes_get<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
-    environ_count_ptr: wasm32::uintptr_t,
-    environ_size_ptr: wasm32::uintptr_t,
+    environ_count_ptr: P,
+    environ_size_ptr: P,
 ) -> Result<()> {
     trace!(
         "environ_sizes_get(environ_count_ptr={:#x?}, environ_size_ptr={:#x?})",
@@ -109,6 +125,8 @@ pub(crate) fn environ_sizes_get(
         environ_size_ptr,
     );
 
+    wasi_ctx.capabilities.check(Capability::ArgsEnv)?;
+
     let environ_count = wasi_ctx.env.len();
     let environ_size = wasi_ctx
         .env
@@ -127,26 +145,30 @@ pub(crate) fn environ_sizes_get(
     enc_usize_byref(memory, environ_size_ptr, environ_size as usize)
 }
 
-pub(crate) fn random_get(
+pub(crate) fn random_get<P: WasmPtr>(
+    wasi_ctx: &WasiCtx,
     memory: &mut [u8],
-    buf_ptr: wasm32::uintptr_t,
-    buf_len: wasm32::size_t,
+    buf_ptr: P,
+    buf_len: P,
 ) -> Result<()> {
     use rand::{thread_rng, RngCore};
 
     trace!("random_get(buf_ptr={:#x?}, buf_len={:?})", buf_ptr, buf_len);
 
-    let buf = dec_slice_of_mut::<u8>(memory, buf_ptr, buf_len)?;
+    wasi_ctx.capabilities.check(Capability::Random)?;
+
+    let buf = dec_slice_of_mut::<u8, _>(memory, buf_ptr, buf_len)?;
 
     thread_rng().fill_bytes(buf);
 
     Ok(())
 }
 
-pub(crate) fn clock_res_get(
+pub(crate) fn clock_res_get<P: WasmPtr>(
+    wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     clock_id: wasm32::__wasi_clockid_t,
-    resolution_ptr: wasm32::uintptr_t,
+    resolution_ptr: P,
 ) -> Result<()> {
     trace!(
         "clock_res_get(clock_id={:?}, resolution_ptr={:#x?})",
@@ -154,6 +176,8 @@ pub(crate) fn clock_res_get(
         resolution_ptr,
     );
 
+    wasi_ctx.capabilities.check(Capability::Clocks)?;
+
     let clock_id = dec_clockid(clock_id);
     let resolution = hostcalls_impl::clock_res_get(clock_id)?;
 
@@ -162,11 +186,12 @@ pub(crate) fn clock_res_get(
     enc_timestamp_byref(memory, resolution_ptr, resolution)
 }
 
-pub(crate) fn clock_time_get(
+pub(crate) fn clock_time_get<P: WasmPtr>(
+    wasi_ctx: &WasiCtx,
     memory: &mut [u8],
     clock_id: wasm32::__wasi_clockid_t,
     precision: wasm32::__wasi_timestamp_t,
-    time_ptr: wasm32::uintptr_t,
+    time_ptr: P,
 ) -> Result<()> {
     trace!(
         "clock_time_get(clock_id={:?}, precision={:?}, time_ptr={:#x?})",
@@ -175,6 +200,8 @@ pub(crate) fn clock_time_get(
         time_ptr,
     );
 
+    wasi_ctx.capabilities.check(Capability::Clocks)?;
+
     let clock_id = dec_clockid(clock_id);
     let time = hostcalls_impl::clock_time_get(clock_id)?;
 
@@ -191,13 +218,13 @@ pub(crate) fn sched_yield() -> Result<()> {
     Ok(())
 }
 
-pub(crate) fn poll_oneoff(
+pub(crate) fn poll_oneoff<P: WasmPtr>(
     wasi_ctx: &WasiCtx,
     memory: &mut [u8],
-    input: wasm32::uintptr_t,
-    output: wasm32::uintptr_t,
-    nsubscriptions: wasm32::size_t,
-    nevents: wasm32::uintptr_t,
+    input: P,
+    output: P,
+    nsubscriptions: P,
+    nevents: P,
 ) -> Result<()> {
     trace!(
         "poll_oneoff(input={:#x?}, output={:#x?}, nsubscriptions={}, nevents={:#x?})",
@@ -207,23 +234,26 @@ pub(crate) fn poll_oneoff(
         nevents,
     );
 
-    if u64::from(nsubscriptions) > wasm32::__wasi_filesize_t::max_value() {
-        return Err(Error::EINVAL);
-    }
+    wasi_ctx.capabilities.check(Capability::Poll)?;
+    let _cancel = cancel::enter(&wasi_ctx.canceller);
 
-    enc_pointee(memory, nevents, 0)?;
+    enc_usize_byref(memory, nevents, 0)?;
 
-    let input_slice = dec_slice_of::<wasm32::__wasi_subscription_t>(memory, input, nsubscriptions)?;
+    let input_slice =
+        dec_slice_of::<wasm32::__wasi_subscription_t, _>(memory, input, nsubscriptions)?;
     let subscriptions = input_slice
-        .into_iter()
+        .iter()
         .map(dec_subscription)
         .collect::<Result<Vec<_>>>()?;
-    let output_slice = dec_slice_of_mut::<wasm32::__wasi_event_t>(memory, output, nsubscriptions)?;
+    let output_slice =
+        dec_slice_of_mut::<wasm32::__wasi_event_t, _>(memory, output, nsubscriptions)?;
     let mut output_slice_iter = output_slice.iter_mut();
     let mut events_count = 0;
 
     let mut timeout: Option<ClockEventData> = None;
     let mut fd_events = Vec::new();
+    let mut virtual_events = Vec::new();
+    let mut virtual_ready = false;
     for subscription in subscriptions {
         match subscription.type_ {
             host::__WASI_EVENTTYPE_CLOCK => {
@@ -258,6 +288,21 @@ pub(crate) fn poll_oneoff(
                         .get_fd_entry(wasi_fd)
                         .and_then(|fe| fe.as_descriptor(rights, 0))
                 } {
+                    Ok(Descriptor::VirtualFile(file)) if file.would_block() => {
+                        virtual_events.push(VirtualEventData {
+                            file: &**file,
+                            type_,
+                            userdata: subscription.userdata,
+                        })
+                    }
+                    Ok(Descriptor::VirtualFile(file)) => {
+                        *output_slice_iter
+                            .next()
+                            .expect("number of subscriptions has to match number of events") =
+                            enc_event(virtual_event(&**file, type_, subscription.userdata));
+                        events_count += 1;
+                        virtual_ready = true;
+                    }
                     Ok(descriptor) => fd_events.push(FdEventData {
                         descriptor,
                         type_: subscription.type_,
@@ -290,7 +335,14 @@ pub(crate) fn poll_oneoff(
     log::debug!("poll_oneoff timeout = {:?}", timeout);
     log::debug!("poll_oneoff fd_events = {:?}", fd_events);
 
-    let events = hostcalls_impl::poll_oneoff(timeout, fd_events)?;
+    let events = if virtual_ready {
+        // don't block if any of the virtual files are ready already
+        poll_oneoff_ready(timeout, fd_events)?
+    } else if virtual_events.is_empty() {
+        hostcalls_impl::poll_oneoff(timeout, fd_events, None)?
+    } else {
+        poll_oneoff_virtual(timeout, fd_events, virtual_events)?
+    };
     events_count += events.len();
     for event in events {
         *output_slice_iter
@@ -300,7 +352,245 @@ pub(crate) fn poll_oneoff(
 
     trace!("     | *nevents={:?}", events_count);
 
-    enc_pointee(memory, nevents, events_count)
+    enc_usize_byref(memory, nevents, events_count)
+}
+
+/// The event reported for a virtual file which is ready, or whose other end is closed.
+fn virtual_event(
+    file: &dyn VirtualFile,
+    type_: host::__wasi_eventtype_t,
+    userdata: host::__wasi_userdata_t,
+) -> host::__wasi_event_t {
+    let (error, nbytes, flags) = if type_ == host::__WASI_EVENTTYPE_FD_READ {
+        let flags = if file.hung_up() {
+            host::__WASI_EVENT_FD_READWRITE_HANGUP
+        } else {
+            0
+        };
+        match file.bytes_available() {
+            Ok(nbytes) => (host::__WASI_ESUCCESS, nbytes, flags),
+            Err(err) => (err.as_wasi_errno(), 0, flags),
+        }
+    } else if file.hung_up() {
+        (
+            host::__WASI_EPIPE,
+            0,
+            host::__WASI_EVENT_FD_READWRITE_HANGUP,
+        )
+    } else {
+        (host::__WASI_ESUCCESS, 0, 0)
+    };
+    host::__wasi_event_t {
+        userdata,
+        type_,
+        error,
+        u: host::__wasi_event_t___wasi_event_u {
+            fd_readwrite: host::__wasi_event_t___wasi_event_u___wasi_event_u_fd_readwrite_t {
+                nbytes,
+                flags,
+            },
+        },
+    }
+}
+
+/// The events of `fd_events` which are ready already, along with `timeout` if it has elapsed, for
+/// when there are virtual files to report, so that `poll_oneoff` mustn't block.
+fn poll_oneoff_ready(
+    timeout: Option<ClockEventData>,
+    fd_events: Vec<FdEventData>,
+) -> Result<Vec<host::__wasi_event_t>> {
+    let mut events = Vec::new();
+    if !fd_events.is_empty() {
+        let now = ClockEventData {
+            delay: 0,
+            userdata: 0,
+        };
+        events = hostcalls_impl::poll_oneoff(Some(now), fd_events, None)?;
+        events.retain(|event| event.type_ != host::__WASI_EVENTTYPE_CLOCK);
+    }
+    if let Some(timeout) = timeout {
+        if timeout.delay == 0 {
+            events.extend(poll_oneoff_clock_event(timeout));
+        }
+    }
+    Ok(events)
+}
+
+/// Waits for any of `virtual_events`, which would all block at first, or any of `fd_events`, to
+/// become ready, or for `timeout` to elapse.
+///
+/// Virtual files can't be polled by the host, so the hostcall is woken whenever any of them
+/// changes, through a pipe polled along with the host descriptors if there are any, and checks
+/// them again.
+fn poll_oneoff_virtual(
+    timeout: Option<ClockEventData>,
+    fd_events: Vec<FdEventData>,
+    virtual_events: Vec<VirtualEventData>,
+) -> Result<Vec<host::__wasi_event_t>> {
+    let deadline = timeout.map(|timeout| {
+        let delay = timeout.delay.try_into().unwrap_or(u64::max_value());
+        Instant::now() + Duration::from_nanos(delay)
+    });
+    let wakeup = Arc::new(Wakeup::new(!fd_events.is_empty())?);
+    let _watches: Vec<_> = virtual_events
+        .iter()
+        .filter_map(|event| {
+            let wakeup = Arc::clone(&wakeup);
+            event.file.watch(Arc::new(move || wakeup.wake()))
+        })
+        .collect();
+
+    loop {
+        // reset first, so that no change after the check below is missed
+        wakeup.reset();
+        let events: Vec<_> = virtual_events
+            .iter()
+            .filter(|event| !event.file.would_block())
+            .map(|event| virtual_event(event.file, event.type_, event.userdata))
+            .collect();
+        if !events.is_empty() {
+            return Ok(events);
+        }
+
+        let remaining = deadline.map(|deadline| {
+            let now = Instant::now();
+            if now < deadline {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            }
+        });
+        if fd_events.is_empty() {
+            if let Some(timeout) = timeout {
+                if remaining == Some(Duration::from_secs(0)) {
+                    return Ok(poll_oneoff_clock_event(timeout));
+                }
+            }
+            Wakeup::wait(&wakeup, remaining)?;
+            continue;
+        }
+
+        let remaining = timeout.and_then(|timeout| {
+            remaining.map(|remaining| ClockEventData {
+                delay: remaining.as_nanos(),
+                userdata: timeout.userdata,
+            })
+        });
+        let events = hostcalls_impl::poll_oneoff(remaining, fd_events.clone(), wakeup.file())?;
+        // no events means that a virtual file changed
+        if !events.is_empty() {
+            return Ok(events);
+        }
+    }
+}
+
+/// Wakes `poll_oneoff` when any of the virtual files it waits for changes.
+struct Wakeup {
+    woken: Mutex<bool>,
+    changed: Condvar,
+    /// A pipe made readable along with `woken`, while host descriptors are polled as well.
+    #[cfg(unix)]
+    pipe: Option<(File, File)>,
+}
+
+// `woken` is waited on with `changed`, which an atomic can't be
+#[allow(clippy::mutex_atomic)]
+impl Wakeup {
+    fn new(with_pipe: bool) -> Result<Self> {
+        #[cfg(unix)]
+        let pipe = if with_pipe {
+            Some(hostcalls_impl::wake_pipe()?)
+        } else {
+            None
+        };
+        #[cfg(not(unix))]
+        let _ = with_pipe;
+        Ok(Self {
+            woken: Mutex::new(false),
+            changed: Condvar::new(),
+            #[cfg(unix)]
+            pipe,
+        })
+    }
+
+    fn wake(&self) {
+        let mut woken = self.woken.lock().unwrap();
+        if !*woken {
+            *woken = true;
+            #[cfg(unix)]
+            {
+                use std::io::Write;
+                if let Some((_, writer)) = &self.pipe {
+                    // the pipe is drained on every reset, so it can't be full
+                    let _ = (&*writer).write(&[0]);
+                }
+            }
+        }
+        self.changed.notify_all();
+    }
+
+    fn reset(&self) {
+        let mut woken = self.woken.lock().unwrap();
+        if *woken {
+            *woken = false;
+            #[cfg(unix)]
+            {
+                use std::io::Read;
+                if let Some((reader, _)) = &self.pipe {
+                    let _ = (&*reader).read(&mut [0]);
+                }
+            }
+        }
+    }
+
+    /// The reading end of the pipe, to poll.
+    fn file(&self) -> Option<&File> {
+        #[cfg(unix)]
+        {
+            self.pipe.as_ref().map(|(reader, _)| reader)
+        }
+        #[cfg(not(unix))]
+        {
+            None
+        }
+    }
+
+    /// Blocks until woken, or until `timeout` elapses, failing with `ECANCELED` if the hostcall
+    /// running on this thread is cancelled.
+    fn wait(wakeup: &Arc<Self>, timeout: Option<Duration>) -> Result<()> {
+        let woken = wakeup.woken.lock().unwrap();
+        if *woken {
+            return Ok(());
+        }
+        let wait = || match timeout {
+            Some(timeout) => drop(wakeup.changed.wait_timeout(woken, timeout).unwrap()),
+            None => drop(wakeup.changed.wait(woken).unwrap()),
+        };
+        match cancel::current() {
+            Some(canceller) => {
+                let woken_by = Arc::clone(wakeup);
+                canceller.wait(Arc::new(move || woken_by.wake()), wait)
+            }
+            None => {
+                wait();
+                Ok(())
+            }
+        }
+    }
+}
+
+fn poll_oneoff_clock_event(timeout: ClockEventData) -> Vec<host::__wasi_event_t> {
+    vec![host::__wasi_event_t {
+        userdata: timeout.userdata,
+        type_: host::__WASI_EVENTTYPE_CLOCK,
+        error: host::__WASI_ESUCCESS,
+        u: host::__wasi_event_t___wasi_event_u {
+            fd_readwrite: host::__wasi_event_t___wasi_event_u___wasi_event_u_fd_readwrite_t {
+                nbytes: 0,
+                flags: 0,
+            },
+        },
+    }]
 }
 
 fn wasi_clock_to_relative_ns_delay(
@@ -325,9 +615,16 @@ pub(crate) struct ClockEventData {
     pub(crate) userdata: host::__wasi_userdata_t,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct FdEventData<'a> {
     pub(crate) descriptor: &'a Descriptor,
     pub(crate) type_: host::__wasi_eventtype_t,
     pub(crate) userdata: host::__wasi_userdata_t,
 }
+
+#[derive(Debug)]
+struct VirtualEventData<'a> {
+    file: &'a dyn VirtualFile,
+    type_: host::__wasi_eventtype_t,
+    userdata: host::__wasi_userdata_t,
+}
@@ -3,5 +3,5 @@ mod fs_helpers;
 mod misc;
 
 pub(crate) use self::fs::*;
-pub(crate) use self::fs_helpers::PathGet;
+pub(crate) use self::fs_helpers::{PathGet, RawDirent};
 pub(crate) use self::misc::*;
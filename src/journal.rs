@@ -0,0 +1,102 @@
+//! A record of the modifications a guest makes to files, see `WasiCtxBuilder::journal`.
+use crate::wasm32;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// A path in the guest, relative to the preopened directory it was reached through.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GuestPath {
+    /// The guest path of the preopen.
+    pub preopen: PathBuf,
+    /// The path relative to the preopen, which is empty for the preopen itself.
+    pub path: PathBuf,
+}
+
+/// A modification recorded in the journal of a `WasiCtx`, as returned by `WasiCtx::journal`.
+///
+/// Only successful hostcalls are recorded, in the order they returned. Modifications through
+/// descriptors which weren't opened from a preopened directory, such as those handed to the guest
+/// by the embedder, and through anything but regular files, such as devices, aren't recorded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum JournalEntry {
+    /// A file was created by `path_open`.
+    Create(GuestPath),
+    /// An existing file was truncated by `path_open`.
+    Truncate(GuestPath),
+    /// `len` bytes were written to a file at `offset` by `fd_write` or `fd_pwrite`. Consecutive
+    /// writes to adjacent ranges of the same file are merged into one entry.
+    Write {
+        path: GuestPath,
+        offset: wasm32::__wasi_filesize_t,
+        len: wasm32::__wasi_filesize_t,
+    },
+    /// The size of a file was set by `fd_filestat_set_size`.
+    SetSize {
+        path: GuestPath,
+        size: wasm32::__wasi_filesize_t,
+    },
+    /// The timestamps of a file or directory were set, where `None` leaves one unchanged.
+    SetTimes {
+        path: GuestPath,
+        atim: Option<wasm32::__wasi_timestamp_t>,
+        mtim: Option<wasm32::__wasi_timestamp_t>,
+    },
+    Rename {
+        from: GuestPath,
+        to: GuestPath,
+    },
+    /// A hard link was created at `to` to the file at `from`.
+    Link {
+        from: GuestPath,
+        to: GuestPath,
+    },
+    /// A symbolic link was created at `path`, with the contents `target`.
+    Symlink {
+        target: String,
+        path: GuestPath,
+    },
+    /// A file was removed by `path_unlink_file`.
+    Unlink(GuestPath),
+    CreateDirectory(GuestPath),
+    RemoveDirectory(GuestPath),
+}
+
+/// The journal of a `WasiCtx`, updated by hostcalls which may only borrow the context.
+#[derive(Debug, Default)]
+pub(crate) struct Journal(Mutex<Vec<JournalEntry>>);
+
+impl Journal {
+    pub(crate) fn entries(&self) -> Vec<JournalEntry> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub(crate) fn record(&self, entry: JournalEntry) {
+        self.0.lock().unwrap().push(entry);
+    }
+
+    pub(crate) fn written(
+        &self,
+        path: GuestPath,
+        offset: wasm32::__wasi_filesize_t,
+        len: wasm32::__wasi_filesize_t,
+    ) {
+        if len == 0 {
+            return;
+        }
+        let mut entries = self.0.lock().unwrap();
+        if let Some(JournalEntry::Write {
+            path: last_path,
+            offset: last_offset,
+            len: last_len,
+        }) = entries.last_mut()
+        {
+            if *last_path == path && *last_offset + *last_len == offset {
+                *last_len += len;
+                return;
+            }
+        }
+        entries.push(JournalEntry::Write { path, offset, len });
+    }
+}
@@ -20,12 +20,26 @@
     )
 )]
 
+mod cancel;
+mod capabilities;
+#[cfg(feature = "config")]
+mod config;
+mod creation;
 mod ctx;
+mod device;
+mod encoding;
+pub mod errno;
 mod error;
 mod fdentry;
 mod helpers;
 mod hostcalls_impl;
+mod journal;
+mod metadata;
+mod metrics;
+mod policy;
+mod snapshot;
 mod sys;
+mod virtfs;
 #[macro_use]
 mod macros;
 pub mod fs;
@@ -33,9 +47,31 @@ mod host;
 pub mod hostcalls;
 mod memory;
 pub mod wasm32;
+pub mod wasm64;
 
-pub use ctx::{WasiCtx, WasiCtxBuilder};
+pub use cancel::CancelHandle;
+pub use capabilities::{Capability, DisabledError};
+#[cfg(feature = "config")]
+pub use config::{
+    Config, ConfigError, EnvConfig, MetadataConfig, OverlayConfig, PolicyConfig, PreopenConfig,
+    RuleConfig, Stdio, StdioConfig,
+};
+pub use creation::CreationMode;
+pub use ctx::{ForkOptions, WasiCtx, WasiCtxBuilder};
+pub use device::DevicePolicy;
+pub use encoding::PathEncoding;
+pub use fdentry::FdInfo;
+pub use journal::{GuestPath, JournalEntry};
+pub use metadata::VirtualMetadata;
+pub use metrics::{FdMetrics, HostcallMetrics, MetricsReport};
+pub use policy::{Denial, PathOp, PathPolicy};
+pub use snapshot::{
+    FdSnapshot, FdSource, FsSnapshot, LayerSnapshot, MemoryEntry, MemoryNode, PolicySnapshot,
+    Snapshot,
+};
 pub use sys::preopen_dir;
+pub use virtfs::{pipe, OverlayUpper, PipeEnd, PipeReader, PipeWriter};
 
+pub use error::ErrorContext;
 pub type Error = error::Error;
 pub(crate) type Result<T> = std::result::Result<T, Error>;
@@ -1,13 +1,52 @@
+/// Defines the hostcalls exported for wasm32, which call their implementation in
+/// `crate::hostcalls_impl`. Those operating on a guest descriptor are marked with `#[fd(arg)]`,
+/// naming the argument which holds it, so that it's recorded in their errors.
 macro_rules! hostcalls {
-    ($(pub unsafe fn $name:ident($($arg:ident: $ty:ty,)*) -> $ret:ty;)*) => ($(
+    ($($(#[fd($fd:ident)])? pub unsafe fn $name:ident($($arg:ident: $ty:ty,)*) -> $ret:ty;)*) => ($(
             #[wasi_common_cbindgen::wasi_common_cbindgen]
             pub unsafe fn $name($($arg: $ty,)*) -> $ret {
-                let ret = match crate::hostcalls_impl::$name($($arg,)*) {
-                    Ok(()) => crate::host::__WASI_ESUCCESS,
-                    Err(e) => e.as_wasi_errno(),
-                };
+                hostcall_body!($name($($arg,)*) $(, $fd)?)
+            }
+    )*)
+}
 
-                crate::hostcalls::return_enc_errno(ret)
+/// Same as `hostcalls!`, but without the C bindings, which are only exported for wasm32.
+macro_rules! hostcalls_wasm64 {
+    ($($(#[fd($fd:ident)])? pub unsafe fn $name:ident($($arg:ident: $ty:ty,)*) -> $ret:ty;)*) => ($(
+            pub unsafe fn $name($($arg: $ty,)*) -> $ret {
+                hostcall_body!($name($($arg,)*) $(, $fd)?)
             }
     )*)
 }
+
+/// Hostcalls without a context, such as `sched_yield`, have nothing to record their errors or
+/// metrics in.
+macro_rules! hostcall_body {
+    ($name:ident()) => {{
+        let ret = match crate::hostcalls_impl::$name() {
+            Ok(()) => crate::host::__WASI_ESUCCESS,
+            Err(e) => e.as_wasi_errno(),
+        };
+
+        crate::hostcalls::return_enc_errno(ret)
+    }};
+    ($name:ident($wasi_ctx:ident, $($arg:ident,)*) $(, $fd:ident)?) => {{
+        let fd = None::<crate::wasm32::__wasi_fd_t> $(.or(Some($fd)))?;
+        let start = $wasi_ctx.metrics.as_ref().map(|_| std::time::Instant::now());
+        let result = crate::hostcalls_impl::$name($wasi_ctx, $($arg,)*);
+        let ret = match result {
+            Ok(()) => crate::host::__WASI_ESUCCESS,
+            Err(e) => {
+                let e = e.in_hostcall(stringify!($name), fd);
+                let errno = e.as_wasi_errno();
+                $wasi_ctx.set_last_error(e);
+                errno
+            }
+        };
+        if let (Some(metrics), Some(start)) = (&$wasi_ctx.metrics, start) {
+            metrics.hostcall(stringify!($name), ret, start.elapsed());
+        }
+
+        crate::hostcalls::return_enc_errno(ret)
+    }};
+}
@@ -1,64 +1,180 @@
-//! Functions to go back and forth between WASI types in host and wasm32 representations.
+//! Functions to go back and forth between WASI types in host and wasm32/wasm64 representations.
 #![allow(unused)]
-use crate::{host, wasm32, Error, Result};
+use crate::{host, wasm32, wasm64, Error, Result};
 use std::convert::TryFrom;
+use std::fmt;
 use std::mem::{align_of, size_of};
 use std::{ptr, slice};
 
-fn dec_ptr(memory: &[u8], ptr: wasm32::uintptr_t, len: usize) -> Result<*const u8> {
+/// A guest pointer or size, i.e. `uintptr_t` and `size_t` as defined by either `wasm32` or
+/// `wasm64`. The WASI types embedding pointers are the only ones whose layout differs between
+/// the two, hence they're exposed as associated types. Like everything else in guest memory,
+/// pointers and sizes are little-endian, whether they're read from it or passed as arguments.
+#[allow(non_camel_case_types)]
+pub(crate) trait WasmPtr: Copy + fmt::Debug + fmt::Display {
+    type __wasi_ciovec_t: Copy;
+    type __wasi_iovec_t: Copy;
+    type __wasi_prestat_t: Copy;
+
+    /// Offset into the guest memory designated by this pointer, or `None` if it can't be
+    /// represented on the host.
+    fn offset(self) -> Option<usize>;
+
+    /// Pointer `offset` bytes past this one, or `None` on overflow.
+    fn add_offset(self, offset: usize) -> Option<Self>;
+
+    /// Decodes a guest size, saturating at `usize::max_value()` if it can't be represented on
+    /// the host. Any access to guest memory of that size is bound to fail anyway.
+    fn to_usize(self) -> usize;
+
+    /// Encodes a host size, or `None` if it can't be represented in the guest.
+    fn from_usize(size: usize) -> Option<Self>;
+
+    fn ciovec_parts(ciovec: &Self::__wasi_ciovec_t) -> (Self, Self);
+
+    fn iovec_parts(iovec: &Self::__wasi_iovec_t) -> (Self, Self);
+
+    fn dec_prestat(prestat: Self::__wasi_prestat_t) -> Result<host::__wasi_prestat_t>;
+
+    fn enc_prestat(prestat: host::__wasi_prestat_t) -> Result<Self::__wasi_prestat_t>;
+}
+
+macro_rules! wasm_ptr {
+    ($abi:ident) => {
+        impl WasmPtr for $abi::uintptr_t {
+            type __wasi_ciovec_t = $abi::__wasi_ciovec_t;
+            type __wasi_iovec_t = $abi::__wasi_iovec_t;
+            type __wasi_prestat_t = $abi::__wasi_prestat_t;
+
+            fn offset(self) -> Option<usize> {
+                usize::try_from(Self::from_le(self)).ok()
+            }
+
+            fn add_offset(self, offset: usize) -> Option<Self> {
+                Self::try_from(offset)
+                    .ok()
+                    .and_then(|offset| Self::from_le(self).checked_add(offset))
+                    .map(Self::to_le)
+            }
+
+            fn to_usize(self) -> usize {
+                usize::try_from(Self::from_le(self)).unwrap_or_else(|_| usize::max_value())
+            }
+
+            fn from_usize(size: usize) -> Option<Self> {
+                Self::try_from(size).ok().map(Self::to_le)
+            }
+
+            fn ciovec_parts(ciovec: &$abi::__wasi_ciovec_t) -> (Self, Self) {
+                (ciovec.buf, ciovec.buf_len)
+            }
+
+            fn iovec_parts(iovec: &$abi::__wasi_iovec_t) -> (Self, Self) {
+                (iovec.buf, iovec.buf_len)
+            }
+
+            fn dec_prestat(prestat: $abi::__wasi_prestat_t) -> Result<host::__wasi_prestat_t> {
+                match prestat.pr_type {
+                    $abi::__WASI_PREOPENTYPE_DIR => {
+                        let u = host::__wasi_prestat_t___wasi_prestat_u {
+                            dir: host::__wasi_prestat_t___wasi_prestat_u___wasi_prestat_u_dir_t {
+                                pr_name_len: dec_usize(unsafe { prestat.u.dir.pr_name_len }),
+                            },
+                        };
+                        Ok(host::__wasi_prestat_t {
+                            pr_type: host::__WASI_PREOPENTYPE_DIR,
+                            u,
+                        })
+                    }
+                    _ => Err(Error::EINVAL),
+                }
+            }
+
+            fn enc_prestat(prestat: host::__wasi_prestat_t) -> Result<$abi::__wasi_prestat_t> {
+                match prestat.pr_type {
+                    host::__WASI_PREOPENTYPE_DIR => {
+                        let pr_name_len = unsafe { prestat.u.dir.pr_name_len };
+                        let u = $abi::__wasi_prestat_t___wasi_prestat_u {
+                            dir: $abi::__wasi_prestat_t___wasi_prestat_u___wasi_prestat_u_dir_t {
+                                pr_name_len: Self::from_usize(pr_name_len)
+                                    .ok_or(Error::EOVERFLOW)?,
+                            },
+                        };
+                        Ok($abi::__wasi_prestat_t {
+                            pr_type: $abi::__WASI_PREOPENTYPE_DIR,
+                            u,
+                        })
+                    }
+                    _ => Err(Error::EINVAL),
+                }
+            }
+        }
+    };
+}
+
+wasm_ptr!(wasm32);
+wasm_ptr!(wasm64);
+
+fn dec_offset<P: WasmPtr>(ptr: P) -> Result<usize> {
+    ptr.offset().ok_or(Error::EFAULT)
+}
+
+fn dec_ptr<P: WasmPtr>(memory: &[u8], ptr: P, len: usize) -> Result<*const u8> {
     // check for overflow
-    let checked_len = (ptr as usize).checked_add(len).ok_or(Error::EFAULT)?;
+    let ptr = dec_offset(ptr)?;
+    let checked_len = ptr.checked_add(len).ok_or(Error::EFAULT)?;
 
     // translate the pointer
     memory
-        .get(ptr as usize..checked_len)
+        .get(ptr..checked_len)
         .ok_or(Error::EFAULT)
         .map(|mem| mem.as_ptr())
 }
 
-fn dec_ptr_mut(memory: &mut [u8], ptr: wasm32::uintptr_t, len: usize) -> Result<*mut u8> {
+fn dec_ptr_mut<P: WasmPtr>(memory: &mut [u8], ptr: P, len: usize) -> Result<*mut u8> {
     // check for overflow
-    let checked_len = (ptr as usize).checked_add(len).ok_or(Error::EFAULT)?;
+    let ptr = dec_offset(ptr)?;
+    let checked_len = ptr.checked_add(len).ok_or(Error::EFAULT)?;
 
     // translate the pointer
     memory
-        .get_mut(ptr as usize..checked_len)
+        .get_mut(ptr..checked_len)
         .ok_or(Error::EFAULT)
         .map(|mem| mem.as_mut_ptr())
 }
 
-fn dec_ptr_to<'memory, T>(memory: &'memory [u8], ptr: wasm32::uintptr_t) -> Result<&'memory T> {
+fn dec_ptr_to<'memory, T, P: WasmPtr>(memory: &'memory [u8], ptr: P) -> Result<&'memory T> {
     // check that the ptr is aligned
-    if ptr as usize % align_of::<T>() != 0 {
+    if dec_offset(ptr)? % align_of::<T>() != 0 {
         return Err(Error::EINVAL);
     }
 
     dec_ptr(memory, ptr, size_of::<T>()).map(|p| unsafe { &*(p as *const T) })
 }
 
-fn dec_ptr_to_mut<'memory, T>(
+fn dec_ptr_to_mut<'memory, T, P: WasmPtr>(
     memory: &'memory mut [u8],
-    ptr: wasm32::uintptr_t,
+    ptr: P,
 ) -> Result<&'memory mut T> {
     // check that the ptr is aligned
-    if ptr as usize % align_of::<T>() != 0 {
+    if dec_offset(ptr)? % align_of::<T>() != 0 {
         return Err(Error::EINVAL);
     }
 
     dec_ptr_mut(memory, ptr, size_of::<T>()).map(|p| unsafe { &mut *(p as *mut T) })
 }
 
-pub(crate) fn dec_pointee<T>(memory: &[u8], ptr: wasm32::uintptr_t) -> Result<T> {
-    dec_ptr_to::<T>(memory, ptr).map(|p| unsafe { ptr::read(p) })
+pub(crate) fn dec_pointee<T, P: WasmPtr>(memory: &[u8], ptr: P) -> Result<T> {
+    dec_ptr_to::<T, _>(memory, ptr).map(|p| unsafe { ptr::read(p) })
 }
 
-pub(crate) fn enc_pointee<T>(memory: &mut [u8], ptr: wasm32::uintptr_t, t: T) -> Result<()> {
-    dec_ptr_to_mut::<T>(memory, ptr).map(|p| unsafe { ptr::write(p, t) })
+pub(crate) fn enc_pointee<T, P: WasmPtr>(memory: &mut [u8], ptr: P, t: T) -> Result<()> {
+    dec_ptr_to_mut::<T, _>(memory, ptr).map(|p| unsafe { ptr::write(p, t) })
 }
 
-fn check_slice_of<T>(ptr: wasm32::uintptr_t, len: wasm32::size_t) -> Result<(usize, usize)> {
+fn check_slice_of<T, P: WasmPtr>(ptr: P, len: P) -> Result<(usize, usize)> {
     // check alignment, and that length doesn't overflow
-    if ptr as usize % align_of::<T>() != 0 {
+    if dec_offset(ptr)? % align_of::<T>() != 0 {
         return Err(Error::EINVAL);
     }
     let len = dec_usize(len);
@@ -71,33 +187,29 @@ fn check_slice_of<T>(ptr: wasm32::uintptr_t, len: wasm32::size_t) -> Result<(usi
     Ok((len, len_bytes))
 }
 
-pub(crate) fn dec_slice_of<'memory, T>(
+pub(crate) fn dec_slice_of<'memory, T, P: WasmPtr>(
     memory: &'memory [u8],
-    ptr: wasm32::uintptr_t,
-    len: wasm32::size_t,
+    ptr: P,
+    len: P,
 ) -> Result<&'memory [T]> {
-    let (len, len_bytes) = check_slice_of::<T>(ptr, len)?;
+    let (len, len_bytes) = check_slice_of::<T, _>(ptr, len)?;
     let ptr = dec_ptr(memory, ptr, len_bytes)? as *const T;
     Ok(unsafe { slice::from_raw_parts(ptr, len) })
 }
 
-pub(crate) fn dec_slice_of_mut<'memory, T>(
+pub(crate) fn dec_slice_of_mut<'memory, T, P: WasmPtr>(
     memory: &'memory mut [u8],
-    ptr: wasm32::uintptr_t,
-    len: wasm32::size_t,
+    ptr: P,
+    len: P,
 ) -> Result<&'memory mut [T]> {
-    let (len, len_bytes) = check_slice_of::<T>(ptr, len)?;
+    let (len, len_bytes) = check_slice_of::<T, _>(ptr, len)?;
     let ptr = dec_ptr_mut(memory, ptr, len_bytes)? as *mut T;
     Ok(unsafe { slice::from_raw_parts_mut(ptr, len) })
 }
 
-pub(crate) fn enc_slice_of<T>(
-    memory: &mut [u8],
-    slice: &[T],
-    ptr: wasm32::uintptr_t,
-) -> Result<()> {
+pub(crate) fn enc_slice_of<T, P: WasmPtr>(memory: &mut [u8], slice: &[T], ptr: P) -> Result<()> {
     // check alignment
-    if ptr as usize % align_of::<T>() != 0 {
+    if dec_offset(ptr)? % align_of::<T>() != 0 {
         return Err(Error::EINVAL);
     }
     // check that length doesn't overflow
@@ -122,62 +234,70 @@ macro_rules! dec_enc_scalar {
             host::$ty::from_le(x)
         }
 
-        pub(crate) fn $dec_byref(memory: &mut [u8], ptr: wasm32::uintptr_t) -> Result<host::$ty> {
-            dec_pointee::<wasm32::$ty>(memory, ptr).map($dec)
+        pub(crate) fn $dec_byref<P: WasmPtr>(memory: &mut [u8], ptr: P) -> Result<host::$ty> {
+            dec_pointee::<wasm32::$ty, _>(memory, ptr).map($dec)
         }
 
         pub(crate) fn $enc(x: host::$ty) -> wasm32::$ty {
             x.to_le()
         }
 
-        pub(crate) fn $enc_byref(
+        pub(crate) fn $enc_byref<P: WasmPtr>(
             memory: &mut [u8],
-            ptr: wasm32::uintptr_t,
+            ptr: P,
             x: host::$ty,
         ) -> Result<()> {
-            enc_pointee::<wasm32::$ty>(memory, ptr, $enc(x))
+            enc_pointee::<wasm32::$ty, _>(memory, ptr, $enc(x))
         }
     };
 }
 
-pub(crate) fn dec_ciovec(
+pub(crate) fn dec_ciovec<P: WasmPtr>(
     memory: &[u8],
-    ciovec: &wasm32::__wasi_ciovec_t,
+    ciovec: &P::__wasi_ciovec_t,
 ) -> Result<host::__wasi_ciovec_t> {
-    let len = dec_usize(ciovec.buf_len);
+    let (buf, buf_len) = P::ciovec_parts(ciovec);
+    let len = dec_usize(buf_len);
     Ok(host::__wasi_ciovec_t {
-        buf: dec_ptr(memory, ciovec.buf, len)? as *const host::void,
+        buf: dec_ptr(memory, buf, len)? as *const host::void,
         buf_len: len,
     })
 }
 
-pub(crate) fn dec_ciovec_slice(
+pub(crate) fn dec_ciovec_slice<P: WasmPtr>(
     memory: &[u8],
-    ptr: wasm32::uintptr_t,
-    len: wasm32::size_t,
+    ptr: P,
+    len: P,
 ) -> Result<Vec<host::__wasi_ciovec_t>> {
-    let slice = dec_slice_of::<wasm32::__wasi_ciovec_t>(memory, ptr, len)?;
-    slice.iter().map(|iov| dec_ciovec(memory, iov)).collect()
+    let slice = dec_slice_of::<P::__wasi_ciovec_t, P>(memory, ptr, len)?;
+    slice
+        .iter()
+        .map(|iov| dec_ciovec::<P>(memory, iov))
+        .collect()
 }
 
-pub(crate) fn dec_iovec(
+pub(crate) fn dec_iovec<P: WasmPtr>(
     memory: &[u8],
-    iovec: &wasm32::__wasi_iovec_t,
+    iovec: &P::__wasi_iovec_t,
 ) -> Result<host::__wasi_iovec_t> {
-    let len = dec_usize(iovec.buf_len);
+    let (buf, buf_len) = P::iovec_parts(iovec);
+    let len = dec_usize(buf_len);
     Ok(host::__wasi_iovec_t {
-        buf: dec_ptr(memory, iovec.buf, len)? as *mut host::void,
+        buf: dec_ptr(memory, buf, len)? as *mut host::void,
         buf_len: len,
     })
 }
 
-pub(crate) fn dec_iovec_slice(
+pub(crate) fn dec_iovec_slice<P: WasmPtr>(
     memory: &[u8],
-    ptr: wasm32::uintptr_t,
-    len: wasm32::size_t,
+    ptr: P,
+    len: P,
 ) -> Result<Vec<host::__wasi_iovec_t>> {
-    let slice = dec_slice_of::<wasm32::__wasi_iovec_t>(memory, ptr, len)?;
-    slice.iter().map(|iov| dec_iovec(memory, iov)).collect()
+    let slice = dec_slice_of::<P::__wasi_iovec_t, P>(memory, ptr, len)?;
+    slice
+        .iter()
+        .map(|iov| dec_iovec::<P>(memory, iov))
+        .collect()
 }
 
 dec_enc_scalar!(
@@ -251,11 +371,11 @@ pub(crate) fn dec_filestat(filestat: wasm32::__wasi_filestat_t) -> host::__wasi_
     }
 }
 
-pub(crate) fn dec_filestat_byref(
+pub(crate) fn dec_filestat_byref<P: WasmPtr>(
     memory: &mut [u8],
-    filestat_ptr: wasm32::uintptr_t,
+    filestat_ptr: P,
 ) -> Result<host::__wasi_filestat_t> {
-    dec_pointee::<wasm32::__wasi_filestat_t>(memory, filestat_ptr).map(dec_filestat)
+    dec_pointee::<wasm32::__wasi_filestat_t, _>(memory, filestat_ptr).map(dec_filestat)
 }
 
 pub(crate) fn enc_filestat(filestat: host::__wasi_filestat_t) -> wasm32::__wasi_filestat_t {
@@ -271,13 +391,13 @@ pub(crate) fn enc_filestat(filestat: host::__wasi_filestat_t) -> wasm32::__wasi_
     }
 }
 
-pub(crate) fn enc_filestat_byref(
+pub(crate) fn enc_filestat_byref<P: WasmPtr>(
     memory: &mut [u8],
-    filestat_ptr: wasm32::uintptr_t,
+    filestat_ptr: P,
     host_filestat: host::__wasi_filestat_t,
 ) -> Result<()> {
     let filestat = enc_filestat(host_filestat);
-    enc_pointee::<wasm32::__wasi_filestat_t>(memory, filestat_ptr, filestat)
+    enc_pointee::<wasm32::__wasi_filestat_t, _>(memory, filestat_ptr, filestat)
 }
 
 pub(crate) fn dec_fdstat(fdstat: wasm32::__wasi_fdstat_t) -> host::__wasi_fdstat_t {
@@ -289,11 +409,11 @@ pub(crate) fn dec_fdstat(fdstat: wasm32::__wasi_fdstat_t) -> host::__wasi_fdstat
     }
 }
 
-pub(crate) fn dec_fdstat_byref(
+pub(crate) fn dec_fdstat_byref<P: WasmPtr>(
     memory: &mut [u8],
-    fdstat_ptr: wasm32::uintptr_t,
+    fdstat_ptr: P,
 ) -> Result<host::__wasi_fdstat_t> {
-    dec_pointee::<wasm32::__wasi_fdstat_t>(memory, fdstat_ptr).map(dec_fdstat)
+    dec_pointee::<wasm32::__wasi_fdstat_t, _>(memory, fdstat_ptr).map(dec_fdstat)
 }
 
 pub(crate) fn enc_fdstat(fdstat: host::__wasi_fdstat_t) -> wasm32::__wasi_fdstat_t {
@@ -306,13 +426,13 @@ pub(crate) fn enc_fdstat(fdstat: host::__wasi_fdstat_t) -> wasm32::__wasi_fdstat
     }
 }
 
-pub(crate) fn enc_fdstat_byref(
+pub(crate) fn enc_fdstat_byref<P: WasmPtr>(
     memory: &mut [u8],
-    fdstat_ptr: wasm32::uintptr_t,
+    fdstat_ptr: P,
     host_fdstat: host::__wasi_fdstat_t,
 ) -> Result<()> {
     let fdstat = enc_fdstat(host_fdstat);
-    enc_pointee::<wasm32::__wasi_fdstat_t>(memory, fdstat_ptr, fdstat)
+    enc_pointee::<wasm32::__wasi_fdstat_t, _>(memory, fdstat_ptr, fdstat)
 }
 
 dec_enc_scalar!(
@@ -355,54 +475,20 @@ dec_enc_scalar!(
     enc_oflags_byref
 );
 
-pub(crate) fn dec_prestat(prestat: wasm32::__wasi_prestat_t) -> Result<host::__wasi_prestat_t> {
-    match prestat.pr_type {
-        wasm32::__WASI_PREOPENTYPE_DIR => {
-            let u = host::__wasi_prestat_t___wasi_prestat_u {
-                dir: host::__wasi_prestat_t___wasi_prestat_u___wasi_prestat_u_dir_t {
-                    pr_name_len: dec_usize(unsafe { prestat.u.dir.pr_name_len }),
-                },
-            };
-            Ok(host::__wasi_prestat_t {
-                pr_type: host::__WASI_PREOPENTYPE_DIR,
-                u,
-            })
-        }
-        _ => Err(Error::EINVAL),
-    }
-}
-
-pub(crate) fn dec_prestat_byref(
+pub(crate) fn dec_prestat_byref<P: WasmPtr>(
     memory: &mut [u8],
-    prestat_ptr: wasm32::uintptr_t,
+    prestat_ptr: P,
 ) -> Result<host::__wasi_prestat_t> {
-    dec_pointee::<wasm32::__wasi_prestat_t>(memory, prestat_ptr).and_then(dec_prestat)
-}
-
-pub(crate) fn enc_prestat(prestat: host::__wasi_prestat_t) -> Result<wasm32::__wasi_prestat_t> {
-    match prestat.pr_type {
-        host::__WASI_PREOPENTYPE_DIR => {
-            let u = wasm32::__wasi_prestat_t___wasi_prestat_u {
-                dir: wasm32::__wasi_prestat_t___wasi_prestat_u___wasi_prestat_u_dir_t {
-                    pr_name_len: enc_usize(unsafe { prestat.u.dir.pr_name_len }),
-                },
-            };
-            Ok(wasm32::__wasi_prestat_t {
-                pr_type: wasm32::__WASI_PREOPENTYPE_DIR,
-                u,
-            })
-        }
-        _ => Err(Error::EINVAL),
-    }
+    dec_pointee::<P::__wasi_prestat_t, P>(memory, prestat_ptr).and_then(P::dec_prestat)
 }
 
-pub(crate) fn enc_prestat_byref(
+pub(crate) fn enc_prestat_byref<P: WasmPtr>(
     memory: &mut [u8],
-    prestat_ptr: wasm32::uintptr_t,
+    prestat_ptr: P,
     host_prestat: host::__wasi_prestat_t,
 ) -> Result<()> {
-    let prestat = enc_prestat(host_prestat)?;
-    enc_pointee::<wasm32::__wasi_prestat_t>(memory, prestat_ptr, prestat)
+    let prestat = P::enc_prestat(host_prestat)?;
+    enc_pointee(memory, prestat_ptr, prestat)
 }
 
 dec_enc_scalar!(
@@ -429,20 +515,20 @@ pub(crate) fn enc_u32(x: u32) -> u32 {
     x.to_le()
 }
 
-pub(crate) fn dec_usize(size: wasm32::size_t) -> usize {
-    usize::try_from(u32::from_le(size)).unwrap()
+pub(crate) fn dec_usize<P: WasmPtr>(size: P) -> usize {
+    size.to_usize()
 }
 
-pub(crate) fn enc_usize(size: usize) -> wasm32::size_t {
-    wasm32::size_t::try_from(size).unwrap()
+pub(crate) fn enc_usize<P: WasmPtr>(size: usize) -> Result<P> {
+    P::from_usize(size).ok_or(Error::EOVERFLOW)
 }
 
-pub(crate) fn enc_usize_byref(
+pub(crate) fn enc_usize_byref<P: WasmPtr>(
     memory: &mut [u8],
-    usize_ptr: wasm32::uintptr_t,
+    usize_ptr: P,
     host_usize: usize,
 ) -> Result<()> {
-    enc_pointee::<wasm32::size_t>(memory, usize_ptr, enc_usize(host_usize))
+    enc_pointee::<P, P>(memory, usize_ptr, enc_usize(host_usize)?)
 }
 
 dec_enc_scalar!(
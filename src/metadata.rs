@@ -0,0 +1,129 @@
+//! Virtualized file metadata, see `WasiCtxBuilder::virtual_metadata`.
+use crate::helpers::systemtime_to_timestamp;
+use crate::{host, Result};
+use std::cmp;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// How file metadata is presented to the guest, see `WasiCtxBuilder::virtual_metadata`.
+///
+/// Instead of the device and inode numbers of the host, which reveal how its file systems are
+/// laid out and differ from run to run, the guest sees the number of the descriptor of the preopen
+/// a file was reached through as its device, and inode numbers handed out in the order the guest
+/// comes across files, starting from 1. A file keeps its inode number for the lifetime of the
+/// context and of the contexts forked from it, whether it's seen through `fd_filestat_get`,
+/// `path_filestat_get` or `fd_readdir`. Snapshots don't record inode numbers, so they're handed
+/// out anew after restoring one.
+///
+/// Timestamps are passed through unless they're clamped with
+/// `VirtualMetadata::clamp_timestamps`.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VirtualMetadata {
+    epoch: Option<SystemTime>,
+}
+
+impl VirtualMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report timestamps later than `epoch` as `epoch`, like `SOURCE_DATE_EPOCH` does for
+    /// reproducible builds, so that files created or modified by the guest look the same in
+    /// every run.
+    pub fn clamp_timestamps(mut self, epoch: SystemTime) -> Self {
+        self.epoch = Some(epoch);
+        self
+    }
+
+    pub(crate) fn resolve(&self) -> Result<MetadataMap> {
+        Ok(MetadataMap {
+            settings: *self,
+            epoch: self.epoch.map(systemtime_to_timestamp).transpose()?,
+            inodes: Arc::default(),
+        })
+    }
+}
+
+/// A host inode, by the device of the preopen it was reached through, and its device and inode
+/// numbers on the host.
+type HostInode = (host::__wasi_device_t, u64, u64);
+
+/// The state of `VirtualMetadata` in a `WasiCtx`.
+#[derive(Clone, Debug)]
+pub(crate) struct MetadataMap {
+    /// The settings, which are recorded by snapshots.
+    pub(crate) settings: VirtualMetadata,
+    epoch: Option<host::__wasi_timestamp_t>,
+    /// The inode numbers handed out so far.
+    inodes: Arc<Mutex<HashMap<HostInode, host::__wasi_inode_t>>>,
+}
+
+impl MetadataMap {
+    /// The inode number the guest sees for the inode `ino` of the host device `host_dev`,
+    /// reached through the preopen with the device `dev`.
+    fn inode(&self, dev: host::__wasi_device_t, host_dev: u64, ino: u64) -> host::__wasi_inode_t {
+        let mut inodes = self.inodes.lock().unwrap();
+        let next = inodes.len() as u64 + 1;
+        *inodes.entry((dev, host_dev, ino)).or_insert(next)
+    }
+
+    fn timestamp(&self, timestamp: host::__wasi_timestamp_t) -> host::__wasi_timestamp_t {
+        match self.epoch {
+            Some(epoch) => cmp::min(timestamp, epoch),
+            None => timestamp,
+        }
+    }
+
+    /// Virtualizes `filestat` of a file reached through the preopen with the device `dev`. The
+    /// host device of files of virtual file systems is ignored, since it's made up by them and
+    /// doesn't show up in their directory entries.
+    pub(crate) fn filestat(
+        &self,
+        dev: host::__wasi_device_t,
+        is_virtual: bool,
+        filestat: host::__wasi_filestat_t,
+    ) -> host::__wasi_filestat_t {
+        let host_dev = if is_virtual { 0 } else { filestat.st_dev };
+        host::__wasi_filestat_t {
+            st_dev: dev,
+            st_ino: self.inode(dev, host_dev, filestat.st_ino),
+            st_atim: self.timestamp(filestat.st_atim),
+            st_mtim: self.timestamp(filestat.st_mtim),
+            st_ctim: self.timestamp(filestat.st_ctim),
+            ..filestat
+        }
+    }
+
+    /// Virtualizes the inode numbers of the entries `fd_readdir` wrote to `buf`, which are those
+    /// of a directory on the host device `host_dev`, or 0 if it's virtual, reached through the
+    /// preopen with the device `dev`.
+    pub(crate) fn readdir(&self, dev: host::__wasi_device_t, host_dev: u64, buf: &mut [u8]) {
+        let dirent_size = std::mem::size_of::<host::__wasi_dirent_t>();
+        let mut offset = 0;
+        while offset < buf.len() {
+            let ino_start = offset + 8;
+            let ino_end = ino_start + 8;
+            if ino_end > buf.len() {
+                // the inode number of a truncated entry can't be looked up, so it's hidden
+                let len = buf.len();
+                for byte in &mut buf[cmp::min(ino_start, len)..] {
+                    *byte = 0;
+                }
+                break;
+            }
+            let ino = u64::from_le_bytes(buf[ino_start..ino_end].try_into().unwrap());
+            let ino = self.inode(dev, host_dev, ino);
+            buf[ino_start..ino_end].copy_from_slice(&ino.to_le_bytes());
+
+            let namlen_end = ino_end + 4;
+            if namlen_end > buf.len() {
+                break;
+            }
+            let namlen = u32::from_le_bytes(buf[ino_end..namlen_end].try_into().unwrap());
+            offset += dirent_size + namlen as usize;
+        }
+    }
+}
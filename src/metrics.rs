@@ -0,0 +1,119 @@
+//! Counters of the I/O a guest performs through a `WasiCtx`, see `WasiCtxBuilder::metrics`.
+use crate::{host, wasm32};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The counters kept by a `WasiCtx` built with `WasiCtxBuilder::metrics`, as returned by
+/// `WasiCtx::metrics`.
+///
+/// All counters only ever increase, except for those of a descriptor, which are discarded when
+/// the guest closes it, so they can be exported to a metrics registry as they are.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MetricsReport {
+    /// Bytes read and written through each open descriptor.
+    pub fds: HashMap<wasm32::__wasi_fd_t, FdMetrics>,
+    /// Bytes read through all descriptors, including closed ones.
+    pub bytes_read: u64,
+    /// Bytes written through all descriptors, including closed ones.
+    pub bytes_written: u64,
+    /// Invocations of each hostcall, by its name. `proc_exit` and `sched_yield` aren't counted,
+    /// since they aren't given the context.
+    pub hostcalls: HashMap<String, HostcallMetrics>,
+    /// Failed hostcalls, by the errno they returned.
+    pub errors: HashMap<wasm32::__wasi_errno_t, u64>,
+    /// Time spent in `poll_oneoff`.
+    pub poll_time: Duration,
+    /// Time spent in `fd_read` and `fd_pread`.
+    pub read_time: Duration,
+    /// Paths opened with `path_open`, and how often each was opened. Paths are relative to the
+    /// directory descriptor they were opened from, or joined to its guest path if it's a preopen.
+    pub paths_opened: HashMap<PathBuf, u64>,
+}
+
+/// The counters of a single descriptor.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FdMetrics {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+/// The counters of a single hostcall.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HostcallMetrics {
+    pub calls: u64,
+    /// The invocations which returned an errno other than `ESUCCESS`.
+    pub errors: u64,
+    /// The time spent in the hostcall, including any time spent blocked.
+    pub time: Duration,
+}
+
+/// The counters of a `WasiCtx`, updated by hostcalls which may only borrow the context.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics(Mutex<MetricsReport>);
+
+impl Metrics {
+    pub(crate) fn report(&self) -> MetricsReport {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub(crate) fn hostcall(&self, name: &str, errno: host::__wasi_errno_t, time: Duration) {
+        let mut report = self.0.lock().unwrap();
+        if let Some(hostcall) = report.hostcalls.get_mut(name) {
+            hostcall.calls += 1;
+            hostcall.time += time;
+        } else {
+            let hostcall = HostcallMetrics {
+                calls: 1,
+                errors: 0,
+                time,
+            };
+            report.hostcalls.insert(name.to_owned(), hostcall);
+        }
+        if errno != host::__WASI_ESUCCESS {
+            if let Some(hostcall) = report.hostcalls.get_mut(name) {
+                hostcall.errors += 1;
+            }
+            *report.errors.entry(errno).or_insert(0) += 1;
+        }
+
+        match name {
+            "poll_oneoff" => report.poll_time += time,
+            "fd_read" | "fd_pread" => report.read_time += time,
+            _ => {}
+        }
+    }
+
+    pub(crate) fn read(&self, fd: host::__wasi_fd_t, nread: usize) {
+        let mut report = self.0.lock().unwrap();
+        report.bytes_read += nread as u64;
+        report.fds.entry(fd).or_default().bytes_read += nread as u64;
+    }
+
+    pub(crate) fn written(&self, fd: host::__wasi_fd_t, nwritten: usize) {
+        let mut report = self.0.lock().unwrap();
+        report.bytes_written += nwritten as u64;
+        report.fds.entry(fd).or_default().bytes_written += nwritten as u64;
+    }
+
+    pub(crate) fn opened(&self, path: PathBuf) {
+        *self.0.lock().unwrap().paths_opened.entry(path).or_insert(0) += 1;
+    }
+
+    pub(crate) fn closed(&self, fd: host::__wasi_fd_t) {
+        self.0.lock().unwrap().fds.remove(&fd);
+    }
+
+    /// Moves the counters of `from` to `to`, replacing those of the descriptor closed by it.
+    pub(crate) fn renumbered(&self, from: host::__wasi_fd_t, to: host::__wasi_fd_t) {
+        let mut report = self.0.lock().unwrap();
+        match report.fds.remove(&from) {
+            Some(metrics) => report.fds.insert(to, metrics),
+            None => report.fds.remove(&to),
+        };
+    }
+}
@@ -0,0 +1,271 @@
+//! Access policies restricting which paths below a preopened directory the guest may operate on.
+use crate::{Error, Result};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// The kind of operation a path is resolved for, as seen by a `PathPolicy`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PathOp {
+    /// `path_open` with read rights, or without any rights to read or write.
+    OpenRead,
+    /// `path_open` with write rights, or with `O_TRUNC`.
+    OpenWrite,
+    /// `path_open` with `O_CREAT`, and `path_create_directory`.
+    Create,
+    /// `path_unlink_file` and `path_remove_directory`.
+    Unlink,
+    /// `path_rename`, checked for both the old and the new path.
+    Rename,
+    /// `path_link`, checked for both the existing and the new path.
+    Link,
+    /// `path_symlink`, checked for the path of the new link.
+    Symlink,
+    /// `path_readlink`.
+    Readlink,
+}
+
+impl PathOp {
+    /// All operations, for rules which should apply regardless of the operation.
+    pub const ALL: &'static [Self] = &[
+        Self::OpenRead,
+        Self::OpenWrite,
+        Self::Create,
+        Self::Unlink,
+        Self::Rename,
+        Self::Link,
+        Self::Symlink,
+        Self::Readlink,
+    ];
+}
+
+/// The error returned to the guest for an operation denied by a `PathPolicy`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Denial {
+    /// `EACCES`, as if the host denied access to the file.
+    AccessDenied,
+    /// `ENOTCAPABLE`, as for paths escaping the preopened directory.
+    NotCapable,
+}
+
+impl Default for Denial {
+    fn default() -> Self {
+        Denial::AccessDenied
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    ops: Vec<PathOp>,
+    pattern: Vec<String>,
+    anchored: bool,
+}
+
+impl Rule {
+    fn new(ops: &[PathOp], pattern: &str) -> Self {
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+        Self {
+            ops: ops.to_vec(),
+            pattern: pattern
+                .split('/')
+                .filter(|component| !component.is_empty() && *component != ".")
+                .map(str::to_owned)
+                .collect(),
+            anchored,
+        }
+    }
+
+    /// Whether the rule covers `path`, given as its components relative to the preopen.
+    ///
+    /// A rule covering a directory covers everything below it, so that a path matches if it, or
+    /// any of its ancestors, matches the pattern.
+    fn matches(&self, op: PathOp, path: &[&str]) -> bool {
+        if !self.ops.contains(&op) {
+            return false;
+        }
+        if self.anchored {
+            (1..=path.len()).any(|len| glob_match(&self.pattern, &path[..len]))
+        } else {
+            path.iter()
+                .any(|component| glob_match(&self.pattern, &[component]))
+        }
+    }
+}
+
+type Filter = dyn Fn(PathOp, &Path) -> bool + Send + Sync;
+
+/// Rules deciding which operations the guest may perform on which paths of a preopened
+/// directory, on top of the paths having to stay below it.
+///
+/// Paths are checked once fully resolved, i.e. after following any symlinks and `..` components,
+/// relative to the preopened directory and without a leading `/`. Directories opened by the guest
+/// inherit the policy of the preopen they were opened from.
+///
+/// An operation is allowed if its path matches one of the `allow` rules for the operation, if
+/// there are any, doesn't match any of the `deny` rules for it, and is accepted by the `filter`
+/// if one was given.
+///
+/// Patterns are globs, where `*` matches any part of a single path component, `?` matches a
+/// single character and a `**` component matches any number of components. Patterns without a
+/// `/` match any single component, such as `*.key`, while patterns with one are matched from the
+/// root of the preopen, such as `data/**/*.csv`. A trailing `/` is ignored, since a pattern
+/// matching a directory always matches everything below it as well.
+#[derive(Clone, Default)]
+pub struct PathPolicy {
+    allow: Vec<Rule>,
+    deny: Vec<Rule>,
+    filter: Option<Arc<Filter>>,
+    denial: Denial,
+}
+
+impl PathPolicy {
+    /// A policy allowing everything, to be restricted with rules.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only allow `ops` on paths matching any of the allowed patterns.
+    pub fn allow(mut self, ops: &[PathOp], pattern: &str) -> Self {
+        self.allow.push(Rule::new(ops, pattern));
+        self
+    }
+
+    /// Deny `ops` on paths matching `pattern`, even if they're allowed otherwise.
+    pub fn deny(mut self, ops: &[PathOp], pattern: &str) -> Self {
+        self.deny.push(Rule::new(ops, pattern));
+        self
+    }
+
+    /// Decide on operations allowed by the rules with a callback, which returns whether the
+    /// operation on the given path may proceed.
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(PathOp, &Path) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// The error denied operations fail with, `Denial::AccessDenied` by default.
+    pub fn denial(mut self, denial: Denial) -> Self {
+        self.denial = denial;
+        self
+    }
+
+    pub(crate) fn check(&self, op: PathOp, path: &Path) -> Result<()> {
+        let components: Vec<&str> = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(component) => component.to_str(),
+                _ => None,
+            })
+            .collect();
+
+        let allowed = (self.allow.iter().all(|rule| !rule.ops.contains(&op))
+            || self.allow.iter().any(|rule| rule.matches(op, &components)))
+            && !self.deny.iter().any(|rule| rule.matches(op, &components))
+            && match &self.filter {
+                Some(filter) => filter(op, path),
+                None => true,
+            };
+        if allowed {
+            return Ok(());
+        }
+
+        log::debug!("PathPolicy denied {:?} of {:?}", op, path);
+        match self.denial {
+            Denial::AccessDenied => Err(Error::EACCES),
+            Denial::NotCapable => Err(Error::ENOTCAPABLE),
+        }
+    }
+}
+
+impl fmt::Debug for PathPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PathPolicy")
+            .field("allow", &self.allow)
+            .field("deny", &self.deny)
+            .field("filter", &self.filter.is_some())
+            .field("denial", &self.denial)
+            .finish()
+    }
+}
+
+/// The policy applying to a directory descriptor, along with the guest path of the preopen the
+/// policy was given for, and the directory's path relative to it.
+#[derive(Clone, Debug)]
+pub(crate) struct PolicyScope {
+    pub(crate) policy: Arc<PathPolicy>,
+    pub(crate) preopen: PathBuf,
+    pub(crate) dir: PathBuf,
+}
+
+impl PolicyScope {
+    pub(crate) fn new(policy: Arc<PathPolicy>, preopen: PathBuf, dir: PathBuf) -> Self {
+        Self {
+            policy,
+            preopen,
+            dir,
+        }
+    }
+
+    /// The scope of the directory at `path` below this one.
+    pub(crate) fn join(&self, path: &Path) -> Self {
+        Self {
+            policy: Arc::clone(&self.policy),
+            preopen: self.preopen.clone(),
+            dir: self.dir.join(path),
+        }
+    }
+
+    pub(crate) fn check(&self, op: PathOp, path: &Path) -> Result<()> {
+        self.policy.check(op, &self.dir.join(path))
+    }
+}
+
+fn glob_match(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| glob_match(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((component, path)) => {
+                component_match(first.as_bytes(), component.as_bytes()) && glob_match(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+fn component_match(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| component_match(rest, &name[skip..])),
+        Some((b'?', rest)) => match utf8_char_len(name) {
+            Some(len) => component_match(rest, &name[len..]),
+            None => false,
+        },
+        Some((c, rest)) => name.first() == Some(c) && component_match(rest, &name[1..]),
+    }
+}
+
+/// Length of the first UTF-8 encoded character of `s`, which is valid UTF-8.
+fn utf8_char_len(s: &[u8]) -> Option<usize> {
+    let first = *s.first()?;
+    let len = if first < 0x80 {
+        1
+    } else if first >= 0xf0 {
+        4
+    } else if first >= 0xe0 {
+        3
+    } else {
+        2
+    };
+    Some(len.min(s.len()))
+}
@@ -0,0 +1,260 @@
+//! Portable snapshots of the state of a `WasiCtx`, which can be restored on another host.
+use crate::capabilities::{Capabilities, Capability, DisabledError};
+use crate::creation::CreationMode;
+use crate::device::DevicePolicy;
+use crate::encoding::PathEncoding;
+use crate::fdentry::{open_host_file, Descriptor, FdEntry};
+use crate::journal::GuestPath;
+use crate::metadata::VirtualMetadata;
+use crate::sys::fdentry_impl::file_path;
+use crate::sys::hostcalls_impl;
+use crate::virtfs::{device_path, DevDir, FsTable, OverlayFs};
+use crate::{host, wasm32, Error, Result};
+use std::io::{Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// The state of a `WasiCtx`, as taken by `WasiCtx::snapshot`, from which an equivalent context
+/// can be built with `WasiCtxBuilder::from_snapshot`.
+///
+/// Host files and directories are recorded by their paths, which have to refer to the same files
+/// when restoring, while in-memory file systems are recorded along with their contents. Path
+/// policies can't be recorded: descriptors subject to one only refer to the preopen it was given
+/// for, and it has to be given again with `WasiCtxBuilder::path_policy`. The other settings of the
+/// builder are recorded, including the paths of the devices allowed by the `DevicePolicy`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    pub args: Vec<String>,
+    /// Environment variables as `(name, value)` pairs.
+    pub env: Vec<(String, String)>,
+    /// The open descriptors, ordered by their numbers.
+    pub fds: Vec<FdSnapshot>,
+    /// The virtual file systems referred to by `FdSource::Virtual`.
+    pub filesystems: Vec<FsSnapshot>,
+    pub disabled: Vec<Capability>,
+    pub disabled_error: DisabledError,
+    /// Whether metrics are collected, see `WasiCtxBuilder::metrics`. Those collected so far aren't
+    /// recorded.
+    pub metrics: bool,
+    /// Whether modifications are journaled, see `WasiCtxBuilder::journal`. The entries recorded so
+    /// far aren't part of the snapshot.
+    pub journal: bool,
+    pub cross_device_rename: bool,
+    pub path_encoding: PathEncoding,
+    pub device_policy: DevicePolicy,
+    pub virtual_metadata: Option<VirtualMetadata>,
+}
+
+/// An open descriptor of a `Snapshot`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FdSnapshot {
+    pub fd: wasm32::__wasi_fd_t,
+    pub filetype: wasm32::__wasi_filetype_t,
+    pub rights_base: wasm32::__wasi_rights_t,
+    pub rights_inheriting: wasm32::__wasi_rights_t,
+    pub fdflags: wasm32::__wasi_fdflags_t,
+    /// The current offset of regular files, and 0 for everything else.
+    pub offset: wasm32::__wasi_filesize_t,
+    /// The guest path of preopened directories.
+    pub preopen_path: Option<PathBuf>,
+    pub policy: Option<PolicySnapshot>,
+    pub creation_mode: Option<CreationMode>,
+    /// The device number reported for the descriptor under `VirtualMetadata`.
+    pub virtual_dev: wasm32::__wasi_device_t,
+    /// Whether `fd_readdir` returns the entries of the directory in sorted order.
+    pub sorted_readdir: bool,
+    /// The path modifications through the descriptor are journaled under, see `JournalEntry`.
+    pub origin: Option<GuestPath>,
+    pub source: FdSource,
+}
+
+/// What a descriptor of a `Snapshot` refers to.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FdSource {
+    Stdin,
+    Stdout,
+    Stderr,
+    /// A host file or directory, which is opened again at its path.
+    Host(PathBuf),
+    /// An entry of the virtual file system `Snapshot::filesystems[fs]`, at `path` relative to its
+    /// root.
+    Virtual {
+        fs: usize,
+        path: PathBuf,
+    },
+    /// A directory added with `WasiCtxBuilder::preopened_devices`, or a device opened from one,
+    /// at `path` relative to it.
+    Device(PathBuf),
+}
+
+/// The `PathPolicy` a directory descriptor is subject to.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PolicySnapshot {
+    /// The guest path of the preopen the policy was given for.
+    pub preopen: PathBuf,
+    /// The path of the directory relative to the preopen.
+    pub dir: PathBuf,
+}
+
+/// A virtual file system, as created by `WasiCtxBuilder::preopened_overlay` or
+/// `WasiCtxBuilder::preopened_archive`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FsSnapshot {
+    pub lower: LayerSnapshot,
+    /// The layer receiving modifications, if the file system isn't read-only.
+    pub upper: Option<LayerSnapshot>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LayerSnapshot {
+    /// A host directory.
+    Host { root: PathBuf, read_only: bool },
+    /// A tar or zip archive on the host.
+    #[cfg(feature = "archive")]
+    Archive(PathBuf),
+    /// An in-memory directory tree, with all of its entries.
+    Memory(Vec<MemoryEntry>),
+}
+
+/// An entry of an in-memory directory tree, whose parent directory comes before it. The root has
+/// an empty path.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryEntry {
+    pub path: PathBuf,
+    pub node: MemoryNode,
+    pub atim: wasm32::__wasi_timestamp_t,
+    pub mtim: wasm32::__wasi_timestamp_t,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemoryNode {
+    File(Vec<u8>),
+    Directory,
+    Symlink(String),
+    /// A hard link to the file at the given path, which comes before it.
+    Link(PathBuf),
+}
+
+/// Records the descriptor `fd`, whose virtual file system, if any, has to be in `filesystems`.
+pub(crate) fn snapshot_fd(
+    fd: host::__wasi_fd_t,
+    fe: &mut FdEntry,
+    filesystems: &FsTable,
+) -> Result<FdSnapshot> {
+    let is_file = fe.file_type == host::__WASI_FILETYPE_REGULAR_FILE;
+    let (source, fdflags, offset) = match fe.as_descriptor_mut(0, 0)? {
+        Descriptor::Stdin => (FdSource::Stdin, 0, 0),
+        Descriptor::Stdout => (FdSource::Stdout, 0, 0),
+        Descriptor::Stderr => (FdSource::Stderr, 0, 0),
+        Descriptor::OsFile(file) => {
+            let offset = if is_file {
+                file.seek(SeekFrom::Current(0))?
+            } else {
+                0
+            };
+            (
+                FdSource::Host(file_path(file)?),
+                hostcalls_impl::fd_fdstat_get(file)?,
+                offset,
+            )
+        }
+        Descriptor::VirtualFile(file) => match device_path(file.as_ref()) {
+            Some(path) => (FdSource::Device(path), file.fdstat_get(), 0),
+            None => {
+                let (fs, path) = filesystems.locate(file.as_ref()).ok_or(Error::ENOTSUP)?;
+                let offset = if is_file {
+                    file.seek(SeekFrom::Current(0))?
+                } else {
+                    0
+                };
+                (FdSource::Virtual { fs, path }, file.fdstat_get(), offset)
+            }
+        },
+    };
+
+    Ok(FdSnapshot {
+        fd,
+        filetype: fe.file_type,
+        rights_base: fe.rights_base,
+        rights_inheriting: fe.rights_inheriting,
+        fdflags,
+        offset,
+        preopen_path: fe.preopen_path.clone(),
+        policy: fe.policy.as_ref().map(|scope| PolicySnapshot {
+            preopen: scope.preopen.clone(),
+            dir: scope.dir.clone(),
+        }),
+        creation_mode: fe.creation_mode,
+        virtual_dev: fe.virtual_dev,
+        sorted_readdir: fe.sorted_readdir.is_some(),
+        origin: fe.origin.clone(),
+        source,
+    })
+}
+
+/// Opens the descriptor recorded by `snapshot` again, with at most the recorded rights.
+///
+/// Fails with `EBADF` if the file it refers to isn't of the recorded type anymore.
+///
+/// Directories of devices are created anew, depending on the `capabilities` of the context.
+pub(crate) fn restore_fd(
+    snapshot: &FdSnapshot,
+    filesystems: &[Arc<OverlayFs>],
+    capabilities: Capabilities,
+) -> Result<FdEntry> {
+    let read = snapshot.rights_base & host::__WASI_RIGHT_FD_READ != 0;
+    let write = snapshot.rights_base & host::__WASI_RIGHT_FD_WRITE != 0;
+    let is_file = snapshot.filetype == host::__WASI_FILETYPE_REGULAR_FILE;
+
+    let mut fe = match &snapshot.source {
+        FdSource::Stdin => FdEntry::duplicate_stdin()?,
+        FdSource::Stdout => FdEntry::duplicate_stdout()?,
+        FdSource::Stderr => FdEntry::duplicate_stderr()?,
+        FdSource::Host(path) if snapshot.filetype == host::__WASI_FILETYPE_DIRECTORY => {
+            FdEntry::from(crate::preopen_dir(path)?)?
+        }
+        FdSource::Host(path) => FdEntry::from(open_host_file(
+            path,
+            false,
+            snapshot.rights_base,
+            snapshot.fdflags,
+            snapshot.offset,
+        )?)?,
+        FdSource::Virtual { fs, path } => {
+            let fs = filesystems.get(*fs).ok_or(Error::EINVAL)?;
+            let mut descriptor = OverlayFs::reopen(fs, path, read, write, snapshot.fdflags)?;
+            if is_file {
+                if let Descriptor::VirtualFile(file) = &mut descriptor {
+                    file.seek(SeekFrom::Start(snapshot.offset))?;
+                }
+            }
+            FdEntry::from_descriptor(descriptor)?
+        }
+        FdSource::Device(path) => {
+            let random = capabilities.check(Capability::Random).is_ok();
+            FdEntry::from_descriptor(DevDir::new(random).reopen(path, snapshot.fdflags)?)?
+        }
+    };
+
+    if fe.file_type != snapshot.filetype {
+        return Err(Error::EBADF);
+    }
+    fe.rights_base &= snapshot.rights_base;
+    fe.rights_inheriting &= snapshot.rights_inheriting;
+    fe.preopen_path = snapshot.preopen_path.clone();
+    fe.creation_mode = snapshot.creation_mode;
+    fe.virtual_dev = snapshot.virtual_dev;
+    if snapshot.sorted_readdir {
+        fe.sorted_readdir = Some(Vec::new());
+    }
+    fe.origin = snapshot.origin.clone();
+    Ok(fe)
+}
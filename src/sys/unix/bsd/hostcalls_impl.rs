@@ -1,4 +1,5 @@
 use super::osfile::OsFile;
+use crate::encoding::PathEncoding;
 use crate::hostcalls_impl::PathGet;
 use crate::sys::host_impl;
 use crate::sys::unix::str_to_cstring;
@@ -33,7 +34,7 @@ pub(crate) fn path_unlink_file(resolved: PathGet) -> Result<()> {
             if e == errno::Errno::EPERM {
                 if let Ok(stat) = fstatat(
                     resolved.dirfd().as_raw_fd(),
-                    resolved.path(),
+                    &*host_impl::path_to_host(resolved.path())?,
                     AtFlags::AT_SYMLINK_NOFOLLOW,
                 ) {
                     if SFlag::from_bits_truncate(stat.st_mode).contains(SFlag::S_IFDIR) {
@@ -76,7 +77,7 @@ pub(crate) fn path_symlink(old_path: &str, resolved: PathGet) -> Result<()> {
                 let new_path = resolved.path().trim_end_matches('/');
                 if let Ok(_) = fstatat(
                     resolved.dirfd().as_raw_fd(),
-                    new_path,
+                    &*host_impl::path_to_host(new_path)?,
                     AtFlags::AT_SYMLINK_NOFOLLOW,
                 ) {
                     Err(Error::EEXIST)
@@ -91,7 +92,7 @@ pub(crate) fn path_symlink(old_path: &str, resolved: PathGet) -> Result<()> {
     }
 }
 
-pub(crate) fn path_rename(resolved_old: PathGet, resolved_new: PathGet) -> Result<()> {
+pub(crate) fn path_rename(resolved_old: &PathGet, resolved_new: &PathGet) -> Result<()> {
     use nix::{errno::Errno, fcntl::AtFlags, libc::renameat, sys::stat::fstatat};
     let old_path_cstr = str_to_cstring(resolved_old.path())?;
     let new_path_cstr = str_to_cstring(resolved_new.path())?;
@@ -119,7 +120,7 @@ pub(crate) fn path_rename(resolved_old: PathGet, resolved_new: PathGet) -> Resul
                 // check if the source path exists
                 if let Ok(_) = fstatat(
                     resolved_old.dirfd().as_raw_fd(),
-                    resolved_old.path(),
+                    &*host_impl::path_to_host(resolved_old.path())?,
                     AtFlags::AT_SYMLINK_NOFOLLOW,
                 ) {
                     // check if destination contains a trailing slash
@@ -143,6 +144,7 @@ pub(crate) fn fd_readdir(
     os_file: &mut OsFile,
     host_buf: &mut [u8],
     cookie: host::__wasi_dircookie_t,
+    encoding: PathEncoding,
 ) -> Result<usize> {
     use crate::sys::unix::bsd::osfile::DirStream;
     use libc::{fdopendir, readdir, rewinddir, seekdir, telldir};
@@ -200,6 +202,11 @@ pub(crate) fn fd_readdir(
         log::debug!("fd_readdir entry = {:?}", entry);
 
         let name_len = entry.d_namlen.try_into()?;
+        let name_ptr = unsafe { (*host_entry).d_name.as_ptr() };
+        let name = unsafe { std::slice::from_raw_parts(name_ptr as *const u8, name_len) };
+        if encoding.check_name(name, false).is_err() {
+            continue;
+        }
         let required_space = std::mem::size_of_val(&entry) + name_len;
         if required_space > left {
             break;
@@ -207,14 +214,13 @@ pub(crate) fn fd_readdir(
         unsafe {
             let ptr = host_buf_ptr.offset(host_buf_offset.try_into()?) as *mut c_void
                 as *mut host::__wasi_dirent_t;
-            *ptr = entry;
+            ptr.write_unaligned(entry);
         }
         host_buf_offset += std::mem::size_of_val(&entry);
-        let name_ptr = unsafe { *host_entry }.d_name.as_ptr();
         unsafe {
             std::ptr::copy_nonoverlapping(
-                name_ptr as *const _,
-                host_buf_ptr.offset(host_buf_offset.try_into()?) as *mut _,
+                name.as_ptr(),
+                host_buf_ptr.offset(host_buf_offset.try_into()?),
                 name_len,
             )
         };
@@ -3,11 +3,33 @@ pub(crate) mod osfile;
 
 pub(crate) mod fdentry_impl {
     use crate::{sys::host_impl, Result};
+    use std::fs::File;
     use std::os::unix::prelude::AsRawFd;
+    use std::path::PathBuf;
+
+    /// The path `file` was opened at, as last known by the kernel.
+    pub(crate) fn fd_path(file: &File) -> Result<PathBuf> {
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "macos", target_os = "ios"))] {
+                use std::ffi::{CStr, OsStr};
+                use std::os::unix::ffi::OsStrExt;
+
+                let mut buf = vec![0 as libc::c_char; libc::PATH_MAX as usize];
+                if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETPATH, buf.as_mut_ptr()) } == -1 {
+                    return Err(host_impl::errno_from_nix(nix::errno::Errno::last()));
+                }
+                let path = unsafe { CStr::from_ptr(buf.as_ptr()) };
+                Ok(PathBuf::from(OsStr::from_bytes(path.to_bytes())))
+            } else {
+                let _ = file;
+                Err(crate::Error::ENOTSUP)
+            }
+        }
+    }
 
     pub(crate) unsafe fn isatty(fd: &impl AsRawFd) -> Result<bool> {
         let res = libc::isatty(fd.as_raw_fd());
-        if res == 0 {
+        if res == 1 {
             Ok(true)
         } else {
             match nix::errno::Errno::last() {
@@ -22,6 +22,13 @@ pub(crate) struct OsFile {
     pub(crate) dir_stream: Option<Mutex<DirStream>>,
 }
 
+impl OsFile {
+    /// Unwrap the file, closing the directory stream if one was opened.
+    pub(crate) fn into_file(self) -> fs::File {
+        self.file
+    }
+}
+
 impl From<fs::File> for OsFile {
     fn from(file: fs::File) -> Self {
         Self {
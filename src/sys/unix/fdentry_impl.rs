@@ -1,7 +1,9 @@
 use crate::fdentry::Descriptor;
 use crate::{host, Error, Result};
+use std::fs::File;
 use std::io;
-use std::os::unix::prelude::{AsRawFd, FileTypeExt, FromRawFd, RawFd};
+use std::os::unix::prelude::{AsRawFd, FileTypeExt, FromRawFd, MetadataExt, RawFd};
+use std::path::PathBuf;
 
 cfg_if::cfg_if! {
     if #[cfg(target_os = "linux")] {
@@ -27,10 +29,51 @@ impl AsRawFd for Descriptor {
             Self::Stdin => io::stdin().as_raw_fd(),
             Self::Stdout => io::stdout().as_raw_fd(),
             Self::Stderr => io::stderr().as_raw_fd(),
+            Self::VirtualFile(_) => {
+                unreachable!("virtual files aren't backed by a host descriptor")
+            }
+        }
+    }
+}
+
+/// The path of the host `file`, which it can be opened again at.
+///
+/// Fails with `ENOTSUP` if the path doesn't refer to `file` anymore, e.g. because it was removed,
+/// or if there's no path at all, as for pipes.
+pub(crate) fn file_path(file: &File) -> Result<PathBuf> {
+    let path = fd_path(file)?;
+    let metadata = file.metadata()?;
+    match std::fs::metadata(&path) {
+        Ok(ref found) if found.dev() == metadata.dev() && found.ino() == metadata.ino() => Ok(path),
+        _ => Err(Error::ENOTSUP),
+    }
+}
+
+/// A path opening `file` again, as a new open file description with its own offset.
+///
+/// On Linux, this is the entry of `file` in `/proc/self/fd`, which refers to it even if it was
+/// renamed or removed. Elsewhere, this is `file_path`.
+pub(crate) fn reopen_path(file: &File) -> Result<PathBuf> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            Ok(PathBuf::from(format!("/proc/self/fd/{}", file.as_raw_fd())))
+        } else {
+            file_path(file)
         }
     }
 }
 
+/// The device number of `file`, if it's a block or character device.
+pub(crate) fn device_id(file: &File) -> Result<Option<u64>> {
+    let metadata = file.metadata()?;
+    let file_type = metadata.file_type();
+    if file_type.is_block_device() || file_type.is_char_device() {
+        Ok(Some(metadata.rdev()))
+    } else {
+        Ok(None)
+    }
+}
+
 /// This function is unsafe because it operates on a raw file descriptor.
 pub(crate) unsafe fn determine_type_and_access_rights<Fd: AsRawFd>(
     fd: &Fd,
@@ -120,10 +163,11 @@ pub(crate) unsafe fn determine_type_rights<Fd: AsRawFd>(
             }
         } else if ft.is_fifo() {
             log::debug!("Host fd {:?} is a fifo", fd.as_raw_fd());
+            // WASI has no file type for pipes, which are streams like sockets
             (
-                host::__WASI_FILETYPE_UNKNOWN,
-                host::RIGHTS_REGULAR_FILE_BASE,
-                host::RIGHTS_REGULAR_FILE_INHERITING,
+                host::__WASI_FILETYPE_SOCKET_STREAM,
+                host::RIGHTS_PIPE_BASE,
+                host::RIGHTS_PIPE_INHERITING,
             )
         } else {
             log::debug!("Host fd {:?} is unknown", fd.as_raw_fd());
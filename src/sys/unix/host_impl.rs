@@ -3,10 +3,11 @@
 #![allow(non_snake_case)]
 #![allow(dead_code)]
 use crate::hostcalls_impl::FileType;
-use crate::{host, Error, Result};
+use crate::{encoding, host, Error, Result};
 use log::warn;
-use std::ffi::OsStr;
-use std::os::unix::prelude::OsStrExt;
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::prelude::{OsStrExt, OsStringExt};
 
 cfg_if::cfg_if! {
     if #[cfg(target_os = "linux")] {
@@ -99,13 +100,146 @@ pub(crate) fn errno_from_nix(errno: nix::errno::Errno) -> Error {
         nix::errno::Errno::ECANCELED => Error::ECANCELED,
         nix::errno::Errno::EOWNERDEAD => Error::EOWNERDEAD,
         nix::errno::Errno::ENOTRECOVERABLE => Error::ENOTRECOVERABLE,
-        other => {
+        other => errno_from_other(other as i32).unwrap_or_else(|| {
             warn!("Unknown error from nix: {}", other);
-            Error::ENOSYS
-        }
+            Error::EIO
+        }),
+    }
+}
+
+/// Host errnos without an exact WASI counterpart, which are mapped to the closest one. These are
+/// compared by value, since some of them are aliases of each other on some hosts.
+fn errno_from_other(code: i32) -> Option<Error> {
+    let error = if code == libc::ENOTSUP || code == libc::EOPNOTSUPP {
+        Error::ENOTSUP
+    } else if code == libc::EWOULDBLOCK {
+        Error::EAGAIN
+    } else if code == libc::ESHUTDOWN {
+        Error::EPIPE
+    } else if code == libc::EHOSTDOWN {
+        Error::EHOSTUNREACH
+    } else if code == libc::EPFNOSUPPORT {
+        Error::EAFNOSUPPORT
+    } else if code == libc::ESOCKTNOSUPPORT {
+        Error::EPROTONOSUPPORT
+    } else if code == libc::ENOTBLK {
+        Error::ENOTSUP
+    } else if code == libc::ETOOMANYREFS || code == libc::EUSERS {
+        Error::EMFILE
+    } else {
+        return errno_from_os_specific(code);
+    };
+    Some(error)
+}
+
+#[cfg(target_os = "linux")]
+fn errno_from_os_specific(code: i32) -> Option<Error> {
+    let error = match code {
+        libc::EBADFD => Error::EBADF,
+        libc::ETIME => Error::ETIMEDOUT,
+        libc::ENOMEDIUM | libc::EMEDIUMTYPE => Error::ENODEV,
+        libc::ENONET => Error::ENETUNREACH,
+        libc::ENOTUNIQ => Error::EEXIST,
+        libc::EKEYEXPIRED | libc::EKEYREJECTED | libc::EKEYREVOKED | libc::ENOKEY => Error::EACCES,
+        libc::ERFKILL => Error::ENETDOWN,
+        _ => return None,
+    };
+    Some(error)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn errno_from_os_specific(code: i32) -> Option<Error> {
+    if code == libc::EAUTH || code == libc::ENEEDAUTH {
+        Some(Error::EACCES)
+    } else {
+        None
     }
 }
 
+/// The host errno corresponding to a WASI errno, if there's one. This is the inverse of
+/// `errno_from_nix` for all errnos except `__WASI_ENOTCAPABLE`, which has no counterpart.
+pub(crate) fn errno_to_host(errno: host::__wasi_errno_t) -> Option<i32> {
+    let code = match errno {
+        host::__WASI_EIO => libc::EIO,
+        host::__WASI_EPERM => libc::EPERM,
+        host::__WASI_EINVAL => libc::EINVAL,
+        host::__WASI_EPIPE => libc::EPIPE,
+        host::__WASI_ENOTCONN => libc::ENOTCONN,
+        host::__WASI_E2BIG => libc::E2BIG,
+        host::__WASI_EACCES => libc::EACCES,
+        host::__WASI_EADDRINUSE => libc::EADDRINUSE,
+        host::__WASI_EADDRNOTAVAIL => libc::EADDRNOTAVAIL,
+        host::__WASI_EAFNOSUPPORT => libc::EAFNOSUPPORT,
+        host::__WASI_EAGAIN => libc::EAGAIN,
+        host::__WASI_EALREADY => libc::EALREADY,
+        host::__WASI_EBADF => libc::EBADF,
+        host::__WASI_EBADMSG => libc::EBADMSG,
+        host::__WASI_EBUSY => libc::EBUSY,
+        host::__WASI_ECANCELED => libc::ECANCELED,
+        host::__WASI_ECHILD => libc::ECHILD,
+        host::__WASI_ECONNABORTED => libc::ECONNABORTED,
+        host::__WASI_ECONNREFUSED => libc::ECONNREFUSED,
+        host::__WASI_ECONNRESET => libc::ECONNRESET,
+        host::__WASI_EDEADLK => libc::EDEADLK,
+        host::__WASI_EDESTADDRREQ => libc::EDESTADDRREQ,
+        host::__WASI_EDOM => libc::EDOM,
+        host::__WASI_EDQUOT => libc::EDQUOT,
+        host::__WASI_EEXIST => libc::EEXIST,
+        host::__WASI_EFAULT => libc::EFAULT,
+        host::__WASI_EFBIG => libc::EFBIG,
+        host::__WASI_EHOSTUNREACH => libc::EHOSTUNREACH,
+        host::__WASI_EIDRM => libc::EIDRM,
+        host::__WASI_EILSEQ => libc::EILSEQ,
+        host::__WASI_EINPROGRESS => libc::EINPROGRESS,
+        host::__WASI_EINTR => libc::EINTR,
+        host::__WASI_EISCONN => libc::EISCONN,
+        host::__WASI_EISDIR => libc::EISDIR,
+        host::__WASI_ELOOP => libc::ELOOP,
+        host::__WASI_EMFILE => libc::EMFILE,
+        host::__WASI_EMLINK => libc::EMLINK,
+        host::__WASI_EMSGSIZE => libc::EMSGSIZE,
+        host::__WASI_EMULTIHOP => libc::EMULTIHOP,
+        host::__WASI_ENAMETOOLONG => libc::ENAMETOOLONG,
+        host::__WASI_ENETDOWN => libc::ENETDOWN,
+        host::__WASI_ENETRESET => libc::ENETRESET,
+        host::__WASI_ENETUNREACH => libc::ENETUNREACH,
+        host::__WASI_ENFILE => libc::ENFILE,
+        host::__WASI_ENOBUFS => libc::ENOBUFS,
+        host::__WASI_ENODEV => libc::ENODEV,
+        host::__WASI_ENOENT => libc::ENOENT,
+        host::__WASI_ENOEXEC => libc::ENOEXEC,
+        host::__WASI_ENOLCK => libc::ENOLCK,
+        host::__WASI_ENOLINK => libc::ENOLINK,
+        host::__WASI_ENOMEM => libc::ENOMEM,
+        host::__WASI_ENOMSG => libc::ENOMSG,
+        host::__WASI_ENOPROTOOPT => libc::ENOPROTOOPT,
+        host::__WASI_ENOSPC => libc::ENOSPC,
+        host::__WASI_ENOSYS => libc::ENOSYS,
+        host::__WASI_ENOTDIR => libc::ENOTDIR,
+        host::__WASI_ENOTEMPTY => libc::ENOTEMPTY,
+        host::__WASI_ENOTRECOVERABLE => libc::ENOTRECOVERABLE,
+        host::__WASI_ENOTSOCK => libc::ENOTSOCK,
+        host::__WASI_ENOTSUP => libc::ENOTSUP,
+        host::__WASI_ENOTTY => libc::ENOTTY,
+        host::__WASI_ENXIO => libc::ENXIO,
+        host::__WASI_EOVERFLOW => libc::EOVERFLOW,
+        host::__WASI_EOWNERDEAD => libc::EOWNERDEAD,
+        host::__WASI_EPROTO => libc::EPROTO,
+        host::__WASI_EPROTONOSUPPORT => libc::EPROTONOSUPPORT,
+        host::__WASI_EPROTOTYPE => libc::EPROTOTYPE,
+        host::__WASI_ERANGE => libc::ERANGE,
+        host::__WASI_EROFS => libc::EROFS,
+        host::__WASI_ESPIPE => libc::ESPIPE,
+        host::__WASI_ESRCH => libc::ESRCH,
+        host::__WASI_ESTALE => libc::ESTALE,
+        host::__WASI_ETIMEDOUT => libc::ETIMEDOUT,
+        host::__WASI_ETXTBSY => libc::ETXTBSY,
+        host::__WASI_EXDEV => libc::EXDEV,
+        _ => return None,
+    };
+    Some(code)
+}
+
 pub(crate) fn nix_from_fdflags(fdflags: host::__wasi_fdflags_t) -> nix::fcntl::OFlag {
     use nix::fcntl::OFlag;
     let mut nix_flags = OFlag::empty();
@@ -180,6 +314,9 @@ pub(crate) fn filetype_from_nix(sflags: nix::sys::stat::SFlag) -> FileType {
         FileType::RegularFile
     } else if sflags.contains(SFlag::S_IFLNK) {
         FileType::Symlink
+    } else if sflags.contains(SFlag::S_IFIFO) {
+        // WASI has no file type for pipes, which are streams like sockets
+        FileType::SocketStream
     } else {
         FileType::Unknown
     }
@@ -218,7 +355,7 @@ pub(crate) fn dirent_filetype_from_host(
     host_entry: &nix::libc::dirent,
 ) -> Result<host::__wasi_filetype_t> {
     match host_entry.d_type {
-        libc::DT_FIFO => Ok(host::__WASI_FILETYPE_UNKNOWN),
+        libc::DT_FIFO => Ok(host::__WASI_FILETYPE_SOCKET_STREAM),
         libc::DT_CHR => Ok(host::__WASI_FILETYPE_CHARACTER_DEVICE),
         libc::DT_DIR => Ok(host::__WASI_FILETYPE_DIRECTORY),
         libc::DT_BLK => Ok(host::__WASI_FILETYPE_BLOCK_DEVICE),
@@ -237,10 +374,16 @@ pub(crate) fn dirent_filetype_from_host(
     }
 }
 
-/// Creates owned WASI path from OS string.
-///
-/// NB WASI spec requires OS string to be valid UTF-8. Otherwise,
-/// `__WASI_EILSEQ` error is returned.
+/// Creates owned WASI path from OS string, escaping bytes which aren't valid UTF-8 as described
+/// in `crate::encoding`.
 pub(crate) fn path_from_host<S: AsRef<OsStr>>(s: S) -> Result<String> {
-    host::path_from_slice(s.as_ref().as_bytes()).map(String::from)
+    Ok(encoding::path_from_bytes(s.as_ref().as_bytes()).into_owned())
+}
+
+/// Converts a WASI path back to the OS string it was created from by `path_from_host`.
+pub(crate) fn path_to_host(path: &str) -> Result<Cow<'_, OsStr>> {
+    Ok(match encoding::path_to_bytes(path) {
+        Cow::Borrowed(bytes) => Cow::Borrowed(OsStr::from_bytes(bytes)),
+        Cow::Owned(bytes) => Cow::Owned(OsString::from_vec(bytes)),
+    })
 }
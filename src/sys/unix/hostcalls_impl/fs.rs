@@ -8,9 +8,12 @@ use crate::sys::unix::str_to_cstring;
 use crate::{host, Error, Result};
 use nix::libc;
 use std::convert::TryInto;
+use std::ffi::{OsStr, OsString};
 use std::fs::{File, Metadata};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::FileExt;
-use std::os::unix::prelude::{AsRawFd, FromRawFd};
+use std::os::unix::prelude::{AsRawFd, FromRawFd, RawFd};
 
 cfg_if::cfg_if! {
     if #[cfg(target_os = "linux")] {
@@ -57,13 +60,44 @@ pub(crate) fn fd_fdstat_set_flags(fd: &File, fdflags: host::__wasi_fdflags_t) ->
 }
 
 pub(crate) fn path_create_directory(resolved: PathGet) -> Result<()> {
+    use nix::fcntl::{openat, OFlag};
     use nix::libc::mkdirat;
+    use nix::sys::stat::Mode;
     let path_cstr = str_to_cstring(resolved.path())?;
+    let creation_mode = resolved.creation_mode();
+    let mode = creation_mode.map_or(0o777, |mode| mode.dir_mode & 0o777);
     // nix doesn't expose mkdirat() yet
-    match unsafe { mkdirat(resolved.dirfd().as_raw_fd(), path_cstr.as_ptr(), 0o777) } {
-        0 => Ok(()),
-        _ => Err(host_impl::errno_from_nix(nix::errno::Errno::last())),
+    if unsafe { mkdirat(resolved.dirfd().as_raw_fd(), path_cstr.as_ptr(), mode) } != 0 {
+        return Err(host_impl::errno_from_nix(nix::errno::Errno::last()));
+    }
+
+    if let Some(creation_mode) = creation_mode {
+        // the umask and the setgid bit of the parent may have changed the mode
+        let fd = openat(
+            resolved.dirfd().as_raw_fd(),
+            &*host_impl::path_to_host(resolved.path())?,
+            OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW,
+            Mode::empty(),
+        )
+        .map_err(|e| host_impl::errno_from_nix(e.as_errno().unwrap()))?;
+        let dir = unsafe { File::from_raw_fd(fd) };
+        set_creation_mode(&dir, creation_mode.dir_mode, creation_mode.group)?;
+    }
+    Ok(())
+}
+
+/// Gives the newly created `file` exactly the permission bits `mode` and, if any, the group
+/// `group`.
+fn set_creation_mode(file: &File, mode: u32, group: Option<u32>) -> Result<()> {
+    use nix::sys::stat::{fchmod, Mode};
+    // changing the group may clear the setgid bit, so it's done first
+    if let Some(gid) = group {
+        if unsafe { libc::fchown(file.as_raw_fd(), !0, gid) } != 0 {
+            return Err(host_impl::errno_from_nix(nix::errno::Errno::last()));
+        }
     }
+    fchmod(file.as_raw_fd(), Mode::from_bits_truncate(mode))
+        .map_err(|e| host_impl::errno_from_nix(e.as_errno().unwrap()))
 }
 
 pub(crate) fn path_link(resolved_old: PathGet, resolved_new: PathGet) -> Result<()> {
@@ -89,6 +123,54 @@ pub(crate) fn path_link(resolved_old: PathGet, resolved_new: PathGet) -> Result<
     }
 }
 
+/// Whether there's a file, or a symbolic link, at the path.
+pub(crate) fn path_exists(resolved: &PathGet) -> Result<bool> {
+    use nix::errno::Errno;
+    use nix::fcntl::AtFlags;
+    use nix::sys::stat::fstatat;
+
+    match fstatat(
+        resolved.dirfd().as_raw_fd(),
+        &*host_impl::path_to_host(resolved.path())?,
+        AtFlags::AT_SYMLINK_NOFOLLOW,
+    ) {
+        Ok(_) => Ok(true),
+        Err(err) => match err.as_errno() {
+            Some(Errno::ENOENT) => Ok(false),
+            errno => Err(host_impl::errno_from_nix(errno.unwrap_or(Errno::EIO))),
+        },
+    }
+}
+
+/// The device number of the block or character device at the path, without opening it, or `None`
+/// if there's no device there.
+pub(crate) fn path_device_id(resolved: &PathGet) -> Result<Option<u64>> {
+    use nix::errno::Errno;
+    use nix::fcntl::AtFlags;
+    use nix::sys::stat::{fstatat, SFlag};
+    use std::convert::TryFrom;
+
+    match fstatat(
+        resolved.dirfd().as_raw_fd(),
+        &*host_impl::path_to_host(resolved.path())?,
+        AtFlags::AT_SYMLINK_NOFOLLOW,
+    ) {
+        Ok(stat) => {
+            let file_type = SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT;
+            if file_type == SFlag::S_IFBLK || file_type == SFlag::S_IFCHR {
+                Ok(Some(host::__wasi_device_t::try_from(stat.st_rdev)?))
+            } else {
+                Ok(None)
+            }
+        }
+        Err(err) => match err.as_errno() {
+            // `path_open` may create a regular file there
+            Some(Errno::ENOENT) => Ok(None),
+            errno => Err(host_impl::errno_from_nix(errno.unwrap_or(Errno::EIO))),
+        },
+    }
+}
+
 pub(crate) fn path_open(
     resolved: PathGet,
     read: bool,
@@ -124,12 +206,39 @@ pub(crate) fn path_open(
     log::debug!("path_open resolved = {:?}", resolved);
     log::debug!("path_open oflags = {:?}", nix_all_oflags);
 
-    let new_fd = match openat(
-        resolved.dirfd().as_raw_fd(),
-        resolved.path(),
-        nix_all_oflags,
-        Mode::from_bits_truncate(0o666),
-    ) {
+    // With a creation mode, the file must be set up if it's created by this call, which can
+    // only be told by creating it exclusively.
+    let creation_mode = resolved
+        .creation_mode()
+        .filter(|_| nix_all_oflags.contains(OFlag::O_CREAT));
+    let mode = creation_mode.map_or(0o666, |mode| mode.file_mode);
+    let mut created = creation_mode.is_some() && nix_all_oflags.contains(OFlag::O_EXCL);
+    let exclusive = if creation_mode.is_some() && !created {
+        // if the file exists, or anything else goes wrong, the regular open below takes over
+        openat(
+            resolved.dirfd().as_raw_fd(),
+            &*host_impl::path_to_host(resolved.path())?,
+            nix_all_oflags | OFlag::O_EXCL,
+            Mode::from_bits_truncate(mode),
+        )
+        .ok()
+    } else {
+        None
+    };
+    let opened = match exclusive {
+        Some(fd) => {
+            created = true;
+            Ok(fd)
+        }
+        None => openat(
+            resolved.dirfd().as_raw_fd(),
+            &*host_impl::path_to_host(resolved.path())?,
+            nix_all_oflags,
+            Mode::from_bits_truncate(mode),
+        ),
+    };
+
+    let new_fd = match opened {
         Ok(fd) => fd,
         Err(e) => {
             match e.as_errno() {
@@ -137,7 +246,7 @@ pub(crate) fn path_open(
                 Some(Errno::ENXIO) => {
                     if let Ok(stat) = fstatat(
                         resolved.dirfd().as_raw_fd(),
-                        resolved.path(),
+                        &*host_impl::path_to_host(resolved.path())?,
                         AtFlags::AT_SYMLINK_NOFOLLOW,
                     ) {
                         if SFlag::from_bits_truncate(stat.st_mode).contains(SFlag::S_IFSOCK) {
@@ -156,7 +265,7 @@ pub(crate) fn path_open(
                 {
                     if let Ok(stat) = fstatat(
                         resolved.dirfd().as_raw_fd(),
-                        resolved.path(),
+                        &*host_impl::path_to_host(resolved.path())?,
                         AtFlags::AT_SYMLINK_NOFOLLOW,
                     ) {
                         if SFlag::from_bits_truncate(stat.st_mode).contains(SFlag::S_IFLNK) {
@@ -179,7 +288,11 @@ pub(crate) fn path_open(
     log::debug!("path_open (host) new_fd = {:?}", new_fd);
 
     // Determine the type of the new file descriptor and which rights contradict with this type
-    Ok(unsafe { File::from_raw_fd(new_fd) })
+    let file = unsafe { File::from_raw_fd(new_fd) };
+    if let Some(creation_mode) = creation_mode.filter(|_| created) {
+        set_creation_mode(&file, creation_mode.file_mode, creation_mode.group)?;
+    }
+    Ok(file)
 }
 
 pub(crate) fn path_readlink(resolved: PathGet, buf: &mut [u8]) -> Result<usize> {
@@ -269,8 +382,12 @@ pub(crate) fn path_filestat_get(
         _ => AtFlags::AT_SYMLINK_NOFOLLOW,
     };
 
-    let filestat = fstatat(resolved.dirfd().as_raw_fd(), resolved.path(), atflags)
-        .map_err(|err| host_impl::errno_from_nix(err.as_errno().unwrap()))?;
+    let filestat = fstatat(
+        resolved.dirfd().as_raw_fd(),
+        &*host_impl::path_to_host(resolved.path())?,
+        atflags,
+    )
+    .map_err(|err| host_impl::errno_from_nix(err.as_errno().unwrap()))?;
     host_impl::filestat_from_nix(filestat)
 }
 
@@ -334,7 +451,14 @@ pub(crate) fn path_filestat_set_times(
     };
 
     let fd = resolved.dirfd().as_raw_fd().into();
-    utimensat(fd, resolved.path(), &atim, &mtim, atflags).map_err(Into::into)
+    utimensat(
+        fd,
+        &*host_impl::path_to_host(resolved.path())?,
+        &atim,
+        &mtim,
+        atflags,
+    )
+    .map_err(Into::into)
 }
 
 pub(crate) fn path_remove_directory(resolved: PathGet) -> Result<()> {
@@ -355,3 +479,151 @@ pub(crate) fn path_remove_directory(resolved: PathGet) -> Result<()> {
         _ => Err(host_impl::errno_from_nix(errno::Errno::last())),
     }
 }
+
+/// Moves the file, symbolic link or directory tree at `resolved_old` to `resolved_new` by copying
+/// it and removing the original, for when renaming it failed with `EXDEV`.
+///
+/// The copy is made under a temporary name next to `resolved_new` and then renamed over it, so
+/// that `resolved_new` refers either to what it did before or to the complete copy. Everything is
+/// opened relative to its parent directory without following symbolic links, so that the guest
+/// can't swap in links leading out of its preopens while the tree is being copied.
+pub(crate) fn path_rename_copy(resolved_old: &PathGet, resolved_new: &PathGet) -> Result<()> {
+    use nix::fcntl::renameat;
+    use rand::{thread_rng, RngCore};
+
+    let old_dirfd = resolved_old.dirfd().as_raw_fd();
+    let new_dirfd = resolved_new.dirfd().as_raw_fd();
+    let old_path = host_impl::path_to_host(resolved_old.path())?;
+    let new_path = host_impl::path_to_host(resolved_new.path())?;
+    let temp_path = format!(".wasi-rename-{:016x}", thread_rng().next_u64());
+
+    let copied = copy_tree(old_dirfd, &old_path, new_dirfd, temp_path.as_ref()).and_then(|()| {
+        renameat(
+            Some(new_dirfd),
+            temp_path.as_str(),
+            Some(new_dirfd),
+            &*new_path,
+        )
+        .map_err(Into::into)
+    });
+    if let Err(e) = copied {
+        // the original is left in place, and so is whatever was at the new path
+        let _ = remove_tree(new_dirfd, temp_path.as_ref());
+        return Err(e);
+    }
+    remove_tree(old_dirfd, &old_path)
+}
+
+/// Copies the entry `src_name` of the directory `src_dirfd` to the new entry `dst_name` of the
+/// directory `dst_dirfd`, along with its permissions and timestamps.
+fn copy_tree(src_dirfd: RawFd, src_name: &OsStr, dst_dirfd: RawFd, dst_name: &OsStr) -> Result<()> {
+    use nix::dir::Dir;
+    use nix::fcntl::{openat, readlinkat, AtFlags, OFlag};
+    use nix::sys::stat::{fchmod, fstatat, mkdirat, utimensat, Mode, SFlag, UtimensatFlags};
+    use nix::sys::time::{TimeSpec, TimeValLike};
+    use nix::unistd::symlinkat;
+
+    let stat = fstatat(src_dirfd, src_name, AtFlags::AT_SYMLINK_NOFOLLOW)?;
+    let mode = Mode::from_bits_truncate(stat.st_mode);
+    let private = Mode::S_IRUSR | Mode::S_IWUSR | Mode::S_IXUSR;
+    match SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT {
+        SFlag::S_IFREG => {
+            let mut src = unsafe {
+                File::from_raw_fd(openat(
+                    src_dirfd,
+                    src_name,
+                    OFlag::O_RDONLY | OFlag::O_NOFOLLOW,
+                    Mode::empty(),
+                )?)
+            };
+            let mut dst = unsafe {
+                File::from_raw_fd(openat(
+                    dst_dirfd,
+                    dst_name,
+                    OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_NOFOLLOW,
+                    private,
+                )?)
+            };
+            io::copy(&mut src, &mut dst)?;
+            fchmod(dst.as_raw_fd(), mode)?;
+        }
+        SFlag::S_IFDIR => {
+            mkdirat(dst_dirfd, dst_name, private)?;
+            let mut src = Dir::openat(
+                src_dirfd,
+                src_name,
+                OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW,
+                Mode::empty(),
+            )?;
+            let dst = unsafe {
+                File::from_raw_fd(openat(
+                    dst_dirfd,
+                    dst_name,
+                    OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW,
+                    Mode::empty(),
+                )?)
+            };
+            for name in dir_entries(&mut src)? {
+                copy_tree(src.as_raw_fd(), &name, dst.as_raw_fd(), &name)?;
+            }
+            fchmod(dst.as_raw_fd(), mode)?;
+        }
+        SFlag::S_IFLNK => {
+            let mut buf = vec![0; libc::PATH_MAX as usize];
+            let target = readlinkat(src_dirfd, src_name, &mut buf)?;
+            symlinkat(target, Some(dst_dirfd), dst_name)?;
+        }
+        // devices, pipes and sockets can't be moved across file systems
+        _ => return Err(Error::EXDEV),
+    }
+
+    // directories get their timestamps last, since copying their entries changes them
+    let filestat = host_impl::filestat_from_nix(stat)?;
+    let atim = TimeSpec::nanoseconds(filestat.st_atim.try_into()?);
+    let mtim = TimeSpec::nanoseconds(filestat.st_mtim.try_into()?);
+    utimensat(
+        Some(dst_dirfd),
+        dst_name,
+        &atim,
+        &mtim,
+        UtimensatFlags::NoFollowSymlink,
+    )
+    .map_err(Into::into)
+}
+
+/// Removes the entry `name` of the directory `dirfd`, and everything below it if it's a
+/// directory.
+fn remove_tree(dirfd: RawFd, name: &OsStr) -> Result<()> {
+    use nix::dir::Dir;
+    use nix::fcntl::{AtFlags, OFlag};
+    use nix::sys::stat::{fstatat, Mode, SFlag};
+    use nix::unistd::{unlinkat, UnlinkatFlags};
+
+    let stat = fstatat(dirfd, name, AtFlags::AT_SYMLINK_NOFOLLOW)?;
+    if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT != SFlag::S_IFDIR {
+        return unlinkat(Some(dirfd), name, UnlinkatFlags::NoRemoveDir).map_err(Into::into);
+    }
+    let mut dir = Dir::openat(
+        dirfd,
+        name,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW,
+        Mode::empty(),
+    )?;
+    for entry in dir_entries(&mut dir)? {
+        remove_tree(dir.as_raw_fd(), &entry)?;
+    }
+    unlinkat(Some(dirfd), name, UnlinkatFlags::RemoveDir).map_err(Into::into)
+}
+
+/// The names of the entries of `dir`, other than `.` and `..`.
+fn dir_entries(dir: &mut nix::dir::Dir) -> Result<Vec<OsString>> {
+    let mut names = Vec::new();
+    for entry in dir.iter() {
+        let entry = entry?;
+        let name = OsStr::from_bytes(entry.file_name().to_bytes());
+        if name != "." && name != ".." {
+            names.push(name.to_owned());
+        }
+    }
+    Ok(names)
+}
@@ -1,8 +1,9 @@
 #![allow(non_camel_case_types)]
 #![allow(unused_unsafe)]
 use crate::sys::host_impl;
+use crate::virtfs::Dirent;
 use crate::{host, Result};
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 
 cfg_if::cfg_if! {
     if #[cfg(target_os = "linux")] {
@@ -61,7 +62,7 @@ pub(crate) fn openat(dirfd: &File, path: &str) -> Result<File> {
 
     fcntl::openat(
         dirfd.as_raw_fd(),
-        path,
+        &*host_impl::path_to_host(path)?,
         OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW,
         Mode::empty(),
     )
@@ -77,7 +78,121 @@ pub(crate) fn readlinkat(dirfd: &File, path: &str) -> Result<String> {
 
     let readlink_buf = &mut [0u8; libc::PATH_MAX as usize + 1];
 
-    fcntl::readlinkat(dirfd.as_raw_fd(), path, readlink_buf)
-        .map_err(Into::into)
-        .and_then(host_impl::path_from_host)
+    fcntl::readlinkat(
+        dirfd.as_raw_fd(),
+        &*host_impl::path_to_host(path)?,
+        readlink_buf,
+    )
+    .map_err(Into::into)
+    .and_then(host_impl::path_from_host)
+}
+
+/// Metadata of the entry at `path` relative to `dirfd`, without following symlinks, as used by
+/// `virtfs::HostLayer`.
+pub(crate) fn lstatat(dirfd: &File, path: &str) -> Result<host::__wasi_filestat_t> {
+    use nix::fcntl::AtFlags;
+    use nix::sys::stat::fstatat;
+    use std::os::unix::prelude::AsRawFd;
+
+    fstatat(
+        dirfd.as_raw_fd(),
+        &*host_impl::path_to_host(path)?,
+        AtFlags::AT_SYMLINK_NOFOLLOW,
+    )
+    .map_err(Into::into)
+    .and_then(host_impl::filestat_from_nix)
+}
+
+/// Entries of the directory at `path` relative to `dirfd`, excluding `.` and `..`, as used by
+/// `virtfs::HostLayer`. Like in `openat`, the directory must not be a symlink.
+pub(crate) fn read_dir(dirfd: &File, path: &str) -> Result<Vec<Dirent>> {
+    use nix::dir::{Dir, Type};
+    use nix::fcntl::{AtFlags, OFlag};
+    use nix::sys::stat::{fstatat, Mode};
+    use std::ffi::OsStr;
+    use std::os::unix::prelude::{AsRawFd, OsStrExt};
+
+    let mut dir = Dir::openat(
+        dirfd.as_raw_fd(),
+        &*host_impl::path_to_host(path)?,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW,
+        Mode::empty(),
+    )?;
+    let fd = dir.as_raw_fd();
+    let mut entries = Vec::new();
+    for entry in dir.iter() {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name.to_bytes() == b"." || name.to_bytes() == b".." {
+            continue;
+        }
+        let filetype = match entry.file_type() {
+            Some(Type::CharacterDevice) => host::__WASI_FILETYPE_CHARACTER_DEVICE,
+            Some(Type::Directory) => host::__WASI_FILETYPE_DIRECTORY,
+            Some(Type::BlockDevice) => host::__WASI_FILETYPE_BLOCK_DEVICE,
+            Some(Type::File) => host::__WASI_FILETYPE_REGULAR_FILE,
+            Some(Type::Symlink) => host::__WASI_FILETYPE_SYMBOLIC_LINK,
+            Some(Type::Fifo) => host::__WASI_FILETYPE_SOCKET_STREAM,
+            Some(Type::Socket) => host::__WASI_FILETYPE_UNKNOWN,
+            // the file system doesn't tell the type along with the name
+            None => {
+                fstatat(fd, name, AtFlags::AT_SYMLINK_NOFOLLOW)
+                    .map_err(Into::into)
+                    .and_then(host_impl::filestat_from_nix)?
+                    .st_filetype
+            }
+        };
+        entries.push(Dirent {
+            name: host_impl::path_from_host(OsStr::from_bytes(name.to_bytes()))?,
+            ino: entry.ino(),
+            filetype,
+        });
+    }
+    Ok(entries)
+}
+
+/// Creates a hard link at `new_path` relative to `new_dirfd` to the file at `old_path` relative to
+/// `old_dirfd`, which is linked itself if it's a symlink, as used by `virtfs::HostLayer`.
+pub(crate) fn linkat(
+    old_dirfd: &File,
+    old_path: &str,
+    new_dirfd: &File,
+    new_path: &str,
+) -> Result<()> {
+    use crate::sys::unix::str_to_cstring;
+    use std::os::unix::prelude::AsRawFd;
+
+    let old_path = str_to_cstring(old_path)?;
+    let new_path = str_to_cstring(new_path)?;
+    let res = unsafe {
+        libc::linkat(
+            old_dirfd.as_raw_fd(),
+            old_path.as_ptr(),
+            new_dirfd.as_raw_fd(),
+            new_path.as_ptr(),
+            0,
+        )
+    };
+    if res != 0 {
+        Err(host_impl::errno_from_nix(nix::errno::Errno::last()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Applies `fdflags` to `options`, and makes sure that the final component isn't followed if
+/// it happens to be a symlink, unless `follow` is set.
+pub(crate) fn open_options_ext(
+    options: &mut OpenOptions,
+    fdflags: host::__wasi_fdflags_t,
+    follow: bool,
+) {
+    use nix::fcntl::OFlag;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut flags = host_impl::nix_from_fdflags(fdflags);
+    if !follow {
+        flags |= OFlag::O_NOFOLLOW;
+    }
+    options.custom_flags(flags.bits());
 }
@@ -1,10 +1,14 @@
 #![allow(non_camel_case_types)]
 #![allow(unused_unsafe)]
+use crate::cancel::{self, Canceller};
 use crate::hostcalls_impl::{ClockEventData, FdEventData};
 use crate::sys::host_impl;
 use crate::{host, Error, Result};
 use nix::libc::{self, c_int};
-use std::mem::MaybeUninit;
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem::{ManuallyDrop, MaybeUninit};
+use std::os::unix::prelude::{AsRawFd, FromRawFd, RawFd};
 
 pub(crate) fn clock_res_get(clock_id: host::__wasi_clockid_t) -> Result<host::__wasi_timestamp_t> {
     // convert the supported clocks to the libc types, or return EINVAL
@@ -67,20 +71,24 @@ pub(crate) fn clock_time_get(clock_id: host::__wasi_clockid_t) -> Result<host::_
         .map_or(Err(Error::EOVERFLOW), Ok)
 }
 
+/// Waits for any of `fd_events` to become ready, or for `timeout` to elapse. If `wake` becomes
+/// readable before that, no events are returned.
 pub(crate) fn poll_oneoff(
     timeout: Option<ClockEventData>,
     fd_events: Vec<FdEventData>,
+    wake: Option<&File>,
 ) -> Result<Vec<host::__wasi_event_t>> {
     use nix::{
         errno::Errno,
         poll::{poll, PollFd, PollFlags},
     };
-    use std::{convert::TryInto, os::unix::prelude::AsRawFd};
+    use std::convert::TryInto;
 
     if fd_events.is_empty() && timeout.is_none() {
         return Ok(vec![]);
     }
 
+    let canceller = cancel::current();
     let mut poll_fds: Vec<_> = fd_events
         .iter()
         .map(|event| {
@@ -96,6 +104,12 @@ pub(crate) fn poll_oneoff(
             PollFd::new(event.descriptor.as_raw_fd(), flags)
         })
         .collect();
+    if let Some(wake) = wake {
+        poll_fds.push(PollFd::new(wake.as_raw_fd(), PollFlags::POLLIN));
+    }
+    if let Some(canceller) = &canceller {
+        poll_fds.push(PollFd::new(canceller.wake_fd(), PollFlags::POLLIN));
+    }
 
     let poll_timeout = timeout.map_or(-1, |timeout| {
         let delay = timeout.delay / 1_000_000; // poll syscall requires delay to expressed in milliseconds
@@ -103,10 +117,13 @@ pub(crate) fn poll_oneoff(
     });
     log::debug!("poll_oneoff poll_timeout = {:?}", poll_timeout);
 
-    let ready = loop {
+    let mut ready = loop {
         match poll(&mut poll_fds, poll_timeout) {
             Err(_) => {
                 if Errno::last() == Errno::EINTR {
+                    if let Some(canceller) = &canceller {
+                        canceller.check()?;
+                    }
                     continue;
                 }
                 return Err(host_impl::errno_from_nix(Errno::last()));
@@ -114,15 +131,92 @@ pub(crate) fn poll_oneoff(
             Ok(ready) => break ready as usize,
         }
     };
+    if let Some(canceller) = &canceller {
+        // the wake pipe is only ready once cancelled, so `ready` doesn't count it otherwise
+        canceller.check()?;
+        poll_fds.pop();
+    }
+    if wake.is_some() {
+        let revents = poll_fds.pop().and_then(|poll_fd| poll_fd.revents());
+        if revents.filter(|revents| !revents.is_empty()).is_some() {
+            ready -= 1;
+            if ready == 0 {
+                return Ok(vec![]);
+            }
+        }
+    }
 
     Ok(if ready == 0 {
         poll_oneoff_handle_timeout_event(timeout.expect("timeout should not be None"))
     } else {
-        let events = fd_events.into_iter().zip(poll_fds.into_iter()).take(ready);
+        let events = fd_events.into_iter().zip(poll_fds.into_iter());
         poll_oneoff_handle_fd_event(events)?
     })
 }
 
+/// Creates a pipe whose reading end is polled along with host descriptors, so that writing to it
+/// wakes the thread polling, as done by a `Canceller` when cancelled and by `poll_oneoff` when a
+/// virtual file changes. Neither end blocks.
+pub(crate) fn wake_pipe() -> Result<(File, File)> {
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+
+    let (reader, writer) = nix::unistd::pipe()?;
+    let (reader, writer) = unsafe { (File::from_raw_fd(reader), File::from_raw_fd(writer)) };
+    for fd in &[reader.as_raw_fd(), writer.as_raw_fd()] {
+        fcntl(*fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        fcntl(*fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+    }
+    Ok((reader, writer))
+}
+
+/// Blocks until `fd` can be read from, failing with `ECANCELED` if `canceller` is cancelled
+/// first. Descriptors in non-blocking mode are left for the read to fail with `EAGAIN`.
+fn wait_readable(fd: RawFd, canceller: &Canceller) -> Result<()> {
+    use nix::{
+        errno::Errno,
+        fcntl::{fcntl, FcntlArg, OFlag},
+        poll::{poll, PollFd, PollFlags},
+    };
+
+    canceller.check()?;
+    let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
+    if flags.contains(OFlag::O_NONBLOCK) {
+        return Ok(());
+    }
+    let mut poll_fds = [
+        PollFd::new(fd, PollFlags::POLLIN),
+        PollFd::new(canceller.wake_fd(), PollFlags::POLLIN),
+    ];
+    loop {
+        match poll(&mut poll_fds, -1) {
+            Err(_) if Errno::last() == Errno::EINTR => canceller.check()?,
+            Err(_) => return Err(host_impl::errno_from_nix(Errno::last())),
+            // a hang up or an error is left for the read to report
+            Ok(_) => return canceller.check(),
+        }
+    }
+}
+
+/// Reads from `file`, a host stream such as a pipe, a socket or a terminal, unless `canceller`
+/// is cancelled while waiting for data.
+pub(crate) fn read_stream(
+    file: &mut File,
+    iovs: &mut [io::IoSliceMut],
+    canceller: &Canceller,
+) -> Result<usize> {
+    wait_readable(file.as_raw_fd(), canceller)?;
+    file.read_vectored(iovs).map_err(Into::into)
+}
+
+/// Same as `read_stream` for the standard input of the process, which is read directly, as
+/// whether the buffer of `io::stdin` has data left can't be polled for.
+pub(crate) fn read_stdin(iovs: &mut [io::IoSliceMut], canceller: &Canceller) -> Result<usize> {
+    wait_readable(libc::STDIN_FILENO, canceller)?;
+    // the descriptor belongs to the process, so it mustn't be closed
+    let stdin = ManuallyDrop::new(unsafe { File::from_raw_fd(libc::STDIN_FILENO) });
+    (&*stdin).read_vectored(iovs).map_err(Into::into)
+}
+
 // define the `fionread()` function, equivalent to `ioctl(fd, FIONREAD, *bytes)`
 nix::ioctl_read_bad!(fionread, nix::libc::FIONREAD, c_int);
 
@@ -144,7 +238,7 @@ fn poll_oneoff_handle_fd_event<'a>(
     events: impl Iterator<Item = (FdEventData<'a>, nix::poll::PollFd)>,
 ) -> Result<Vec<host::__wasi_event_t>> {
     use nix::poll::PollFlags;
-    use std::{convert::TryInto, os::unix::prelude::AsRawFd};
+    use std::convert::TryInto;
 
     let mut output_events = Vec::new();
     for (fd_event, poll_fd) in events {
@@ -190,6 +284,7 @@ fn poll_oneoff_handle_fd_event<'a>(
                 },
             }
         } else if revents.contains(PollFlags::POLLHUP) {
+            // the other end of a pipe hung up, which may have left data to be read
             host::__wasi_event_t {
                 userdata: fd_event.userdata,
                 type_: fd_event.type_,
@@ -197,7 +292,7 @@ fn poll_oneoff_handle_fd_event<'a>(
                 u: host::__wasi_event_t___wasi_event_u {
                     fd_readwrite:
                         host::__wasi_event_t___wasi_event_u___wasi_event_u_fd_readwrite_t {
-                            nbytes: 0,
+                            nbytes: nbytes.try_into()?,
                             flags: host::__WASI_EVENT_FD_READWRITE_HANGUP,
                         },
                 },
@@ -1,4 +1,5 @@
 use super::osfile::OsFile;
+use crate::encoding::PathEncoding;
 use crate::hostcalls_impl::PathGet;
 use crate::sys::host_impl;
 use crate::sys::unix::str_to_cstring;
@@ -47,7 +48,7 @@ pub(crate) fn path_symlink(old_path: &str, resolved: PathGet) -> Result<()> {
     }
 }
 
-pub(crate) fn path_rename(resolved_old: PathGet, resolved_new: PathGet) -> Result<()> {
+pub(crate) fn path_rename(resolved_old: &PathGet, resolved_new: &PathGet) -> Result<()> {
     use nix::libc::renameat;
     let old_path_cstr = str_to_cstring(resolved_old.path())?;
     let new_path_cstr = str_to_cstring(resolved_new.path())?;
@@ -71,6 +72,7 @@ pub(crate) fn fd_readdir(
     os_file: &mut OsFile,
     host_buf: &mut [u8],
     cookie: host::__wasi_dircookie_t,
+    encoding: PathEncoding,
 ) -> Result<usize> {
     use libc::{dirent, fdopendir, readdir_r, rewinddir, seekdir};
 
@@ -113,6 +115,11 @@ pub(crate) fn fd_readdir(
         log::debug!("fd_readdir entry = {:?}", entry);
 
         let name_len = entry.d_namlen.try_into()?;
+        let name_ptr = unsafe { (*host_entry).d_name.as_ptr() };
+        let name = unsafe { std::slice::from_raw_parts(name_ptr as *const u8, name_len) };
+        if encoding.check_name(name, false).is_err() {
+            continue;
+        }
         let required_space = std::mem::size_of_val(&entry) + name_len;
         if required_space > left {
             break;
@@ -120,14 +127,13 @@ pub(crate) fn fd_readdir(
         unsafe {
             let ptr = host_buf_ptr.offset(host_buf_offset.try_into()?) as *mut c_void
                 as *mut host::__wasi_dirent_t;
-            *ptr = entry;
+            ptr.write_unaligned(entry);
         }
         host_buf_offset += std::mem::size_of_val(&entry);
-        let name_ptr = unsafe { *host_entry }.d_name.as_ptr();
         unsafe {
             std::ptr::copy_nonoverlapping(
-                name_ptr as *const _,
-                host_buf_ptr.offset(host_buf_offset.try_into()?) as *mut _,
+                name.as_ptr(),
+                host_buf_ptr.offset(host_buf_offset.try_into()?),
                 name_len,
             )
         };
@@ -3,13 +3,20 @@ pub(crate) mod osfile;
 
 pub(crate) mod fdentry_impl {
     use crate::{sys::host_impl, Result};
+    use std::fs::File;
     use std::os::unix::prelude::AsRawFd;
+    use std::path::PathBuf;
+
+    /// The path `file` was opened at, as last known by the kernel.
+    pub(crate) fn fd_path(file: &File) -> Result<PathBuf> {
+        std::fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd())).map_err(Into::into)
+    }
 
     pub(crate) unsafe fn isatty(fd: &impl AsRawFd) -> Result<bool> {
         use nix::errno::Errno;
 
         let res = libc::isatty(fd.as_raw_fd());
-        if res == 0 {
+        if res == 1 {
             Ok(true)
         } else {
             match Errno::last() {
@@ -5,6 +5,12 @@ use std::os::unix::prelude::{AsRawFd, RawFd};
 #[derive(Debug)]
 pub(crate) struct OsFile(fs::File);
 
+impl OsFile {
+    pub(crate) fn into_file(self) -> fs::File {
+        self.0
+    }
+}
+
 impl From<fs::File> for OsFile {
     fn from(file: fs::File) -> Self {
         Self(file)
@@ -14,7 +14,7 @@ mod bsd;
 #[cfg(target_os = "linux")]
 mod linux;
 
-use crate::{Error, Result};
+use crate::{encoding, Error, Result};
 use std::ffi::CString;
 use std::fs::{File, OpenOptions};
 use std::path::Path;
@@ -28,7 +28,7 @@ pub(crate) fn dev_null() -> Result<File> {
 }
 
 pub(crate) fn str_to_cstring(s: &str) -> Result<CString> {
-    CString::new(s.as_bytes()).map_err(|_| Error::EILSEQ)
+    CString::new(encoding::path_to_bytes(s)).map_err(|_| Error::EILSEQ)
 }
 
 pub fn preopen_dir<P: AsRef<Path>>(path: P) -> Result<File> {
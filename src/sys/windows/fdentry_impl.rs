@@ -4,10 +4,17 @@ use std::fs::File;
 use std::io;
 use std::ops::{Deref, DerefMut};
 use std::os::windows::prelude::{AsRawHandle, FromRawHandle, RawHandle};
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub(crate) struct OsFile(File);
 
+impl OsFile {
+    pub(crate) fn into_file(self) -> File {
+        self.0
+    }
+}
+
 impl From<File> for OsFile {
     fn from(file: File) -> Self {
         Self(file)
@@ -41,10 +48,37 @@ impl AsRawHandle for Descriptor {
             Self::Stdin => io::stdin().as_raw_handle(),
             Self::Stdout => io::stdout().as_raw_handle(),
             Self::Stderr => io::stderr().as_raw_handle(),
+            Self::VirtualFile(_) => {
+                unreachable!("virtual files aren't backed by a host descriptor")
+            }
         }
     }
 }
 
+/// The path of the host `file`, which it can be opened again at.
+pub(crate) fn file_path(file: &File) -> Result<PathBuf> {
+    winx::file::get_file_path(file)
+        .map(PathBuf::from)
+        .map_err(Into::into)
+}
+
+/// A path opening `file` again, as a new handle with its own offset, which is `file_path`.
+pub(crate) fn reopen_path(file: &File) -> Result<PathBuf> {
+    file_path(file)
+}
+
+/// The device number of `file`, if it's a character device. Those can't be told apart, so they
+/// all have the same number.
+pub(crate) fn device_id(file: &File) -> Result<Option<u64>> {
+    // the handle is valid for as long as `file` is borrowed
+    let file_type = unsafe { winx::file::get_file_type(file.as_raw_handle())? };
+    if file_type.is_char() {
+        Ok(Some(0))
+    } else {
+        Ok(None)
+    }
+}
+
 /// This function is unsafe because it operates on a raw file handle.
 pub(crate) unsafe fn determine_type_and_access_rights<Handle: AsRawHandle>(
     handle: &Handle,
@@ -2,11 +2,13 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 #![allow(unused)]
-use crate::{host, Error, Result};
-use std::ffi::OsStr;
+use crate::{encoding, host, Error, Result};
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
 use std::fs::OpenOptions;
-use std::os::windows::ffi::OsStrExt;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
 use std::os::windows::fs::OpenOptionsExt;
+use std::str;
 use winx::file::{AccessMode, Attributes, CreationDisposition, Flags};
 
 pub(crate) fn errno_from_win(error: winx::winerror::WinError) -> host::__wasi_errno_t {
@@ -36,10 +38,59 @@ pub(crate) fn errno_from_win(error: winx::winerror::WinError) -> host::__wasi_er
         ERROR_NEGATIVE_SEEK => host::__WASI_EINVAL,
         ERROR_DIRECTORY => host::__WASI_ENOTDIR,
         ERROR_ALREADY_EXISTS => host::__WASI_EEXIST,
+        ERROR_NOT_SAME_DEVICE => host::__WASI_EXDEV,
         _ => host::__WASI_ENOTSUP,
     }
 }
 
+/// The Windows error code corresponding to a WASI errno, if there's one. Each error code is
+/// only used for a single errno, so that it can be converted back.
+pub(crate) fn errno_to_host(errno: host::__wasi_errno_t) -> Option<i32> {
+    use winapi::shared::winerror::*;
+    let code = match errno {
+        host::__WASI_EINVAL => WSAEINVAL,
+        host::__WASI_EPIPE => ERROR_BROKEN_PIPE,
+        host::__WASI_ENOTCONN => WSAENOTCONN,
+        host::__WASI_EACCES => ERROR_ACCESS_DENIED,
+        host::__WASI_EADDRINUSE => WSAEADDRINUSE,
+        host::__WASI_EADDRNOTAVAIL => WSAEADDRNOTAVAIL,
+        host::__WASI_EAGAIN => WSAEWOULDBLOCK,
+        host::__WASI_ECONNABORTED => WSAECONNABORTED,
+        host::__WASI_ECONNREFUSED => WSAECONNREFUSED,
+        host::__WASI_ECONNRESET => WSAECONNRESET,
+        host::__WASI_EEXIST => ERROR_ALREADY_EXISTS,
+        host::__WASI_ENOENT => ERROR_FILE_NOT_FOUND,
+        host::__WASI_ETIMEDOUT => WSAETIMEDOUT,
+        host::__WASI_EAFNOSUPPORT => WSAEAFNOSUPPORT,
+        host::__WASI_EALREADY => WSAEALREADY,
+        host::__WASI_EBADF => WSAEBADF,
+        host::__WASI_EDESTADDRREQ => WSAEDESTADDRREQ,
+        host::__WASI_EDQUOT => WSAEDQUOT,
+        host::__WASI_EFAULT => WSAEFAULT,
+        host::__WASI_EHOSTUNREACH => WSAEHOSTUNREACH,
+        host::__WASI_EINPROGRESS => WSAEINPROGRESS,
+        host::__WASI_EINTR => WSAEINTR,
+        host::__WASI_EISCONN => WSAEISCONN,
+        host::__WASI_ELOOP => WSAELOOP,
+        host::__WASI_EMFILE => WSAEMFILE,
+        host::__WASI_EMSGSIZE => WSAEMSGSIZE,
+        host::__WASI_ENAMETOOLONG => WSAENAMETOOLONG,
+        host::__WASI_ENETDOWN => WSAENETDOWN,
+        host::__WASI_ENETRESET => WSAENETRESET,
+        host::__WASI_ENETUNREACH => WSAENETUNREACH,
+        host::__WASI_ENOBUFS => WSAENOBUFS,
+        host::__WASI_ENOPROTOOPT => WSAENOPROTOOPT,
+        host::__WASI_ENOTEMPTY => WSAENOTEMPTY,
+        host::__WASI_ENOTSOCK => WSAENOTSOCK,
+        host::__WASI_EPROTONOSUPPORT => WSAEPROTONOSUPPORT,
+        host::__WASI_EPROTOTYPE => WSAEPROTOTYPE,
+        host::__WASI_ESTALE => WSAESTALE,
+        host::__WASI_EXDEV => ERROR_NOT_SAME_DEVICE,
+        _ => return None,
+    };
+    Some(code as i32)
+}
+
 pub(crate) fn fdflags_from_win(mode: AccessMode) -> host::__wasi_fdflags_t {
     let mut fdflags = 0;
     // TODO verify this!
@@ -98,11 +149,64 @@ pub(crate) fn win_from_oflags(oflags: host::__wasi_oflags_t) -> CreationDisposit
     }
 }
 
-/// Creates owned WASI path from OS string.
-///
-/// NB WASI spec requires OS string to be valid UTF-8. Otherwise,
-/// `__WASI_EILSEQ` error is returned.
+/// Creates owned WASI path from OS string, which is encoded as WTF-8 and escaped as described in
+/// `crate::encoding`. WTF-8 is UTF-8 extended to the unpaired surrogates a Windows name may
+/// contain, whose encodings are always escaped since they aren't valid UTF-8.
 pub(crate) fn path_from_host<S: AsRef<OsStr>>(s: S) -> Result<String> {
-    let vec: Vec<u16> = s.as_ref().encode_wide().collect();
-    String::from_utf16(&vec).map_err(|_| Error::EILSEQ)
+    if let Some(path) = s.as_ref().to_str() {
+        return Ok(encoding::path_from_bytes(path.as_bytes()).into_owned());
+    }
+
+    let mut bytes = Vec::new();
+    for c in std::char::decode_utf16(s.as_ref().encode_wide()) {
+        match c {
+            Ok(c) => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+            Err(e) => {
+                let surrogate = e.unpaired_surrogate();
+                bytes.push(0xe0 | (surrogate >> 12) as u8);
+                bytes.push(0x80 | (surrogate >> 6 & 0x3f) as u8);
+                bytes.push(0x80 | (surrogate & 0x3f) as u8);
+            }
+        }
+    }
+    Ok(encoding::path_from_bytes(&bytes).into_owned())
+}
+
+/// Converts a WASI path back to the OS string it was created from by `path_from_host`, failing
+/// with `__WASI_EILSEQ` if it isn't valid WTF-8.
+pub(crate) fn path_to_host(path: &str) -> Result<Cow<'_, OsStr>> {
+    let bytes = match encoding::path_to_bytes(path) {
+        Cow::Borrowed(_) => return Ok(Cow::Borrowed(OsStr::new(path))),
+        Cow::Owned(bytes) => bytes,
+    };
+    let mut bytes = &bytes[..];
+
+    let mut wide = Vec::with_capacity(bytes.len());
+    loop {
+        match str::from_utf8(bytes) {
+            Ok(valid) => {
+                wide.extend(valid.encode_utf16());
+                return Ok(Cow::Owned(OsString::from_wide(&wide)));
+            }
+            Err(e) => {
+                let (valid, invalid) = bytes.split_at(e.valid_up_to());
+                wide.extend(unsafe { str::from_utf8_unchecked(valid) }.encode_utf16());
+                // The only sequences WTF-8 adds to UTF-8 are those of surrogates.
+                if invalid.len() < 3
+                    || invalid[0] != 0xed
+                    || invalid[1] & 0xe0 != 0xa0
+                    || invalid[2] & 0xc0 != 0x80
+                {
+                    return Err(Error::EILSEQ);
+                }
+                wide.push(
+                    0xd000 | u16::from(invalid[1] & 0x3f) << 6 | u16::from(invalid[2] & 0x3f),
+                );
+                bytes = &invalid[3..];
+            }
+        }
+    }
 }
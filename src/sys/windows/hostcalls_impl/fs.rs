@@ -8,7 +8,7 @@ use crate::hostcalls_impl::{fd_filestat_set_times_impl, FileType, PathGet};
 use crate::sys::fdentry_impl::{determine_type_rights, OsFile};
 use crate::sys::host_impl;
 use crate::sys::hostcalls_impl::fs_helpers::PathGetExt;
-use crate::{host, Error, Result};
+use crate::{encoding, host, Error, Result};
 use std::convert::TryInto;
 use std::fs::{File, Metadata, OpenOptions};
 use std::io::{self, Seek, SeekFrom};
@@ -89,6 +89,21 @@ pub(crate) fn path_link(resolved_old: PathGet, resolved_new: PathGet) -> Result<
     unimplemented!("path_link")
 }
 
+/// Whether there's a file, or a symbolic link, at the path.
+pub(crate) fn path_exists(resolved: &PathGet) -> Result<bool> {
+    match std::fs::symlink_metadata(resolved.concatenate()?) {
+        Ok(_) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Devices aren't found in directories on Windows, so there's never one at the path; files are
+/// checked once opened instead.
+pub(crate) fn path_device_id(_resolved: &PathGet) -> Result<Option<u64>> {
+    Ok(None)
+}
+
 pub(crate) fn path_open(
     resolved: PathGet,
     read: bool,
@@ -170,6 +185,7 @@ pub(crate) fn fd_readdir(
     fd: &mut OsFile,
     host_buf: &mut [u8],
     cookie: host::__wasi_dircookie_t,
+    encoding: encoding::PathEncoding,
 ) -> Result<usize> {
     unimplemented!("fd_readdir")
 }
@@ -189,29 +205,15 @@ pub(crate) fn path_readlink(resolved: PathGet, buf: &mut [u8]) -> Result<usize>
     let target_path = target_path
         .strip_prefix(dir_path)
         .map_err(|_| Error::ENOTCAPABLE)
-        .and_then(|path| path.to_str().map(String::from).ok_or(Error::EILSEQ))?;
-
-    if buf.len() > 0 {
-        let mut chars = target_path.chars();
-        let mut nread = 0usize;
-
-        for i in 0..buf.len() {
-            match chars.next() {
-                Some(ch) => {
-                    buf[i] = ch as u8;
-                    nread += 1;
-                }
-                None => break,
-            }
-        }
+        .and_then(host_impl::path_from_host)?;
+    let target_path = encoding::path_to_bytes(&target_path);
 
-        Ok(nread)
-    } else {
-        Ok(0)
-    }
+    let nread = std::cmp::min(target_path.len(), buf.len());
+    buf[..nread].copy_from_slice(&target_path[..nread]);
+    Ok(nread)
 }
 
-pub(crate) fn path_rename(resolved_old: PathGet, resolved_new: PathGet) -> Result<()> {
+pub(crate) fn path_rename(resolved_old: &PathGet, resolved_new: &PathGet) -> Result<()> {
     use std::fs;
 
     let old_path = resolved_old.concatenate()?;
@@ -256,6 +258,103 @@ pub(crate) fn path_rename(resolved_old: PathGet, resolved_new: PathGet) -> Resul
     })
 }
 
+/// Moves the file, symbolic link or directory tree at `resolved_old` to `resolved_new` by copying
+/// it and removing the original, for when renaming it failed with `EXDEV`.
+///
+/// The copy is made under a temporary name next to `resolved_new` and then renamed over it, so
+/// that `resolved_new` refers either to what it did before or to the complete copy. An empty
+/// directory at `resolved_new` can't be renamed over, so it's first moved aside under another
+/// temporary name, and put back if renaming the copy fails. In between, `resolved_new` doesn't
+/// exist.
+pub(crate) fn path_rename_copy(resolved_old: &PathGet, resolved_new: &PathGet) -> Result<()> {
+    use rand::{thread_rng, RngCore};
+    use std::fs;
+
+    let temp_path = || {
+        concatenate(
+            resolved_new.dirfd(),
+            format!(".wasi-rename-{:016x}", thread_rng().next_u64()),
+        )
+    };
+    let old_path = resolved_old.concatenate()?;
+    let new_path = resolved_new.concatenate()?;
+    let copy_path = temp_path()?;
+
+    let copied = copy_tree(&old_path, &copy_path)
+        .map_err(Error::from)
+        .and_then(|()| -> Result<()> {
+            // like `path_rename`, an empty directory may be replaced by another directory
+            if !(copy_path.is_dir() && new_path.is_dir()) {
+                return fs::rename(&copy_path, &new_path).map_err(Into::into);
+            }
+            if fs::read_dir(&new_path)?.next().is_some() {
+                return Err(Error::ENOTEMPTY);
+            }
+            let aside_path = temp_path()?;
+            fs::rename(&new_path, &aside_path)?;
+            if let Err(e) = fs::rename(&copy_path, &new_path) {
+                let _ = fs::rename(&aside_path, &new_path);
+                return Err(e.into());
+            }
+            // this only fails if an entry was created in the directory meanwhile, which is kept
+            let _ = fs::remove_dir(&aside_path);
+            Ok(())
+        });
+    if let Err(e) = copied {
+        // the original is left in place
+        let _ = remove_tree(&copy_path);
+        return Err(e);
+    }
+    remove_tree(&old_path).map_err(Into::into)
+}
+
+/// Copies the file, symbolic link or directory tree at `src` to `dst`, along with its
+/// permissions and timestamps.
+fn copy_tree(src: &Path, dst: &Path) -> io::Result<()> {
+    use filetime::{set_file_times, set_symlink_file_times, FileTime};
+    use std::fs;
+    use std::os::windows::fs::{symlink_dir, symlink_file};
+
+    let metadata = fs::symlink_metadata(src)?;
+    let atime = FileTime::from_last_access_time(&metadata);
+    let mtime = FileTime::from_last_modification_time(&metadata);
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(src)?;
+        if fs::metadata(src).map(|m| m.is_dir()).unwrap_or(false) {
+            symlink_dir(target, dst)?;
+        } else {
+            symlink_file(target, dst)?;
+        }
+        return set_symlink_file_times(dst, atime, mtime);
+    }
+    if metadata.is_dir() {
+        fs::create_dir(dst)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(src, dst)?;
+    }
+    // directories get their timestamps last, since copying their entries changes them
+    set_file_times(dst, atime, mtime)?;
+    fs::set_permissions(dst, metadata.permissions())
+}
+
+/// Removes the file or symbolic link at `path`, or the directory tree if it's a directory.
+fn remove_tree(path: &Path) -> io::Result<()> {
+    use std::fs;
+
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        fs::remove_dir_all(path)
+    } else if metadata.file_type().is_symlink() && fs::metadata(path)?.is_dir() {
+        fs::remove_dir(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
 pub(crate) fn num_hardlinks(file: &File, _metadata: &Metadata) -> io::Result<u64> {
     Ok(winx::file::get_fileinfo(file)?.nNumberOfLinks.into())
 }
@@ -333,7 +432,7 @@ pub(crate) fn path_symlink(old_path: &str, resolved: PathGet) -> Result<()> {
     use std::os::windows::fs::{symlink_dir, symlink_file};
     use winx::winerror::WinError;
 
-    let old_path = concatenate(resolved.dirfd(), Path::new(old_path))?;
+    let old_path = concatenate(resolved.dirfd(), host_impl::path_to_host(old_path)?)?;
     let new_path = resolved.concatenate()?;
 
     // try creating a file symlink
@@ -357,7 +456,8 @@ pub(crate) fn path_symlink(old_path: &str, resolved: PathGet) -> Result<()> {
                     WinError::ERROR_INVALID_NAME => {
                         // does the target without trailing slashes exist?
                         let suffix = resolved.path().trim_end_matches('/');
-                        let out_path = concatenate(resolved.dirfd(), Path::new(suffix))?;
+                        let out_path =
+                            concatenate(resolved.dirfd(), host_impl::path_to_host(suffix)?)?;
                         if out_path.exists() {
                             Err(Error::EEXIST)
                         } else {
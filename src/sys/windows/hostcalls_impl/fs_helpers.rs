@@ -1,5 +1,7 @@
 #![allow(non_camel_case_types)]
 use crate::hostcalls_impl::PathGet;
+use crate::sys::host_impl;
+use crate::virtfs::Dirent;
 use crate::{host, Error, Result};
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
@@ -12,7 +14,7 @@ pub(crate) trait PathGetExt {
 
 impl PathGetExt for PathGet {
     fn concatenate(&self) -> Result<PathBuf> {
-        concatenate(self.dirfd(), Path::new(self.path()))
+        concatenate(self.dirfd(), host_impl::path_to_host(self.path())?)
     }
 }
 
@@ -51,7 +53,7 @@ pub(crate) fn openat(dirfd: &File, path: &str) -> Result<File> {
     use winx::file::Flags;
     use winx::winerror::WinError;
 
-    let path = concatenate(dirfd, Path::new(path))?;
+    let path = concatenate(dirfd, host_impl::path_to_host(path)?)?;
     OpenOptions::new()
         .read(true)
         .custom_flags(Flags::FILE_FLAG_BACKUP_SEMANTICS.bits())
@@ -75,7 +77,7 @@ pub(crate) fn readlinkat(dirfd: &File, s_path: &str) -> Result<String> {
     use winx::file::get_file_path;
     use winx::winerror::WinError;
 
-    let path = concatenate(dirfd, Path::new(s_path))?;
+    let path = concatenate(dirfd, host_impl::path_to_host(s_path)?)?;
     match path.read_link() {
         Ok(target_path) => {
             // since on Windows we are effectively emulating 'at' syscalls
@@ -87,7 +89,7 @@ pub(crate) fn readlinkat(dirfd: &File, s_path: &str) -> Result<String> {
             target_path
                 .strip_prefix(dir_path)
                 .map_err(|_| Error::ENOTCAPABLE)
-                .and_then(|path| path.to_str().map(String::from).ok_or(Error::EILSEQ))
+                .and_then(host_impl::path_from_host)
         }
         Err(e) => match e.raw_os_error() {
             Some(e) => {
@@ -96,7 +98,10 @@ pub(crate) fn readlinkat(dirfd: &File, s_path: &str) -> Result<String> {
                     WinError::ERROR_INVALID_NAME => {
                         if s_path.ends_with('/') {
                             // strip "/" and check if exists
-                            let path = concatenate(dirfd, Path::new(s_path.trim_end_matches('/')))?;
+                            let path = concatenate(
+                                dirfd,
+                                host_impl::path_to_host(s_path.trim_end_matches('/'))?,
+                            )?;
                             if path.exists() && !path.is_dir() {
                                 Err(Error::ENOTDIR)
                             } else {
@@ -147,3 +152,69 @@ pub(crate) fn concatenate<P: AsRef<Path>>(dirfd: &File, path: P) -> Result<PathB
 
     Ok(out_path)
 }
+
+/// Metadata of the entry at `path` relative to `dirfd`, without following symlinks, as used by
+/// `virtfs::HostLayer`.
+pub(crate) fn lstatat(dirfd: &File, path: &str) -> Result<host::__wasi_filestat_t> {
+    use std::fs::OpenOptions;
+    use std::os::windows::fs::OpenOptionsExt;
+    use winx::file::Flags;
+
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(
+            (Flags::FILE_FLAG_BACKUP_SEMANTICS | Flags::FILE_FLAG_OPEN_REPARSE_POINT).bits(),
+        )
+        .open(concatenate(dirfd, host_impl::path_to_host(path)?)?)?;
+    super::fs::fd_filestat_get_impl(&file)
+}
+
+/// Entries of the directory at `path` relative to `dirfd`, excluding `.` and `..`, as used by
+/// `virtfs::HostLayer`.
+pub(crate) fn read_dir(dirfd: &File, path: &str) -> Result<Vec<Dirent>> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(concatenate(dirfd, host_impl::path_to_host(path)?)?)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let filetype = if file_type.is_file() {
+            host::__WASI_FILETYPE_REGULAR_FILE
+        } else if file_type.is_dir() {
+            host::__WASI_FILETYPE_DIRECTORY
+        } else if file_type.is_symlink() {
+            host::__WASI_FILETYPE_SYMBOLIC_LINK
+        } else {
+            host::__WASI_FILETYPE_UNKNOWN
+        };
+        entries.push(Dirent {
+            name: host_impl::path_from_host(entry.file_name())?,
+            // there is no cheap way of getting the file index of a directory entry
+            ino: 0,
+            filetype,
+        });
+    }
+    Ok(entries)
+}
+
+/// Creates a hard link at `new_path` relative to `new_dirfd` to the file at `old_path` relative to
+/// `old_dirfd`, as used by `virtfs::HostLayer`.
+pub(crate) fn linkat(
+    old_dirfd: &File,
+    old_path: &str,
+    new_dirfd: &File,
+    new_path: &str,
+) -> Result<()> {
+    std::fs::hard_link(
+        concatenate(old_dirfd, host_impl::path_to_host(old_path)?)?,
+        concatenate(new_dirfd, host_impl::path_to_host(new_path)?)?,
+    )
+    .map_err(Into::into)
+}
+
+/// Applies `fdflags` to `options`. Windows doesn't follow symlinks implicitly when opening
+/// files, and checks for symlinks are left to the caller.
+pub(crate) fn open_options_ext(
+    _options: &mut std::fs::OpenOptions,
+    _fdflags: host::__wasi_fdflags_t,
+    _follow: bool,
+) {
+}
@@ -1,6 +1,7 @@
 #![allow(non_camel_case_types)]
 #![allow(unused_unsafe)]
 #![allow(unused)]
+use crate::cancel::Canceller;
 use crate::helpers::systemtime_to_timestamp;
 use crate::hostcalls_impl::{ClockEventData, FdEventData};
 use crate::memory::*;
@@ -9,6 +10,8 @@ use crate::{host, wasm32, Error, Result};
 use cpu_time::{ProcessTime, ThreadTime};
 use lazy_static::lazy_static;
 use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Read};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 lazy_static! {
@@ -33,10 +36,28 @@ pub(crate) fn clock_time_get(clock_id: host::__wasi_clockid_t) -> Result<host::_
 pub(crate) fn poll_oneoff(
     timeout: Option<ClockEventData>,
     fd_events: Vec<FdEventData>,
+    wake: Option<&File>,
 ) -> Result<Vec<host::__wasi_event_t>> {
     unimplemented!("poll_oneoff")
 }
 
+/// Reads from `file`, a host stream such as a pipe, a socket or a console, unless `canceller` is
+/// cancelled already, as blocked reads can't be interrupted on Windows.
+pub(crate) fn read_stream(
+    file: &mut File,
+    iovs: &mut [io::IoSliceMut],
+    canceller: &Canceller,
+) -> Result<usize> {
+    canceller.check()?;
+    file.read_vectored(iovs).map_err(Into::into)
+}
+
+/// Same as `read_stream` for the standard input of the process.
+pub(crate) fn read_stdin(iovs: &mut [io::IoSliceMut], canceller: &Canceller) -> Result<usize> {
+    canceller.check()?;
+    io::stdin().lock().read_vectored(iovs).map_err(Into::into)
+}
+
 fn get_monotonic_time() -> Duration {
     // We're circumventing the fact that we can't get a Duration from an Instant
     // The epoch of __WASI_CLOCK_MONOTONIC is undefined, so we fix a time point once
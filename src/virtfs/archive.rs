@@ -0,0 +1,578 @@
+use super::{Dirent, FileOptions, Layer, VirtualFile, RIGHTS_READ_ONLY_FILE_BASE};
+use crate::fdentry::Descriptor;
+use crate::helpers::systemtime_to_timestamp;
+use crate::snapshot::LayerSnapshot;
+use crate::sys::hostcalls_impl;
+use crate::{host, Error, Result};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Read, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The most memory allocated up front for inflating a zip entry, whose size is only what the
+/// archive claims it to be.
+const MAX_PREALLOCATION: u64 = 1 << 20;
+
+#[derive(Clone, Debug)]
+enum Contents {
+    /// Stored uncompressed at the given range of the archive file.
+    Range { offset: u64, len: u64 },
+    /// Compressed zip entry with the given index.
+    ZipEntry(usize),
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    File(Contents),
+    Directory,
+    Symlink(String),
+}
+
+#[derive(Clone, Debug)]
+struct ArchiveEntry {
+    node: Node,
+    stat: host::__wasi_filestat_t,
+}
+
+/// Index of the entries of an archive, keyed by their path relative to the archive root.
+#[derive(Debug)]
+struct Index {
+    entries: BTreeMap<PathBuf, ArchiveEntry>,
+    /// Modification time of the archive file, used for directories without an entry of their own.
+    mtim: host::__wasi_timestamp_t,
+    next_ino: host::__wasi_inode_t,
+}
+
+impl Index {
+    fn new(mtim: host::__wasi_timestamp_t) -> Self {
+        let mut index = Self {
+            entries: BTreeMap::new(),
+            mtim,
+            next_ino: 1,
+        };
+        index
+            .insert(PathBuf::new(), Node::Directory, 0, mtim)
+            .expect("the root directory has no parent");
+        index
+    }
+
+    /// Adds an entry, along with any missing parent directories. An entry which is present
+    /// already is replaced, as extracting the archive would do, but keeps its inode number.
+    ///
+    /// Fails with `ENOTDIR` if a parent of `path` is a file, or if a directory with entries of
+    /// its own would be replaced by a file, as the archive couldn't be extracted either.
+    fn insert(
+        &mut self,
+        path: PathBuf,
+        node: Node,
+        size: host::__wasi_filesize_t,
+        mtim: host::__wasi_timestamp_t,
+    ) -> Result<()> {
+        let st_filetype = match node {
+            Node::File(_) => host::__WASI_FILETYPE_REGULAR_FILE,
+            Node::Directory => host::__WASI_FILETYPE_DIRECTORY,
+            Node::Symlink(_) => host::__WASI_FILETYPE_SYMBOLIC_LINK,
+        };
+        if path.as_os_str().is_empty() && st_filetype != host::__WASI_FILETYPE_DIRECTORY {
+            log::debug!("ArchiveLayer skipping non-directory root entry");
+            return Ok(());
+        }
+        self.insert_parent(&path)?;
+        if st_filetype != host::__WASI_FILETYPE_DIRECTORY && self.has_children(&path) {
+            log::debug!("ArchiveLayer directory {:?} replaced by a file", path);
+            return Err(Error::ENOTDIR);
+        }
+
+        let st_ino = match self.entries.get(&path) {
+            Some(entry) => entry.stat.st_ino,
+            None => {
+                self.next_ino += 1;
+                self.next_ino - 1
+            }
+        };
+        let stat = host::__wasi_filestat_t {
+            st_dev: 0,
+            st_ino,
+            st_filetype,
+            st_nlink: 1,
+            st_size: size,
+            st_atim: mtim,
+            st_mtim: mtim,
+            st_ctim: mtim,
+        };
+        self.entries.insert(path, ArchiveEntry { node, stat });
+        Ok(())
+    }
+
+    /// Adds a hard link to an existing entry, which shares its inode number.
+    fn link(&mut self, path: PathBuf, entry: ArchiveEntry) -> Result<()> {
+        self.insert_parent(&path)?;
+        if self.has_children(&path) {
+            log::debug!("ArchiveLayer directory {:?} replaced by a hard link", path);
+            return Err(Error::ENOTDIR);
+        }
+        self.entries.insert(path, entry);
+        Ok(())
+    }
+
+    fn insert_parent(&mut self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            match self.entries.get(parent) {
+                Some(entry) => {
+                    if entry.stat.st_filetype != host::__WASI_FILETYPE_DIRECTORY {
+                        log::debug!("ArchiveLayer parent {:?} is not a directory", parent);
+                        return Err(Error::ENOTDIR);
+                    }
+                }
+                None => self.insert(parent.to_owned(), Node::Directory, 0, self.mtim)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn has_children(&self, path: &Path) -> bool {
+        self.entries.keys().any(|key| key.parent() == Some(path))
+    }
+
+    fn get(&self, path: &Path) -> Result<&ArchiveEntry> {
+        self.entries.get(path).ok_or(Error::ENOENT)
+    }
+}
+
+/// Turns the name of an archive entry into a path relative to the archive root, refusing names
+/// which would escape it or which aren't valid UTF-8.
+fn normalize(name: &Path) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(component) => path.push(component.to_str()?),
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            Component::ParentDir => return None,
+        }
+    }
+    Some(path)
+}
+
+/// A read-only layer serving the contents of a tar or zip archive.
+///
+/// The archive is indexed once when the layer is created. Uncompressed entries are read from the
+/// archive file on demand, while compressed zip entries are inflated whenever they're opened.
+#[derive(Debug)]
+pub(crate) struct ArchiveLayer {
+    path: PathBuf,
+    archive: Arc<File>,
+    zip: Option<Mutex<zip::ZipArchive<File>>>,
+    index: Index,
+}
+
+impl ArchiveLayer {
+    pub(crate) fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        let mut archive = File::open(&path)?;
+        let metadata = archive.metadata()?;
+        if !metadata.is_file() {
+            return Err(Error::EINVAL);
+        }
+        let mut index = Index::new(systemtime_to_timestamp(metadata.modified()?)?);
+
+        let mut magic = [0; 4];
+        let is_zip = match archive.read_exact(&mut magic) {
+            Ok(()) => &magic == b"PK\x03\x04" || &magic == b"PK\x05\x06",
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+            Err(e) => return Err(e.into()),
+        };
+
+        let zip = if is_zip {
+            let mut zip = zip::ZipArchive::new(archive.try_clone()?).map_err(zip_error)?;
+            index_zip(&mut index, &mut zip)?;
+            Some(Mutex::new(zip))
+        } else {
+            io::Seek::seek(&mut archive, SeekFrom::Start(0))?;
+            index_tar(&mut index, &mut archive)?;
+            None
+        };
+
+        Ok(Self {
+            path,
+            archive: Arc::new(archive),
+            zip,
+            index,
+        })
+    }
+
+    fn data(&self, contents: &Contents) -> Result<FileData> {
+        match *contents {
+            Contents::Range { offset, len } => Ok(FileData::Archived {
+                archive: Arc::clone(&self.archive),
+                offset,
+                len,
+            }),
+            Contents::ZipEntry(index) => {
+                let mut zip = self.zip.as_ref().ok_or(Error::EIO)?.lock().unwrap();
+                let mut entry = zip.by_index(index).map_err(zip_error)?;
+                let size = entry.size();
+                let mut data = Vec::with_capacity(size.min(MAX_PREALLOCATION).try_into()?);
+                // a corrupt entry may inflate to more than its size
+                (&mut entry).take(size).read_to_end(&mut data)?;
+                Ok(FileData::Inflated(Arc::new(data)))
+            }
+        }
+    }
+}
+
+fn index_tar(index: &mut Index, archive: &mut File) -> Result<()> {
+    for entry in tar::Archive::new(archive).entries()? {
+        let entry = entry?;
+        let name = entry.path()?;
+        let path = match normalize(&name) {
+            Some(path) => path,
+            None => {
+                log::debug!("ArchiveLayer skipping tar entry {:?}", name);
+                continue;
+            }
+        };
+        let header = entry.header();
+        let mtim = header.mtime()?.saturating_mul(1_000_000_000);
+        let entry_type = header.entry_type();
+
+        if entry_type.is_file() {
+            let contents = Contents::Range {
+                offset: entry.raw_file_position(),
+                len: entry.size(),
+            };
+            index.insert(path, Node::File(contents), entry.size(), mtim)?;
+        } else if entry_type.is_dir() {
+            index.insert(path, Node::Directory, 0, mtim)?;
+        } else if entry_type.is_symlink() {
+            let target = entry
+                .link_name()?
+                .as_ref()
+                .and_then(|target| target.to_str())
+                .map(str::to_owned);
+            match target {
+                Some(target) => {
+                    let size = target.len() as u64;
+                    index.insert(path, Node::Symlink(target), size, mtim)?;
+                }
+                None => log::debug!("ArchiveLayer skipping tar symlink {:?}", path),
+            }
+        } else if entry_type.is_hard_link() {
+            let target = entry
+                .link_name()?
+                .and_then(|target| normalize(&target))
+                .and_then(|target| index.entries.get(&target).cloned());
+            match target {
+                Some(target) => index.link(path, target)?,
+                None => log::debug!("ArchiveLayer skipping tar hard link {:?}", path),
+            }
+        } else {
+            log::debug!(
+                "ArchiveLayer skipping tar entry {:?} of type {:?}",
+                path,
+                entry_type
+            );
+        }
+    }
+    Ok(())
+}
+
+fn index_zip(index: &mut Index, zip: &mut zip::ZipArchive<File>) -> Result<()> {
+    for i in 0..zip.len() {
+        let mut entry = match zip.by_index(i) {
+            Ok(entry) => entry,
+            // e.g. encrypted entries, which can't be read without a password
+            Err(zip::result::ZipError::UnsupportedArchive(reason)) => {
+                log::debug!("ArchiveLayer skipping zip entry {}: {}", i, reason);
+                continue;
+            }
+            Err(e) => return Err(zip_error(e)),
+        };
+        let path = match entry.enclosed_name().and_then(normalize) {
+            Some(path) => path,
+            None => {
+                log::debug!("ArchiveLayer skipping zip entry {:?}", entry.name());
+                continue;
+            }
+        };
+        let mtim = dos_datetime_to_timestamp(entry.last_modified());
+
+        if entry.is_dir() {
+            index.insert(path, Node::Directory, 0, mtim)?;
+        } else if entry.unix_mode().unwrap_or(0) & 0o170_000 == 0o120_000 {
+            let mut target = String::new();
+            if entry.read_to_string(&mut target).is_err() {
+                log::debug!("ArchiveLayer skipping zip symlink {:?}", path);
+                continue;
+            }
+            let size = target.len() as u64;
+            index.insert(path, Node::Symlink(target), size, mtim)?;
+        } else {
+            let contents = if entry.compression() == zip::CompressionMethod::Stored {
+                Contents::Range {
+                    offset: entry.data_start(),
+                    len: entry.size(),
+                }
+            } else {
+                Contents::ZipEntry(i)
+            };
+            index.insert(path, Node::File(contents), entry.size(), mtim)?;
+        }
+    }
+    Ok(())
+}
+
+fn zip_error(e: zip::result::ZipError) -> Error {
+    match e {
+        zip::result::ZipError::Io(e) => e.into(),
+        e => {
+            log::debug!("ArchiveLayer zip error: {}", e);
+            Error::EIO
+        }
+    }
+}
+
+/// Converts a zip timestamp, which has no time zone and is taken to be UTC, to nanoseconds since
+/// the Unix epoch.
+fn dos_datetime_to_timestamp(datetime: zip::DateTime) -> host::__wasi_timestamp_t {
+    // Days since the epoch of the given proleptic Gregorian date, see
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let (year, month, day) = (
+        i64::from(datetime.year()),
+        i64::from(datetime.month()),
+        i64::from(datetime.day()),
+    );
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let secs = days * 86_400
+        + i64::from(datetime.hour()) * 3_600
+        + i64::from(datetime.minute()) * 60
+        + i64::from(datetime.second());
+    (secs.max(0) as u64).saturating_mul(1_000_000_000)
+}
+
+impl Layer for ArchiveLayer {
+    fn stat(&self, path: &Path) -> Result<host::__wasi_filestat_t> {
+        self.index.get(path).map(|entry| entry.stat)
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<Dirent>> {
+        match self.index.get(path)?.node {
+            Node::Directory => {}
+            _ => return Err(Error::ENOTDIR),
+        }
+        self.index
+            .entries
+            .iter()
+            .filter(|(key, _)| key.parent() == Some(path))
+            .map(|(key, entry)| {
+                Ok(Dirent {
+                    name: key
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .ok_or(Error::EILSEQ)?
+                        .to_owned(),
+                    ino: entry.stat.st_ino,
+                    filetype: entry.stat.st_filetype,
+                })
+            })
+            .collect()
+    }
+
+    fn readlink(&self, path: &Path) -> Result<String> {
+        match &self.index.get(path)?.node {
+            Node::Symlink(target) => Ok(target.clone()),
+            _ => Err(Error::EINVAL),
+        }
+    }
+
+    fn open(&self, path: &Path, options: FileOptions) -> Result<Descriptor> {
+        if options.mutates() {
+            return Err(Error::EROFS);
+        }
+        let entry = self.index.get(path)?;
+        let data = match &entry.node {
+            Node::File(contents) => self.data(contents)?,
+            Node::Directory => return Err(Error::EISDIR),
+            Node::Symlink(_) => return Err(Error::ELOOP),
+        };
+        Ok(Descriptor::VirtualFile(Box::new(ArchiveFile {
+            archive: Arc::clone(&self.archive),
+            path: path.to_owned(),
+            data,
+            stat: entry.stat,
+            offset: 0,
+        })))
+    }
+
+    fn reader(&self, path: &Path) -> Result<Box<dyn Read + '_>> {
+        let entry = self.index.get(path)?;
+        let data = match &entry.node {
+            Node::File(contents) => self.data(contents)?,
+            _ => return Err(Error::EBADF),
+        };
+        Ok(Box::new(ArchiveFile {
+            archive: Arc::clone(&self.archive),
+            path: path.to_owned(),
+            data,
+            stat: entry.stat,
+            offset: 0,
+        }))
+    }
+
+    fn snapshot(&self) -> Result<LayerSnapshot> {
+        Ok(LayerSnapshot::Archive(self.path.clone()))
+    }
+
+    fn locate(&self, file: &dyn VirtualFile) -> Option<PathBuf> {
+        file.as_any()
+            .downcast_ref::<ArchiveFile>()
+            .filter(|file| Arc::ptr_eq(&file.archive, &self.archive))
+            .map(|file| file.path.clone())
+    }
+}
+
+#[derive(Clone, Debug)]
+enum FileData {
+    Archived {
+        archive: Arc<File>,
+        offset: u64,
+        len: u64,
+    },
+    Inflated(Arc<Vec<u8>>),
+}
+
+/// A regular file of an archive, opened for reading.
+#[derive(Debug)]
+pub(crate) struct ArchiveFile {
+    /// The archive the file was opened from, and its path within it.
+    archive: Arc<File>,
+    path: PathBuf,
+    data: FileData,
+    stat: host::__wasi_filestat_t,
+    offset: u64,
+}
+
+impl ArchiveFile {
+    fn len(&self) -> u64 {
+        match &self.data {
+            FileData::Archived { len, .. } => *len,
+            FileData::Inflated(data) => data.len() as u64,
+        }
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let remaining = self.len().saturating_sub(offset);
+        let len = match remaining.try_into() {
+            Ok(remaining) => buf.len().min(remaining),
+            Err(_) => buf.len(),
+        };
+        if len == 0 {
+            return Ok(0);
+        }
+        match &self.data {
+            FileData::Archived {
+                archive,
+                offset: start,
+                ..
+            } => hostcalls_impl::fd_pread(archive, &mut buf[..len], start + offset),
+            FileData::Inflated(data) => {
+                let start = offset as usize;
+                buf[..len].copy_from_slice(&data[start..start + len]);
+                Ok(len)
+            }
+        }
+    }
+}
+
+impl Read for ArchiveFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let nread = self
+            .read_at(buf, self.offset)
+            .map_err(|e| crate::errno::to_io_error(e.as_wasi_errno()))?;
+        self.offset += nread as u64;
+        Ok(nread)
+    }
+}
+
+impl VirtualFile for ArchiveFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VirtualFile>> {
+        Ok(Box::new(Self {
+            archive: Arc::clone(&self.archive),
+            path: self.path.clone(),
+            data: self.data.clone(),
+            stat: self.stat,
+            offset: self.offset,
+        }))
+    }
+
+    fn filetype(&self) -> host::__wasi_filetype_t {
+        host::__WASI_FILETYPE_REGULAR_FILE
+    }
+
+    fn rights_base(&self) -> host::__wasi_rights_t {
+        RIGHTS_READ_ONLY_FILE_BASE
+    }
+
+    fn rights_inheriting(&self) -> host::__wasi_rights_t {
+        host::RIGHTS_REGULAR_FILE_INHERITING
+    }
+
+    fn filestat_get(&self) -> Result<host::__wasi_filestat_t> {
+        Ok(self.stat)
+    }
+
+    fn read_vectored(&mut self, iovs: &mut [io::IoSliceMut]) -> Result<usize> {
+        let mut total = 0;
+        for iov in iovs {
+            let nread = self.read_at(iov, self.offset)?;
+            self.offset += nread as u64;
+            total += nread;
+            if nread < iov.len() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    fn pread(&self, buf: &mut [u8], offset: host::__wasi_filesize_t) -> Result<usize> {
+        self.read_at(buf, offset)
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(offset) => {
+                self.offset = offset;
+                return Ok(offset);
+            }
+            SeekFrom::Current(delta) => (self.offset, delta),
+            SeekFrom::End(delta) => (self.len(), delta),
+        };
+        let offset = if delta < 0 {
+            base.checked_sub(delta.wrapping_neg() as u64)
+        } else {
+            base.checked_add(delta as u64)
+        };
+        self.offset = offset.ok_or(Error::EINVAL)?;
+        Ok(self.offset)
+    }
+
+    fn offset(&self) -> Option<host::__wasi_filesize_t> {
+        Some(self.offset)
+    }
+
+    fn bytes_available(&self) -> Result<host::__wasi_filesize_t> {
+        Ok(self.len().saturating_sub(self.offset))
+    }
+}
@@ -0,0 +1,318 @@
+//! A directory of devices implemented in-process, see `WasiCtxBuilder::preopened_devices`.
+use super::{Dirent, VirtualFile};
+use crate::fdentry::Descriptor;
+use crate::{host, Error, Result};
+use rand::{thread_rng, RngCore};
+use std::any::Any;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// A device of a `DevDir`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Device {
+    /// Discards everything written to it, and is always at end of file.
+    Null,
+    /// Discards everything written to it, and reads as zeros.
+    Zero,
+    /// Discards everything written to it, and reads as random bytes from the same source as
+    /// `random_get`.
+    Random,
+    /// Opens the standard stream `fd` of the context again.
+    Stdio(host::__wasi_fd_t),
+}
+
+/// The entries of a `DevDir`, in the order `readdir` returns them.
+const DEVICES: &[(&str, Device)] = &[
+    ("null", Device::Null),
+    ("zero", Device::Zero),
+    ("random", Device::Random),
+    ("urandom", Device::Random),
+    ("stdin", Device::Stdio(0)),
+    ("stdout", Device::Stdio(1)),
+    ("stderr", Device::Stdio(2)),
+];
+
+/// The inode of the directory itself; its entries follow in the order of `DEVICES`.
+const DIR_INO: host::__wasi_inode_t = 1;
+
+fn stat(filetype: host::__wasi_filetype_t, ino: host::__wasi_inode_t) -> host::__wasi_filestat_t {
+    host::__wasi_filestat_t {
+        st_dev: 0,
+        st_ino: ino,
+        st_filetype: filetype,
+        st_nlink: 1,
+        st_size: 0,
+        st_atim: 0,
+        st_mtim: 0,
+        st_ctim: 0,
+    }
+}
+
+/// A read-only directory of devices, which stand in for those found in `/dev` on POSIX systems.
+///
+/// `random` and `urandom` are missing if the `Random` capability is disabled, so that they
+/// can't be used to work around it.
+#[derive(Clone, Debug)]
+pub(crate) struct DevDir {
+    random: bool,
+}
+
+impl DevDir {
+    pub(crate) fn new(random: bool) -> Self {
+        Self { random }
+    }
+
+    /// Opens the device at `path` relative to the directory, which is `.` for the directory
+    /// itself, as for `WasiCtx::snapshot`.
+    pub(crate) fn reopen(self, path: &Path, fdflags: host::__wasi_fdflags_t) -> Result<Descriptor> {
+        let path = path.to_str().ok_or(Error::ENOENT)?;
+        self.openat(path, false, false, 0, fdflags)
+    }
+
+    /// The device named `name`, along with its inode.
+    fn lookup(&self, name: &str) -> Result<(Device, host::__wasi_inode_t)> {
+        DEVICES
+            .iter()
+            .zip(DIR_INO + 1..)
+            .find(|((entry, _), _)| *entry == name)
+            .map(|((_, device), ino)| (*device, ino))
+            .filter(|(device, _)| self.random || *device != Device::Random)
+            .ok_or(Error::ENOENT)
+    }
+}
+
+impl VirtualFile for DevDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VirtualFile>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn filetype(&self) -> host::__wasi_filetype_t {
+        host::__WASI_FILETYPE_DIRECTORY
+    }
+
+    fn rights_base(&self) -> host::__wasi_rights_t {
+        host::RIGHTS_DIRECTORY_BASE
+    }
+
+    fn rights_inheriting(&self) -> host::__wasi_rights_t {
+        host::RIGHTS_DIRECTORY_INHERITING
+    }
+
+    fn filestat_get(&self) -> Result<host::__wasi_filestat_t> {
+        Ok(stat(host::__WASI_FILETYPE_DIRECTORY, DIR_INO))
+    }
+
+    fn readdir(&self) -> Result<Vec<Dirent>> {
+        let mut entries: Vec<_> = [".", ".."]
+            .iter()
+            .map(|name| Dirent {
+                name: (*name).to_owned(),
+                ino: DIR_INO,
+                filetype: host::__WASI_FILETYPE_DIRECTORY,
+            })
+            .collect();
+        for (name, _) in DEVICES {
+            if let Ok((_, ino)) = self.lookup(name) {
+                entries.push(Dirent {
+                    name: (*name).to_owned(),
+                    ino,
+                    filetype: host::__WASI_FILETYPE_CHARACTER_DEVICE,
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    fn stdio_alias(&self, path: &str) -> Option<host::__wasi_fd_t> {
+        match self.lookup(path) {
+            Ok((Device::Stdio(fd), _)) => Some(fd),
+            _ => None,
+        }
+    }
+
+    fn openat(
+        &self,
+        path: &str,
+        _read: bool,
+        _write: bool,
+        oflags: host::__wasi_oflags_t,
+        fdflags: host::__wasi_fdflags_t,
+    ) -> Result<Descriptor> {
+        let name = path.trim_end_matches('/');
+        let dir_only = name.len() != path.len() || oflags & host::__WASI_O_DIRECTORY != 0;
+        if name == "." {
+            if oflags & (host::__WASI_O_CREAT | host::__WASI_O_TRUNC) != 0 {
+                return Err(Error::EISDIR);
+            }
+            return Ok(Descriptor::VirtualFile(Box::new(self.clone())));
+        }
+
+        let (device, ino) = match self.lookup(name) {
+            Ok(found) => found,
+            Err(_) if oflags & host::__WASI_O_CREAT != 0 => return Err(Error::EROFS),
+            Err(e) => return Err(e),
+        };
+        if oflags & host::__WASI_O_CREAT != 0 && oflags & host::__WASI_O_EXCL != 0 {
+            return Err(Error::EEXIST);
+        }
+        if dir_only {
+            return Err(Error::ENOTDIR);
+        }
+        match device {
+            // the standard streams are opened by `path_open` from the context itself
+            Device::Stdio(_) => Err(Error::ENOTSUP),
+            device => {
+                let file = DeviceFile {
+                    device,
+                    ino,
+                    fdflags: Mutex::new(0),
+                };
+                file.fdstat_set_flags(fdflags)?;
+                Ok(Descriptor::VirtualFile(Box::new(file)))
+            }
+        }
+    }
+
+    fn readlinkat(&self, path: &str) -> Result<String> {
+        self.lookup(path)?;
+        Err(Error::EINVAL)
+    }
+
+    fn create_directory(&self, _path: &str) -> Result<()> {
+        Err(Error::EROFS)
+    }
+
+    fn remove_directory(&self, _path: &str) -> Result<()> {
+        Err(Error::EROFS)
+    }
+
+    fn unlink_file(&self, _path: &str) -> Result<()> {
+        Err(Error::EROFS)
+    }
+
+    fn symlink(&self, _old_path: &str, _new_path: &str) -> Result<()> {
+        Err(Error::EROFS)
+    }
+
+    fn rename(&self, _old_path: &str, _new_dir: &dyn VirtualFile, _new_path: &str) -> Result<()> {
+        Err(Error::EROFS)
+    }
+
+    fn link(&self, _old_path: &str, _new_dir: &dyn VirtualFile, _new_path: &str) -> Result<()> {
+        Err(Error::EROFS)
+    }
+
+    fn filestat_get_at(&self, path: &str) -> Result<host::__wasi_filestat_t> {
+        match path.trim_end_matches('/') {
+            "." => self.filestat_get(),
+            name => {
+                let (_, ino) = self.lookup(name)?;
+                Ok(stat(host::__WASI_FILETYPE_CHARACTER_DEVICE, ino))
+            }
+        }
+    }
+
+    fn filestat_set_times_at(
+        &self,
+        _path: &str,
+        _atim: Option<SystemTime>,
+        _mtim: Option<SystemTime>,
+    ) -> Result<()> {
+        Err(Error::EROFS)
+    }
+}
+
+/// A device opened from a `DevDir`.
+#[derive(Debug)]
+struct DeviceFile {
+    device: Device,
+    ino: host::__wasi_inode_t,
+    fdflags: Mutex<host::__wasi_fdflags_t>,
+}
+
+impl VirtualFile for DeviceFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VirtualFile>> {
+        Ok(Box::new(Self {
+            device: self.device,
+            ino: self.ino,
+            fdflags: Mutex::new(self.fdstat_get()),
+        }))
+    }
+
+    fn filetype(&self) -> host::__wasi_filetype_t {
+        host::__WASI_FILETYPE_CHARACTER_DEVICE
+    }
+
+    fn rights_base(&self) -> host::__wasi_rights_t {
+        host::RIGHTS_CHARACTER_DEVICE_BASE
+    }
+
+    fn rights_inheriting(&self) -> host::__wasi_rights_t {
+        host::RIGHTS_CHARACTER_DEVICE_INHERITING
+    }
+
+    fn fdstat_get(&self) -> host::__wasi_fdflags_t {
+        *self.fdflags.lock().unwrap()
+    }
+
+    fn fdstat_set_flags(&self, fdflags: host::__wasi_fdflags_t) -> Result<()> {
+        // devices never block, and there's nothing to synchronize
+        if fdflags & !(host::__WASI_FDFLAG_APPEND | host::__WASI_FDFLAG_NONBLOCK) != 0 {
+            return Err(Error::ENOTSUP);
+        }
+        *self.fdflags.lock().unwrap() = fdflags;
+        Ok(())
+    }
+
+    fn filestat_get(&self) -> Result<host::__wasi_filestat_t> {
+        Ok(stat(host::__WASI_FILETYPE_CHARACTER_DEVICE, self.ino))
+    }
+
+    fn read_vectored(&mut self, iovs: &mut [io::IoSliceMut]) -> Result<usize> {
+        let mut nread = 0;
+        for iov in iovs {
+            match self.device {
+                Device::Null => break,
+                Device::Zero => {
+                    for byte in iov.iter_mut() {
+                        *byte = 0;
+                    }
+                }
+                Device::Random => thread_rng().fill_bytes(iov),
+                Device::Stdio(_) => unreachable!("standard streams aren't opened as devices"),
+            }
+            nread += iov.len();
+        }
+        Ok(nread)
+    }
+
+    fn write_vectored(&mut self, iovs: &[io::IoSlice]) -> Result<usize> {
+        Ok(iovs.iter().map(|iov| iov.len()).sum())
+    }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
+}
+
+/// The path relative to its `DevDir` of `file`, if it's the directory itself or one of its
+/// devices.
+pub(crate) fn device_path(file: &dyn VirtualFile) -> Option<PathBuf> {
+    let any = file.as_any();
+    if any.is::<DevDir>() {
+        return Some(PathBuf::from("."));
+    }
+    let ino = any.downcast_ref::<DeviceFile>()?.ino;
+    let (name, _) = DEVICES.get((ino - DIR_INO - 1) as usize)?;
+    Some(PathBuf::from(name))
+}
@@ -0,0 +1,363 @@
+use super::{Dirent, FileOptions, VirtualFile, RIGHTS_READ_ONLY_FILE_BASE};
+use crate::fdentry::Descriptor;
+use crate::helpers::{stream_position, systemtime_to_timestamp};
+use crate::hostcalls_impl::PathGet;
+use crate::snapshot::LayerSnapshot;
+use crate::sys::fdentry_impl::OsFile;
+use crate::sys::host_impl;
+use crate::sys::hostcalls_impl::{self, fs_helpers};
+use crate::{host, Error, Result};
+use std::any::Any;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+/// A directory tree making up one layer of an `OverlayFs`.
+///
+/// Paths are relative to the root of the layer, which is the empty path, and have already been
+/// resolved by the overlay: none of their components but the last one may be a symlink. Layers
+/// are read-only unless they override the mutating methods.
+pub(crate) trait Layer: fmt::Debug + Send + Sync {
+    /// Metadata of the entry at `path`, without following symlinks.
+    fn stat(&self, path: &Path) -> Result<host::__wasi_filestat_t>;
+
+    /// Entries of the directory at `path`, excluding `.` and `..`.
+    fn read_dir(&self, path: &Path) -> Result<Vec<Dirent>>;
+
+    fn readlink(&self, path: &Path) -> Result<String>;
+
+    /// Opens the regular file at `path`, creating it if requested by `options`.
+    fn open(&self, path: &Path, options: FileOptions) -> Result<Descriptor>;
+
+    /// Contents of the regular file at `path`, which are read when copying it to another layer.
+    fn reader(&self, path: &Path) -> Result<Box<dyn Read + '_>>;
+
+    /// Description of this layer for `WasiCtx::snapshot`, which includes its contents unless
+    /// they're stored on the host.
+    fn snapshot(&self) -> Result<LayerSnapshot>;
+
+    /// The path of `file`, if it's a regular file opened from this layer.
+    fn locate(&self, _file: &dyn VirtualFile) -> Option<PathBuf> {
+        None
+    }
+
+    /// Creates, or replaces, the regular file at `path` with the given contents.
+    fn write_file(&self, _path: &Path, _contents: &mut dyn Read) -> Result<()> {
+        Err(Error::EROFS)
+    }
+
+    fn create_dir(&self, _path: &Path) -> Result<()> {
+        Err(Error::EROFS)
+    }
+
+    fn remove_dir(&self, _path: &Path) -> Result<()> {
+        Err(Error::EROFS)
+    }
+
+    fn remove_file(&self, _path: &Path) -> Result<()> {
+        Err(Error::EROFS)
+    }
+
+    fn rename(&self, _old_path: &Path, _new_path: &Path) -> Result<()> {
+        Err(Error::EROFS)
+    }
+
+    fn symlink(&self, _old_path: &str, _new_path: &Path) -> Result<()> {
+        Err(Error::EROFS)
+    }
+
+    fn hard_link(&self, _old_path: &Path, _new_path: &Path) -> Result<()> {
+        Err(Error::EROFS)
+    }
+
+    fn set_times(
+        &self,
+        _path: &Path,
+        _atim: Option<SystemTime>,
+        _mtim: Option<SystemTime>,
+    ) -> Result<()> {
+        Err(Error::EROFS)
+    }
+}
+
+/// A layer backed by a directory of the host file system.
+///
+/// Paths are resolved one component at a time from a descriptor of the root, without following
+/// symlinks: the overlay has resolved them beforehand, but the guest may have replaced one of
+/// their directories by a symlink since, such as the directory one of its descriptors was opened
+/// at, which must not lead out of the layer.
+#[derive(Debug)]
+pub(crate) struct HostLayer {
+    root: PathBuf,
+    dir: File,
+    read_only: bool,
+}
+
+impl HostLayer {
+    pub(crate) fn new<P: AsRef<Path>>(root: P, read_only: bool) -> Result<Self> {
+        let root = root.as_ref().to_owned();
+        if !fs::metadata(&root)?.is_dir() {
+            return Err(Error::ENOTDIR);
+        }
+        let dir = crate::sys::preopen_dir(&root)?;
+        Ok(Self {
+            root,
+            dir,
+            read_only,
+        })
+    }
+
+    /// The directory containing `path` and the name of its last component, which is `.` for the
+    /// root itself.
+    fn resolve(&self, path: &Path) -> Result<PathGet> {
+        let mut names = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => names.push(name.to_str().ok_or(Error::EILSEQ)?),
+                Component::CurDir => {}
+                _ => return Err(Error::ENOTCAPABLE),
+            }
+        }
+        let name = names.pop().unwrap_or(".");
+        let mut dir = self.dir.try_clone()?;
+        for parent in names {
+            dir = fs_helpers::openat(&dir, parent)?;
+        }
+        Ok(PathGet::host(dir, name.to_owned()))
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(Error::EROFS)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Layer for HostLayer {
+    fn stat(&self, path: &Path) -> Result<host::__wasi_filestat_t> {
+        let resolved = self.resolve(path)?;
+        fs_helpers::lstatat(resolved.dirfd(), resolved.path())
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<Dirent>> {
+        let resolved = self.resolve(path)?;
+        fs_helpers::read_dir(resolved.dirfd(), resolved.path())
+    }
+
+    fn readlink(&self, path: &Path) -> Result<String> {
+        let resolved = self.resolve(path)?;
+        fs_helpers::readlinkat(resolved.dirfd(), resolved.path())
+    }
+
+    fn open(&self, path: &Path, mut options: FileOptions) -> Result<Descriptor> {
+        if options.mutates() {
+            self.check_writable()?;
+        }
+        if self.read_only {
+            // The overlay only opens existing files in the lower layer.
+            options.create = false;
+            options.create_new = false;
+        }
+
+        let mut oflags = 0;
+        if options.create {
+            oflags |= host::__WASI_O_CREAT;
+        }
+        if options.create_new {
+            oflags |= host::__WASI_O_EXCL;
+        }
+        if options.truncate {
+            oflags |= host::__WASI_O_TRUNC;
+        }
+        let file = hostcalls_impl::path_open(
+            self.resolve(path)?,
+            options.read || !options.write,
+            options.write,
+            oflags,
+            options.fdflags,
+        )?;
+
+        if self.read_only {
+            let path = path.to_str().ok_or(Error::EILSEQ)?;
+            Ok(Descriptor::VirtualFile(Box::new(ReadOnlyFile {
+                file,
+                host_path: self.root.join(host_impl::path_to_host(path)?),
+            })))
+        } else {
+            Ok(Descriptor::OsFile(OsFile::from(file)))
+        }
+    }
+
+    fn reader(&self, path: &Path) -> Result<Box<dyn Read + '_>> {
+        let file = hostcalls_impl::path_open(self.resolve(path)?, true, false, 0, 0)?;
+        Ok(Box::new(file))
+    }
+
+    fn snapshot(&self) -> Result<LayerSnapshot> {
+        Ok(LayerSnapshot::Host {
+            root: self.root.clone(),
+            read_only: self.read_only,
+        })
+    }
+
+    fn locate(&self, file: &dyn VirtualFile) -> Option<PathBuf> {
+        // Files of writable layers are opened as host files rather than virtual ones.
+        let file = file.as_any().downcast_ref::<ReadOnlyFile>()?;
+        let path = file.host_path.strip_prefix(&self.root).ok()?;
+        host_impl::path_from_host(path).ok().map(PathBuf::from)
+    }
+
+    fn write_file(&self, path: &Path, contents: &mut dyn Read) -> Result<()> {
+        self.check_writable()?;
+        let mut file = hostcalls_impl::path_open(
+            self.resolve(path)?,
+            false,
+            true,
+            host::__WASI_O_CREAT | host::__WASI_O_TRUNC,
+            0,
+        )?;
+        io::copy(contents, &mut file)?;
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.check_writable()?;
+        hostcalls_impl::path_create_directory(self.resolve(path)?)
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        self.check_writable()?;
+        hostcalls_impl::path_remove_directory(self.resolve(path)?)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        self.check_writable()?;
+        hostcalls_impl::path_unlink_file(self.resolve(path)?)
+    }
+
+    fn rename(&self, old_path: &Path, new_path: &Path) -> Result<()> {
+        self.check_writable()?;
+        hostcalls_impl::path_rename(&self.resolve(old_path)?, &self.resolve(new_path)?)
+    }
+
+    fn symlink(&self, old_path: &str, new_path: &Path) -> Result<()> {
+        self.check_writable()?;
+        // the target is escaped like any other path, which `path_symlink` undoes
+        hostcalls_impl::path_symlink(old_path, self.resolve(new_path)?)
+    }
+
+    fn hard_link(&self, old_path: &Path, new_path: &Path) -> Result<()> {
+        self.check_writable()?;
+        let old = self.resolve(old_path)?;
+        let new = self.resolve(new_path)?;
+        fs_helpers::linkat(old.dirfd(), old.path(), new.dirfd(), new.path())
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        atim: Option<SystemTime>,
+        mtim: Option<SystemTime>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        let mut fst_flags = 0;
+        let st_atim = match atim {
+            Some(atim) => {
+                fst_flags |= host::__WASI_FILESTAT_SET_ATIM;
+                systemtime_to_timestamp(atim)?
+            }
+            None => 0,
+        };
+        let st_mtim = match mtim {
+            Some(mtim) => {
+                fst_flags |= host::__WASI_FILESTAT_SET_MTIM;
+                systemtime_to_timestamp(mtim)?
+            }
+            None => 0,
+        };
+        hostcalls_impl::path_filestat_set_times(self.resolve(path)?, 0, st_atim, st_mtim, fst_flags)
+    }
+}
+
+/// A host file opened from a read-only layer, which must not be modified through its descriptor
+/// either, e.g. by setting its timestamps.
+#[derive(Debug)]
+pub(crate) struct ReadOnlyFile {
+    file: File,
+    host_path: PathBuf,
+}
+
+impl VirtualFile for ReadOnlyFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VirtualFile>> {
+        // Like other virtual files, the clone gets its own offset rather than sharing it.
+        let mut file = File::open(&self.host_path)?;
+        file.seek(SeekFrom::Start(stream_position(&self.file)?))?;
+        Ok(Box::new(Self {
+            file,
+            host_path: self.host_path.clone(),
+        }))
+    }
+
+    fn filetype(&self) -> host::__wasi_filetype_t {
+        host::__WASI_FILETYPE_REGULAR_FILE
+    }
+
+    fn rights_base(&self) -> host::__wasi_rights_t {
+        RIGHTS_READ_ONLY_FILE_BASE
+    }
+
+    fn rights_inheriting(&self) -> host::__wasi_rights_t {
+        host::RIGHTS_REGULAR_FILE_INHERITING
+    }
+
+    fn filestat_get(&self) -> Result<host::__wasi_filestat_t> {
+        hostcalls_impl::fd_filestat_get_impl(&self.file)
+    }
+
+    fn read_vectored(&mut self, iovs: &mut [io::IoSliceMut]) -> Result<usize> {
+        self.file.read_vectored(iovs).map_err(Into::into)
+    }
+
+    fn pread(&self, buf: &mut [u8], offset: host::__wasi_filesize_t) -> Result<usize> {
+        hostcalls_impl::fd_pread(&self.file, buf, offset)
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.file.seek(pos).map_err(Into::into)
+    }
+
+    fn offset(&self) -> Option<host::__wasi_filesize_t> {
+        stream_position(&self.file).ok()
+    }
+
+    fn host_path(&self) -> Option<PathBuf> {
+        Some(self.host_path.clone())
+    }
+
+    fn host_file(&self) -> Option<&File> {
+        Some(&self.file)
+    }
+
+    fn bytes_available(&self) -> Result<host::__wasi_filesize_t> {
+        let len = self.file.metadata()?.len();
+        let pos = stream_position(&self.file)?;
+        Ok(len.saturating_sub(pos))
+    }
+
+    fn advise(
+        &self,
+        advice: host::__wasi_advice_t,
+        offset: host::__wasi_filesize_t,
+        len: host::__wasi_filesize_t,
+    ) -> Result<()> {
+        hostcalls_impl::fd_advise(&self.file, advice, offset, len)
+    }
+}
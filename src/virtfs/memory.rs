@@ -0,0 +1,627 @@
+use super::{Dirent, FileOptions, Layer, VirtualFile};
+use crate::fdentry::Descriptor;
+use crate::helpers::{systemtime_to_timestamp, timestamp_to_systemtime};
+use crate::snapshot::{LayerSnapshot, MemoryEntry, MemoryNode};
+use crate::{host, Error, Result};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io::{self, Read, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+#[derive(Debug)]
+enum InodeKind {
+    File(Vec<u8>),
+    Directory,
+    Symlink(String),
+}
+
+#[derive(Debug)]
+struct Inode {
+    ino: host::__wasi_inode_t,
+    kind: InodeKind,
+    nlink: host::__wasi_linkcount_t,
+    atim: SystemTime,
+    mtim: SystemTime,
+    ctim: SystemTime,
+}
+
+impl Inode {
+    fn filetype(&self) -> host::__wasi_filetype_t {
+        match self.kind {
+            InodeKind::File(_) => host::__WASI_FILETYPE_REGULAR_FILE,
+            InodeKind::Directory => host::__WASI_FILETYPE_DIRECTORY,
+            InodeKind::Symlink(_) => host::__WASI_FILETYPE_SYMBOLIC_LINK,
+        }
+    }
+
+    fn filestat(&self) -> Result<host::__wasi_filestat_t> {
+        let size = match &self.kind {
+            InodeKind::File(data) => data.len(),
+            InodeKind::Directory => 0,
+            InodeKind::Symlink(target) => target.len(),
+        };
+        Ok(host::__wasi_filestat_t {
+            st_dev: 0,
+            st_ino: self.ino,
+            st_filetype: self.filetype(),
+            st_nlink: self.nlink,
+            st_size: size.try_into()?,
+            st_atim: systemtime_to_timestamp(self.atim)?,
+            st_mtim: systemtime_to_timestamp(self.mtim)?,
+            st_ctim: systemtime_to_timestamp(self.ctim)?,
+        })
+    }
+
+    fn data(&self) -> Result<&Vec<u8>> {
+        match &self.kind {
+            InodeKind::File(data) => Ok(data),
+            _ => Err(Error::EBADF),
+        }
+    }
+
+    fn data_mut(&mut self) -> Result<&mut Vec<u8>> {
+        let now = SystemTime::now();
+        self.mtim = now;
+        self.ctim = now;
+        match &mut self.kind {
+            InodeKind::File(data) => Ok(data),
+            _ => Err(Error::EBADF),
+        }
+    }
+
+    fn set_times(&mut self, atim: Option<SystemTime>, mtim: Option<SystemTime>) {
+        if let Some(atim) = atim {
+            self.atim = atim;
+        }
+        if let Some(mtim) = mtim {
+            self.mtim = mtim;
+        }
+        self.ctim = SystemTime::now();
+    }
+}
+
+type InodeRef = Arc<Mutex<Inode>>;
+
+fn lock(inode: &InodeRef) -> MutexGuard<'_, Inode> {
+    inode.lock().unwrap()
+}
+
+#[derive(Debug)]
+struct Tree {
+    inodes: BTreeMap<PathBuf, InodeRef>,
+    next_ino: host::__wasi_inode_t,
+}
+
+impl Tree {
+    fn get(&self, path: &Path) -> Result<&InodeRef> {
+        self.inodes.get(path).ok_or(Error::ENOENT)
+    }
+
+    /// Checks that the parent directory of `path` exists, and that `path` itself doesn't.
+    fn check_new(&self, path: &Path) -> Result<()> {
+        let parent = path.parent().ok_or(Error::EEXIST)?;
+        match lock(self.get(parent)?).kind {
+            InodeKind::Directory => {}
+            _ => return Err(Error::ENOTDIR),
+        }
+        if self.inodes.contains_key(path) {
+            return Err(Error::EEXIST);
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, path: &Path, kind: InodeKind) -> InodeRef {
+        let now = SystemTime::now();
+        let inode = Arc::new(Mutex::new(Inode {
+            ino: self.next_ino,
+            kind,
+            nlink: 1,
+            atim: now,
+            mtim: now,
+            ctim: now,
+        }));
+        self.next_ino += 1;
+        self.inodes.insert(path.to_owned(), Arc::clone(&inode));
+        inode
+    }
+
+    fn has_children(&self, path: &Path) -> bool {
+        self.inodes
+            .keys()
+            .any(|key| key.parent() == Some(path) && key != path)
+    }
+
+    fn unlink(&mut self, path: &Path) {
+        if let Some(inode) = self.inodes.remove(path) {
+            let mut inode = lock(&inode);
+            inode.nlink = inode.nlink.saturating_sub(1);
+            inode.ctim = SystemTime::now();
+        }
+    }
+}
+
+/// A layer keeping the whole directory tree in memory, which is discarded once the last
+/// descriptor referring to it is closed.
+#[derive(Debug)]
+pub(crate) struct MemoryLayer {
+    tree: Mutex<Tree>,
+}
+
+impl MemoryLayer {
+    pub(crate) fn new() -> Self {
+        let mut tree = Tree {
+            inodes: BTreeMap::new(),
+            next_ino: 1,
+        };
+        tree.insert(Path::new(""), InodeKind::Directory);
+        Self {
+            tree: Mutex::new(tree),
+        }
+    }
+
+    /// A layer with the contents recorded by `Layer::snapshot`.
+    pub(crate) fn from_snapshot(entries: &[MemoryEntry]) -> Result<Self> {
+        let layer = Self::new();
+        {
+            let mut tree = layer.tree();
+            for entry in entries {
+                let path = &entry.path;
+                let inode = if path.as_os_str().is_empty() {
+                    match entry.node {
+                        MemoryNode::Directory => Arc::clone(tree.get(path)?),
+                        _ => return Err(Error::ENOTDIR),
+                    }
+                } else {
+                    tree.check_new(path)?;
+                    match &entry.node {
+                        MemoryNode::File(data) => tree.insert(path, InodeKind::File(data.clone())),
+                        MemoryNode::Directory => tree.insert(path, InodeKind::Directory),
+                        MemoryNode::Symlink(target) => {
+                            tree.insert(path, InodeKind::Symlink(target.clone()))
+                        }
+                        MemoryNode::Link(target) => {
+                            let inode = Arc::clone(tree.get(target)?);
+                            if let InodeKind::Directory = lock(&inode).kind {
+                                return Err(Error::EPERM);
+                            }
+                            lock(&inode).nlink += 1;
+                            tree.inodes.insert(path.clone(), Arc::clone(&inode));
+                            continue;
+                        }
+                    }
+                };
+                lock(&inode).set_times(
+                    Some(timestamp_to_systemtime(entry.atim)),
+                    Some(timestamp_to_systemtime(entry.mtim)),
+                );
+            }
+        }
+        Ok(layer)
+    }
+
+    fn tree(&self) -> MutexGuard<'_, Tree> {
+        self.tree.lock().unwrap()
+    }
+}
+
+impl Layer for MemoryLayer {
+    fn stat(&self, path: &Path) -> Result<host::__wasi_filestat_t> {
+        lock(self.tree().get(path)?).filestat()
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<Dirent>> {
+        let tree = self.tree();
+        match lock(tree.get(path)?).kind {
+            InodeKind::Directory => {}
+            _ => return Err(Error::ENOTDIR),
+        }
+        tree.inodes
+            .iter()
+            .filter(|(key, _)| key.parent() == Some(path) && *key != path)
+            .map(|(key, inode)| {
+                let inode = lock(inode);
+                Ok(Dirent {
+                    name: key
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .ok_or(Error::EILSEQ)?
+                        .to_owned(),
+                    ino: inode.ino,
+                    filetype: inode.filetype(),
+                })
+            })
+            .collect()
+    }
+
+    fn readlink(&self, path: &Path) -> Result<String> {
+        match &lock(self.tree().get(path)?).kind {
+            InodeKind::Symlink(target) => Ok(target.clone()),
+            _ => Err(Error::EINVAL),
+        }
+    }
+
+    fn open(&self, path: &Path, options: FileOptions) -> Result<Descriptor> {
+        let mut tree = self.tree();
+        let inode = match tree.inodes.get(path) {
+            Some(inode) => {
+                if options.create_new {
+                    return Err(Error::EEXIST);
+                }
+                let inode = Arc::clone(inode);
+                {
+                    let mut locked = lock(&inode);
+                    match locked.kind {
+                        InodeKind::File(_) => {}
+                        InodeKind::Directory => return Err(Error::EISDIR),
+                        InodeKind::Symlink(_) => return Err(Error::ELOOP),
+                    }
+                    if options.truncate {
+                        locked.data_mut()?.clear();
+                    }
+                }
+                inode
+            }
+            None if options.create => {
+                tree.check_new(path)?;
+                tree.insert(path, InodeKind::File(Vec::new()))
+            }
+            None => return Err(Error::ENOENT),
+        };
+
+        Ok(Descriptor::VirtualFile(Box::new(InMemoryFile {
+            inode,
+            offset: 0,
+            read: options.read,
+            write: options.write,
+            fdflags: Mutex::new(options.fdflags),
+        })))
+    }
+
+    fn reader(&self, path: &Path) -> Result<Box<dyn Read + '_>> {
+        let data = lock(self.tree().get(path)?).data()?.clone();
+        Ok(Box::new(io::Cursor::new(data)))
+    }
+
+    fn snapshot(&self) -> Result<LayerSnapshot> {
+        let tree = self.tree();
+        // Paths of files with more than one link, which later links refer to.
+        let mut linked: Vec<(&InodeRef, &PathBuf)> = Vec::new();
+        let mut entries = Vec::with_capacity(tree.inodes.len());
+        for (path, inode) in &tree.inodes {
+            let first = linked
+                .iter()
+                .find(|(other, _)| Arc::ptr_eq(other, inode))
+                .map(|(_, first)| (*first).clone());
+            let locked = lock(inode);
+            let node = match (&locked.kind, first) {
+                (_, Some(first)) => MemoryNode::Link(first),
+                (InodeKind::File(data), None) => {
+                    if locked.nlink > 1 {
+                        linked.push((inode, path));
+                    }
+                    MemoryNode::File(data.clone())
+                }
+                (InodeKind::Directory, None) => MemoryNode::Directory,
+                (InodeKind::Symlink(target), None) => MemoryNode::Symlink(target.clone()),
+            };
+            entries.push(MemoryEntry {
+                path: path.clone(),
+                node,
+                atim: systemtime_to_timestamp(locked.atim)?,
+                mtim: systemtime_to_timestamp(locked.mtim)?,
+            });
+        }
+        Ok(LayerSnapshot::Memory(entries))
+    }
+
+    fn locate(&self, file: &dyn VirtualFile) -> Option<PathBuf> {
+        let file = file.as_any().downcast_ref::<InMemoryFile>()?;
+        self.tree()
+            .inodes
+            .iter()
+            .find(|(_, inode)| Arc::ptr_eq(inode, &file.inode))
+            .map(|(path, _)| path.clone())
+    }
+
+    fn write_file(&self, path: &Path, contents: &mut dyn Read) -> Result<()> {
+        let mut data = Vec::new();
+        contents.read_to_end(&mut data)?;
+
+        let mut tree = self.tree();
+        if let Some(inode) = tree.inodes.get(path) {
+            *lock(inode).data_mut()? = data;
+        } else {
+            tree.check_new(path)?;
+            tree.insert(path, InodeKind::File(data));
+        }
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        let mut tree = self.tree();
+        tree.check_new(path)?;
+        tree.insert(path, InodeKind::Directory);
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        let mut tree = self.tree();
+        match lock(tree.get(path)?).kind {
+            InodeKind::Directory => {}
+            _ => return Err(Error::ENOTDIR),
+        }
+        if path.parent().is_none() {
+            return Err(Error::EBUSY);
+        }
+        if tree.has_children(path) {
+            return Err(Error::ENOTEMPTY);
+        }
+        tree.unlink(path);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        let mut tree = self.tree();
+        if let InodeKind::Directory = lock(tree.get(path)?).kind {
+            return Err(Error::EISDIR);
+        }
+        tree.unlink(path);
+        Ok(())
+    }
+
+    fn rename(&self, old_path: &Path, new_path: &Path) -> Result<()> {
+        let mut tree = self.tree();
+        let old_is_dir = lock(tree.get(old_path)?).filetype() == host::__WASI_FILETYPE_DIRECTORY;
+        if old_path.parent().is_none() || new_path.parent().is_none() {
+            return Err(Error::EBUSY);
+        }
+        if old_path == new_path {
+            return Ok(());
+        }
+        if new_path.starts_with(old_path) {
+            return Err(Error::EINVAL);
+        }
+
+        match tree.inodes.get(new_path) {
+            Some(existing) => {
+                let new_is_dir = lock(existing).filetype() == host::__WASI_FILETYPE_DIRECTORY;
+                match (old_is_dir, new_is_dir) {
+                    (true, false) => return Err(Error::ENOTDIR),
+                    (false, true) => return Err(Error::EISDIR),
+                    (true, true) if tree.has_children(new_path) => return Err(Error::ENOTEMPTY),
+                    _ => {}
+                }
+                tree.unlink(new_path);
+            }
+            None => tree.check_new(new_path)?,
+        }
+
+        let moved: Vec<PathBuf> = tree
+            .inodes
+            .keys()
+            .filter(|key| key.starts_with(old_path))
+            .cloned()
+            .collect();
+        for old_key in moved {
+            let inode = tree.inodes.remove(&old_key).unwrap();
+            let suffix = old_key.strip_prefix(old_path).unwrap();
+            let new_key = if suffix.as_os_str().is_empty() {
+                new_path.to_owned()
+            } else {
+                new_path.join(suffix)
+            };
+            tree.inodes.insert(new_key, inode);
+        }
+        Ok(())
+    }
+
+    fn symlink(&self, old_path: &str, new_path: &Path) -> Result<()> {
+        let mut tree = self.tree();
+        tree.check_new(new_path)?;
+        tree.insert(new_path, InodeKind::Symlink(old_path.to_owned()));
+        Ok(())
+    }
+
+    fn hard_link(&self, old_path: &Path, new_path: &Path) -> Result<()> {
+        let mut tree = self.tree();
+        let inode = Arc::clone(tree.get(old_path)?);
+        if let InodeKind::Directory = lock(&inode).kind {
+            return Err(Error::EPERM);
+        }
+        tree.check_new(new_path)?;
+        {
+            let mut locked = lock(&inode);
+            locked.nlink += 1;
+            locked.ctim = SystemTime::now();
+        }
+        tree.inodes.insert(new_path.to_owned(), inode);
+        Ok(())
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        atim: Option<SystemTime>,
+        mtim: Option<SystemTime>,
+    ) -> Result<()> {
+        lock(self.tree().get(path)?).set_times(atim, mtim);
+        Ok(())
+    }
+}
+
+/// A regular file of a `MemoryLayer`, opened with its own offset.
+#[derive(Debug)]
+pub(crate) struct InMemoryFile {
+    inode: InodeRef,
+    offset: u64,
+    read: bool,
+    write: bool,
+    fdflags: Mutex<host::__wasi_fdflags_t>,
+}
+
+impl InMemoryFile {
+    fn read_at(data: &[u8], buf: &mut [u8], offset: u64) -> usize {
+        let start = match offset.try_into() {
+            Ok(start) if start < data.len() => start,
+            _ => return 0,
+        };
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        len
+    }
+
+    fn write_at(data: &mut Vec<u8>, buf: &[u8], offset: u64) -> Result<usize> {
+        let start: usize = offset.try_into()?;
+        let end = start.checked_add(buf.len()).ok_or(Error::EFBIG)?;
+        if end > data.len() {
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+}
+
+impl VirtualFile for InMemoryFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VirtualFile>> {
+        Ok(Box::new(Self {
+            inode: Arc::clone(&self.inode),
+            offset: self.offset,
+            read: self.read,
+            write: self.write,
+            fdflags: Mutex::new(self.fdstat_get()),
+        }))
+    }
+
+    fn filetype(&self) -> host::__wasi_filetype_t {
+        host::__WASI_FILETYPE_REGULAR_FILE
+    }
+
+    fn rights_base(&self) -> host::__wasi_rights_t {
+        let mut rights = host::RIGHTS_REGULAR_FILE_BASE;
+        if !self.read {
+            rights &= !host::__WASI_RIGHT_FD_READ;
+        }
+        if !self.write {
+            rights &= !host::__WASI_RIGHT_FD_WRITE;
+        }
+        rights
+    }
+
+    fn rights_inheriting(&self) -> host::__wasi_rights_t {
+        host::RIGHTS_REGULAR_FILE_INHERITING
+    }
+
+    fn fdstat_get(&self) -> host::__wasi_fdflags_t {
+        *self.fdflags.lock().unwrap()
+    }
+
+    fn fdstat_set_flags(&self, fdflags: host::__wasi_fdflags_t) -> Result<()> {
+        *self.fdflags.lock().unwrap() = fdflags;
+        Ok(())
+    }
+
+    fn filestat_get(&self) -> Result<host::__wasi_filestat_t> {
+        lock(&self.inode).filestat()
+    }
+
+    fn filestat_set_times(&self, atim: Option<SystemTime>, mtim: Option<SystemTime>) -> Result<()> {
+        lock(&self.inode).set_times(atim, mtim);
+        Ok(())
+    }
+
+    fn filestat_set_size(&self, size: host::__wasi_filesize_t) -> Result<()> {
+        let size = size.try_into().map_err(|_| Error::EFBIG)?;
+        lock(&self.inode).data_mut()?.resize(size, 0);
+        Ok(())
+    }
+
+    fn read_vectored(&mut self, iovs: &mut [io::IoSliceMut]) -> Result<usize> {
+        let inode = lock(&self.inode);
+        let data = inode.data()?;
+        let mut total = 0;
+        for iov in iovs {
+            let nread = Self::read_at(data, iov, self.offset);
+            self.offset += nread as u64;
+            total += nread;
+            if nread < iov.len() {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    fn write_vectored(&mut self, iovs: &[io::IoSlice]) -> Result<usize> {
+        let mut inode = lock(&self.inode);
+        let data = inode.data_mut()?;
+        if self.fdstat_get() & host::__WASI_FDFLAG_APPEND != 0 {
+            self.offset = data.len() as u64;
+        }
+        let mut total = 0;
+        for iov in iovs {
+            let nwritten = Self::write_at(data, iov, self.offset)?;
+            self.offset += nwritten as u64;
+            total += nwritten;
+        }
+        Ok(total)
+    }
+
+    fn pread(&self, buf: &mut [u8], offset: host::__wasi_filesize_t) -> Result<usize> {
+        Ok(Self::read_at(lock(&self.inode).data()?, buf, offset))
+    }
+
+    fn pwrite(&self, buf: &[u8], offset: host::__wasi_filesize_t) -> Result<usize> {
+        Self::write_at(lock(&self.inode).data_mut()?, buf, offset)
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(offset) => {
+                self.offset = offset;
+                return Ok(offset);
+            }
+            SeekFrom::Current(delta) => (self.offset, delta),
+            SeekFrom::End(delta) => (lock(&self.inode).data()?.len() as u64, delta),
+        };
+        let offset = if delta < 0 {
+            base.checked_sub(delta.wrapping_neg() as u64)
+        } else {
+            base.checked_add(delta as u64)
+        };
+        self.offset = offset.ok_or(Error::EINVAL)?;
+        Ok(self.offset)
+    }
+
+    fn offset(&self) -> Option<host::__wasi_filesize_t> {
+        Some(self.offset)
+    }
+
+    fn bytes_available(&self) -> Result<host::__wasi_filesize_t> {
+        let len = lock(&self.inode).data()?.len() as u64;
+        Ok(len.saturating_sub(self.offset))
+    }
+
+    fn allocate(
+        &self,
+        offset: host::__wasi_filesize_t,
+        len: host::__wasi_filesize_t,
+    ) -> Result<()> {
+        let end: usize = offset
+            .checked_add(len)
+            .ok_or(Error::EFBIG)?
+            .try_into()
+            .map_err(|_| Error::EFBIG)?;
+        let mut inode = lock(&self.inode);
+        let data = inode.data_mut()?;
+        if end > data.len() {
+            data.resize(end, 0);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+//! Files and directories which aren't backed by a single host file descriptor.
+//!
+//! A `VirtualFile` is stored in a `Descriptor::VirtualFile`, and every hostcall dispatches to it
+//! instead of the `sys` implementation when it encounters one. Directory trees are assembled from
+//! `Layer`s, see `OverlayFs` for how they are combined.
+#![allow(non_camel_case_types)]
+use crate::cancel::Waker;
+use crate::fdentry::Descriptor;
+use crate::{host, Error, Result};
+use std::any::Any;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, SeekFrom};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+#[cfg(feature = "archive")]
+mod archive;
+mod dev;
+mod layer;
+mod memory;
+mod overlay;
+mod pipe;
+
+#[cfg(feature = "archive")]
+pub(crate) use self::archive::ArchiveLayer;
+pub(crate) use self::dev::{device_path, DevDir};
+pub(crate) use self::layer::{HostLayer, Layer};
+pub(crate) use self::memory::MemoryLayer;
+pub use self::overlay::OverlayUpper;
+pub(crate) use self::overlay::{FsTable, OverlayFs};
+pub use self::pipe::{pipe, PipeEnd, PipeReader, PipeWriter};
+
+/// Base rights of regular files which can't be modified.
+pub(crate) const RIGHTS_READ_ONLY_FILE_BASE: host::__wasi_rights_t = host::RIGHTS_REGULAR_FILE_BASE
+    & !(host::__WASI_RIGHT_FD_DATASYNC
+        | host::__WASI_RIGHT_FD_WRITE
+        | host::__WASI_RIGHT_FD_ALLOCATE
+        | host::__WASI_RIGHT_FD_FILESTAT_SET_SIZE
+        | host::__WASI_RIGHT_FD_FILESTAT_SET_TIMES);
+
+/// An entry of a virtual directory, as returned by `VirtualFile::readdir`.
+#[derive(Clone, Debug)]
+pub(crate) struct Dirent {
+    pub(crate) name: String,
+    pub(crate) ino: host::__wasi_inode_t,
+    pub(crate) filetype: host::__wasi_filetype_t,
+}
+
+/// How a regular file should be opened by `Layer::open`.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct FileOptions {
+    pub(crate) read: bool,
+    pub(crate) write: bool,
+    pub(crate) create: bool,
+    pub(crate) create_new: bool,
+    pub(crate) truncate: bool,
+    pub(crate) fdflags: host::__wasi_fdflags_t,
+}
+
+impl FileOptions {
+    pub(crate) fn new(
+        read: bool,
+        write: bool,
+        oflags: host::__wasi_oflags_t,
+        fdflags: host::__wasi_fdflags_t,
+    ) -> Self {
+        Self {
+            read,
+            write,
+            create: oflags & host::__WASI_O_CREAT != 0,
+            create_new: oflags & host::__WASI_O_CREAT != 0 && oflags & host::__WASI_O_EXCL != 0,
+            truncate: oflags & host::__WASI_O_TRUNC != 0,
+            fdflags,
+        }
+    }
+
+    /// Whether opening with these options may modify the file.
+    pub(crate) fn mutates(self) -> bool {
+        self.write || self.truncate
+    }
+}
+
+/// The wakers to call whenever a virtual file may become ready, or stop being ready, see
+/// `VirtualFile::watch`.
+#[derive(Default)]
+pub(crate) struct Watchers {
+    wakers: Mutex<Vec<(usize, Waker)>>,
+    next_waker: AtomicUsize,
+}
+
+impl fmt::Debug for Watchers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watchers")
+            .field("wakers", &self.wakers.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Watchers {
+    /// Calls `wake` on every `notify` until the returned `Watch` is dropped.
+    pub(crate) fn watch(watchers: &Arc<Self>, wake: Waker) -> Watch {
+        let id = watchers.next_waker.fetch_add(1, Ordering::SeqCst);
+        watchers.wakers.lock().unwrap().push((id, wake));
+        Watch {
+            watchers: Arc::clone(watchers),
+            id,
+        }
+    }
+
+    pub(crate) fn notify(&self) {
+        // the wakers may lock what the watched file locks while notifying
+        let wakers: Vec<Waker> = self
+            .wakers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, wake)| Arc::clone(wake))
+            .collect();
+        for wake in wakers {
+            wake();
+        }
+    }
+}
+
+/// Stops calling a waker registered with `Watchers::watch` when dropped.
+pub(crate) struct Watch {
+    watchers: Arc<Watchers>,
+    id: usize,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        let id = self.id;
+        self.watchers
+            .wakers
+            .lock()
+            .unwrap()
+            .retain(|(other, _)| *other != id);
+    }
+}
+
+/// A file, or directory, which is implemented by `wasi-common` itself rather than by the host.
+///
+/// Methods operating on a path take a single path component relative to this directory, as
+/// resolved by `path_get`; `.` refers to the directory itself. All of them fail by default, so
+/// that implementors only need to provide what makes sense for the kind of file they represent.
+pub(crate) trait VirtualFile: fmt::Debug + Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+
+    fn try_clone(&self) -> io::Result<Box<dyn VirtualFile>>;
+
+    fn filetype(&self) -> host::__wasi_filetype_t;
+
+    /// Maximal base rights of this file.
+    fn rights_base(&self) -> host::__wasi_rights_t;
+
+    /// Maximal inheriting rights of this file.
+    fn rights_inheriting(&self) -> host::__wasi_rights_t;
+
+    fn fdstat_get(&self) -> host::__wasi_fdflags_t {
+        0
+    }
+
+    fn fdstat_set_flags(&self, _fdflags: host::__wasi_fdflags_t) -> Result<()> {
+        Err(Error::ENOTSUP)
+    }
+
+    fn filestat_get(&self) -> Result<host::__wasi_filestat_t>;
+
+    fn filestat_set_times(
+        &self,
+        _atim: Option<SystemTime>,
+        _mtim: Option<SystemTime>,
+    ) -> Result<()> {
+        Err(Error::EROFS)
+    }
+
+    fn filestat_set_size(&self, _size: host::__wasi_filesize_t) -> Result<()> {
+        Err(Error::EINVAL)
+    }
+
+    fn read_vectored(&mut self, _iovs: &mut [io::IoSliceMut]) -> Result<usize> {
+        Err(Error::EBADF)
+    }
+
+    fn write_vectored(&mut self, _iovs: &[io::IoSlice]) -> Result<usize> {
+        Err(Error::EBADF)
+    }
+
+    fn pread(&self, _buf: &mut [u8], _offset: host::__wasi_filesize_t) -> Result<usize> {
+        Err(Error::ESPIPE)
+    }
+
+    fn pwrite(&self, _buf: &[u8], _offset: host::__wasi_filesize_t) -> Result<usize> {
+        Err(Error::ESPIPE)
+    }
+
+    fn seek(&mut self, _pos: SeekFrom) -> Result<u64> {
+        Err(Error::ESPIPE)
+    }
+
+    /// The current offset of regular files, as reported by `WasiCtx::fd_info`.
+    fn offset(&self) -> Option<host::__wasi_filesize_t> {
+        None
+    }
+
+    /// The path of the host file backing this one, if any.
+    fn host_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// The open host file backing this one, if any.
+    fn host_file(&self) -> Option<&File> {
+        None
+    }
+
+    /// Number of bytes which can be read without blocking, as reported by `poll_oneoff`.
+    fn bytes_available(&self) -> Result<host::__wasi_filesize_t> {
+        Ok(0)
+    }
+
+    /// Whether reading from, or writing to, this file would currently block, depending on which
+    /// it supports. Such files aren't reported as ready by `poll_oneoff` until this changes.
+    fn would_block(&self) -> bool {
+        false
+    }
+
+    /// Whether the other end of a stream has been closed.
+    fn hung_up(&self) -> bool {
+        false
+    }
+
+    /// Calls `wake` whenever `would_block` or `hung_up` may have changed, until the returned
+    /// `Watch` is dropped, so that `poll_oneoff` can wait for the file. Files which never block
+    /// have nothing to watch.
+    fn watch(&self, _wake: Waker) -> Option<Watch> {
+        None
+    }
+
+    /// Whether this file is a stream without an offset, which can't be seeked.
+    fn is_stream(&self) -> bool {
+        false
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn datasync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn advise(
+        &self,
+        _advice: host::__wasi_advice_t,
+        _offset: host::__wasi_filesize_t,
+        _len: host::__wasi_filesize_t,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn allocate(
+        &self,
+        _offset: host::__wasi_filesize_t,
+        _len: host::__wasi_filesize_t,
+    ) -> Result<()> {
+        Err(Error::EINVAL)
+    }
+
+    /// All entries of this directory, including `.` and `..`, in a stable order.
+    fn readdir(&self) -> Result<Vec<Dirent>> {
+        Err(Error::ENOTDIR)
+    }
+
+    /// The descriptor of the context which opening `path` opens again, such as stdout for
+    /// `/dev/stdout`.
+    fn stdio_alias(&self, _path: &str) -> Option<host::__wasi_fd_t> {
+        None
+    }
+
+    fn openat(
+        &self,
+        _path: &str,
+        _read: bool,
+        _write: bool,
+        _oflags: host::__wasi_oflags_t,
+        _fdflags: host::__wasi_fdflags_t,
+    ) -> Result<Descriptor> {
+        Err(Error::ENOTDIR)
+    }
+
+    fn readlinkat(&self, _path: &str) -> Result<String> {
+        Err(Error::ENOTDIR)
+    }
+
+    fn create_directory(&self, _path: &str) -> Result<()> {
+        Err(Error::ENOTDIR)
+    }
+
+    fn remove_directory(&self, _path: &str) -> Result<()> {
+        Err(Error::ENOTDIR)
+    }
+
+    fn unlink_file(&self, _path: &str) -> Result<()> {
+        Err(Error::ENOTDIR)
+    }
+
+    fn symlink(&self, _old_path: &str, _new_path: &str) -> Result<()> {
+        Err(Error::ENOTDIR)
+    }
+
+    /// Renames `old_path` in this directory to `new_path` in `new_dir`, which is `EXDEV` unless
+    /// both belong to the same file system.
+    fn rename(&self, _old_path: &str, _new_dir: &dyn VirtualFile, _new_path: &str) -> Result<()> {
+        Err(Error::ENOTDIR)
+    }
+
+    /// Links `old_path` in this directory to `new_path` in `new_dir`, which is `EXDEV` unless
+    /// both belong to the same file system.
+    fn link(&self, _old_path: &str, _new_dir: &dyn VirtualFile, _new_path: &str) -> Result<()> {
+        Err(Error::ENOTDIR)
+    }
+
+    fn filestat_get_at(&self, _path: &str) -> Result<host::__wasi_filestat_t> {
+        Err(Error::ENOTDIR)
+    }
+
+    fn filestat_set_times_at(
+        &self,
+        _path: &str,
+        _atim: Option<SystemTime>,
+        _mtim: Option<SystemTime>,
+    ) -> Result<()> {
+        Err(Error::ENOTDIR)
+    }
+}
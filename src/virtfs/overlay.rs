@@ -0,0 +1,694 @@
+#[cfg(feature = "archive")]
+use super::ArchiveLayer;
+use super::{Dirent, FileOptions, HostLayer, Layer, MemoryLayer, VirtualFile};
+use crate::fdentry::Descriptor;
+use crate::helpers::timestamp_to_systemtime;
+use crate::snapshot::{FsSnapshot, LayerSnapshot};
+use crate::{host, Error, Result};
+use std::any::Any;
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Prefix of the entries of the upper layer hiding the lower entry of the same name.
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Entry of the upper layer hiding all lower entries of the directory containing it.
+const OPAQUE_MARKER: &str = ".wh..wh..opq";
+
+/// Where the writable upper layer of an overlay preopen is stored.
+#[derive(Clone, Debug)]
+pub enum OverlayUpper {
+    /// A directory on the host, which is either empty or was the upper layer of a previous
+    /// overlay of the same lower directory.
+    Host(PathBuf),
+    /// Memory, which is discarded along with the `WasiCtx`.
+    Memory,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Source {
+    Upper,
+    Lower,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    source: Source,
+    stat: host::__wasi_filestat_t,
+}
+
+impl Entry {
+    fn is_dir(&self) -> bool {
+        self.stat.st_filetype == host::__WASI_FILETYPE_DIRECTORY
+    }
+}
+
+fn is_not_found(e: &Error) -> bool {
+    let errno = e.as_wasi_errno();
+    errno == host::__WASI_ENOENT || errno == host::__WASI_ENOTDIR
+}
+
+/// Whether the last component of `path` is reserved for whiteouts, and thus hidden.
+fn is_reserved(path: &Path) -> bool {
+    match path.file_name() {
+        Some(name) => name.to_string_lossy().starts_with(WHITEOUT_PREFIX),
+        None => false,
+    }
+}
+
+fn whiteout_path(path: &Path) -> PathBuf {
+    let mut name = WHITEOUT_PREFIX.to_owned();
+    name.push_str(&path.file_name().unwrap_or_default().to_string_lossy());
+    path.with_file_name(name)
+}
+
+/// A read-only lower layer, merged with a writable upper layer receiving all modifications. Without
+/// an upper layer, the lower one is exposed as is, and modifications fail with `EROFS`.
+///
+/// Deletions are recorded with the whiteouts of AUFS, which OCI image layers use as well, rather
+/// than with the character devices and extended attributes of Linux's overlayfs: an upper
+/// `.wh.<name>` entry deletes the lower `<name>`, and an upper directory containing `.wh..wh..opq`
+/// hides the lower one entirely.
+/// Modified lower files are copied to the upper layer first, while renaming a lower directory
+/// fails with `EXDEV`.
+#[derive(Debug)]
+pub(crate) struct OverlayFs {
+    lower: Box<dyn Layer>,
+    upper: Option<Box<dyn Layer>>,
+}
+
+impl OverlayFs {
+    /// An overlay of `lower` and `upper`, or a read-only view of `lower` if there's no `upper`.
+    pub(crate) fn new(lower: Box<dyn Layer>, upper: Option<Box<dyn Layer>>) -> Self {
+        Self { lower, upper }
+    }
+
+    /// The root directory of this file system, to be preopened.
+    pub(crate) fn root(self) -> Box<dyn VirtualFile> {
+        Box::new(OverlayDir {
+            fs: Arc::new(self),
+            rel: PathBuf::new(),
+        })
+    }
+
+    /// Description of this file system for `WasiCtx::snapshot`.
+    pub(crate) fn snapshot(&self) -> Result<FsSnapshot> {
+        Ok(FsSnapshot {
+            lower: self.lower.snapshot()?,
+            upper: match &self.upper {
+                Some(upper) => Some(upper.snapshot()?),
+                None => None,
+            },
+        })
+    }
+
+    /// The file system recorded by `OverlayFs::snapshot`.
+    pub(crate) fn from_snapshot(snapshot: &FsSnapshot) -> Result<Self> {
+        fn layer(snapshot: &LayerSnapshot) -> Result<Box<dyn Layer>> {
+            Ok(match snapshot {
+                LayerSnapshot::Host { root, read_only } => {
+                    Box::new(HostLayer::new(root, *read_only)?)
+                }
+                #[cfg(feature = "archive")]
+                LayerSnapshot::Archive(path) => Box::new(ArchiveLayer::open(path)?),
+                LayerSnapshot::Memory(entries) => Box::new(MemoryLayer::from_snapshot(entries)?),
+            })
+        }
+
+        let upper = match &snapshot.upper {
+            Some(upper) => Some(layer(upper)?),
+            None => None,
+        };
+        Ok(Self::new(layer(&snapshot.lower)?, upper))
+    }
+
+    /// Opens the entry at `path` of `fs` again, for `WasiCtxBuilder::from_snapshot`.
+    pub(crate) fn reopen(
+        fs: &Arc<Self>,
+        path: &Path,
+        read: bool,
+        write: bool,
+        fdflags: host::__wasi_fdflags_t,
+    ) -> Result<Descriptor> {
+        let entry = fs.lookup(path)?.ok_or(Error::ENOENT)?;
+        if entry.is_dir() {
+            return Ok(Descriptor::VirtualFile(Box::new(OverlayDir {
+                fs: Arc::clone(fs),
+                rel: path.to_owned(),
+            })));
+        }
+        let name = path
+            .file_name()
+            .ok_or(Error::EINVAL)?
+            .to_str()
+            .ok_or(Error::EILSEQ)?;
+        let dir = OverlayDir {
+            fs: Arc::clone(fs),
+            rel: path.parent().unwrap_or_else(|| Path::new("")).to_owned(),
+        };
+        dir.openat(name, read, write, 0, fdflags)
+    }
+
+    /// The path of the regular file `file`, if it was opened from this file system.
+    fn locate(&self, file: &dyn VirtualFile) -> Option<PathBuf> {
+        let upper = self.upper.as_ref().and_then(|upper| upper.locate(file));
+        upper.or_else(|| self.lower.locate(file))
+    }
+
+    /// The upper layer, which all modifications are made to.
+    fn upper(&self) -> Result<&dyn Layer> {
+        match &self.upper {
+            Some(upper) => Ok(upper.as_ref()),
+            None => Err(Error::EROFS),
+        }
+    }
+
+    fn upper_stat(&self, path: &Path) -> Result<Option<host::__wasi_filestat_t>> {
+        let upper = match &self.upper {
+            Some(upper) => upper,
+            None => return Ok(None),
+        };
+        match upper.stat(path) {
+            Ok(stat) => Ok(Some(stat)),
+            Err(ref e) if is_not_found(e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn is_whiteout(&self, path: &Path) -> Result<bool> {
+        if path.as_os_str().is_empty() {
+            return Ok(false);
+        }
+        self.upper_stat(&whiteout_path(path))
+            .map(|stat| stat.is_some())
+    }
+
+    /// Whether the lower layer shows through at `dir`, i.e. neither `dir` nor any of its
+    /// ancestors are deleted, replaced by a non-directory, or opaque in the upper layer.
+    fn lower_visible(&self, dir: &Path) -> Result<bool> {
+        for ancestor in dir.ancestors() {
+            if self.is_whiteout(ancestor)? {
+                return Ok(false);
+            }
+            match self.upper_stat(ancestor)? {
+                Some(ref stat) if stat.st_filetype != host::__WASI_FILETYPE_DIRECTORY => {
+                    return Ok(false)
+                }
+                Some(_) if self.upper_stat(&ancestor.join(OPAQUE_MARKER))?.is_some() => {
+                    return Ok(false)
+                }
+                _ => {}
+            }
+        }
+        Ok(true)
+    }
+
+    /// Whether the lower layer has an entry at `path`, which needs to be hidden by a whiteout
+    /// once `path` is removed from the merged view.
+    fn in_lower(&self, path: &Path) -> Result<bool> {
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        if !self.lower_visible(parent)? {
+            return Ok(false);
+        }
+        match self.lower.stat(path) {
+            Ok(_) => Ok(true),
+            Err(ref e) if is_not_found(e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The merged entry at `path`, if any.
+    fn lookup(&self, path: &Path) -> Result<Option<Entry>> {
+        if is_reserved(path) {
+            return Ok(None);
+        }
+        if let Some(stat) = self.upper_stat(path)? {
+            return Ok(Some(Entry {
+                source: Source::Upper,
+                stat,
+            }));
+        }
+        if self.is_whiteout(path)? || !self.in_lower(path)? {
+            return Ok(None);
+        }
+        Ok(Some(Entry {
+            source: Source::Lower,
+            stat: self.lower.stat(path)?,
+        }))
+    }
+
+    /// Entries of the merged directory at `dir`, excluding `.` and `..`.
+    fn read_dir(&self, dir: &Path) -> Result<BTreeMap<String, Dirent>> {
+        let mut entries = BTreeMap::new();
+        let mut whiteouts = HashSet::new();
+        if self.upper_stat(dir)?.is_some() {
+            for entry in self.upper()?.read_dir(dir)? {
+                if entry.name == OPAQUE_MARKER {
+                    continue;
+                } else if entry.name.starts_with(WHITEOUT_PREFIX) {
+                    whiteouts.insert(entry.name[WHITEOUT_PREFIX.len()..].to_owned());
+                } else {
+                    entries.insert(entry.name.clone(), entry);
+                }
+            }
+        }
+        if self.lower_visible(dir)? {
+            let lower_entries = match self.lower.read_dir(dir) {
+                Ok(lower_entries) => lower_entries,
+                Err(ref e) if is_not_found(e) => Vec::new(),
+                Err(e) => return Err(e),
+            };
+            for entry in lower_entries {
+                if !entry.name.starts_with(WHITEOUT_PREFIX)
+                    && !whiteouts.contains(&entry.name)
+                    && !entries.contains_key(&entry.name)
+                {
+                    entries.insert(entry.name.clone(), entry);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Makes sure that the merged directory `dir` exists in the upper layer.
+    fn copy_up_dir(&self, dir: &Path) -> Result<()> {
+        let mut missing = Vec::new();
+        for ancestor in dir.ancestors() {
+            if self.upper_stat(ancestor)?.is_some() {
+                break;
+            }
+            missing.push(ancestor);
+        }
+        for ancestor in missing.into_iter().rev() {
+            self.upper()?.create_dir(ancestor)?;
+        }
+        Ok(())
+    }
+
+    /// Copies the lower entry at `path` to the upper layer, so that it can be modified.
+    fn copy_up(&self, path: &Path, entry: Entry) -> Result<()> {
+        if entry.source == Source::Upper {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            self.copy_up_dir(parent)?;
+        }
+        match entry.stat.st_filetype {
+            host::__WASI_FILETYPE_DIRECTORY => self.upper()?.create_dir(path)?,
+            host::__WASI_FILETYPE_SYMBOLIC_LINK => {
+                self.upper()?.symlink(&self.lower.readlink(path)?, path)?
+            }
+            host::__WASI_FILETYPE_REGULAR_FILE => self
+                .upper()?
+                .write_file(path, &mut *self.lower.reader(path)?)?,
+            _ => return Err(Error::ENOTSUP),
+        }
+        self.upper()?.set_times(
+            path,
+            Some(timestamp_to_systemtime(entry.stat.st_atim)),
+            Some(timestamp_to_systemtime(entry.stat.st_mtim)),
+        )
+    }
+
+    /// Prepares the upper layer for creating a new entry at `path`.
+    fn prepare_create(&self, path: &Path) -> Result<()> {
+        if is_reserved(path) {
+            return Err(Error::EINVAL);
+        }
+        if let Some(parent) = path.parent() {
+            self.copy_up_dir(parent)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the whiteout at `path` once a new entry has been created there, returning
+    /// whether there was one.
+    fn finish_create(&self, path: &Path) -> Result<bool> {
+        if self.is_whiteout(path)? {
+            self.upper()?.remove_file(&whiteout_path(path))?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Removes `path` from the merged view.
+    fn remove(&self, path: &Path, entry: Entry) -> Result<()> {
+        if entry.source == Source::Upper {
+            if entry.is_dir() {
+                // only whiteouts are left, as the merged directory is empty
+                for upper_entry in self.upper()?.read_dir(path)? {
+                    self.upper()?.remove_file(&path.join(upper_entry.name))?;
+                }
+                self.upper()?.remove_dir(path)?;
+            } else {
+                self.upper()?.remove_file(path)?;
+            }
+        }
+        if self.in_lower(path)? {
+            if let Some(parent) = path.parent() {
+                self.copy_up_dir(parent)?;
+            }
+            self.upper()?
+                .write_file(&whiteout_path(path), &mut io::empty())?;
+        }
+        Ok(())
+    }
+
+    fn mark_opaque(&self, dir: &Path) -> Result<()> {
+        self.upper()?
+            .write_file(&dir.join(OPAQUE_MARKER), &mut io::empty())
+    }
+}
+
+/// The distinct file systems of the virtual descriptors of a `WasiCtx`, as collected by
+/// `WasiCtx::snapshot`.
+#[derive(Debug, Default)]
+pub(crate) struct FsTable(Vec<Arc<OverlayFs>>);
+
+impl FsTable {
+    /// Adds the file system of `file`, if it's a directory of one.
+    pub(crate) fn add(&mut self, file: &dyn VirtualFile) {
+        if let Some(dir) = file.as_any().downcast_ref::<OverlayDir>() {
+            if !self.0.iter().any(|fs| Arc::ptr_eq(fs, &dir.fs)) {
+                self.0.push(Arc::clone(&dir.fs));
+            }
+        }
+    }
+
+    /// The index of the file system `file` belongs to, and its path relative to the root.
+    ///
+    /// Regular files are only found if a directory of their file system was added.
+    pub(crate) fn locate(&self, file: &dyn VirtualFile) -> Option<(usize, PathBuf)> {
+        if let Some(dir) = file.as_any().downcast_ref::<OverlayDir>() {
+            let index = self.0.iter().position(|fs| Arc::ptr_eq(fs, &dir.fs))?;
+            return Some((index, dir.rel.clone()));
+        }
+        self.0
+            .iter()
+            .enumerate()
+            .filter_map(|(index, fs)| fs.locate(file).map(|path| (index, path)))
+            .next()
+    }
+
+    pub(crate) fn snapshot(&self) -> Result<Vec<FsSnapshot>> {
+        self.0.iter().map(|fs| fs.snapshot()).collect()
+    }
+}
+
+/// A directory of an `OverlayFs`.
+#[derive(Debug)]
+pub(crate) struct OverlayDir {
+    fs: Arc<OverlayFs>,
+    rel: PathBuf,
+}
+
+impl OverlayDir {
+    /// The path of `path` relative to the root, along with whether it has to be a directory
+    /// because of a trailing slash.
+    fn resolve(&self, path: &str) -> Result<(PathBuf, bool)> {
+        let name = path.trim_end_matches('/');
+        let dir_only = name.len() != path.len();
+        match name {
+            "." => Ok((self.rel.clone(), dir_only)),
+            "" | ".." => Err(Error::EINVAL),
+            name if name.contains('/') => Err(Error::EINVAL),
+            name => Ok((self.rel.join(name), dir_only)),
+        }
+    }
+
+    /// The merged entry at `path`, which must exist.
+    fn lookup(&self, path: &str) -> Result<(PathBuf, Entry)> {
+        let (path, dir_only) = self.resolve(path)?;
+        let entry = self.fs.lookup(&path)?.ok_or(Error::ENOENT)?;
+        if dir_only && !entry.is_dir() {
+            return Err(Error::ENOTDIR);
+        }
+        Ok((path, entry))
+    }
+
+    /// The path of a new entry at `path`, which must not exist yet.
+    fn lookup_new(&self, path: &str) -> Result<PathBuf> {
+        let (path, _) = self.resolve(path)?;
+        if self.fs.lookup(&path)?.is_some() {
+            return Err(Error::EEXIST);
+        }
+        Ok(path)
+    }
+
+    fn same_fs<'a>(&self, other: &'a dyn VirtualFile) -> Result<&'a Self> {
+        other
+            .as_any()
+            .downcast_ref::<Self>()
+            .filter(|other| Arc::ptr_eq(&self.fs, &other.fs))
+            .ok_or(Error::EXDEV)
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        entry: Entry,
+        atim: Option<SystemTime>,
+        mtim: Option<SystemTime>,
+    ) -> Result<()> {
+        self.fs.copy_up(path, entry)?;
+        self.fs.upper()?.set_times(path, atim, mtim)
+    }
+}
+
+impl VirtualFile for OverlayDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VirtualFile>> {
+        Ok(Box::new(Self {
+            fs: Arc::clone(&self.fs),
+            rel: self.rel.clone(),
+        }))
+    }
+
+    fn filetype(&self) -> host::__wasi_filetype_t {
+        host::__WASI_FILETYPE_DIRECTORY
+    }
+
+    fn rights_base(&self) -> host::__wasi_rights_t {
+        host::RIGHTS_DIRECTORY_BASE
+    }
+
+    fn rights_inheriting(&self) -> host::__wasi_rights_t {
+        host::RIGHTS_DIRECTORY_INHERITING
+    }
+
+    fn filestat_get(&self) -> Result<host::__wasi_filestat_t> {
+        self.lookup(".").map(|(_, entry)| entry.stat)
+    }
+
+    fn filestat_set_times(&self, atim: Option<SystemTime>, mtim: Option<SystemTime>) -> Result<()> {
+        let (path, entry) = self.lookup(".")?;
+        self.set_times(&path, entry, atim, mtim)
+    }
+
+    fn readdir(&self) -> Result<Vec<Dirent>> {
+        let this = self.filestat_get()?;
+        let parent = match self.rel.parent() {
+            Some(parent) => self.fs.lookup(parent)?.ok_or(Error::ENOENT)?.stat,
+            None => this,
+        };
+        let mut entries = vec![
+            Dirent {
+                name: ".".to_owned(),
+                ino: this.st_ino,
+                filetype: host::__WASI_FILETYPE_DIRECTORY,
+            },
+            Dirent {
+                name: "..".to_owned(),
+                ino: parent.st_ino,
+                filetype: host::__WASI_FILETYPE_DIRECTORY,
+            },
+        ];
+        entries.extend(self.fs.read_dir(&self.rel)?.values().cloned());
+        Ok(entries)
+    }
+
+    fn openat(
+        &self,
+        path: &str,
+        read: bool,
+        write: bool,
+        oflags: host::__wasi_oflags_t,
+        fdflags: host::__wasi_fdflags_t,
+    ) -> Result<Descriptor> {
+        let options = FileOptions::new(read, write, oflags, fdflags);
+        let (path, dir_only) = self.resolve(path)?;
+        let entry = match self.fs.lookup(&path)? {
+            Some(entry) => entry,
+            None if options.create => {
+                if dir_only || oflags & host::__WASI_O_DIRECTORY != 0 {
+                    return Err(Error::EISDIR);
+                }
+                self.fs.prepare_create(&path)?;
+                let descriptor = self.fs.upper()?.open(&path, options)?;
+                self.fs.finish_create(&path)?;
+                return Ok(descriptor);
+            }
+            None => return Err(Error::ENOENT),
+        };
+
+        if options.create_new {
+            return Err(Error::EEXIST);
+        }
+        match entry.stat.st_filetype {
+            host::__WASI_FILETYPE_DIRECTORY => {
+                if options.mutates() {
+                    return Err(Error::EISDIR);
+                }
+                Ok(Descriptor::VirtualFile(Box::new(Self {
+                    fs: Arc::clone(&self.fs),
+                    rel: path,
+                })))
+            }
+            host::__WASI_FILETYPE_SYMBOLIC_LINK => Err(Error::ELOOP),
+            _ if dir_only || oflags & host::__WASI_O_DIRECTORY != 0 => Err(Error::ENOTDIR),
+            host::__WASI_FILETYPE_REGULAR_FILE => {
+                if entry.source == Source::Lower && !options.mutates() {
+                    return self.fs.lower.open(&path, options);
+                }
+                self.fs.copy_up(&path, entry)?;
+                self.fs.upper()?.open(&path, options)
+            }
+            _ => Err(Error::ENOTSUP),
+        }
+    }
+
+    fn readlinkat(&self, path: &str) -> Result<String> {
+        let (path, entry) = self.lookup(path)?;
+        match entry.source {
+            Source::Upper => self.fs.upper()?.readlink(&path),
+            Source::Lower => self.fs.lower.readlink(&path),
+        }
+    }
+
+    fn create_directory(&self, path: &str) -> Result<()> {
+        let path = self.lookup_new(path)?;
+        self.fs.prepare_create(&path)?;
+        self.fs.upper()?.create_dir(&path)?;
+        if self.fs.finish_create(&path)? {
+            // a lower directory of the same name might have been deleted before
+            self.fs.mark_opaque(&path)?;
+        }
+        Ok(())
+    }
+
+    fn remove_directory(&self, path: &str) -> Result<()> {
+        if path.trim_end_matches('/') == "." {
+            return Err(Error::EINVAL);
+        }
+        let (path, entry) = self.lookup(path)?;
+        if !entry.is_dir() {
+            return Err(Error::ENOTDIR);
+        }
+        if !self.fs.read_dir(&path)?.is_empty() {
+            return Err(Error::ENOTEMPTY);
+        }
+        self.fs.remove(&path, entry)
+    }
+
+    fn unlink_file(&self, path: &str) -> Result<()> {
+        let (path, entry) = self.lookup(path)?;
+        if entry.is_dir() {
+            return Err(Error::EISDIR);
+        }
+        self.fs.remove(&path, entry)
+    }
+
+    fn symlink(&self, old_path: &str, new_path: &str) -> Result<()> {
+        let new_path = self.lookup_new(new_path)?;
+        self.fs.prepare_create(&new_path)?;
+        self.fs.upper()?.symlink(old_path, &new_path)?;
+        self.fs.finish_create(&new_path).map(|_| ())
+    }
+
+    fn rename(&self, old_path: &str, new_dir: &dyn VirtualFile, new_path: &str) -> Result<()> {
+        let new_dir = self.same_fs(new_dir)?;
+        if old_path.trim_end_matches('/') == "." {
+            return Err(Error::EINVAL);
+        }
+        let (old_path, old_entry) = self.lookup(old_path)?;
+        let (new_path, dir_only) = new_dir.resolve(new_path)?;
+        if old_path == new_path {
+            return Ok(());
+        }
+        if dir_only && !old_entry.is_dir() {
+            return Err(Error::ENOTDIR);
+        }
+        if old_entry.is_dir() && new_path.starts_with(&old_path) {
+            return Err(Error::EINVAL);
+        }
+
+        let new_entry = self.fs.lookup(&new_path)?;
+        if let Some(new_entry) = new_entry {
+            match (old_entry.is_dir(), new_entry.is_dir()) {
+                (true, false) => return Err(Error::ENOTDIR),
+                (false, true) => return Err(Error::EISDIR),
+                (true, true) if !self.fs.read_dir(&new_path)?.is_empty() => {
+                    return Err(Error::ENOTEMPTY)
+                }
+                _ => {}
+            }
+        }
+        if old_entry.is_dir() && self.fs.in_lower(&old_path)? {
+            // moving a merged directory would require moving its lower part too
+            return Err(Error::EXDEV);
+        }
+
+        self.fs.copy_up(&old_path, old_entry)?;
+        self.fs.prepare_create(&new_path)?;
+        if let Some(new_entry) = new_entry {
+            if new_entry.is_dir() {
+                self.fs.remove(&new_path, new_entry)?;
+            }
+        }
+        self.fs.upper()?.rename(&old_path, &new_path)?;
+        self.fs.finish_create(&new_path)?;
+        if old_entry.is_dir() && self.fs.in_lower(&new_path)? {
+            self.fs.mark_opaque(&new_path)?;
+        }
+        if self.fs.in_lower(&old_path)? {
+            self.fs
+                .upper()?
+                .write_file(&whiteout_path(&old_path), &mut io::empty())?;
+        }
+        Ok(())
+    }
+
+    fn link(&self, old_path: &str, new_dir: &dyn VirtualFile, new_path: &str) -> Result<()> {
+        let new_dir = self.same_fs(new_dir)?;
+        let (old_path, old_entry) = self.lookup(old_path)?;
+        if old_entry.is_dir() {
+            return Err(Error::EPERM);
+        }
+        let new_path = new_dir.lookup_new(new_path)?;
+        self.fs.copy_up(&old_path, old_entry)?;
+        self.fs.prepare_create(&new_path)?;
+        self.fs.upper()?.hard_link(&old_path, &new_path)?;
+        self.fs.finish_create(&new_path).map(|_| ())
+    }
+
+    fn filestat_get_at(&self, path: &str) -> Result<host::__wasi_filestat_t> {
+        self.lookup(path).map(|(_, entry)| entry.stat)
+    }
+
+    fn filestat_set_times_at(
+        &self,
+        path: &str,
+        atim: Option<SystemTime>,
+        mtim: Option<SystemTime>,
+    ) -> Result<()> {
+        let (path, entry) = self.lookup(path)?;
+        self.set_times(&path, entry, atim, mtim)
+    }
+}
@@ -0,0 +1,393 @@
+use super::{VirtualFile, Watch, Watchers};
+use crate::cancel::{self, Waker};
+use crate::{errno, host, Error, Result};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// The number of bytes a pipe buffers before writers block, as on Linux.
+const PIPE_CAPACITY: usize = 65536;
+
+/// Creates an in-process pipe, whose ends can be installed as descriptors of different
+/// `WasiCtx`s with `WasiCtxBuilder::stdin_pipe`, `WasiCtx::insert_pipe` and the like, or used by
+/// the host through `Read` and `Write`.
+///
+/// Reading from the pipe blocks until data is written to it, and fails with `EAGAIN` instead for
+/// descriptors with `__WASI_FDFLAG_NONBLOCK` set. Writing blocks while the pipe is full, in the
+/// same way. Once all writers are closed, reading returns end of file, and once all readers are
+/// closed, writing fails with `EPIPE`. An end is closed when it's dropped, or closed by every
+/// guest it was installed in, including those forked from them.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe {
+        state: Mutex::new(PipeState {
+            buf: VecDeque::new(),
+            readers: 1,
+            writers: 1,
+        }),
+        changed: Condvar::new(),
+        watchers: Arc::new(Watchers::default()),
+    });
+    let reader = PipeReader {
+        pipe: Arc::clone(&pipe),
+        fdflags: Mutex::new(0),
+    };
+    let writer = PipeWriter {
+        pipe,
+        fdflags: Mutex::new(0),
+    };
+    (reader, writer)
+}
+
+/// Either end of a pipe created by `pipe`.
+#[derive(Debug)]
+pub enum PipeEnd {
+    Reader(PipeReader),
+    Writer(PipeWriter),
+}
+
+impl From<PipeReader> for PipeEnd {
+    fn from(reader: PipeReader) -> Self {
+        PipeEnd::Reader(reader)
+    }
+}
+
+impl From<PipeWriter> for PipeEnd {
+    fn from(writer: PipeWriter) -> Self {
+        PipeEnd::Writer(writer)
+    }
+}
+
+impl PipeEnd {
+    pub(crate) fn into_virtual_file(self) -> Box<dyn VirtualFile> {
+        match self {
+            PipeEnd::Reader(reader) => Box::new(reader),
+            PipeEnd::Writer(writer) => Box::new(writer),
+        }
+    }
+}
+
+/// The state shared by the ends of a pipe.
+#[derive(Debug)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    /// Signalled whenever data is read or written, or an end is closed.
+    changed: Condvar,
+    /// Notified along with `changed`, for `poll_oneoff`.
+    watchers: Arc<Watchers>,
+}
+
+#[derive(Debug)]
+struct PipeState {
+    buf: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+impl Pipe {
+    fn lock(&self) -> MutexGuard<'_, PipeState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Waits for `changed` to be signalled, unless the hostcall running on this thread is
+    /// cancelled.
+    fn wait<'a>(
+        pipe: &Arc<Self>,
+        state: MutexGuard<'a, PipeState>,
+    ) -> Result<MutexGuard<'a, PipeState>> {
+        let canceller = match cancel::current() {
+            Some(canceller) => canceller,
+            None => return Ok(pipe.changed.wait(state).unwrap()),
+        };
+        let woken = Arc::clone(pipe);
+        let wake = Arc::new(move || {
+            let _state = woken.lock();
+            woken.changed.notify_all();
+        });
+        canceller.wait(wake, || pipe.changed.wait(state).unwrap())
+    }
+
+    fn notify(&self) {
+        self.changed.notify_all();
+        self.watchers.notify();
+    }
+
+    fn filestat(&self, size: usize) -> host::__wasi_filestat_t {
+        host::__wasi_filestat_t {
+            st_dev: 0,
+            st_ino: self as *const Self as host::__wasi_inode_t,
+            st_filetype: host::__WASI_FILETYPE_SOCKET_STREAM,
+            st_nlink: 1,
+            st_size: size as host::__wasi_filesize_t,
+            st_atim: 0,
+            st_mtim: 0,
+            st_ctim: 0,
+        }
+    }
+}
+
+fn set_fdflags(fdflags: &Mutex<host::__wasi_fdflags_t>, new: host::__wasi_fdflags_t) -> Result<()> {
+    // appending makes no difference to a pipe, and it can't be synchronized
+    if new & !(host::__WASI_FDFLAG_APPEND | host::__WASI_FDFLAG_NONBLOCK) != 0 {
+        return Err(Error::ENOTSUP);
+    }
+    *fdflags.lock().unwrap() = new;
+    Ok(())
+}
+
+/// The reading end of a pipe created by `pipe`.
+#[derive(Debug)]
+pub struct PipeReader {
+    pipe: Arc<Pipe>,
+    fdflags: Mutex<host::__wasi_fdflags_t>,
+}
+
+impl PipeReader {
+    fn read_impl(&self, buf: &mut [u8], nonblock: bool) -> Result<usize> {
+        let mut state = self.pipe.lock();
+        while state.buf.is_empty() && state.writers > 0 && !buf.is_empty() {
+            if nonblock {
+                return Err(Error::EAGAIN);
+            }
+            state = Pipe::wait(&self.pipe, state)?;
+        }
+        let nread = std::cmp::min(buf.len(), state.buf.len());
+        for (dst, src) in buf.iter_mut().zip(state.buf.drain(..nread)) {
+            *dst = src;
+        }
+        drop(state);
+        if nread > 0 {
+            self.pipe.notify();
+        }
+        Ok(nread)
+    }
+}
+
+impl Clone for PipeReader {
+    fn clone(&self) -> Self {
+        self.pipe.lock().readers += 1;
+        Self {
+            pipe: Arc::clone(&self.pipe),
+            fdflags: Mutex::new(*self.fdflags.lock().unwrap()),
+        }
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.pipe.lock().readers -= 1;
+        self.pipe.notify();
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_impl(buf, false)
+            .map_err(|e| errno::to_io_error(e.as_wasi_errno()))
+    }
+}
+
+impl VirtualFile for PipeReader {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VirtualFile>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn filetype(&self) -> host::__wasi_filetype_t {
+        host::__WASI_FILETYPE_SOCKET_STREAM
+    }
+
+    fn rights_base(&self) -> host::__wasi_rights_t {
+        host::RIGHTS_PIPE_BASE & !host::__WASI_RIGHT_FD_WRITE
+    }
+
+    fn rights_inheriting(&self) -> host::__wasi_rights_t {
+        host::RIGHTS_PIPE_INHERITING
+    }
+
+    fn fdstat_get(&self) -> host::__wasi_fdflags_t {
+        *self.fdflags.lock().unwrap()
+    }
+
+    fn fdstat_set_flags(&self, fdflags: host::__wasi_fdflags_t) -> Result<()> {
+        set_fdflags(&self.fdflags, fdflags)
+    }
+
+    fn filestat_get(&self) -> Result<host::__wasi_filestat_t> {
+        let size = self.pipe.lock().buf.len();
+        Ok(self.pipe.filestat(size))
+    }
+
+    fn read_vectored(&mut self, iovs: &mut [io::IoSliceMut]) -> Result<usize> {
+        let nonblock = self.fdstat_get() & host::__WASI_FDFLAG_NONBLOCK != 0;
+        match iovs.iter_mut().find(|iov| !iov.is_empty()) {
+            Some(iov) => self.read_impl(iov, nonblock),
+            None => Ok(0),
+        }
+    }
+
+    fn bytes_available(&self) -> Result<host::__wasi_filesize_t> {
+        Ok(self.pipe.lock().buf.len() as host::__wasi_filesize_t)
+    }
+
+    fn would_block(&self) -> bool {
+        let state = self.pipe.lock();
+        state.buf.is_empty() && state.writers > 0
+    }
+
+    fn hung_up(&self) -> bool {
+        self.pipe.lock().writers == 0
+    }
+
+    fn watch(&self, wake: Waker) -> Option<Watch> {
+        Some(Watchers::watch(&self.pipe.watchers, wake))
+    }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
+}
+
+/// The writing end of a pipe created by `pipe`.
+#[derive(Debug)]
+pub struct PipeWriter {
+    pipe: Arc<Pipe>,
+    fdflags: Mutex<host::__wasi_fdflags_t>,
+}
+
+impl PipeWriter {
+    fn write_impl(&self, buf: &[u8], nonblock: bool) -> Result<usize> {
+        let mut nwritten = 0;
+        let mut state = self.pipe.lock();
+        while nwritten < buf.len() {
+            if state.readers == 0 {
+                break;
+            }
+            let space = PIPE_CAPACITY - state.buf.len();
+            if space == 0 {
+                if nonblock {
+                    break;
+                }
+                state = Pipe::wait(&self.pipe, state)?;
+                continue;
+            }
+            let len = std::cmp::min(space, buf.len() - nwritten);
+            state.buf.extend(&buf[nwritten..nwritten + len]);
+            nwritten += len;
+            self.pipe.notify();
+        }
+        let readers = state.readers;
+        drop(state);
+
+        if nwritten == 0 && !buf.is_empty() {
+            if readers == 0 {
+                Err(Error::EPIPE)
+            } else {
+                Err(Error::EAGAIN)
+            }
+        } else {
+            Ok(nwritten)
+        }
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> Self {
+        self.pipe.lock().writers += 1;
+        Self {
+            pipe: Arc::clone(&self.pipe),
+            fdflags: Mutex::new(*self.fdflags.lock().unwrap()),
+        }
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.pipe.lock().writers -= 1;
+        self.pipe.notify();
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_impl(buf, false)
+            .map_err(|e| errno::to_io_error(e.as_wasi_errno()))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl VirtualFile for PipeWriter {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VirtualFile>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn filetype(&self) -> host::__wasi_filetype_t {
+        host::__WASI_FILETYPE_SOCKET_STREAM
+    }
+
+    fn rights_base(&self) -> host::__wasi_rights_t {
+        host::RIGHTS_PIPE_BASE & !host::__WASI_RIGHT_FD_READ
+    }
+
+    fn rights_inheriting(&self) -> host::__wasi_rights_t {
+        host::RIGHTS_PIPE_INHERITING
+    }
+
+    fn fdstat_get(&self) -> host::__wasi_fdflags_t {
+        *self.fdflags.lock().unwrap()
+    }
+
+    fn fdstat_set_flags(&self, fdflags: host::__wasi_fdflags_t) -> Result<()> {
+        set_fdflags(&self.fdflags, fdflags)
+    }
+
+    fn filestat_get(&self) -> Result<host::__wasi_filestat_t> {
+        let size = self.pipe.lock().buf.len();
+        Ok(self.pipe.filestat(size))
+    }
+
+    fn write_vectored(&mut self, iovs: &[io::IoSlice]) -> Result<usize> {
+        let nonblock = self.fdstat_get() & host::__WASI_FDFLAG_NONBLOCK != 0;
+        let mut nwritten = 0;
+        for iov in iovs {
+            match self.write_impl(iov, nonblock) {
+                Ok(n) => {
+                    nwritten += n;
+                    if n < iov.len() {
+                        break;
+                    }
+                }
+                Err(_) if nwritten > 0 => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(nwritten)
+    }
+
+    fn would_block(&self) -> bool {
+        let state = self.pipe.lock();
+        state.buf.len() >= PIPE_CAPACITY && state.readers > 0
+    }
+
+    fn hung_up(&self) -> bool {
+        self.pipe.lock().readers == 0
+    }
+
+    fn watch(&self, wake: Waker) -> Option<Watch> {
+        Some(Watchers::watch(&self.pipe.watchers, wake))
+    }
+
+    fn is_stream(&self) -> bool {
+        true
+    }
+}
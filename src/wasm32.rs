@@ -8,6 +8,7 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 #![allow(dead_code)]
+#![allow(clippy::unreadable_literal)]
 
 // C types
 pub type char = i8;
@@ -0,0 +1,104 @@
+//! WASI types as defined in wasm64, i.e. for guests using 64-bit linear
+//! memories (memory64). The layout of all WASI types is shared with wasm32,
+//! except for the C types that are pointer-sized, and for the structures
+//! embedding them, which are redefined here.
+
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(dead_code)]
+
+pub use crate::wasm32::*;
+
+// C types
+pub type long = i64;
+pub type ulong = u64;
+
+// libc stdint types
+pub type size_t = ulong;
+pub type intptr_t = long;
+pub type uintptr_t = ulong;
+
+// libc types
+pub type timer_t = uintptr_t; // *mut ::std::os::raw::c_void
+pub type ssize_t = i64;
+pub type caddr_t = uintptr_t; // *mut i8
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct __wasi_prestat_t {
+    pub pr_type: __wasi_preopentype_t,
+    pub u: __wasi_prestat_t___wasi_prestat_u,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union __wasi_prestat_t___wasi_prestat_u {
+    pub dir: __wasi_prestat_t___wasi_prestat_u___wasi_prestat_u_dir_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct __wasi_prestat_t___wasi_prestat_u___wasi_prestat_u_dir_t {
+    pub pr_name_len: size_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct __wasi_ciovec_t {
+    pub buf: uintptr_t, // *const ::std::os::raw::c_void
+    pub buf_len: size_t,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct __wasi_iovec_t {
+    pub buf: uintptr_t, // *mut ::std::os::raw::c_void
+    pub buf_len: size_t,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{__wasi_ciovec_t, __wasi_iovec_t, __wasi_prestat_t};
+
+    #[test]
+    fn layout___wasi_prestat_t() {
+        assert_eq!(
+            ::std::mem::size_of::<__wasi_prestat_t>(),
+            16usize,
+            concat!("Size of: ", stringify!(__wasi_prestat_t))
+        );
+        assert_eq!(
+            ::std::mem::align_of::<__wasi_prestat_t>(),
+            8usize,
+            concat!("Alignment of ", stringify!(__wasi_prestat_t))
+        );
+    }
+
+    #[test]
+    fn layout___wasi_ciovec_t() {
+        assert_eq!(
+            ::std::mem::size_of::<__wasi_ciovec_t>(),
+            16usize,
+            concat!("Size of: ", stringify!(__wasi_ciovec_t))
+        );
+        assert_eq!(
+            ::std::mem::align_of::<__wasi_ciovec_t>(),
+            8usize,
+            concat!("Alignment of ", stringify!(__wasi_ciovec_t))
+        );
+    }
+
+    #[test]
+    fn layout___wasi_iovec_t() {
+        assert_eq!(
+            ::std::mem::size_of::<__wasi_iovec_t>(),
+            16usize,
+            concat!("Size of: ", stringify!(__wasi_iovec_t))
+        );
+        assert_eq!(
+            ::std::mem::align_of::<__wasi_iovec_t>(),
+            8usize,
+            concat!("Alignment of ", stringify!(__wasi_iovec_t))
+        );
+    }
+}
@@ -0,0 +1,182 @@
+#![cfg(feature = "archive")]
+
+mod common;
+
+use common::{guest_with, DIR};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use wasi_common::{wasm32, WasiCtxBuilder};
+
+/// Rights of files opened from the archive, which is read-only.
+const READ: wasm32::__wasi_rights_t = wasm32::__WASI_RIGHT_FD_READ | wasm32::__WASI_RIGHT_FD_SEEK;
+
+fn open_error(archive: &Path) -> wasm32::__wasi_errno_t {
+    match WasiCtxBuilder::new()
+        .unwrap()
+        .preopened_archive(archive, "/")
+    {
+        Ok(_) => panic!("{} was preopened", archive.display()),
+        Err(e) => e.as_wasi_errno(),
+    }
+}
+
+fn tar_header(entry_type: tar::EntryType, size: u64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_size(size);
+    header.set_mode(0o644);
+    header
+}
+
+fn write_tar(path: &Path, files: &[(&str, &[u8])]) {
+    let mut builder = tar::Builder::new(File::create(path).unwrap());
+    for (name, contents) in files {
+        let mut header = tar_header(tar::EntryType::Regular, contents.len() as u64);
+        builder.append_data(&mut header, name, *contents).unwrap();
+    }
+    builder.finish().unwrap();
+}
+
+fn write_zip(path: &Path, files: &[(&str, zip::CompressionMethod, &[u8])]) {
+    let mut writer = zip::ZipWriter::new(File::create(path).unwrap());
+    for (name, method, contents) in files {
+        let options = zip::write::FileOptions::default().compression_method(*method);
+        writer.start_file(*name, options).unwrap();
+        writer.write_all(contents).unwrap();
+    }
+    writer.finish().unwrap();
+}
+
+#[test]
+fn reads_tar_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("archive.tar");
+    let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+    builder
+        .append_data(
+            &mut tar_header(tar::EntryType::Regular, 4),
+            "dir/file",
+            &b"file"[..],
+        )
+        .unwrap();
+    let mut header = tar_header(tar::EntryType::Symlink, 0);
+    header.set_link_name("dir/file").unwrap();
+    builder
+        .append_data(&mut header, "link", io::empty())
+        .unwrap();
+    builder.finish().unwrap();
+    drop(builder);
+    let mut guest = guest_with(
+        WasiCtxBuilder::new()
+            .unwrap()
+            .preopened_archive(&archive, "/")
+            .unwrap(),
+    );
+
+    let mut names = guest.readdir(DIR).unwrap();
+    names.sort();
+    assert_eq!(names, vec![".", "..", "dir", "link"]);
+    let fd = guest
+        .open_with_rights(DIR, "dir/file", 0, 0, Some(READ))
+        .unwrap();
+    assert_eq!(guest.read(fd, 100).unwrap(), b"file");
+    assert_eq!(guest.readlink(DIR, "link").unwrap(), b"dir/file");
+    assert_eq!(guest.create(DIR, "new"), Err(wasm32::__WASI_EROFS));
+}
+
+#[test]
+fn reads_zip_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("archive.zip");
+    write_zip(
+        &archive,
+        &[
+            ("stored", zip::CompressionMethod::Stored, b"stored"),
+            (
+                "dir/deflated",
+                zip::CompressionMethod::Deflated,
+                b"deflated",
+            ),
+        ],
+    );
+    let mut guest = guest_with(
+        WasiCtxBuilder::new()
+            .unwrap()
+            .preopened_archive(&archive, "/")
+            .unwrap(),
+    );
+
+    let fd = guest
+        .open_with_rights(DIR, "stored", 0, 0, Some(READ))
+        .unwrap();
+    assert_eq!(guest.read(fd, 100).unwrap(), b"stored");
+    let fd = guest
+        .open_with_rights(DIR, "dir/deflated", 0, 0, Some(READ))
+        .unwrap();
+    assert_eq!(guest.seek(fd, 2, wasm32::__WASI_WHENCE_SET), Ok(2));
+    assert_eq!(guest.read(fd, 100).unwrap(), b"flated");
+}
+
+#[test]
+fn zip_entries_inflate_to_no_more_than_their_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("archive.zip");
+    write_zip(
+        &archive,
+        &[("file", zip::CompressionMethod::Deflated, b"0123456789")],
+    );
+    // claim a smaller uncompressed size in the central directory, as a zip bomb could claim a
+    // tiny one, or a huge one to be allocated up front
+    let mut bytes = std::fs::read(&archive).unwrap();
+    let central = (0..bytes.len() - 4)
+        .find(|&i| &bytes[i..i + 4] == b"PK\x01\x02")
+        .unwrap();
+    bytes[central + 24..central + 28].copy_from_slice(&4u32.to_le_bytes());
+    std::fs::write(&archive, bytes).unwrap();
+    let mut guest = guest_with(
+        WasiCtxBuilder::new()
+            .unwrap()
+            .preopened_archive(&archive, "/")
+            .unwrap(),
+    );
+
+    let fd = guest
+        .open_with_rights(DIR, "file", 0, 0, Some(READ))
+        .unwrap();
+    assert_eq!(guest.read(fd, 100).unwrap(), b"0123");
+}
+
+#[test]
+fn file_used_as_a_parent_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("archive.tar");
+    write_tar(&archive, &[("a", b"file"), ("a/b", b"nested")]);
+    assert_eq!(open_error(&archive), wasm32::__WASI_ENOTDIR);
+
+    write_tar(&archive, &[("a/b", b"nested"), ("a", b"file")]);
+    assert_eq!(open_error(&archive), wasm32::__WASI_ENOTDIR);
+}
+
+#[test]
+fn entries_outside_the_archive_are_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("archive.tar");
+    let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+    let mut header = tar_header(tar::EntryType::Regular, 6);
+    // `append_data` refuses `..`, so the name is set on the header directly
+    header.as_gnu_mut().unwrap().name[..9].copy_from_slice(b"../escape");
+    header.set_cksum();
+    builder.append(&header, &b"escape"[..]).unwrap();
+    builder.finish().unwrap();
+    drop(builder);
+    let mut guest = guest_with(
+        WasiCtxBuilder::new()
+            .unwrap()
+            .preopened_archive(&archive, "/")
+            .unwrap(),
+    );
+
+    assert_eq!(guest.readdir(DIR).unwrap().len(), 2);
+    assert!(!dir.path().join("escape").exists());
+}
@@ -0,0 +1,141 @@
+mod common;
+
+use common::{Guest, Subscription};
+use std::thread;
+use std::time::Duration;
+use wasi_common::{pipe, preopen_dir, wasm32, CancelHandle, WasiCtxBuilder};
+
+/// Cancels through `handle` from another thread once the hostcall had time to block.
+fn cancel_soon(handle: CancelHandle) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        handle.cancel();
+    })
+}
+
+#[test]
+fn blocked_reads_of_virtual_pipes_are_cancelled() {
+    let (reader, _writer) = pipe();
+    let ctx = WasiCtxBuilder::new()
+        .unwrap()
+        .stdin_pipe(reader)
+        .unwrap()
+        .build()
+        .unwrap();
+    let mut guest = Guest::new(ctx);
+    let handle = guest.ctx.cancel_handle().unwrap();
+
+    let canceller = cancel_soon(handle.clone());
+    assert_eq!(guest.read(0, 100), Err(wasm32::__WASI_ECANCELED));
+    canceller.join().unwrap();
+    assert!(handle.is_cancelled());
+    // and so are those which would block from now on
+    assert_eq!(guest.read(0, 100), Err(wasm32::__WASI_ECANCELED));
+}
+
+#[test]
+fn blocked_polls_are_cancelled() {
+    let (reader, _writer) = pipe();
+    let ctx = WasiCtxBuilder::new()
+        .unwrap()
+        .stdin_pipe(reader)
+        .unwrap()
+        .build()
+        .unwrap();
+    let mut guest = Guest::new(ctx);
+    let handle = guest.ctx.cancel_handle().unwrap();
+
+    let canceller = cancel_soon(handle);
+    assert_eq!(
+        guest.poll(&[Subscription::FdRead(0)]),
+        Err(wasm32::__WASI_ECANCELED)
+    );
+    canceller.join().unwrap();
+}
+
+#[test]
+fn sleeping_polls_are_cancelled() {
+    let mut guest = Guest::new(WasiCtxBuilder::new().unwrap().build().unwrap());
+    let handle = guest.ctx.cancel_handle().unwrap();
+
+    let canceller = cancel_soon(handle);
+    let hour = 3600 * 1_000_000_000;
+    assert_eq!(
+        guest.poll(&[Subscription::Clock(hour)]),
+        Err(wasm32::__WASI_ECANCELED)
+    );
+    canceller.join().unwrap();
+}
+
+#[test]
+fn other_hostcalls_are_unaffected() {
+    let dir = tempfile::tempdir().unwrap();
+    let (reader, mut writer) = pipe();
+    let ctx = WasiCtxBuilder::new()
+        .unwrap()
+        .stdin_pipe(reader)
+        .unwrap()
+        .preopened_dir(preopen_dir(dir.path()).unwrap(), "/sandbox")
+        .build()
+        .unwrap();
+    let mut guest = Guest::new(ctx);
+    guest.ctx.cancel_handle().unwrap().cancel();
+
+    let fd = guest.create(3, "file").unwrap();
+    assert_eq!(guest.write(fd, b"written"), Ok(7));
+    assert_eq!(guest.seek(fd, 0, wasm32::__WASI_WHENCE_SET), Ok(0));
+    // reads which don't block go ahead too
+    assert_eq!(guest.read(fd, 100).unwrap(), b"written");
+    std::io::Write::write_all(&mut writer, b"piped").unwrap();
+    assert_eq!(guest.read(0, 100).unwrap(), b"piped");
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use nix::unistd;
+    use std::fs::File;
+    use std::os::unix::io::FromRawFd;
+
+    fn host_pipe() -> (File, File) {
+        let (reader, writer) = unistd::pipe().unwrap();
+        unsafe { (File::from_raw_fd(reader), File::from_raw_fd(writer)) }
+    }
+
+    #[test]
+    fn blocked_reads_of_host_pipes_are_cancelled() {
+        let (reader, _writer) = host_pipe();
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .stdin(reader)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut guest = Guest::new(ctx);
+        let handle = guest.ctx.cancel_handle().unwrap();
+
+        let canceller = cancel_soon(handle);
+        assert_eq!(guest.read(0, 100), Err(wasm32::__WASI_ECANCELED));
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn blocked_polls_of_host_pipes_are_cancelled() {
+        let (reader, _writer) = host_pipe();
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .stdin(reader)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut guest = Guest::new(ctx);
+        let handle = guest.ctx.cancel_handle().unwrap();
+
+        let canceller = cancel_soon(handle);
+        assert_eq!(
+            guest.poll(&[Subscription::FdRead(0)]),
+            Err(wasm32::__WASI_ECANCELED)
+        );
+        canceller.join().unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::{guest_with, sandbox, Guest, Subscription, DIR};
+use std::fs::{self, File};
+use wasi_common::hostcalls;
+use wasi_common::{wasm32, Capability, DisabledError, WasiCtxBuilder};
+
+/// A builder with `dir` preopened, in which there's a file, and stdout going to `dir/stdout`.
+fn builder(dir: &tempfile::TempDir) -> WasiCtxBuilder {
+    fs::write(dir.path().join("file"), "contents").unwrap();
+    sandbox(dir)
+        .arg("program")
+        .unwrap()
+        .stdout(File::create(dir.path().join("stdout")).unwrap())
+        .unwrap()
+}
+
+fn clock_time_get(guest: &mut Guest) -> wasm32::__wasi_errno_t {
+    unsafe {
+        hostcalls::clock_time_get(
+            &guest.ctx,
+            &mut guest.mem,
+            wasm32::__WASI_CLOCK_MONOTONIC,
+            1,
+            0x10,
+        )
+    }
+}
+
+fn random_get(guest: &mut Guest) -> wasm32::__wasi_errno_t {
+    unsafe { hostcalls::random_get(&guest.ctx, &mut guest.mem, 0x10, 16) }
+}
+
+fn args_sizes_get(guest: &mut Guest) -> wasm32::__wasi_errno_t {
+    unsafe { hostcalls::args_sizes_get(&guest.ctx, &mut guest.mem, 0x10, 0x14) }
+}
+
+#[test]
+fn everything_is_enabled_by_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+
+    assert_eq!(clock_time_get(&mut guest), wasm32::__WASI_ESUCCESS);
+    assert_eq!(random_get(&mut guest), wasm32::__WASI_ESUCCESS);
+    assert_eq!(args_sizes_get(&mut guest), wasm32::__WASI_ESUCCESS);
+    assert!(guest.poll(&[Subscription::Clock(0)]).is_ok());
+    assert!(guest.create(DIR, "new").is_ok());
+}
+
+#[test]
+fn disabled_fs_mutation() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir).disable(Capability::FsMutation));
+
+    assert_eq!(guest.create(DIR, "new"), Err(wasm32::__WASI_ENOSYS));
+    assert_eq!(guest.mkdir(DIR, "dir"), Err(wasm32::__WASI_ENOSYS));
+    assert_eq!(guest.unlink(DIR, "file"), Err(wasm32::__WASI_ENOSYS));
+    assert_eq!(
+        guest.rename(DIR, "file", DIR, "renamed"),
+        Err(wasm32::__WASI_ENOSYS)
+    );
+    assert_eq!(
+        guest.open(DIR, "file", 0, wasm32::__WASI_FDFLAG_APPEND),
+        Err(wasm32::__WASI_ENOSYS)
+    );
+    // reading files, and writing to the descriptors of the embedder, remain possible
+    let fd = guest
+        .open_with_rights(DIR, "file", 0, 0, Some(wasm32::__WASI_RIGHT_FD_READ))
+        .unwrap();
+    assert_eq!(guest.read(fd, 100).unwrap(), b"contents");
+    assert_eq!(guest.write(1, b"output"), Ok(6));
+    assert_eq!(fs::read(dir.path().join("stdout")).unwrap(), b"output");
+    assert!(!dir.path().join("new").exists());
+}
+
+#[test]
+fn disabled_categories() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(
+        builder(&dir)
+            .disable(Capability::Clocks)
+            .disable(Capability::Random)
+            .disable(Capability::ArgsEnv)
+            .disable(Capability::Poll),
+    );
+
+    assert_eq!(clock_time_get(&mut guest), wasm32::__WASI_ENOSYS);
+    assert_eq!(random_get(&mut guest), wasm32::__WASI_ENOSYS);
+    assert_eq!(args_sizes_get(&mut guest), wasm32::__WASI_ENOSYS);
+    assert_eq!(
+        guest.poll(&[Subscription::Clock(0)]),
+        Err(wasm32::__WASI_ENOSYS)
+    );
+    assert!(guest.create(DIR, "new").is_ok());
+}
+
+#[test]
+fn disabled_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(
+        builder(&dir)
+            .disable(Capability::Random)
+            .disabled_error(DisabledError::NotCapable),
+    );
+
+    assert_eq!(random_get(&mut guest), wasm32::__WASI_ENOTCAPABLE);
+    assert_eq!(clock_time_get(&mut guest), wasm32::__WASI_ESUCCESS);
+}
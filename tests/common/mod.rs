@@ -0,0 +1,469 @@
+//! Helpers shared by the integration tests, calling hostcalls the way a wasm32 guest would.
+#![allow(dead_code)]
+use std::convert::TryInto;
+use std::path::Path;
+use wasi_common::{hostcalls, preopen_dir, wasm32, WasiCtx, WasiCtxBuilder};
+
+pub type Errno = wasm32::__wasi_errno_t;
+pub type Fd = wasm32::__wasi_fd_t;
+
+/// The descriptor of the first preopened directory, such as the one of `sandbox`.
+pub const DIR: Fd = 3;
+
+/// Where results written by hostcalls go.
+const RESULT: u32 = 0x8;
+const IOVS: u32 = 0x100;
+const PATH: u32 = 0x200;
+const PATH2: u32 = 0x600;
+const SUBSCRIPTIONS: u32 = 0x1000;
+const EVENTS: u32 = 0x2000;
+const BUF: u32 = 0x4000;
+const BUF_LEN: u32 = 0xc000;
+
+/// Size of a `__wasi_subscription_t`.
+const SUBSCRIPTION_SIZE: u32 = 56;
+/// Size of a `__wasi_event_t`.
+const EVENT_SIZE: u32 = 32;
+
+/// A builder with the host directory `dir` preopened as `/sandbox`.
+pub fn sandbox<P: AsRef<Path>>(dir: P) -> WasiCtxBuilder {
+    WasiCtxBuilder::new()
+        .unwrap()
+        .preopened_dir(preopen_dir(dir).unwrap(), "/sandbox")
+}
+
+/// A guest of the context built by `builder`.
+pub fn guest_with(builder: WasiCtxBuilder) -> Guest {
+    Guest::new(builder.build().unwrap())
+}
+
+pub fn ok(errno: Errno) -> Result<(), Errno> {
+    if errno == wasm32::__WASI_ESUCCESS {
+        Ok(())
+    } else {
+        Err(errno)
+    }
+}
+
+/// A subscription of `poll_oneoff`.
+pub enum Subscription {
+    /// A relative timeout in nanoseconds on the monotonic clock.
+    Clock(u64),
+    FdRead(Fd),
+    FdWrite(Fd),
+}
+
+/// An event returned by `poll_oneoff`, as `(userdata, error, type)`, where userdata is the
+/// position of the subscription.
+pub type Event = (u64, Errno, wasm32::__wasi_eventtype_t);
+
+/// A `__wasi_dirent_t` with the name following it.
+#[derive(Debug)]
+pub struct Dirent {
+    pub next: u64,
+    pub ino: u64,
+    pub filetype: wasm32::__wasi_filetype_t,
+    pub name: Vec<u8>,
+}
+
+/// A `__wasi_filestat_t`.
+#[derive(Debug)]
+pub struct Filestat {
+    pub dev: u64,
+    pub ino: u64,
+    pub filetype: wasm32::__wasi_filetype_t,
+    pub nlink: u64,
+    pub size: u64,
+    pub atim: u64,
+    pub mtim: u64,
+    pub ctim: u64,
+}
+
+/// A `__wasi_fdstat_t`.
+#[derive(Debug)]
+pub struct Fdstat {
+    pub filetype: wasm32::__wasi_filetype_t,
+    pub flags: wasm32::__wasi_fdflags_t,
+    pub rights_base: wasm32::__wasi_rights_t,
+    pub rights_inheriting: wasm32::__wasi_rights_t,
+}
+
+pub struct Guest {
+    pub ctx: WasiCtx,
+    pub mem: Vec<u8>,
+}
+
+impl Guest {
+    pub fn new(ctx: WasiCtx) -> Self {
+        Self {
+            ctx,
+            mem: vec![0; 0x10000],
+        }
+    }
+
+    fn put(&mut self, offset: u32, bytes: &[u8]) -> u32 {
+        let offset = offset as usize;
+        self.mem[offset..offset + bytes.len()].copy_from_slice(bytes);
+        bytes.len() as u32
+    }
+
+    fn get(&self, offset: u32, len: u32) -> &[u8] {
+        &self.mem[offset as usize..(offset + len) as usize]
+    }
+
+    fn u32_at(&self, offset: u32) -> u32 {
+        u32::from_le_bytes(self.get(offset, 4).try_into().unwrap())
+    }
+
+    fn u64_at(&self, offset: u32) -> u64 {
+        u64::from_le_bytes(self.get(offset, 8).try_into().unwrap())
+    }
+
+    fn put_iovec(&mut self, buf: u32, len: u32) {
+        self.put(IOVS, &buf.to_le_bytes());
+        self.put(IOVS + 4, &len.to_le_bytes());
+    }
+
+    pub fn fdstat(&mut self, fd: Fd) -> Result<Fdstat, Errno> {
+        ok(unsafe { hostcalls::fd_fdstat_get(&self.ctx, &mut self.mem, fd, RESULT) })?;
+        Ok(Fdstat {
+            filetype: self.mem[RESULT as usize],
+            flags: u16::from_le_bytes(self.get(RESULT + 2, 2).try_into().unwrap()),
+            rights_base: self.u64_at(RESULT + 8),
+            rights_inheriting: self.u64_at(RESULT + 16),
+        })
+    }
+
+    /// Opens `path` relative to `dirfd` with all the rights `dirfd` can pass on, except for
+    /// writing to directories.
+    pub fn open<T: AsRef<[u8]>>(
+        &mut self,
+        dirfd: Fd,
+        path: T,
+        oflags: wasm32::__wasi_oflags_t,
+        fdflags: wasm32::__wasi_fdflags_t,
+    ) -> Result<Fd, Errno> {
+        self.open_with_rights(dirfd, path, oflags, fdflags, None)
+    }
+
+    /// Opens `path` like `open`, but with the base rights `rights` if given.
+    pub fn open_with_rights<T: AsRef<[u8]>>(
+        &mut self,
+        dirfd: Fd,
+        path: T,
+        oflags: wasm32::__wasi_oflags_t,
+        fdflags: wasm32::__wasi_fdflags_t,
+        rights: Option<wasm32::__wasi_rights_t>,
+    ) -> Result<Fd, Errno> {
+        let inheriting = self.fdstat(dirfd)?.rights_inheriting;
+        let rights = match rights {
+            Some(rights) => rights,
+            // directories can't be opened for writing
+            None if oflags & wasm32::__WASI_O_DIRECTORY != 0 => {
+                inheriting
+                    & !(wasm32::__WASI_RIGHT_FD_DATASYNC
+                        | wasm32::__WASI_RIGHT_FD_WRITE
+                        | wasm32::__WASI_RIGHT_FD_ALLOCATE
+                        | wasm32::__WASI_RIGHT_FD_FILESTAT_SET_SIZE)
+            }
+            None => inheriting,
+        };
+        let len = self.put(PATH, path.as_ref());
+        ok(unsafe {
+            hostcalls::path_open(
+                &mut self.ctx,
+                &mut self.mem,
+                dirfd,
+                0,
+                PATH,
+                len,
+                oflags,
+                rights,
+                inheriting,
+                fdflags,
+                RESULT,
+            )
+        })?;
+        Ok(self.u32_at(RESULT))
+    }
+
+    pub fn create(&mut self, dirfd: Fd, path: &str) -> Result<Fd, Errno> {
+        self.open(
+            dirfd,
+            path,
+            wasm32::__WASI_O_CREAT | wasm32::__WASI_O_TRUNC,
+            0,
+        )
+    }
+
+    /// The name of the preopened directory `fd`.
+    pub fn prestat_dir_name(&mut self, fd: Fd) -> Result<String, Errno> {
+        ok(unsafe { hostcalls::fd_prestat_get(&self.ctx, &mut self.mem, fd, RESULT) })?;
+        let len = self.u32_at(RESULT + 4);
+        ok(unsafe { hostcalls::fd_prestat_dir_name(&self.ctx, &mut self.mem, fd, BUF, len) })?;
+        Ok(String::from_utf8(self.get(BUF, len).to_vec()).unwrap())
+    }
+
+    pub fn close(&mut self, fd: Fd) -> Result<(), Errno> {
+        ok(unsafe { hostcalls::fd_close(&mut self.ctx, fd) })
+    }
+
+    pub fn renumber(&mut self, from: Fd, to: Fd) -> Result<(), Errno> {
+        ok(unsafe { hostcalls::fd_renumber(&mut self.ctx, from, to) })
+    }
+
+    pub fn write(&mut self, fd: Fd, data: &[u8]) -> Result<u32, Errno> {
+        let len = self.put(BUF, data);
+        self.put_iovec(BUF, len);
+        ok(unsafe { hostcalls::fd_write(&mut self.ctx, &mut self.mem, fd, IOVS, 1, RESULT) })?;
+        Ok(self.u32_at(RESULT))
+    }
+
+    pub fn pwrite(&mut self, fd: Fd, data: &[u8], offset: u64) -> Result<u32, Errno> {
+        let len = self.put(BUF, data);
+        self.put_iovec(BUF, len);
+        ok(unsafe { hostcalls::fd_pwrite(&self.ctx, &mut self.mem, fd, IOVS, 1, offset, RESULT) })?;
+        Ok(self.u32_at(RESULT))
+    }
+
+    pub fn read(&mut self, fd: Fd, len: u32) -> Result<Vec<u8>, Errno> {
+        assert!(len <= BUF_LEN);
+        self.put_iovec(BUF, len);
+        ok(unsafe { hostcalls::fd_read(&mut self.ctx, &mut self.mem, fd, IOVS, 1, RESULT) })?;
+        let nread = self.u32_at(RESULT);
+        Ok(self.get(BUF, nread).to_vec())
+    }
+
+    pub fn seek(
+        &mut self,
+        fd: Fd,
+        offset: i64,
+        whence: wasm32::__wasi_whence_t,
+    ) -> Result<u64, Errno> {
+        ok(unsafe {
+            hostcalls::fd_seek(&mut self.ctx, &mut self.mem, fd, offset, whence, RESULT)
+        })?;
+        Ok(self.u64_at(RESULT))
+    }
+
+    pub fn tell(&mut self, fd: Fd) -> Result<u64, Errno> {
+        ok(unsafe { hostcalls::fd_tell(&mut self.ctx, &mut self.mem, fd, RESULT) })?;
+        Ok(self.u64_at(RESULT))
+    }
+
+    pub fn mkdir(&mut self, dirfd: Fd, path: &str) -> Result<(), Errno> {
+        let len = self.put(PATH, path.as_bytes());
+        ok(unsafe { hostcalls::path_create_directory(&self.ctx, &mut self.mem, dirfd, PATH, len) })
+    }
+
+    pub fn rmdir(&mut self, dirfd: Fd, path: &str) -> Result<(), Errno> {
+        let len = self.put(PATH, path.as_bytes());
+        ok(unsafe { hostcalls::path_remove_directory(&self.ctx, &mut self.mem, dirfd, PATH, len) })
+    }
+
+    pub fn unlink(&mut self, dirfd: Fd, path: &str) -> Result<(), Errno> {
+        let len = self.put(PATH, path.as_bytes());
+        ok(unsafe { hostcalls::path_unlink_file(&self.ctx, &mut self.mem, dirfd, PATH, len) })
+    }
+
+    pub fn symlink<T: AsRef<[u8]>>(
+        &mut self,
+        target: T,
+        dirfd: Fd,
+        path: &str,
+    ) -> Result<(), Errno> {
+        let target_len = self.put(PATH, target.as_ref());
+        let len = self.put(PATH2, path.as_bytes());
+        ok(unsafe {
+            hostcalls::path_symlink(
+                &self.ctx,
+                &mut self.mem,
+                PATH,
+                target_len,
+                dirfd,
+                PATH2,
+                len,
+            )
+        })
+    }
+
+    pub fn readlink<T: AsRef<[u8]>>(&mut self, dirfd: Fd, path: T) -> Result<Vec<u8>, Errno> {
+        let len = self.put(PATH, path.as_ref());
+        ok(unsafe {
+            hostcalls::path_readlink(
+                &self.ctx,
+                &mut self.mem,
+                dirfd,
+                PATH,
+                len,
+                BUF,
+                BUF_LEN,
+                RESULT,
+            )
+        })?;
+        let used = self.u32_at(RESULT);
+        Ok(self.get(BUF, used).to_vec())
+    }
+
+    pub fn rename(&mut self, dirfd: Fd, old: &str, new_dirfd: Fd, new: &str) -> Result<(), Errno> {
+        let old_len = self.put(PATH, old.as_bytes());
+        let new_len = self.put(PATH2, new.as_bytes());
+        ok(unsafe {
+            hostcalls::path_rename(
+                &self.ctx,
+                &mut self.mem,
+                dirfd,
+                PATH,
+                old_len,
+                new_dirfd,
+                PATH2,
+                new_len,
+            )
+        })
+    }
+
+    pub fn link(&mut self, dirfd: Fd, old: &str, new_dirfd: Fd, new: &str) -> Result<(), Errno> {
+        let old_len = self.put(PATH, old.as_bytes());
+        let new_len = self.put(PATH2, new.as_bytes());
+        ok(unsafe {
+            hostcalls::path_link(
+                &self.ctx,
+                &mut self.mem,
+                dirfd,
+                0,
+                PATH,
+                old_len,
+                new_dirfd,
+                PATH2,
+                new_len,
+            )
+        })
+    }
+
+    /// The names of the entries of `fd`, in the order returned.
+    pub fn readdir(&mut self, fd: Fd) -> Result<Vec<String>, Errno> {
+        let names = self.readdir_bytes(fd)?;
+        Ok(names
+            .into_iter()
+            .map(|name| String::from_utf8(name).unwrap())
+            .collect())
+    }
+
+    /// The names of the entries of `fd`, as they're returned by `fd_readdir`.
+    pub fn readdir_bytes(&mut self, fd: Fd) -> Result<Vec<Vec<u8>>, Errno> {
+        let entries = self.readdir_inodes(fd)?;
+        Ok(entries.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// The names and inode numbers of the entries of `fd`, in the order returned.
+    pub fn readdir_inodes(&mut self, fd: Fd) -> Result<Vec<(Vec<u8>, u64)>, Errno> {
+        let (entries, used) = self.readdir_from(fd, 0, BUF_LEN)?;
+        assert!(used < BUF_LEN, "the buffer is too small for the entries");
+        Ok(entries
+            .into_iter()
+            .map(|entry| (entry.name, entry.ino))
+            .collect())
+    }
+
+    /// The entries of `fd` returned by a single `fd_readdir` starting at `cookie` with a buffer
+    /// of `len` bytes, leaving out a truncated entry at the end, and the number of bytes used.
+    pub fn readdir_from(
+        &mut self,
+        fd: Fd,
+        cookie: u64,
+        len: u32,
+    ) -> Result<(Vec<Dirent>, u32), Errno> {
+        assert!(len <= BUF_LEN);
+        ok(unsafe {
+            hostcalls::fd_readdir(&mut self.ctx, &mut self.mem, fd, BUF, len, cookie, RESULT)
+        })?;
+        let used = self.u32_at(RESULT);
+        let mut entries = Vec::new();
+        let mut offset = BUF;
+        // d_next: u64, d_ino: u64, d_namlen: u32, d_type: u8
+        while offset + 24 <= BUF + used {
+            let namlen = self.u32_at(offset + 16);
+            if offset + 24 + namlen > BUF + used {
+                break;
+            }
+            entries.push(Dirent {
+                next: self.u64_at(offset),
+                ino: self.u64_at(offset + 8),
+                filetype: self.mem[(offset + 20) as usize],
+                name: self.get(offset + 24, namlen).to_vec(),
+            });
+            offset += 24 + namlen;
+        }
+        Ok((entries, used))
+    }
+
+    fn decode_filestat(&self) -> Filestat {
+        Filestat {
+            dev: self.u64_at(RESULT),
+            ino: self.u64_at(RESULT + 8),
+            filetype: self.mem[(RESULT + 16) as usize],
+            nlink: self.u32_at(RESULT + 20).into(),
+            size: self.u64_at(RESULT + 24),
+            atim: self.u64_at(RESULT + 32),
+            mtim: self.u64_at(RESULT + 40),
+            ctim: self.u64_at(RESULT + 48),
+        }
+    }
+
+    pub fn filestat(&mut self, fd: Fd) -> Result<Filestat, Errno> {
+        ok(unsafe { hostcalls::fd_filestat_get(&self.ctx, &mut self.mem, fd, RESULT) })?;
+        Ok(self.decode_filestat())
+    }
+
+    pub fn path_filestat(&mut self, dirfd: Fd, path: &str) -> Result<Filestat, Errno> {
+        let len = self.put(PATH, path.as_bytes());
+        ok(unsafe {
+            hostcalls::path_filestat_get(&self.ctx, &mut self.mem, dirfd, 0, PATH, len, RESULT)
+        })?;
+        Ok(self.decode_filestat())
+    }
+
+    /// Polls `subscriptions`, returning the events in the order they're written.
+    pub fn poll(&mut self, subscriptions: &[Subscription]) -> Result<Vec<Event>, Errno> {
+        for (i, subscription) in subscriptions.iter().enumerate() {
+            let at = SUBSCRIPTIONS + i as u32 * SUBSCRIPTION_SIZE;
+            for byte in self.mem[at as usize..(at + SUBSCRIPTION_SIZE) as usize].iter_mut() {
+                *byte = 0;
+            }
+            self.put(at, &(i as u64).to_le_bytes());
+            match *subscription {
+                Subscription::Clock(timeout) => {
+                    self.put(at + 8, &[wasm32::__WASI_EVENTTYPE_CLOCK]);
+                    self.put(at + 24, &wasm32::__WASI_CLOCK_MONOTONIC.to_le_bytes());
+                    self.put(at + 32, &timeout.to_le_bytes());
+                }
+                Subscription::FdRead(fd) => {
+                    self.put(at + 8, &[wasm32::__WASI_EVENTTYPE_FD_READ]);
+                    self.put(at + 16, &fd.to_le_bytes());
+                }
+                Subscription::FdWrite(fd) => {
+                    self.put(at + 8, &[wasm32::__WASI_EVENTTYPE_FD_WRITE]);
+                    self.put(at + 16, &fd.to_le_bytes());
+                }
+            }
+        }
+        ok(unsafe {
+            hostcalls::poll_oneoff(
+                &self.ctx,
+                &mut self.mem,
+                SUBSCRIPTIONS,
+                EVENTS,
+                subscriptions.len() as u32,
+                RESULT,
+            )
+        })?;
+        let nevents = self.u32_at(RESULT);
+        Ok((0..nevents)
+            .map(|i| {
+                let at = EVENTS + i * EVENT_SIZE;
+                let error = u16::from_le_bytes(self.get(at + 8, 2).try_into().unwrap());
+                (self.u64_at(at), error, self.mem[(at + 10) as usize])
+            })
+            .collect())
+    }
+}
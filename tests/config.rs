@@ -0,0 +1,191 @@
+#![cfg(feature = "config")]
+
+mod common;
+
+use common::{guest_with, DIR};
+use std::fs;
+use wasi_common::{wasm32, Capability, Config, PathEncoding, Stdio};
+
+#[test]
+fn parses_toml() {
+    let config = Config::from_toml(
+        r#"
+        args = ["app.wasm", "--verbose"]
+        disable = ["clocks", "random"]
+        path_encoding = "lossless"
+
+        [env]
+        allow = ["HOME"]
+        vars = { RUST_LOG = "debug" }
+
+        [stdio]
+        stdin = "null"
+        stderr = { append = "/var/log/app.log" }
+
+        [[preopen]]
+        guest = "/data"
+        host = "/srv/data"
+        rights = ["fd_read", "path_open"]
+        policy = { deny = [{ pattern = "*.key" }], denial = "not_capable" }
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.args, vec!["app.wasm", "--verbose"]);
+    assert_eq!(config.disable, vec![Capability::Clocks, Capability::Random]);
+    assert_eq!(config.path_encoding, Some(PathEncoding::Lossless));
+    assert_eq!(config.env.allow, vec!["HOME"]);
+    assert_eq!(config.env.vars["RUST_LOG"], "debug");
+    match config.stdio.stderr {
+        Some(Stdio::Append(ref path)) => assert_eq!(path.to_str(), Some("/var/log/app.log")),
+        ref stderr => panic!("unexpected stderr {:?}", stderr),
+    }
+    assert!(config.stdio.stdout.is_none());
+    assert_eq!(config.preopens.len(), 1);
+    let preopen = &config.preopens[0];
+    assert_eq!(preopen.guest.to_str(), Some("/data"));
+    assert_eq!(
+        preopen.host.as_ref().and_then(|p| p.to_str()),
+        Some("/srv/data")
+    );
+    assert_eq!(
+        preopen.rights,
+        Some(vec!["fd_read".to_owned(), "path_open".to_owned()])
+    );
+    assert_eq!(preopen.policy.as_ref().unwrap().deny[0].pattern, "*.key");
+}
+
+#[test]
+fn parses_json() {
+    let config = Config::from_json(
+        r#"{ "args": ["app.wasm"], "preopen": [{ "guest": "/", "devices": true }] }"#,
+    )
+    .unwrap();
+    assert_eq!(config.args, vec!["app.wasm"]);
+    assert!(config.preopens[0].devices);
+
+    let err = Config::from_json(r#"{ "args": [] } trailing"#).unwrap_err();
+    assert_eq!(err.key(), "");
+}
+
+#[test]
+fn errors_name_the_offending_key() {
+    let err = Config::from_toml("[[preopen]]\nguest = \"/\"\nhots = \"/srv\"").unwrap_err();
+    assert_eq!(err.key(), "preopen[0].hots");
+
+    let err = Config::from_toml("disable = [\"time_travel\"]").unwrap_err();
+    assert_eq!(err.key(), "disable[0]");
+
+    let err = Config::from_toml("args = 1").unwrap_err();
+    assert_eq!(err.key(), "args");
+    assert!(err.to_string().starts_with("args: "), "{}", err);
+}
+
+#[test]
+fn invalid_preopens_are_rejected_when_building() {
+    let dir = tempfile::tempdir().unwrap();
+    let host = dir.path().to_str().unwrap();
+
+    let config = Config::from_toml(&format!(
+        "[[preopen]]\nguest = \"/\"\nhost = {:?}\nrights = [\"fd_read\", \"fd_fly\"]",
+        host
+    ))
+    .unwrap();
+    let err = config.into_builder().err().unwrap();
+    assert_eq!(err.key(), "preopen[0].rights[1]");
+    assert!(err.message().contains("fd_fly"), "{}", err);
+
+    let config = Config::from_toml(&format!(
+        "[[preopen]]\nguest = \"/\"\nhost = {:?}\ndevices = true",
+        host
+    ))
+    .unwrap();
+    assert_eq!(config.into_builder().err().unwrap().key(), "preopen[0]");
+
+    let config =
+        Config::from_toml("[[preopen]]\nguest = \"/\"\nhost = \"/does/not/exist\"").unwrap();
+    assert_eq!(
+        config.into_builder().err().unwrap().key(),
+        "preopen[0].host"
+    );
+
+    let config = Config::from_toml("args = [\"a\"]\ninherit_args = true").unwrap();
+    assert_eq!(config.into_builder().err().unwrap().key(), "inherit_args");
+}
+
+#[test]
+fn builds_the_configured_context() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("file"), "contents").unwrap();
+    fs::write(dir.path().join("secret.key"), "key").unwrap();
+    let config_path = dir.path().join("config.toml");
+    fs::write(
+        &config_path,
+        format!(
+            r#"
+            disable = ["random"]
+
+            [stdio]
+            stdout = {{ file = {:?} }}
+
+            [[preopen]]
+            guest = "/data"
+            host = {:?}
+            rights = ["fd_read", "fd_write", "path_open", "path_create_file"]
+            policy = {{ deny = [{{ pattern = "*.key" }}] }}
+            "#,
+            dir.path().join("stdout").to_str().unwrap(),
+            dir.path().to_str().unwrap(),
+        ),
+    )
+    .unwrap();
+
+    let mut guest = guest_with(
+        Config::from_file(&config_path)
+            .unwrap()
+            .into_builder()
+            .unwrap(),
+    );
+
+    assert_eq!(guest.prestat_dir_name(DIR).unwrap(), "/data");
+    let fdstat = guest.fdstat(DIR).unwrap();
+    // the rights which don't apply to directories are only inherited
+    assert_eq!(
+        fdstat.rights_base,
+        wasm32::__WASI_RIGHT_PATH_OPEN | wasm32::__WASI_RIGHT_PATH_CREATE_FILE
+    );
+    assert_eq!(
+        fdstat.rights_inheriting,
+        wasm32::__WASI_RIGHT_FD_READ
+            | wasm32::__WASI_RIGHT_FD_WRITE
+            | wasm32::__WASI_RIGHT_PATH_OPEN
+            | wasm32::__WASI_RIGHT_PATH_CREATE_FILE
+    );
+    let fd = guest
+        .open_with_rights(DIR, "file", 0, 0, Some(wasm32::__WASI_RIGHT_FD_READ))
+        .unwrap();
+    assert_eq!(guest.read(fd, 100).unwrap(), b"contents");
+    assert_eq!(
+        guest.open_with_rights(DIR, "secret.key", 0, 0, Some(wasm32::__WASI_RIGHT_FD_READ)),
+        Err(wasm32::__WASI_EACCES)
+    );
+    guest.write(1, b"output").unwrap();
+    assert_eq!(fs::read(dir.path().join("stdout")).unwrap(), b"output");
+    assert_eq!(
+        unsafe { wasi_common::hostcalls::random_get(&guest.ctx, &mut guest.mem, 0x10, 4) },
+        wasm32::__WASI_ENOSYS
+    );
+}
+
+#[test]
+fn files_need_a_known_extension() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    fs::write(&path, "args: []").unwrap();
+    let err = Config::from_file(&path).unwrap_err();
+    assert!(err.message().contains(".toml or .json"), "{}", err);
+
+    let path = dir.path().join("config.json");
+    fs::write(&path, r#"{ "args": ["app.wasm"] }"#).unwrap();
+    assert_eq!(Config::from_file(&path).unwrap().args, vec!["app.wasm"]);
+}
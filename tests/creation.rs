@@ -0,0 +1,107 @@
+#![cfg(unix)]
+mod common;
+
+use common::{guest_with, sandbox, DIR};
+use nix::sys::stat::{umask, Mode};
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use wasi_common::{wasm32, CreationMode, OverlayUpper, WasiCtxBuilder};
+
+fn builder(dir: &tempfile::TempDir) -> WasiCtxBuilder {
+    // the umask is shared by all tests, so they all set the same one
+    umask(Mode::from_bits_truncate(0o027));
+    sandbox(dir)
+}
+
+fn mode(path: &Path) -> u32 {
+    fs::metadata(path).unwrap().permissions().mode() & 0o7777
+}
+
+#[test]
+fn modes_are_applied_regardless_of_the_umask() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest =
+        guest_with(builder(&dir).creation_mode("/sandbox", CreationMode::new(0o664, 0o2775)));
+    guest.create(DIR, "file").unwrap();
+    guest.mkdir(DIR, "dir").unwrap();
+    assert_eq!(mode(&dir.path().join("file")), 0o664);
+    assert_eq!(mode(&dir.path().join("dir")), 0o2775);
+
+    // and below directories opened from the preopen
+    let sub = guest
+        .open(DIR, "dir", wasm32::__WASI_O_DIRECTORY, 0)
+        .unwrap();
+    guest.create(sub, "nested").unwrap();
+    guest.mkdir(sub, "nested_dir").unwrap();
+    assert_eq!(mode(&dir.path().join("dir/nested")), 0o664);
+    assert_eq!(mode(&dir.path().join("dir/nested_dir")), 0o2775);
+}
+
+#[test]
+fn the_umask_applies_by_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    guest.create(DIR, "file").unwrap();
+    guest.mkdir(DIR, "dir").unwrap();
+    assert_eq!(mode(&dir.path().join("file")), 0o640);
+    assert_eq!(mode(&dir.path().join("dir")), 0o750);
+}
+
+#[test]
+fn existing_files_are_left_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file");
+    fs::write(&path, "contents").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+    let mut guest =
+        guest_with(builder(&dir).creation_mode("/sandbox", CreationMode::new(0o666, 0o777)));
+    guest
+        .open(
+            DIR,
+            "file",
+            wasm32::__WASI_O_CREAT | wasm32::__WASI_O_TRUNC,
+            0,
+        )
+        .unwrap();
+    assert_eq!(mode(&path), 0o600);
+}
+
+#[test]
+fn new_files_get_the_group() {
+    let dir = tempfile::tempdir().unwrap();
+    let gid = nix::unistd::getegid().as_raw();
+    let mut guest = guest_with(
+        builder(&dir).creation_mode("/sandbox", CreationMode::new(0o660, 0o770).group(gid)),
+    );
+    guest.create(DIR, "file").unwrap();
+    guest.mkdir(DIR, "dir").unwrap();
+    for name in &["file", "dir"] {
+        let metadata = fs::metadata(dir.path().join(name)).unwrap();
+        assert_eq!(metadata.gid(), gid);
+    }
+    assert_eq!(mode(&dir.path().join("file")), 0o660);
+}
+
+#[test]
+fn modes_are_only_supported_for_host_directories() {
+    let lower = tempfile::tempdir().unwrap();
+    let err = WasiCtxBuilder::new()
+        .unwrap()
+        .preopened_overlay(lower.path(), OverlayUpper::Memory, "/overlay")
+        .unwrap()
+        .creation_mode("/overlay", CreationMode::new(0o666, 0o777))
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err.as_wasi_errno(), wasm32::__WASI_ENOTSUP);
+
+    let err = WasiCtxBuilder::new()
+        .unwrap()
+        .preopened_devices("/dev")
+        .creation_mode("/dev", CreationMode::new(0o666, 0o777))
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err.as_wasi_errno(), wasm32::__WASI_ENOTSUP);
+}
@@ -0,0 +1,82 @@
+#![cfg(unix)]
+mod common;
+
+use common::{guest_with, sandbox, DIR};
+use std::fs::OpenOptions;
+use wasi_common::{preopen_dir, wasm32, DevicePolicy, WasiCtxBuilder};
+
+/// A builder with `/dev` preopened, subject to `policy`.
+fn builder(policy: DevicePolicy) -> WasiCtxBuilder {
+    WasiCtxBuilder::new()
+        .unwrap()
+        .preopened_dir(preopen_dir("/dev").unwrap(), "/dev")
+        .device_policy(policy)
+}
+
+#[test]
+fn no_device_is_allowed_by_default() {
+    let mut guest = guest_with(builder(DevicePolicy::new()));
+    for name in &["null", "zero", "tty"] {
+        assert_eq!(guest.open(DIR, name, 0, 0), Err(wasm32::__WASI_EACCES));
+    }
+}
+
+#[test]
+fn devices_are_checked_before_being_opened() {
+    // opening `/dev/tty` fails with `ENXIO` without a controlling terminal, if it's opened
+    let mut guest = guest_with(builder(DevicePolicy::standard()));
+    assert_eq!(guest.open(DIR, "tty", 0, 0), Err(wasm32::__WASI_EACCES));
+    assert_eq!(guest.open(DIR, "full", 0, 0), Err(wasm32::__WASI_EACCES));
+}
+
+#[test]
+fn allowed_devices_can_be_read_written_and_synced() {
+    let mut guest = guest_with(builder(DevicePolicy::standard()));
+    let null = guest.open(DIR, "null", 0, 0).unwrap();
+    let fdstat = guest.fdstat(null).unwrap();
+    assert_eq!(fdstat.filetype, wasm32::__WASI_FILETYPE_CHARACTER_DEVICE);
+    for right in &[
+        wasm32::__WASI_RIGHT_FD_READ,
+        wasm32::__WASI_RIGHT_FD_WRITE,
+        wasm32::__WASI_RIGHT_FD_SYNC,
+        wasm32::__WASI_RIGHT_FD_DATASYNC,
+    ] {
+        assert_ne!(fdstat.rights_base & right, 0);
+    }
+    assert_eq!(fdstat.rights_base & wasm32::__WASI_RIGHT_FD_SEEK, 0);
+    assert_eq!(fdstat.rights_inheriting, 0);
+    assert_eq!(guest.write(null, b"discarded"), Ok(9));
+    assert_eq!(guest.read(null, 10).unwrap(), b"");
+
+    let zero = guest.open(DIR, "zero", 0, 0).unwrap();
+    assert_eq!(guest.read(zero, 4).unwrap(), [0; 4]);
+}
+
+#[test]
+fn regular_files_are_unaffected() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(sandbox(&dir));
+    // the sandbox is the only preopen, so it has the same descriptor as `/dev` elsewhere
+    let fd = guest.create(DIR, "new").unwrap();
+    assert_eq!(guest.write(fd, b"data"), Ok(4));
+}
+
+#[test]
+fn only_devices_can_be_allowed() {
+    let dir = tempfile::tempdir().unwrap();
+    let err = WasiCtxBuilder::new()
+        .unwrap()
+        .device_policy(DevicePolicy::new().allow(dir.path()))
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err.as_wasi_errno(), wasm32::__WASI_ENODEV);
+}
+
+#[test]
+fn inserted_devices_are_exempt() {
+    let mut guest = guest_with(builder(DevicePolicy::new()));
+    let full = OpenOptions::new().write(true).open("/dev/full").unwrap();
+    let fd = guest.ctx.insert_file(full, !0, !0).unwrap();
+    assert_eq!(guest.write(fd, b"data"), Err(wasm32::__WASI_ENOSPC));
+}
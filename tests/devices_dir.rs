@@ -0,0 +1,106 @@
+mod common;
+
+use common::{guest_with, Guest, DIR};
+use std::fs::{self, File};
+use wasi_common::{wasm32, Capability, WasiCtxBuilder};
+
+/// A builder with the directory of devices preopened as `/dev`.
+fn builder() -> WasiCtxBuilder {
+    WasiCtxBuilder::new().unwrap().preopened_devices("/dev")
+}
+
+#[test]
+fn devices_are_character_devices() {
+    let mut guest = guest_with(builder());
+    assert_eq!(
+        guest.readdir(DIR).unwrap(),
+        vec![".", "..", "null", "zero", "random", "urandom", "stdin", "stdout", "stderr"]
+    );
+    for name in &["null", "zero", "urandom", "stdout"] {
+        assert_eq!(
+            guest.path_filestat(DIR, name).unwrap().filetype,
+            wasm32::__WASI_FILETYPE_CHARACTER_DEVICE
+        );
+    }
+
+    let null = guest.open(DIR, "null", 0, 0).unwrap();
+    assert_eq!(
+        guest.fdstat(null).unwrap().filetype,
+        wasm32::__WASI_FILETYPE_CHARACTER_DEVICE
+    );
+    assert_eq!(
+        guest.filestat(null).unwrap().filetype,
+        wasm32::__WASI_FILETYPE_CHARACTER_DEVICE
+    );
+    assert_eq!(
+        guest.seek(null, 0, wasm32::__WASI_WHENCE_SET),
+        Err(wasm32::__WASI_ESPIPE)
+    );
+}
+
+#[test]
+fn devices_read_and_discard() {
+    let mut guest = guest_with(builder());
+    let null = guest.open(DIR, "null", 0, 0).unwrap();
+    assert_eq!(guest.write(null, b"discarded"), Ok(9));
+    assert_eq!(guest.read(null, 16).unwrap(), b"");
+
+    let zero = guest.open(DIR, "zero", 0, 0).unwrap();
+    assert_eq!(guest.write(zero, b"discarded"), Ok(9));
+    assert_eq!(guest.read(zero, 16).unwrap(), [0; 16]);
+
+    let urandom = guest.open(DIR, "urandom", 0, 0).unwrap();
+    let first = guest.read(urandom, 32).unwrap();
+    let second = guest.read(urandom, 32).unwrap();
+    assert_eq!(first.len(), 32);
+    assert_ne!(first, second);
+}
+
+#[test]
+fn stdio_devices_open_the_descriptors_of_the_guest() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("stdout");
+    let mut guest = guest_with(builder().stdout(File::create(&path).unwrap()).unwrap());
+
+    let stdout = guest.open(DIR, "stdout", 0, 0).unwrap();
+    guest.write(stdout, b"through /dev/stdout").unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"through /dev/stdout");
+
+    guest.close(1).unwrap();
+    assert_eq!(guest.open(DIR, "stdout", 0, 0), Err(wasm32::__WASI_ENOENT));
+}
+
+#[test]
+fn the_directory_is_read_only() {
+    let mut guest = guest_with(builder());
+    assert_eq!(guest.create(DIR, "new"), Err(wasm32::__WASI_EROFS));
+    assert_eq!(guest.mkdir(DIR, "dir"), Err(wasm32::__WASI_EROFS));
+    assert_eq!(guest.unlink(DIR, "null"), Err(wasm32::__WASI_EROFS));
+}
+
+#[test]
+fn random_devices_need_the_random_capability() {
+    let mut guest = guest_with(builder().disable(Capability::Random));
+    assert_eq!(guest.open(DIR, "urandom", 0, 0), Err(wasm32::__WASI_ENOENT));
+    assert!(!guest.readdir(DIR).unwrap().contains(&"random".to_owned()));
+    assert!(guest.open(DIR, "zero", 0, 0).is_ok());
+}
+
+#[test]
+fn devices_are_restored_from_snapshots() {
+    let mut guest = guest_with(builder().disable(Capability::Random));
+    let zero = guest.open(DIR, "zero", 0, 0).unwrap();
+
+    let snapshot = guest.ctx.snapshot().unwrap();
+    let mut restored = Guest::new(
+        WasiCtxBuilder::from_snapshot(&snapshot)
+            .unwrap()
+            .build()
+            .unwrap(),
+    );
+    assert_eq!(restored.read(zero, 4).unwrap(), [0; 4]);
+    assert_eq!(
+        restored.open(DIR, "urandom", 0, 0),
+        Err(wasm32::__WASI_ENOENT)
+    );
+}
@@ -0,0 +1,72 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{guest_with, sandbox, DIR};
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use wasi_common::{wasm32, PathEncoding};
+
+/// "café.txt" in Latin-1.
+const LATIN1: &[u8] = b"caf\xe9.txt";
+
+fn host_file(dir: &tempfile::TempDir) -> bool {
+    // some file systems, such as those of macOS, only allow UTF-8 names
+    fs::write(dir.path().join(OsStr::from_bytes(LATIN1)), "latin-1").is_ok()
+}
+
+#[test]
+fn lossless_names_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    if !host_file(&dir) {
+        return;
+    }
+    let mut guest = guest_with(sandbox(&dir).path_encoding(PathEncoding::Lossless));
+
+    let names = guest.readdir_bytes(DIR).unwrap();
+    assert!(
+        names.iter().any(|name| name.as_slice() == LATIN1),
+        "{:?}",
+        names
+    );
+    let fd = guest.open(DIR, LATIN1, 0, 0).unwrap();
+    assert_eq!(guest.read(fd, 100).unwrap(), b"latin-1");
+
+    guest.symlink(LATIN1, DIR, "link").unwrap();
+    assert_eq!(guest.readlink(DIR, "link").unwrap(), LATIN1);
+    assert_eq!(
+        fs::read_link(dir.path().join("link")).unwrap().as_os_str(),
+        OsStr::from_bytes(LATIN1)
+    );
+
+    // symlinks to such names are followed when they lead through a directory
+    let mut subdir = b"dir\xe9".to_vec();
+    fs::create_dir(dir.path().join(OsStr::from_bytes(&subdir))).unwrap();
+    guest.symlink(&subdir, DIR, "dirlink").unwrap();
+    subdir.extend_from_slice(b"/new");
+    let fd = guest.create(DIR, "dirlink/new").unwrap();
+    guest.write(fd, b"new").unwrap();
+    assert_eq!(
+        fs::read(dir.path().join(OsStr::from_bytes(&subdir))).unwrap(),
+        b"new"
+    );
+}
+
+#[test]
+fn utf8_guests_can_not_see_other_names() {
+    let dir = tempfile::tempdir().unwrap();
+    if !host_file(&dir) {
+        return;
+    }
+    fs::write(dir.path().join("caf\u{e9}.txt"), "utf-8").unwrap();
+    std::os::unix::fs::symlink(OsStr::from_bytes(LATIN1), dir.path().join("link")).unwrap();
+    let mut guest = guest_with(sandbox(&dir).path_encoding(PathEncoding::Utf8));
+
+    // the Latin-1 name is skipped, while the UTF-8 one is listed as usual
+    let mut names = guest.readdir(DIR).unwrap();
+    names.sort();
+    assert_eq!(names, vec![".", "..", "caf\u{e9}.txt", "link"]);
+    assert_eq!(guest.open(DIR, LATIN1, 0, 0), Err(wasm32::__WASI_EILSEQ));
+    assert_eq!(guest.readlink(DIR, "link"), Err(wasm32::__WASI_EILSEQ));
+}
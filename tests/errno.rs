@@ -0,0 +1,135 @@
+use std::io;
+use wasi_common::errno;
+use wasi_common::wasm32::{self, __wasi_errno_t};
+
+/// Every errno defined by WASI, other than `__WASI_ESUCCESS`.
+fn all_errnos() -> impl Iterator<Item = __wasi_errno_t> {
+    wasm32::__WASI_E2BIG..=wasm32::__WASI_ENOTCAPABLE
+}
+
+const KINDS: &[io::ErrorKind] = &[
+    io::ErrorKind::NotFound,
+    io::ErrorKind::PermissionDenied,
+    io::ErrorKind::ConnectionRefused,
+    io::ErrorKind::ConnectionReset,
+    io::ErrorKind::ConnectionAborted,
+    io::ErrorKind::NotConnected,
+    io::ErrorKind::AddrInUse,
+    io::ErrorKind::AddrNotAvailable,
+    io::ErrorKind::BrokenPipe,
+    io::ErrorKind::AlreadyExists,
+    io::ErrorKind::WouldBlock,
+    io::ErrorKind::InvalidInput,
+    io::ErrorKind::InvalidData,
+    io::ErrorKind::TimedOut,
+    io::ErrorKind::WriteZero,
+    io::ErrorKind::Interrupted,
+    io::ErrorKind::Other,
+    io::ErrorKind::UnexpectedEof,
+];
+
+#[test]
+fn io_error_round_trip() {
+    for errno in all_errnos() {
+        let err = errno::to_io_error(errno);
+        assert_eq!(
+            errno::from_io_error(&err),
+            errno,
+            "{} converted to {:?}",
+            wasm32::strerror(errno),
+            err
+        );
+    }
+}
+
+#[test]
+fn io_result() {
+    assert!(errno::to_io_result(wasm32::__WASI_ESUCCESS).is_ok());
+    for errno in all_errnos() {
+        let err = errno::to_io_result(errno).unwrap_err();
+        assert_eq!(errno::from_io_error(&err), errno);
+    }
+}
+
+#[test]
+fn unknown_errno_round_trip() {
+    let err = errno::to_io_error(1000);
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    assert_eq!(errno::from_io_error(&err), 1000);
+}
+
+#[test]
+fn host_round_trip() {
+    let mut codes = Vec::new();
+    for errno in all_errnos() {
+        if let Some(code) = errno::to_host(errno) {
+            assert!(
+                !codes.contains(&code),
+                "{} shares its host code",
+                wasm32::strerror(errno)
+            );
+            codes.push(code);
+            assert_eq!(errno::from_host(code), errno, "{}", wasm32::strerror(errno));
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn all_errnos_but_notcapable_exist_on_unix() {
+    for errno in all_errnos() {
+        let expected = errno != wasm32::__WASI_ENOTCAPABLE;
+        assert_eq!(
+            errno::to_host(errno).is_some(),
+            expected,
+            "{}",
+            wasm32::strerror(errno)
+        );
+    }
+}
+
+#[test]
+fn os_error_kind_matches() {
+    for errno in all_errnos() {
+        let kind = errno::to_io_error_kind(errno);
+        if kind != io::ErrorKind::Other {
+            assert_eq!(errno::to_io_error(errno).kind(), kind);
+        }
+    }
+}
+
+#[test]
+fn io_error_kind_round_trip() {
+    for kind in KINDS {
+        let errno = errno::from_io_error_kind(*kind);
+        match kind {
+            io::ErrorKind::Other
+            | io::ErrorKind::InvalidData
+            | io::ErrorKind::WriteZero
+            | io::ErrorKind::UnexpectedEof => assert_eq!(errno, wasm32::__WASI_EIO),
+            kind => assert_eq!(errno::to_io_error_kind(errno), *kind),
+        }
+    }
+}
+
+#[test]
+fn io_error_without_os_code() {
+    let err = io::Error::new(io::ErrorKind::NotFound, "no such thing");
+    assert_eq!(errno::from_io_error(&err), wasm32::__WASI_ENOENT);
+    let err = io::Error::new(io::ErrorKind::UnexpectedEof, "short read");
+    assert_eq!(errno::from_io_error(&err), wasm32::__WASI_EIO);
+}
+
+#[test]
+fn descriptions() {
+    assert_eq!(errno::description(wasm32::__WASI_ESUCCESS), "Success");
+    for errno in all_errnos() {
+        assert_ne!(
+            errno::description(errno),
+            "Unknown error",
+            "{}",
+            wasm32::strerror(errno)
+        );
+    }
+    assert_eq!(errno::description(1000), "Unknown error");
+}
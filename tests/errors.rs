@@ -0,0 +1,82 @@
+mod common;
+
+use common::{guest_with, sandbox, DIR};
+use std::error::Error as _;
+use std::fs;
+use std::path::Path;
+use wasi_common::{wasm32, PathOp, PathPolicy, WasiCtxBuilder};
+
+/// A builder with `dir` preopened, denying any operation on keys.
+fn builder(dir: &tempfile::TempDir) -> WasiCtxBuilder {
+    sandbox(dir).path_policy("/sandbox", PathPolicy::new().deny(PathOp::ALL, "*.key"))
+}
+
+#[test]
+fn unresolved_paths_are_recorded_as_given() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    assert_eq!(
+        guest.open(DIR, "missing/file", 0, 0),
+        Err(wasm32::__WASI_ENOENT)
+    );
+
+    let err = guest.ctx.take_last_error().unwrap();
+    assert_eq!(err.as_wasi_errno(), wasm32::__WASI_ENOENT);
+    assert_eq!(err.hostcall(), Some("path_open"));
+    assert_eq!(err.fd(), Some(DIR));
+    assert_eq!(err.path(), Some(Path::new("missing/file")));
+    assert_eq!(
+        err.to_string(),
+        "path_open failed on fd 3 at \"missing/file\": ENOENT"
+    );
+    assert!(guest.ctx.take_last_error().is_none());
+}
+
+#[test]
+fn failed_operations_record_the_resolved_path() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("a")).unwrap();
+    fs::create_dir_all(dir.path().join("b/c")).unwrap();
+    let mut guest = guest_with(builder(&dir));
+    assert_eq!(guest.rmdir(DIR, "a/../b"), Err(wasm32::__WASI_ENOTEMPTY));
+
+    let err = guest.ctx.take_last_error().unwrap();
+    assert_eq!(err.hostcall(), Some("path_remove_directory"));
+    assert_eq!(err.path(), Some(Path::new("b")));
+    assert_eq!(
+        err.to_string(),
+        "path_remove_directory failed on fd 3 at \"b\": ENOTEMPTY"
+    );
+}
+
+#[test]
+fn denied_paths_are_recorded() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("secret.key"), "key").unwrap();
+    let mut guest = guest_with(builder(&dir));
+    assert_eq!(
+        guest.open(DIR, "./secret.key", 0, 0),
+        Err(wasm32::__WASI_EACCES)
+    );
+
+    let err = guest.ctx.take_last_error().unwrap();
+    assert_eq!(err.path(), Some(Path::new("secret.key")));
+    assert!(err.io_error().is_none());
+}
+
+#[test]
+fn errors_without_a_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    guest.open(DIR, "missing", 0, 0).unwrap_err();
+    assert_eq!(guest.close(42), Err(wasm32::__WASI_EBADF));
+
+    // only the most recent error is kept
+    let err = guest.ctx.take_last_error().unwrap();
+    assert_eq!(err.hostcall(), Some("fd_close"));
+    assert_eq!(err.fd(), Some(42));
+    assert_eq!(err.path(), None);
+    assert_eq!(err.to_string(), "fd_close failed on fd 42: EBADF");
+    // the errno is already part of the message
+    assert!(err.source().is_none());
+}
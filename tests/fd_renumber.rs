@@ -0,0 +1,95 @@
+mod common;
+
+use common::{guest_with, sandbox, DIR};
+use std::fs::{self, File};
+use wasi_common::{wasm32, WasiCtxBuilder};
+
+/// A builder with `dir` preopened and stdout going to `dir/stdout`.
+fn builder(dir: &tempfile::TempDir) -> WasiCtxBuilder {
+    sandbox(dir)
+        .stdout(File::create(dir.path().join("stdout")).unwrap())
+        .unwrap()
+}
+
+#[test]
+fn renumber_onto_itself() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    let fd = guest.create(DIR, "file").unwrap();
+
+    guest.renumber(fd, fd).unwrap();
+    assert_eq!(guest.write(fd, b"still open"), Ok(10));
+    assert_eq!(guest.renumber(DIR, DIR), Ok(()));
+    assert_eq!(guest.prestat_dir_name(DIR).unwrap(), "/sandbox");
+
+    assert_eq!(guest.renumber(42, 42), Err(wasm32::__WASI_EBADF));
+}
+
+#[test]
+fn renumber_onto_stdout() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    let fd = guest.create(DIR, "file").unwrap();
+
+    guest.renumber(fd, 1).unwrap();
+    guest.write(1, b"redirected").unwrap();
+    assert_eq!(fs::read(dir.path().join("file")).unwrap(), b"redirected");
+    assert_eq!(fs::read(dir.path().join("stdout")).unwrap(), b"");
+    assert_eq!(guest.write(fd, b"closed"), Err(wasm32::__WASI_EBADF));
+}
+
+#[test]
+fn renumber_stdout_away() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    let fd = guest.create(DIR, "file").unwrap();
+
+    guest.renumber(1, fd).unwrap();
+    guest.write(fd, b"to stdout").unwrap();
+    assert_eq!(fs::read(dir.path().join("stdout")).unwrap(), b"to stdout");
+    assert_eq!(fs::read(dir.path().join("file")).unwrap(), b"");
+    assert_eq!(guest.write(1, b"closed"), Err(wasm32::__WASI_EBADF));
+}
+
+#[test]
+fn renumber_preopen() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    let fd = guest.create(DIR, "file").unwrap();
+
+    guest.renumber(DIR, fd).unwrap();
+    assert_eq!(guest.prestat_dir_name(fd).unwrap(), "/sandbox");
+    assert_eq!(guest.prestat_dir_name(DIR), Err(wasm32::__WASI_EBADF));
+    // the preopen keeps its rights, so files can still be opened from it
+    guest.create(fd, "other").unwrap();
+    // and it's still a preopen, which can't be closed
+    assert_eq!(guest.close(fd), Err(wasm32::__WASI_ENOTSUP));
+}
+
+#[test]
+fn renumber_onto_preopen() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    let fd = guest.create(DIR, "file").unwrap();
+
+    guest.renumber(fd, DIR).unwrap();
+    assert_eq!(
+        guest.prestat_dir_name(DIR),
+        Err(wasm32::__WASI_ENOTSUP),
+        "the preopen was closed"
+    );
+    guest.write(DIR, b"file").unwrap();
+    assert_eq!(fs::read(dir.path().join("file")).unwrap(), b"file");
+    assert_eq!(guest.close(DIR), Ok(()));
+}
+
+#[test]
+fn renumber_missing() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    let fd = guest.create(DIR, "file").unwrap();
+
+    assert_eq!(guest.renumber(fd, 42), Err(wasm32::__WASI_EBADF));
+    assert_eq!(guest.renumber(42, fd), Err(wasm32::__WASI_EBADF));
+    assert_eq!(guest.write(fd, b"open"), Ok(4));
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use common::{guest_with, sandbox, DIR};
+use std::fs::{self, File};
+use wasi_common::{wasm32, OverlayUpper, WasiCtxBuilder};
+
+/// A builder with `dir` preopened, in which there's a file, and stdout going to `dir/stdout`.
+fn builder(dir: &tempfile::TempDir) -> WasiCtxBuilder {
+    fs::write(dir.path().join("file"), "contents").unwrap();
+    sandbox(dir)
+        .stdout(File::create(dir.path().join("stdout")).unwrap())
+        .unwrap()
+}
+
+fn same_file(a: &std::path::Path, b: &std::path::Path) -> bool {
+    a.canonicalize().unwrap() == b.canonicalize().unwrap()
+}
+
+#[test]
+fn lists_open_descriptors() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    let fd = guest
+        .open_with_rights(DIR, "file", 0, 0, Some(wasm32::__WASI_RIGHT_FD_READ))
+        .unwrap();
+    guest.read(fd, 3).unwrap();
+
+    let fds = guest.ctx.fds();
+    assert_eq!(
+        fds.iter().map(|info| info.fd()).collect::<Vec<_>>(),
+        vec![0, 1, 2, DIR, fd]
+    );
+
+    let preopen = &fds[3];
+    assert_eq!(preopen.filetype(), wasm32::__WASI_FILETYPE_DIRECTORY);
+    assert_eq!(preopen.preopen_path().unwrap().to_str(), Some("/sandbox"));
+    assert!(same_file(preopen.host_path().unwrap(), dir.path()));
+    assert_eq!(preopen.offset(), None);
+
+    let file = &fds[4];
+    assert_eq!(file.filetype(), wasm32::__WASI_FILETYPE_REGULAR_FILE);
+    let fdstat = guest.fdstat(fd).unwrap();
+    assert_eq!(file.rights_base(), fdstat.rights_base);
+    assert_eq!(file.rights_inheriting(), fdstat.rights_inheriting);
+    assert_eq!(file.preopen_path(), None);
+    assert!(same_file(
+        file.host_path().unwrap(),
+        &dir.path().join("file")
+    ));
+    assert_eq!(file.offset(), Some(3));
+
+    assert!(same_file(
+        fds[1].host_path().unwrap(),
+        &dir.path().join("stdout")
+    ));
+    assert!(guest.ctx.fd_info(42).is_none());
+}
+
+#[test]
+fn finds_descriptors_by_host_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    let first = guest.open(DIR, "file", 0, 0).unwrap();
+    let second = guest.open(DIR, "./file", 0, 0).unwrap();
+
+    assert_eq!(
+        guest.ctx.fds_by_host_path(dir.path().join("file")),
+        vec![first, second]
+    );
+    assert_eq!(guest.ctx.fds_by_host_path(dir.path()), vec![DIR]);
+    assert!(guest
+        .ctx
+        .fds_by_host_path(dir.path().join("missing"))
+        .is_empty());
+
+    guest.close(first).unwrap();
+    assert_eq!(
+        guest.ctx.fds_by_host_path(dir.path().join("file")),
+        vec![second]
+    );
+}
+
+#[test]
+fn files_of_host_layers_have_host_paths() {
+    let lower = tempfile::tempdir().unwrap();
+    let upper = tempfile::tempdir().unwrap();
+    fs::write(lower.path().join("file"), "contents").unwrap();
+    let mut guest = guest_with(
+        WasiCtxBuilder::new()
+            .unwrap()
+            .preopened_overlay(
+                lower.path(),
+                OverlayUpper::Host(upper.path().to_owned()),
+                "/",
+            )
+            .unwrap(),
+    );
+
+    let read = guest
+        .open_with_rights(DIR, "file", 0, 0, Some(wasm32::__WASI_RIGHT_FD_READ))
+        .unwrap();
+    let written = guest.create(DIR, "new").unwrap();
+    guest.write(written, b"new").unwrap();
+
+    let read = guest.ctx.fd_info(read).unwrap();
+    assert!(same_file(
+        read.host_path().unwrap(),
+        &lower.path().join("file")
+    ));
+    let written = guest.ctx.fd_info(written).unwrap();
+    assert!(same_file(
+        written.host_path().unwrap(),
+        &upper.path().join("new")
+    ));
+    assert_eq!(written.offset(), Some(3));
+}
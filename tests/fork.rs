@@ -0,0 +1,111 @@
+mod common;
+
+use common::{guest_with, sandbox, Guest, DIR};
+use std::fs;
+use wasi_common::{wasm32, ForkOptions, WasiCtxBuilder};
+
+/// A builder with `dir` preopened, in which there's a file, and an environment variable.
+fn builder(dir: &tempfile::TempDir) -> WasiCtxBuilder {
+    fs::write(dir.path().join("file"), "hello world").unwrap();
+    sandbox(dir).env("KEY", "value").unwrap()
+}
+
+fn fork(guest: &Guest, options: ForkOptions) -> Guest {
+    Guest::new(guest.ctx.fork(&options).unwrap())
+}
+
+#[test]
+fn files_get_their_own_offsets() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut parent = guest_with(builder(&dir));
+    let fd = parent.open(DIR, "file", 0, 0).unwrap();
+    parent.seek(fd, 6, wasm32::__WASI_WHENCE_SET).unwrap();
+
+    let mut child = fork(&parent, ForkOptions::new());
+    assert_eq!(child.read(fd, 100).unwrap(), b"world");
+    assert_eq!(parent.tell(fd), Ok(6));
+    parent.seek(fd, 0, wasm32::__WASI_WHENCE_SET).unwrap();
+    assert_eq!(child.tell(fd), Ok(11));
+    assert_eq!(parent.read(fd, 5).unwrap(), b"hello");
+}
+
+#[test]
+fn offsets_are_shared_on_request() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut parent = guest_with(builder(&dir));
+    let fd = parent.open(DIR, "file", 0, 0).unwrap();
+
+    let mut child = fork(&parent, ForkOptions::new().share_offsets(true));
+    assert_eq!(child.read(fd, 6).unwrap(), b"hello ");
+    assert_eq!(parent.read(fd, 100).unwrap(), b"world");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn directories_get_their_own_offsets() {
+    use nix::unistd::{lseek, Whence};
+    use std::io::{Seek, SeekFrom};
+    use std::os::unix::io::AsRawFd;
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("other"), "").unwrap();
+    let mut parent = guest_with(builder(&dir));
+    let mut child = fork(&parent, ForkOptions::new());
+
+    let mut parent_dir = parent.ctx.take_file(DIR).unwrap();
+    let child_dir = child.ctx.take_file(DIR).unwrap();
+    parent_dir.seek(SeekFrom::Start(1)).unwrap();
+    assert_eq!(lseek(child_dir.as_raw_fd(), 0, Whence::SeekCur).unwrap(), 0);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn removed_files_are_duplicated() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut parent = guest_with(builder(&dir));
+    let fd = parent.open(DIR, "file", 0, 0).unwrap();
+    parent.unlink(DIR, "file").unwrap();
+
+    let mut child = fork(&parent, ForkOptions::new());
+    assert_eq!(child.read(fd, 5).unwrap(), b"hello");
+    assert_eq!(parent.tell(fd), Ok(0));
+}
+
+#[test]
+fn rights_environment_and_descriptors() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut parent = guest_with(builder(&dir));
+    let fd = parent.open(DIR, "file", 0, 0).unwrap();
+    let other = parent.open(DIR, "file", 0, 0).unwrap();
+
+    let options = ForkOptions::new()
+        .fd_rights(fd, wasm32::__WASI_RIGHT_FD_READ, 0)
+        .close_fd(other)
+        .clear_env()
+        .env("CHILD", "1")
+        .unwrap();
+    let mut child = fork(&parent, options);
+
+    assert_eq!(
+        child.fdstat(fd).unwrap().rights_base,
+        wasm32::__WASI_RIGHT_FD_READ
+    );
+    assert_eq!(child.write(fd, b"denied"), Err(wasm32::__WASI_ENOTCAPABLE));
+    assert_eq!(child.close(other), Err(wasm32::__WASI_EBADF));
+    assert_eq!(child.prestat_dir_name(DIR).unwrap(), "/sandbox");
+    assert!(parent.write(fd, b"parent").is_ok());
+    assert_eq!(
+        child.ctx.snapshot().unwrap().env,
+        vec![("CHILD".to_owned(), "1".to_owned())]
+    );
+
+    assert_eq!(
+        parent
+            .ctx
+            .fork(&ForkOptions::new().close_fd(42))
+            .err()
+            .unwrap()
+            .as_wasi_errno(),
+        wasm32::__WASI_EBADF
+    );
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use common::{guest_with, sandbox, DIR};
+use std::fs::{self, File};
+use std::path::PathBuf;
+use wasi_common::{hostcalls, wasm32, GuestPath, JournalEntry, WasiCtxBuilder};
+
+/// A builder keeping a journal, with `dir` preopened, in which there's a file.
+fn builder(dir: &tempfile::TempDir) -> WasiCtxBuilder {
+    fs::write(dir.path().join("existing"), "hello world").unwrap();
+    sandbox(dir).journal()
+}
+
+fn path(path: &str) -> GuestPath {
+    GuestPath {
+        preopen: PathBuf::from("/sandbox"),
+        path: PathBuf::from(path),
+    }
+}
+
+#[test]
+fn records_modifications_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    guest.mkdir(DIR, "dir").unwrap();
+    let fd = guest.create(DIR, "dir/../dir/new").unwrap();
+    guest.write(fd, b"hello").unwrap();
+    guest.write(fd, b" world").unwrap();
+    guest.pwrite(fd, b"abc", 100).unwrap();
+    assert_eq!(
+        unsafe { hostcalls::fd_filestat_set_size(&guest.ctx, fd, 4) },
+        wasm32::__WASI_ESUCCESS
+    );
+    guest.rename(DIR, "dir/new", DIR, "renamed").unwrap();
+    guest.link(DIR, "renamed", DIR, "linked").unwrap();
+    guest.symlink("renamed", DIR, "symlink").unwrap();
+    guest.unlink(DIR, "linked").unwrap();
+    guest.rmdir(DIR, "dir").unwrap();
+
+    assert_eq!(
+        guest.ctx.journal().unwrap(),
+        vec![
+            JournalEntry::CreateDirectory(path("dir")),
+            JournalEntry::Create(path("dir/new")),
+            JournalEntry::Write {
+                path: path("dir/new"),
+                offset: 0,
+                len: 11,
+            },
+            JournalEntry::Write {
+                path: path("dir/new"),
+                offset: 100,
+                len: 3,
+            },
+            JournalEntry::SetSize {
+                path: path("dir/new"),
+                size: 4,
+            },
+            JournalEntry::Rename {
+                from: path("dir/new"),
+                to: path("renamed"),
+            },
+            JournalEntry::Link {
+                from: path("renamed"),
+                to: path("linked"),
+            },
+            JournalEntry::Symlink {
+                target: "renamed".to_owned(),
+                path: path("symlink"),
+            },
+            JournalEntry::Unlink(path("linked")),
+            JournalEntry::RemoveDirectory(path("dir")),
+        ]
+    );
+}
+
+#[test]
+fn opening_records_only_creation_and_truncation() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    let fd = guest.open(DIR, "existing", 0, 0).unwrap();
+    guest.read(fd, 100).unwrap();
+    guest
+        .open(DIR, "existing", wasm32::__WASI_O_CREAT, 0)
+        .unwrap();
+    guest
+        .open(DIR, "existing", wasm32::__WASI_O_TRUNC, 0)
+        .unwrap();
+
+    assert_eq!(
+        guest.ctx.journal().unwrap(),
+        vec![JournalEntry::Truncate(path("existing"))]
+    );
+}
+
+#[test]
+fn timestamps_are_recorded() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    let fd = guest.open(DIR, "existing", 0, 0).unwrap();
+    let errno = unsafe {
+        hostcalls::fd_filestat_set_times(&guest.ctx, fd, 0, 42, wasm32::__WASI_FILESTAT_SET_MTIM)
+    };
+    assert_eq!(errno, wasm32::__WASI_ESUCCESS);
+
+    assert_eq!(
+        guest.ctx.journal().unwrap(),
+        vec![JournalEntry::SetTimes {
+            path: path("existing"),
+            atim: None,
+            mtim: Some(42),
+        }]
+    );
+}
+
+#[test]
+fn failures_and_other_descriptors_are_not_recorded() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    assert_eq!(guest.rmdir(DIR, "missing"), Err(wasm32::__WASI_ENOENT));
+    assert_eq!(guest.mkdir(DIR, "existing"), Err(wasm32::__WASI_EEXIST));
+
+    // files handed to the guest by the embedder weren't reached through a preopen
+    let file = File::create(dir.path().join("inserted")).unwrap();
+    let fd = guest.ctx.insert_file(file, !0, !0).unwrap();
+    guest.write(fd, b"data").unwrap();
+
+    assert_eq!(guest.ctx.journal().unwrap(), vec![]);
+}
+
+#[test]
+fn journals_are_opt_in() {
+    let ctx = WasiCtxBuilder::new().unwrap().build().unwrap();
+    assert!(ctx.journal().is_none());
+}
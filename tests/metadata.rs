@@ -0,0 +1,117 @@
+mod common;
+
+use common::{guest_with, sandbox, Guest, DIR};
+use std::fs;
+use std::time::{Duration, UNIX_EPOCH};
+use wasi_common::{hostcalls, wasm32, ForkOptions, VirtualMetadata, WasiCtxBuilder};
+
+/// A builder with `dir` preopened, in which there's a file and a directory.
+fn builder(dir: &tempfile::TempDir) -> WasiCtxBuilder {
+    fs::write(dir.path().join("file"), "hello world").unwrap();
+    fs::create_dir(dir.path().join("dir")).unwrap();
+    sandbox(dir)
+}
+
+fn inode_of(guest: &mut Guest, dirfd: u32, name: &str) -> u64 {
+    let entries = guest.readdir_inodes(dirfd).unwrap();
+    let (_, ino) = entries
+        .into_iter()
+        .find(|(entry, _)| entry.as_slice() == name.as_bytes())
+        .unwrap();
+    ino
+}
+
+#[test]
+fn devices_are_the_preopen_and_inodes_are_handed_out_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir).virtual_metadata(VirtualMetadata::new()));
+
+    let file = guest.path_filestat(DIR, "file").unwrap();
+    assert_eq!(file.dev, u64::from(DIR));
+    assert_eq!(file.ino, 1);
+    let preopen = guest.filestat(DIR).unwrap();
+    assert_eq!(preopen.dev, u64::from(DIR));
+    assert_eq!(preopen.ino, 2);
+    // the size and file type are passed through
+    assert_eq!(file.size, 11);
+    assert_eq!(file.filetype, wasm32::__WASI_FILETYPE_REGULAR_FILE);
+}
+
+#[test]
+fn inodes_are_consistent_across_hostcalls() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir).virtual_metadata(VirtualMetadata::new()));
+
+    let from_readdir = inode_of(&mut guest, DIR, "dir");
+    let fd = guest
+        .open(DIR, "dir", wasm32::__WASI_O_DIRECTORY, 0)
+        .unwrap();
+    assert_eq!(guest.filestat(fd).unwrap().ino, from_readdir);
+    assert_eq!(guest.path_filestat(DIR, "dir").unwrap().ino, from_readdir);
+    // and `..` of the directory is the preopen
+    assert_eq!(
+        inode_of(&mut guest, fd, ".."),
+        guest.filestat(DIR).unwrap().ino
+    );
+
+    // a file keeps its inode number when it's renamed
+    let file = guest.path_filestat(DIR, "file").unwrap().ino;
+    guest.rename(DIR, "file", fd, "moved").unwrap();
+    assert_eq!(guest.path_filestat(fd, "moved").unwrap().ino, file);
+    assert_eq!(inode_of(&mut guest, fd, "moved"), file);
+}
+
+#[test]
+fn forked_contexts_share_inodes() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut parent = guest_with(builder(&dir).virtual_metadata(VirtualMetadata::new()));
+    let dir_ino = parent.path_filestat(DIR, "dir").unwrap().ino;
+
+    let mut child = Guest::new(parent.ctx.fork(&ForkOptions::new()).unwrap());
+    assert_eq!(child.path_filestat(DIR, "dir").unwrap().ino, dir_ino);
+    let file_ino = child.path_filestat(DIR, "file").unwrap().ino;
+    assert_eq!(parent.path_filestat(DIR, "file").unwrap().ino, file_ino);
+}
+
+#[test]
+fn timestamps_are_clamped() {
+    let dir = tempfile::tempdir().unwrap();
+    let epoch = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let mut guest =
+        guest_with(builder(&dir).virtual_metadata(VirtualMetadata::new().clamp_timestamps(epoch)));
+    let epoch = 1_000_000 * 1_000_000_000;
+
+    let file = guest.path_filestat(DIR, "file").unwrap();
+    assert_eq!((file.atim, file.mtim, file.ctim), (epoch, epoch, epoch));
+
+    // earlier timestamps are passed through
+    let fd = guest.open(DIR, "file", 0, 0).unwrap();
+    let errno = unsafe {
+        hostcalls::fd_filestat_set_times(
+            &guest.ctx,
+            fd,
+            0,
+            1_000_000_000,
+            wasm32::__WASI_FILESTAT_SET_MTIM,
+        )
+    };
+    assert_eq!(errno, wasm32::__WASI_ESUCCESS);
+    let file = guest.filestat(fd).unwrap();
+    assert_eq!(file.mtim, 1_000_000_000);
+    assert_eq!(file.atim, epoch);
+}
+
+#[cfg(unix)]
+#[test]
+fn host_metadata_is_passed_through_by_default() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    let host = fs::metadata(dir.path().join("file")).unwrap();
+
+    let file = guest.path_filestat(DIR, "file").unwrap();
+    assert_eq!(file.dev, host.dev());
+    assert_eq!(file.ino, host.ino());
+    assert_eq!(inode_of(&mut guest, DIR, "file"), host.ino());
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use common::{guest_with, sandbox, DIR};
+use std::fs;
+use std::path::Path;
+use wasi_common::{hostcalls, wasm32, WasiCtxBuilder};
+
+/// A builder collecting metrics, with `dir` preopened, in which there's a file.
+fn builder(dir: &tempfile::TempDir) -> WasiCtxBuilder {
+    fs::write(dir.path().join("file"), "hello world").unwrap();
+    sandbox(dir).metrics()
+}
+
+#[test]
+fn counts_bytes_per_descriptor() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    let read = guest.open(DIR, "file", 0, 0).unwrap();
+    let written = guest.create(DIR, "new").unwrap();
+    guest.read(read, 5).unwrap();
+    guest.read(read, 100).unwrap();
+    guest.write(written, b"abc").unwrap();
+
+    let report = guest.ctx.metrics().unwrap();
+    assert_eq!(report.bytes_read, 11);
+    assert_eq!(report.bytes_written, 3);
+    assert_eq!(report.fds[&read].bytes_read, 11);
+    assert_eq!(report.fds[&written].bytes_written, 3);
+    assert_eq!(report.paths_opened[Path::new("/sandbox/file")], 1);
+
+    guest.renumber(written, read).unwrap();
+    let report = guest.ctx.metrics().unwrap();
+    assert!(!report.fds.contains_key(&written));
+    assert_eq!(report.fds[&read].bytes_read, 0);
+    assert_eq!(report.fds[&read].bytes_written, 3);
+
+    guest.close(read).unwrap();
+    let report = guest.ctx.metrics().unwrap();
+    assert!(report.fds.is_empty());
+    assert_eq!(report.bytes_read, 11);
+}
+
+#[test]
+fn bytes_are_only_counted_when_reported_to_the_guest() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    let fd = guest.open(DIR, "file", 0, 0).unwrap();
+
+    // a single iovec of 5 bytes at 0x1000, with nread out of bounds
+    guest.mem[0x100..0x104].copy_from_slice(&0x1000u32.to_le_bytes());
+    guest.mem[0x104..0x108].copy_from_slice(&5u32.to_le_bytes());
+    let nread = guest.mem.len() as u32;
+    let errno = unsafe { hostcalls::fd_read(&mut guest.ctx, &mut guest.mem, fd, 0x100, 1, nread) };
+    assert_eq!(errno, wasm32::__WASI_EFAULT);
+
+    let report = guest.ctx.metrics().unwrap();
+    assert_eq!(report.bytes_read, 0);
+    assert!(!report.fds.contains_key(&fd));
+}
+
+#[test]
+fn counts_hostcalls_and_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    guest.open(DIR, "file", 0, 0).unwrap();
+    assert_eq!(guest.open(DIR, "missing", 0, 0), Err(wasm32::__WASI_ENOENT));
+    assert_eq!(guest.close(42), Err(wasm32::__WASI_EBADF));
+    assert_eq!(unsafe { hostcalls::sched_yield() }, wasm32::__WASI_ESUCCESS);
+
+    let report = guest.ctx.metrics().unwrap();
+    let path_open = &report.hostcalls["path_open"];
+    assert_eq!(path_open.calls, 2);
+    assert_eq!(path_open.errors, 1);
+    assert_eq!(report.hostcalls["fd_close"].errors, 1);
+    assert!(!report.hostcalls.contains_key("sched_yield"));
+    assert_eq!(report.errors[&wasm32::__WASI_ENOENT], 1);
+    assert_eq!(report.errors[&wasm32::__WASI_EBADF], 1);
+}
+
+#[test]
+fn metrics_are_opt_in() {
+    let ctx = WasiCtxBuilder::new().unwrap().build().unwrap();
+    assert!(ctx.metrics().is_none());
+}
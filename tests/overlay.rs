@@ -0,0 +1,143 @@
+mod common;
+
+use common::{guest_with, DIR};
+use std::fs;
+use std::path::Path;
+use wasi_common::{wasm32, OverlayUpper, PathEncoding, WasiCtxBuilder};
+
+struct Dirs {
+    lower: tempfile::TempDir,
+    upper: tempfile::TempDir,
+}
+
+impl Dirs {
+    fn new() -> Self {
+        let dirs = Self {
+            lower: tempfile::tempdir().unwrap(),
+            upper: tempfile::tempdir().unwrap(),
+        };
+        fs::write(dirs.lower.path().join("lower"), "from the lower layer").unwrap();
+        fs::create_dir(dirs.lower.path().join("dir")).unwrap();
+        fs::write(dirs.lower.path().join("dir/nested"), "nested").unwrap();
+        dirs
+    }
+
+    /// A builder with the layers preopened as an overlay.
+    fn builder(&self) -> WasiCtxBuilder {
+        WasiCtxBuilder::new()
+            .unwrap()
+            .preopened_overlay(
+                self.lower.path(),
+                OverlayUpper::Host(self.upper.path().to_owned()),
+                "/",
+            )
+            .unwrap()
+    }
+
+    fn upper(&self, path: &str) -> std::path::PathBuf {
+        self.upper.path().join(path)
+    }
+}
+
+fn sorted(mut names: Vec<String>) -> Vec<String> {
+    names.sort();
+    names
+}
+
+#[test]
+fn reads_through_to_the_lower_layer() {
+    let dirs = Dirs::new();
+    let mut guest = guest_with(dirs.builder());
+
+    let fd = guest
+        .open_with_rights(DIR, "lower", 0, 0, Some(wasm32::__WASI_RIGHT_FD_READ))
+        .unwrap();
+    assert_eq!(guest.read(fd, 100).unwrap(), b"from the lower layer");
+    assert_eq!(
+        sorted(guest.readdir(DIR).unwrap()),
+        vec![".", "..", "dir", "lower"]
+    );
+    assert!(fs::read_dir(dirs.upper.path()).unwrap().next().is_none());
+}
+
+#[test]
+fn writes_go_to_the_upper_layer() {
+    let dirs = Dirs::new();
+    let mut guest = guest_with(dirs.builder());
+
+    let fd = guest
+        .open(DIR, "lower", 0, wasm32::__WASI_FDFLAG_APPEND)
+        .unwrap();
+    guest.write(fd, b", modified").unwrap();
+    let fd = guest.create(DIR, "dir/new").unwrap();
+    guest.write(fd, b"new").unwrap();
+
+    assert_eq!(
+        fs::read_to_string(dirs.upper("lower")).unwrap(),
+        "from the lower layer, modified"
+    );
+    assert_eq!(fs::read_to_string(dirs.upper("dir/new")).unwrap(), "new");
+    assert_eq!(
+        fs::read_to_string(dirs.lower.path().join("lower")).unwrap(),
+        "from the lower layer"
+    );
+    assert!(!dirs.lower.path().join("dir/new").exists());
+}
+
+#[test]
+fn removals_are_whiteouts() {
+    let dirs = Dirs::new();
+    let mut guest = guest_with(dirs.builder());
+
+    guest.unlink(DIR, "lower").unwrap();
+    guest.unlink(DIR, "dir/nested").unwrap();
+    guest.rmdir(DIR, "dir").unwrap();
+
+    assert_eq!(sorted(guest.readdir(DIR).unwrap()), vec![".", ".."]);
+    assert_eq!(guest.open(DIR, "lower", 0, 0), Err(wasm32::__WASI_ENOENT));
+    assert!(dirs.upper(".wh.lower").exists());
+    assert!(dirs.upper(".wh.dir").exists());
+    assert!(dirs.lower.path().join("dir/nested").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn replaced_directory_does_not_lead_out_of_the_layer() {
+    let dirs = Dirs::new();
+    let outside = tempfile::tempdir().unwrap();
+    fs::write(outside.path().join("secret"), "secret").unwrap();
+    let mut guest = guest_with(dirs.builder());
+
+    guest.mkdir(DIR, "d").unwrap();
+    let fd = guest.open(DIR, "d", wasm32::__WASI_O_DIRECTORY, 0).unwrap();
+    guest.rmdir(DIR, "d").unwrap();
+    guest
+        .symlink(outside.path().to_str().unwrap(), DIR, "d")
+        .unwrap();
+    assert!(fs::symlink_metadata(dirs.upper("d"))
+        .unwrap()
+        .file_type()
+        .is_symlink());
+
+    assert!(guest.open(fd, "secret", 0, 0).is_err());
+    assert!(guest.path_filestat(fd, "secret").is_err());
+    assert!(guest.create(fd, "created").is_err());
+    assert!(guest.unlink(fd, "secret").is_err());
+    assert!(guest.readdir(fd).is_err());
+    assert!(outside.path().join("secret").exists());
+    assert!(!outside.path().join("created").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_targets_are_decoded() {
+    let dirs = Dirs::new();
+    let mut guest = guest_with(dirs.builder().path_encoding(PathEncoding::Lossless));
+
+    guest.symlink(&b"caf\xe9"[..], DIR, "link").unwrap();
+    assert_eq!(
+        fs::read_link(dirs.upper("link")).unwrap(),
+        Path::new(std::os::unix::ffi::OsStrExt::from_bytes(&b"caf\xe9"[..]) as &std::ffi::OsStr)
+    );
+    assert_eq!(guest.readlink(DIR, "link").unwrap(), b"caf\xe9");
+}
@@ -0,0 +1,163 @@
+mod common;
+
+use common::{guest_with, sandbox, Guest, DIR};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use wasi_common::{wasm32, Denial, PathOp, PathPolicy, WasiCtxBuilder};
+
+/// A builder with `dir` preopened under `policy`, in which there are some data files and a key.
+fn builder(dir: &tempfile::TempDir, policy: PathPolicy) -> WasiCtxBuilder {
+    fs::create_dir_all(dir.path().join("data/nested")).unwrap();
+    fs::write(dir.path().join("data/nested/table.csv"), "1,2").unwrap();
+    fs::write(dir.path().join("data/notes.txt"), "notes").unwrap();
+    fs::write(dir.path().join("secret.key"), "key").unwrap();
+    sandbox(dir).path_policy("/sandbox", policy)
+}
+
+fn open_read(guest: &mut Guest, dirfd: u32, path: &str) -> Result<u32, wasm32::__wasi_errno_t> {
+    guest.open_with_rights(dirfd, path, 0, 0, Some(wasm32::__WASI_RIGHT_FD_READ))
+}
+
+#[test]
+fn deny_rules_match_single_components_anywhere() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir, PathPolicy::new().deny(PathOp::ALL, "*.key")));
+
+    assert_eq!(
+        open_read(&mut guest, DIR, "secret.key"),
+        Err(wasm32::__WASI_EACCES)
+    );
+    assert_eq!(
+        guest.create(DIR, "data/other.key"),
+        Err(wasm32::__WASI_EACCES)
+    );
+    assert_eq!(guest.unlink(DIR, "secret.key"), Err(wasm32::__WASI_EACCES));
+    assert!(open_read(&mut guest, DIR, "data/notes.txt").is_ok());
+    assert!(dir.path().join("secret.key").exists());
+}
+
+#[test]
+fn allow_rules_restrict_their_operations_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let policy = PathPolicy::new().allow(&[PathOp::OpenWrite, PathOp::Create], "data/**/*.csv");
+    let mut guest = guest_with(builder(&dir, policy));
+
+    assert!(guest.create(DIR, "data/nested/new.csv").is_ok());
+    assert!(guest.create(DIR, "data/new.csv").is_ok());
+    assert_eq!(guest.create(DIR, "new.csv"), Err(wasm32::__WASI_EACCES));
+    assert_eq!(
+        guest.create(DIR, "data/notes.txt"),
+        Err(wasm32::__WASI_EACCES)
+    );
+    // reading isn't covered by any allow rule, so it's allowed everywhere
+    assert!(open_read(&mut guest, DIR, "secret.key").is_ok());
+    assert_eq!(
+        fs::read(dir.path().join("data/notes.txt")).unwrap(),
+        b"notes"
+    );
+}
+
+#[test]
+fn rules_cover_everything_below_a_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(
+        &dir,
+        PathPolicy::new().deny(&[PathOp::Unlink], "data/"),
+    ));
+
+    assert_eq!(
+        guest.unlink(DIR, "data/nested/table.csv"),
+        Err(wasm32::__WASI_EACCES)
+    );
+    assert_eq!(guest.rmdir(DIR, "data"), Err(wasm32::__WASI_EACCES));
+    assert!(guest.unlink(DIR, "secret.key").is_ok());
+}
+
+#[test]
+fn renames_are_checked_for_both_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(
+        &dir,
+        PathPolicy::new().deny(&[PathOp::Rename], "*.key"),
+    ));
+
+    assert_eq!(
+        guest.rename(DIR, "secret.key", DIR, "public"),
+        Err(wasm32::__WASI_EACCES)
+    );
+    assert_eq!(
+        guest.rename(DIR, "data/notes.txt", DIR, "notes.key"),
+        Err(wasm32::__WASI_EACCES)
+    );
+    assert!(guest
+        .rename(DIR, "data/notes.txt", DIR, "notes.txt")
+        .is_ok());
+}
+
+#[test]
+fn directories_inherit_the_policy_with_their_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(
+        &dir,
+        PathPolicy::new().deny(PathOp::ALL, "data/nested"),
+    ));
+    let data = guest
+        .open(DIR, "data", wasm32::__WASI_O_DIRECTORY, 0)
+        .unwrap();
+
+    assert_eq!(
+        open_read(&mut guest, data, "nested/table.csv"),
+        Err(wasm32::__WASI_EACCES)
+    );
+    assert!(open_read(&mut guest, data, "notes.txt").is_ok());
+}
+
+#[test]
+fn paths_are_checked_once_resolved() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(
+        &dir,
+        PathPolicy::new().deny(PathOp::ALL, "data/nested"),
+    ));
+    guest.symlink("data", DIR, "alias").unwrap();
+
+    assert_eq!(
+        open_read(&mut guest, DIR, "alias/nested/table.csv"),
+        Err(wasm32::__WASI_EACCES)
+    );
+    assert_eq!(
+        open_read(&mut guest, DIR, "data/../data/nested/table.csv"),
+        Err(wasm32::__WASI_EACCES)
+    );
+    assert!(open_read(&mut guest, DIR, "alias/notes.txt").is_ok());
+}
+
+#[test]
+fn denial_and_filter() {
+    let dir = tempfile::tempdir().unwrap();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let policy = {
+        let seen = Arc::clone(&seen);
+        PathPolicy::new()
+            .denial(Denial::NotCapable)
+            .filter(move |op, path: &Path| {
+                seen.lock().unwrap().push((op, path.to_owned()));
+                op != PathOp::Create
+            })
+    };
+    let mut guest = guest_with(builder(&dir, policy));
+
+    assert_eq!(
+        guest.mkdir(DIR, "data/new"),
+        Err(wasm32::__WASI_ENOTCAPABLE)
+    );
+    assert!(open_read(&mut guest, DIR, "data/notes.txt").is_ok());
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![
+            (PathOp::Create, Path::new("data/new").to_owned()),
+            (PathOp::OpenRead, Path::new("data/notes.txt").to_owned()),
+        ]
+    );
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use common::{guest_with, Guest, Subscription};
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+use wasi_common::{pipe, wasm32, PipeWriter, WasiCtxBuilder};
+
+const SECOND: u64 = 1_000_000_000;
+
+fn guest() -> (Guest, PipeWriter) {
+    let (reader, writer) = pipe();
+    let builder = WasiCtxBuilder::new().unwrap().stdin_pipe(reader).unwrap();
+    (guest_with(builder), writer)
+}
+
+fn write_later(mut writer: impl Write + Send + 'static) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        writer.write_all(b"data").unwrap();
+    })
+}
+
+#[test]
+fn waits_for_virtual_pipes() {
+    let (mut guest, writer) = guest();
+    let writing = write_later(writer);
+    assert_eq!(
+        guest.poll(&[Subscription::FdRead(0)]).unwrap(),
+        vec![(0, wasm32::__WASI_ESUCCESS, wasm32::__WASI_EVENTTYPE_FD_READ)]
+    );
+    writing.join().unwrap();
+}
+
+#[test]
+fn times_out_waiting_for_virtual_pipes() {
+    let (mut guest, _writer) = guest();
+    let start = Instant::now();
+    assert_eq!(
+        guest
+            .poll(&[Subscription::FdRead(0), Subscription::Clock(SECOND / 20)])
+            .unwrap(),
+        vec![(1, wasm32::__WASI_ESUCCESS, wasm32::__WASI_EVENTTYPE_CLOCK)]
+    );
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use nix::unistd;
+    use std::fs::File;
+    use std::os::unix::io::FromRawFd;
+
+    /// Installs the reading end of a host pipe in `guest`.
+    fn host_pipe(guest: &mut Guest) -> (u32, File) {
+        let (reader, writer) = unistd::pipe().unwrap();
+        let (reader, writer) = unsafe { (File::from_raw_fd(reader), File::from_raw_fd(writer)) };
+        let fd = guest.ctx.insert_file(reader, !0, !0).unwrap();
+        (fd, writer)
+    }
+
+    #[test]
+    fn virtual_pipes_wake_host_polls() {
+        let (mut guest, writer) = guest();
+        let (host_fd, _host_writer) = host_pipe(&mut guest);
+        let writing = write_later(writer);
+        let start = Instant::now();
+        assert_eq!(
+            guest
+                .poll(&[
+                    Subscription::FdRead(host_fd),
+                    Subscription::FdRead(0),
+                    Subscription::Clock(10 * SECOND),
+                ])
+                .unwrap(),
+            vec![(1, wasm32::__WASI_ESUCCESS, wasm32::__WASI_EVENTTYPE_FD_READ)]
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+        writing.join().unwrap();
+    }
+
+    #[test]
+    fn host_descriptors_are_polled_along_with_virtual_pipes() {
+        let (mut guest, _writer) = guest();
+        let (host_fd, host_writer) = host_pipe(&mut guest);
+        let writing = write_later(host_writer);
+        assert_eq!(
+            guest
+                .poll(&[Subscription::FdRead(0), Subscription::FdRead(host_fd)])
+                .unwrap(),
+            vec![(1, wasm32::__WASI_ESUCCESS, wasm32::__WASI_EVENTTYPE_FD_READ)]
+        );
+        writing.join().unwrap();
+    }
+
+    #[test]
+    fn ready_virtual_pipes_are_reported_with_everything_else_ready() {
+        let (mut guest, mut writer) = guest();
+        let (host_fd, mut host_writer) = host_pipe(&mut guest);
+        let (idle_fd, _idle_writer) = host_pipe(&mut guest);
+        writer.write_all(b"data").unwrap();
+        host_writer.write_all(b"data").unwrap();
+
+        let mut events = guest
+            .poll(&[
+                Subscription::FdRead(0),
+                Subscription::FdRead(host_fd),
+                Subscription::FdRead(idle_fd),
+                Subscription::Clock(0),
+            ])
+            .unwrap();
+        events.sort();
+        assert_eq!(
+            events,
+            vec![
+                (0, wasm32::__WASI_ESUCCESS, wasm32::__WASI_EVENTTYPE_FD_READ),
+                (1, wasm32::__WASI_ESUCCESS, wasm32::__WASI_EVENTTYPE_FD_READ),
+                (3, wasm32::__WASI_ESUCCESS, wasm32::__WASI_EVENTTYPE_CLOCK),
+            ]
+        );
+
+        // without blocking on what isn't ready
+        let start = Instant::now();
+        assert_eq!(
+            guest
+                .poll(&[
+                    Subscription::FdRead(idle_fd),
+                    Subscription::FdRead(0),
+                    Subscription::Clock(10 * SECOND),
+                ])
+                .unwrap(),
+            vec![(1, wasm32::__WASI_ESUCCESS, wasm32::__WASI_EVENTTYPE_FD_READ)]
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::{guest_with, sandbox, Dirent, DIR};
+use std::fs;
+use wasi_common::{wasm32, WasiCtxBuilder};
+
+/// Size of a `__wasi_dirent_t`.
+const DIRENT_SIZE: u32 = 24;
+
+/// A builder with `dir` preopened and listed in order, in which there are a few files.
+fn builder(dir: &tempfile::TempDir) -> WasiCtxBuilder {
+    for name in &["b", "c", "a", "dir/z", "dir/y"] {
+        let path = dir.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, name).unwrap();
+    }
+    sandbox(dir).sorted_readdir("/sandbox")
+}
+
+#[test]
+fn entries_are_sorted() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+    assert_eq!(
+        guest.readdir(DIR).unwrap(),
+        vec![".", "..", "a", "b", "c", "dir"]
+    );
+
+    // and so are those of directories opened from the preopen
+    let fd = guest
+        .open(DIR, "dir", wasm32::__WASI_O_DIRECTORY, 0)
+        .unwrap();
+    assert_eq!(guest.readdir(fd).unwrap(), vec![".", "..", "y", "z"]);
+}
+
+#[test]
+fn cookies_resume_the_listing() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+
+    // read the entries one at a time, with room for names of up to 3 bytes
+    let mut names = Vec::new();
+    let mut cookie = wasm32::__WASI_DIRCOOKIE_START;
+    loop {
+        let (entries, _) = guest.readdir_from(DIR, cookie, DIRENT_SIZE + 3).unwrap();
+        let entry = match entries.first() {
+            Some(entry) => entry,
+            None => break,
+        };
+        assert_eq!(entry.next, cookie + 1);
+        names.push(String::from_utf8(entry.name.clone()).unwrap());
+        cookie = entry.next;
+        if cookie == 3 {
+            // the listing isn't taken again until the guest reads from the start
+            fs::write(dir.path().join("0"), "").unwrap();
+            fs::remove_file(dir.path().join("c")).unwrap();
+        }
+    }
+    assert_eq!(names, vec![".", "..", "a", "b", "c", "dir"]);
+    assert_eq!(
+        guest.readdir(DIR).unwrap(),
+        vec![".", "..", "0", "a", "b", "dir"]
+    );
+}
+
+fn names(entries: &[Dirent]) -> Vec<&str> {
+    entries
+        .iter()
+        .map(|entry| std::str::from_utf8(&entry.name).unwrap())
+        .collect()
+}
+
+#[test]
+fn entries_which_dont_fit_are_truncated() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(builder(&dir));
+
+    // ".", ".." and "a" fit, "b" is cut off in its header, which fills the buffer so that the
+    // guest knows to retry with a larger one
+    let len = 3 * DIRENT_SIZE + 4 + DIRENT_SIZE / 2;
+    let (entries, used) = guest.readdir_from(DIR, 0, len).unwrap();
+    assert_eq!(names(&entries), vec![".", "..", "a"]);
+    assert_eq!(used, len);
+    // and so does a name which is cut off
+    let (entries, used) = guest.readdir_from(DIR, 5, DIRENT_SIZE + 1).unwrap();
+    assert!(entries.is_empty());
+    assert_eq!(used, DIRENT_SIZE + 1);
+
+    // retrying from the last complete entry returns the rest
+    let (entries, used) = guest.readdir_from(DIR, 3, 1024).unwrap();
+    assert_eq!(names(&entries), vec!["b", "c", "dir"]);
+    assert_eq!(used, 3 * DIRENT_SIZE + 5);
+    assert_eq!(entries[2].filetype, wasm32::__WASI_FILETYPE_DIRECTORY);
+}
+
+#[test]
+fn virtual_directories_are_sorted() {
+    let mut guest = guest_with(
+        WasiCtxBuilder::new()
+            .unwrap()
+            .preopened_devices("/dev")
+            .sorted_readdir("/dev"),
+    );
+    assert_eq!(
+        guest.readdir(DIR).unwrap(),
+        vec![".", "..", "null", "random", "stderr", "stdin", "stdout", "urandom", "zero"]
+    );
+}
+
+#[test]
+fn sorting_needs_a_preopen() {
+    let err = WasiCtxBuilder::new()
+        .unwrap()
+        .sorted_readdir("/missing")
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err.as_wasi_errno(), wasm32::__WASI_ENOENT);
+}
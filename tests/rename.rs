@@ -0,0 +1,140 @@
+//! `path_rename` between preopens on different host file systems, of which one is `/dev/shm`.
+#![cfg(target_os = "linux")]
+mod common;
+
+use common::guest_with;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::time::{Duration, UNIX_EPOCH};
+use tempfile::TempDir;
+use wasi_common::{hostcalls, preopen_dir, wasm32, WasiCtxBuilder};
+
+/// The descriptors of the preopened directories.
+const DISK: u32 = 3;
+const SHM: u32 = 4;
+
+/// A directory on another file system than the default temporary directory, if there's one.
+fn other_dir() -> Option<(TempDir, TempDir)> {
+    let disk = tempfile::tempdir().unwrap();
+    let shm = tempfile::tempdir_in("/dev/shm").ok()?;
+    let dev = |dir: &TempDir| fs::metadata(dir.path()).unwrap().dev();
+    if dev(&disk) == dev(&shm) {
+        return None;
+    }
+    Some((disk, shm))
+}
+
+/// A builder with both directories preopened.
+fn builder(disk: &TempDir, shm: &TempDir) -> WasiCtxBuilder {
+    WasiCtxBuilder::new()
+        .unwrap()
+        .preopened_dir(preopen_dir(disk.path()).unwrap(), "/disk")
+        .preopened_dir(preopen_dir(shm.path()).unwrap(), "/shm")
+}
+
+macro_rules! dirs {
+    () => {
+        match other_dir() {
+            Some(dirs) => dirs,
+            None => {
+                eprintln!("skipped, /dev/shm isn't a separate file system");
+                return;
+            }
+        }
+    };
+}
+
+#[test]
+fn renaming_across_devices_fails_by_default() {
+    let (disk, shm) = dirs!();
+    fs::write(disk.path().join("file"), "contents").unwrap();
+    let mut guest = guest_with(builder(&disk, &shm));
+    assert_eq!(
+        guest.rename(DISK, "file", SHM, "file"),
+        Err(wasm32::__WASI_EXDEV)
+    );
+    assert!(disk.path().join("file").exists());
+}
+
+#[test]
+fn files_are_copied() {
+    let (disk, shm) = dirs!();
+    let path = disk.path().join("file");
+    fs::write(&path, "contents").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o751)).unwrap();
+    let mut guest = guest_with(builder(&disk, &shm).cross_device_rename());
+    let fd = guest.open(DISK, "file", 0, 0).unwrap();
+    let errno = unsafe {
+        hostcalls::fd_filestat_set_times(
+            &guest.ctx,
+            fd,
+            0,
+            1_000_000_000_000,
+            wasm32::__WASI_FILESTAT_SET_MTIM,
+        )
+    };
+    assert_eq!(errno, wasm32::__WASI_ESUCCESS);
+
+    guest.rename(DISK, "file", SHM, "moved").unwrap();
+    assert!(!path.exists());
+    let moved = shm.path().join("moved");
+    assert_eq!(fs::read(&moved).unwrap(), b"contents");
+    let metadata = fs::metadata(&moved).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o751);
+    assert_eq!(
+        metadata.modified().unwrap(),
+        UNIX_EPOCH + Duration::from_secs(1000)
+    );
+    // the descriptor still refers to the original
+    assert_eq!(guest.read(fd, 100).unwrap(), b"contents");
+}
+
+#[test]
+fn directory_trees_are_copied() {
+    let (disk, shm) = dirs!();
+    fs::create_dir_all(disk.path().join("tree/sub")).unwrap();
+    fs::write(disk.path().join("tree/sub/file"), "nested").unwrap();
+    std::os::unix::fs::symlink("sub/file", disk.path().join("tree/link")).unwrap();
+    let mut guest = guest_with(builder(&disk, &shm).cross_device_rename());
+
+    guest.rename(DISK, "tree", SHM, "tree").unwrap();
+    assert!(!disk.path().join("tree").exists());
+    let tree = shm.path().join("tree");
+    assert_eq!(fs::read(tree.join("sub/file")).unwrap(), b"nested");
+    assert_eq!(
+        fs::read_link(tree.join("link")).unwrap(),
+        std::path::Path::new("sub/file")
+    );
+    assert_eq!(guest.readdir(SHM).unwrap().len(), 3);
+}
+
+#[test]
+fn existing_files_are_replaced() {
+    let (disk, shm) = dirs!();
+    fs::write(disk.path().join("file"), "new").unwrap();
+    fs::write(shm.path().join("file"), "old").unwrap();
+    let mut guest = guest_with(builder(&disk, &shm).cross_device_rename());
+
+    guest.rename(DISK, "file", SHM, "file").unwrap();
+    assert_eq!(fs::read(shm.path().join("file")).unwrap(), b"new");
+    // no temporary copy is left behind
+    let names: Vec<_> = fs::read_dir(shm.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, vec!["file"]);
+}
+
+#[test]
+fn failed_copies_leave_the_original() {
+    let (disk, shm) = dirs!();
+    fs::write(disk.path().join("file"), "contents").unwrap();
+    fs::create_dir(shm.path().join("dir")).unwrap();
+    fs::write(shm.path().join("dir/occupied"), "").unwrap();
+    let mut guest = guest_with(builder(&disk, &shm).cross_device_rename());
+
+    // a file can't replace a non-empty directory
+    assert!(guest.rename(DISK, "file", SHM, "dir").is_err());
+    assert_eq!(fs::read(disk.path().join("file")).unwrap(), b"contents");
+    assert_eq!(fs::read_dir(shm.path()).unwrap().count(), 1);
+}
@@ -0,0 +1,241 @@
+mod common;
+
+use common::{guest_with, sandbox, Guest, DIR};
+use std::fs;
+use std::time::{Duration, UNIX_EPOCH};
+use wasi_common::{
+    pipe, preopen_dir, wasm32, Capability, DevicePolicy, FdSource, GuestPath, JournalEntry,
+    OverlayUpper, PathEncoding, PathOp, PathPolicy, VirtualMetadata, WasiCtx, WasiCtxBuilder,
+};
+
+fn restore(guest: &mut Guest) -> Guest {
+    let snapshot = guest.ctx.snapshot().unwrap();
+    Guest::new(
+        WasiCtxBuilder::from_snapshot(&snapshot)
+            .unwrap()
+            .build()
+            .unwrap(),
+    )
+}
+
+#[test]
+fn host_files_are_reopened_at_their_offset() {
+    let dir = tempfile::tempdir().unwrap();
+    let ctx = WasiCtxBuilder::new()
+        .unwrap()
+        .arg("app.wasm")
+        .unwrap()
+        .env("KEY", "value")
+        .unwrap()
+        .preopened_dir(preopen_dir(dir.path()).unwrap(), "/sandbox")
+        .disable(Capability::Random)
+        .build()
+        .unwrap();
+    let mut guest = Guest::new(ctx);
+    let fd = guest.create(DIR, "file").unwrap();
+    guest.write(fd, b"hello world").unwrap();
+    guest.seek(fd, 6, wasm32::__WASI_WHENCE_SET).unwrap();
+
+    let snapshot = guest.ctx.snapshot().unwrap();
+    assert_eq!(snapshot.args, vec!["app.wasm"]);
+    assert_eq!(snapshot.env, vec![("KEY".to_owned(), "value".to_owned())]);
+    assert_eq!(snapshot.disabled, vec![Capability::Random]);
+    let file = snapshot.fds.iter().find(|f| f.fd == fd).unwrap();
+    assert_eq!(file.offset, 6);
+    match &file.source {
+        FdSource::Host(path) => assert_eq!(
+            path.canonicalize().unwrap(),
+            dir.path().join("file").canonicalize().unwrap()
+        ),
+        source => panic!("unexpected source {:?}", source),
+    }
+
+    let mut restored = Guest::new(
+        WasiCtxBuilder::from_snapshot(&snapshot)
+            .unwrap()
+            .build()
+            .unwrap(),
+    );
+    assert_eq!(restored.prestat_dir_name(DIR).unwrap(), "/sandbox");
+    assert_eq!(restored.read(fd, 100).unwrap(), b"world");
+    assert_eq!(
+        restored.fdstat(fd).unwrap().rights_base,
+        guest.fdstat(fd).unwrap().rights_base
+    );
+    assert_eq!(
+        unsafe { wasi_common::hostcalls::random_get(&restored.ctx, &mut restored.mem, 0x10, 4) },
+        wasm32::__WASI_ENOSYS
+    );
+}
+
+#[test]
+fn in_memory_file_systems_are_recorded_with_their_contents() {
+    let lower = tempfile::tempdir().unwrap();
+    fs::write(lower.path().join("lower"), "lower").unwrap();
+    let ctx = WasiCtxBuilder::new()
+        .unwrap()
+        .preopened_overlay(lower.path(), OverlayUpper::Memory, "/")
+        .unwrap()
+        .build()
+        .unwrap();
+    let mut guest = Guest::new(ctx);
+    guest.mkdir(DIR, "dir").unwrap();
+    let fd = guest.create(DIR, "dir/upper").unwrap();
+    guest.write(fd, b"upper").unwrap();
+    guest.unlink(DIR, "lower").unwrap();
+
+    let mut restored = restore(&mut guest);
+    let mut names = restored.readdir(DIR).unwrap();
+    names.sort();
+    assert_eq!(names, vec![".", "..", "dir"]);
+    // the open file still refers to the same file system as the preopen
+    restored.write(fd, b", appended").unwrap();
+    let fd = restored
+        .open_with_rights(DIR, "dir/upper", 0, 0, Some(wasm32::__WASI_RIGHT_FD_READ))
+        .unwrap();
+    assert_eq!(restored.read(fd, 100).unwrap(), b"upper, appended");
+    assert!(lower.path().join("lower").exists());
+}
+
+#[test]
+fn policies_have_to_be_given_again() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("secret.key"), "key").unwrap();
+    let policy = || PathPolicy::new().deny(PathOp::ALL, "*.key");
+    let ctx = WasiCtxBuilder::new()
+        .unwrap()
+        .preopened_dir(preopen_dir(dir.path()).unwrap(), "/sandbox")
+        .path_policy("/sandbox", policy())
+        .build()
+        .unwrap();
+    let snapshot = Guest::new(ctx).ctx.snapshot().unwrap();
+    assert_eq!(
+        snapshot
+            .fds
+            .iter()
+            .find(|f| f.fd == DIR)
+            .unwrap()
+            .policy
+            .as_ref()
+            .map(|p| p.preopen.to_str()),
+        Some(Some("/sandbox"))
+    );
+
+    let err = WasiCtxBuilder::from_snapshot(&snapshot)
+        .unwrap()
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err.as_wasi_errno(), wasm32::__WASI_EACCES);
+
+    let ctx = WasiCtxBuilder::from_snapshot(&snapshot)
+        .unwrap()
+        .path_policy("/sandbox", policy())
+        .build()
+        .unwrap();
+    let mut restored = Guest::new(ctx);
+    assert_eq!(
+        restored.open_with_rights(DIR, "secret.key", 0, 0, Some(wasm32::__WASI_RIGHT_FD_READ)),
+        Err(wasm32::__WASI_EACCES)
+    );
+}
+
+#[test]
+fn pipes_can_not_be_recorded() {
+    let (reader, _writer) = pipe();
+    let mut ctx: WasiCtx = WasiCtxBuilder::new()
+        .unwrap()
+        .stdin_pipe(reader)
+        .unwrap()
+        .build()
+        .unwrap();
+    assert_eq!(
+        ctx.snapshot().err().unwrap().as_wasi_errno(),
+        wasm32::__WASI_ENOTSUP
+    );
+}
+
+#[test]
+fn metrics_stay_enabled() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(sandbox(&dir).metrics());
+    guest.mkdir(DIR, "dir").unwrap();
+
+    let mut restored = restore(&mut guest);
+    // what was collected before the snapshot isn't carried over
+    let report = restored.ctx.metrics().unwrap();
+    assert!(!report.hostcalls.contains_key("path_create_directory"));
+    restored.rmdir(DIR, "dir").unwrap();
+    let report = restored.ctx.metrics().unwrap();
+    assert_eq!(report.hostcalls["path_remove_directory"].calls, 1);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn path_encodings_are_restored() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(sandbox(&dir).path_encoding(PathEncoding::Lossless));
+
+    let mut restored = restore(&mut guest);
+    restored
+        .open(DIR, b"caf\xe9", wasm32::__WASI_O_CREAT, 0)
+        .unwrap();
+    assert_eq!(restored.readdir_bytes(DIR).unwrap().len(), 3);
+}
+
+#[cfg(unix)]
+#[test]
+fn device_policies_are_restored() {
+    let ctx = WasiCtxBuilder::new()
+        .unwrap()
+        .preopened_dir(preopen_dir("/dev").unwrap(), "/dev")
+        .device_policy(DevicePolicy::new().allow("/dev/null"));
+    let mut guest = guest_with(ctx);
+
+    let mut restored = restore(&mut guest);
+    assert!(restored.open(DIR, "null", 0, 0).is_ok());
+    assert_eq!(restored.open(DIR, "zero", 0, 0), Err(wasm32::__WASI_EACCES));
+}
+
+#[test]
+fn virtual_metadata_is_restored() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("file"), "").unwrap();
+    let epoch = UNIX_EPOCH + Duration::from_secs(1);
+    let metadata = VirtualMetadata::new().clamp_timestamps(epoch);
+    let mut guest = guest_with(sandbox(&dir).virtual_metadata(metadata));
+
+    let mut restored = restore(&mut guest);
+    let file = restored.path_filestat(DIR, "file").unwrap();
+    assert_eq!(file.dev, u64::from(DIR));
+    assert_eq!(file.ino, 1);
+    assert_eq!(file.mtim, 1_000_000_000);
+}
+
+#[test]
+fn journals_are_started_anew() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(sandbox(&dir).journal());
+    guest.mkdir(DIR, "before").unwrap();
+
+    let mut restored = restore(&mut guest);
+    restored.mkdir(DIR, "after").unwrap();
+    assert_eq!(
+        restored.ctx.journal().unwrap(),
+        vec![JournalEntry::CreateDirectory(GuestPath {
+            preopen: "/sandbox".into(),
+            path: "after".into(),
+        })]
+    );
+}
+
+#[test]
+fn cross_device_renames_stay_enabled() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(sandbox(&dir));
+    assert!(!guest.ctx.snapshot().unwrap().cross_device_rename);
+
+    let mut guest = guest_with(sandbox(&dir).cross_device_rename());
+    let mut restored = restore(&mut guest);
+    assert!(restored.ctx.snapshot().unwrap().cross_device_rename);
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use common::Guest;
+use std::fs::File;
+use std::io::Write;
+use wasi_common::{pipe, wasm32, WasiCtxBuilder};
+
+fn assert_not_seekable(guest: &mut Guest, fd: u32) {
+    assert_eq!(
+        guest.seek(fd, 0, wasm32::__WASI_WHENCE_SET),
+        Err(wasm32::__WASI_ESPIPE)
+    );
+    assert_eq!(guest.tell(fd), Err(wasm32::__WASI_ESPIPE));
+}
+
+#[test]
+fn virtual_pipes_are_streams() {
+    let (reader, mut writer) = pipe();
+    let ctx = WasiCtxBuilder::new()
+        .unwrap()
+        .stdin_pipe(reader)
+        .unwrap()
+        .build()
+        .unwrap();
+    let mut guest = Guest::new(ctx);
+
+    let info = guest.ctx.fd_info(0).unwrap();
+    assert_eq!(info.filetype(), wasm32::__WASI_FILETYPE_SOCKET_STREAM);
+    assert_eq!(info.offset(), None);
+    assert_not_seekable(&mut guest, 0);
+    writer.write_all(b"piped").unwrap();
+    assert_eq!(guest.read(0, 100).unwrap(), b"piped");
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use common::{guest_with, sandbox, DIR};
+    use nix::sys::stat::Mode;
+    use nix::unistd;
+    use std::fs::{self, OpenOptions};
+    use std::os::unix::io::FromRawFd;
+
+    fn host_pipe() -> (File, File) {
+        let (reader, writer) = unistd::pipe().unwrap();
+        unsafe { (File::from_raw_fd(reader), File::from_raw_fd(writer)) }
+    }
+
+    #[test]
+    fn fifos_are_streams() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fifo");
+        unistd::mkfifo(&path, Mode::S_IRWXU).unwrap();
+        // keep a writer open so that opening the fifo for reading doesn't block
+        let _writer = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let mut guest = guest_with(sandbox(&dir));
+
+        let fd = guest.open(DIR, "fifo", 0, 0).unwrap();
+        let fdstat = guest.fdstat(fd).unwrap();
+        assert_eq!(fdstat.filetype, wasm32::__WASI_FILETYPE_SOCKET_STREAM);
+        assert_eq!(fdstat.rights_base & wasm32::__WASI_RIGHT_FD_SEEK, 0);
+        assert_not_seekable(&mut guest, fd);
+        assert_eq!(
+            guest.path_filestat(DIR, "fifo").unwrap().filetype,
+            wasm32::__WASI_FILETYPE_SOCKET_STREAM
+        );
+    }
+
+    #[test]
+    fn host_pipes_as_stdio_are_streams() {
+        let (reader, mut writer) = host_pipe();
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .stdin(reader)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut guest = Guest::new(ctx);
+
+        assert_eq!(
+            guest.ctx.fd_info(0).unwrap().filetype(),
+            wasm32::__WASI_FILETYPE_SOCKET_STREAM
+        );
+        assert_not_seekable(&mut guest, 0);
+        writer.write_all(b"piped").unwrap();
+        assert_eq!(guest.read(0, 100).unwrap(), b"piped");
+    }
+
+    #[test]
+    fn inherited_stdin_is_checked_for_pipes() {
+        // replace the stdin of this process with a pipe; no other test reads it
+        let (reader, _writer) = host_pipe();
+        let saved = unistd::dup(0).unwrap();
+        unistd::dup2(std::os::unix::io::AsRawFd::as_raw_fd(&reader), 0).unwrap();
+        let ctx = WasiCtxBuilder::new().unwrap().inherit_stdin();
+        unistd::dup2(saved, 0).unwrap();
+        unistd::close(saved).unwrap();
+        let mut guest = Guest::new(ctx.unwrap().build().unwrap());
+
+        assert_eq!(
+            guest.ctx.fd_info(0).unwrap().filetype(),
+            wasm32::__WASI_FILETYPE_SOCKET_STREAM
+        );
+        assert_not_seekable(&mut guest, 0);
+    }
+
+    #[test]
+    fn regular_files_as_stdio_are_seekable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("input");
+        fs::write(&path, "hello world").unwrap();
+        let ctx = WasiCtxBuilder::new()
+            .unwrap()
+            .stdin(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut guest = Guest::new(ctx);
+
+        assert_eq!(guest.seek(0, 6, wasm32::__WASI_WHENCE_SET), Ok(6));
+        assert_eq!(guest.read(0, 100).unwrap(), b"world");
+    }
+}
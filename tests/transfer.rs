@@ -0,0 +1,123 @@
+mod common;
+
+use common::{guest_with, sandbox, DIR};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use wasi_common::{pipe, wasm32, OverlayUpper, WasiCtxBuilder};
+
+fn read_write(path: &std::path::Path) -> File {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .unwrap()
+}
+
+#[test]
+fn inserted_files_get_the_lowest_free_descriptor() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(sandbox(&dir));
+    let opened = guest.create(DIR, "opened").unwrap();
+    guest.close(opened).unwrap();
+
+    let mut file = read_write(&dir.path().join("file"));
+    file.write_all(b"hello world").unwrap();
+    file.seek(SeekFrom::Start(6)).unwrap();
+    let fd = guest
+        .ctx
+        .insert_file(file, wasm32::__WASI_RIGHT_FD_READ, 0)
+        .unwrap();
+    assert_eq!(fd, opened);
+    assert_eq!(guest.read(fd, 100).unwrap(), b"world");
+    assert_eq!(guest.write(fd, b"denied"), Err(wasm32::__WASI_ENOTCAPABLE));
+    assert_eq!(
+        guest.fdstat(fd).unwrap().rights_base,
+        wasm32::__WASI_RIGHT_FD_READ
+    );
+}
+
+#[test]
+fn files_are_inserted_at_free_descriptors_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(sandbox(&dir));
+
+    let file = read_write(&dir.path().join("file"));
+    assert_eq!(
+        guest
+            .ctx
+            .insert_file_at(DIR, file.try_clone().unwrap(), !0, !0)
+            .err()
+            .unwrap()
+            .as_wasi_errno(),
+        wasm32::__WASI_EEXIST
+    );
+    assert_eq!(guest.prestat_dir_name(DIR).unwrap(), "/sandbox");
+
+    guest.ctx.insert_file_at(10, file, !0, !0).unwrap();
+    guest.write(10, b"at 10").unwrap();
+    assert_eq!(fs::read(dir.path().join("file")).unwrap(), b"at 10");
+}
+
+#[test]
+fn inserted_pipes() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(sandbox(&dir));
+    let (reader, mut writer) = pipe();
+    let fd = guest.ctx.insert_pipe(reader).unwrap();
+
+    writer.write_all(b"piped").unwrap();
+    assert_eq!(guest.read(fd, 100).unwrap(), b"piped");
+    assert_eq!(
+        guest
+            .ctx
+            .insert_pipe_at(fd, writer)
+            .err()
+            .unwrap()
+            .as_wasi_errno(),
+        wasm32::__WASI_EEXIST
+    );
+}
+
+#[test]
+fn taken_files_keep_their_offset() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut guest = guest_with(sandbox(&dir));
+    let fd = guest.create(DIR, "file").unwrap();
+    guest.write(fd, b"hello world").unwrap();
+    guest.seek(fd, 6, wasm32::__WASI_WHENCE_SET).unwrap();
+
+    let mut file = guest.ctx.take_file(fd).unwrap();
+    let mut rest = String::new();
+    file.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "world");
+    assert_eq!(guest.write(fd, b"closed"), Err(wasm32::__WASI_EBADF));
+    assert_eq!(
+        guest.ctx.take_file(fd).err().unwrap().as_wasi_errno(),
+        wasm32::__WASI_EBADF
+    );
+}
+
+#[test]
+fn only_host_files_can_be_taken() {
+    let lower = tempfile::tempdir().unwrap();
+    fs::write(lower.path().join("file"), "contents").unwrap();
+    let mut guest = guest_with(
+        WasiCtxBuilder::new()
+            .unwrap()
+            .inherit_stdout()
+            .unwrap()
+            .preopened_overlay(lower.path(), OverlayUpper::Memory, "/")
+            .unwrap(),
+    );
+    let fd = guest.create(DIR, "new").unwrap();
+
+    for fd in &[1, DIR, fd] {
+        assert_eq!(
+            guest.ctx.take_file(*fd).err().unwrap().as_wasi_errno(),
+            wasm32::__WASI_ENOTSUP
+        );
+        assert!(guest.ctx.fd_info(*fd).is_some(), "{} is still open", fd);
+    }
+}
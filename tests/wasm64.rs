@@ -0,0 +1,126 @@
+use wasi_common::hostcalls::wasm64 as hostcalls;
+use wasi_common::{preopen_dir, wasm64, WasiCtx, WasiCtxBuilder};
+
+const PATH: u64 = 0x100;
+const IOVS: u64 = 0x200;
+const RESULT: u64 = 0x300;
+const BUF: u64 = 0x1000;
+
+/// The descriptor of the preopened directory.
+const DIR: wasm64::__wasi_fd_t = 3;
+
+fn ctx(dir: &tempfile::TempDir) -> WasiCtx {
+    WasiCtxBuilder::new()
+        .unwrap()
+        .preopened_dir(preopen_dir(dir.path()).unwrap(), "/")
+        .build()
+        .unwrap()
+}
+
+fn put(mem: &mut [u8], offset: u64, bytes: &[u8]) {
+    let offset = offset as usize;
+    mem[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+fn u64_at(mem: &[u8], offset: u64) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&mem[offset as usize..offset as usize + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Writes a single `__wasi_ciovec_t` of wasm64, which is made of two 64-bit fields.
+fn put_iovec(mem: &mut [u8], buf: u64, len: u64) {
+    put(mem, IOVS, &buf.to_le_bytes());
+    put(mem, IOVS + 8, &len.to_le_bytes());
+}
+
+fn open(ctx: &mut WasiCtx, mem: &mut [u8], path: &str) -> wasm64::__wasi_fd_t {
+    put(mem, PATH, path.as_bytes());
+    assert_eq!(
+        unsafe { hostcalls::fd_fdstat_get(ctx, mem, DIR, RESULT) },
+        wasm64::__WASI_ESUCCESS
+    );
+    let rights = u64_at(mem, RESULT + 16);
+    assert_eq!(
+        unsafe {
+            hostcalls::path_open(
+                ctx,
+                mem,
+                DIR,
+                0,
+                PATH,
+                path.len() as u64,
+                wasm64::__WASI_O_CREAT,
+                rights,
+                rights,
+                0,
+                RESULT,
+            )
+        },
+        wasm64::__WASI_ESUCCESS
+    );
+    u64_at(mem, RESULT) as u32
+}
+
+#[test]
+fn write_then_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut ctx = ctx(&dir);
+    let mut mem = vec![0; 0x2000];
+    let fd = open(&mut ctx, &mut mem, "file");
+
+    put(&mut mem, BUF, b"hello wasm64");
+    put_iovec(&mut mem, BUF, 12);
+    assert_eq!(
+        unsafe { hostcalls::fd_write(&mut ctx, &mut mem, fd, IOVS, 1, RESULT) },
+        wasm64::__WASI_ESUCCESS
+    );
+    assert_eq!(u64_at(&mem, RESULT), 12);
+    assert_eq!(
+        std::fs::read(dir.path().join("file")).unwrap(),
+        b"hello wasm64"
+    );
+
+    assert_eq!(
+        unsafe { hostcalls::fd_seek(&mut ctx, &mut mem, fd, 6, wasm64::__WASI_WHENCE_SET, RESULT) },
+        wasm64::__WASI_ESUCCESS
+    );
+    put_iovec(&mut mem, BUF + 0x100, 0x100);
+    assert_eq!(
+        unsafe { hostcalls::fd_read(&mut ctx, &mut mem, fd, IOVS, 1, RESULT) },
+        wasm64::__WASI_ESUCCESS
+    );
+    assert_eq!(u64_at(&mem, RESULT), 6);
+    assert_eq!(&mem[0x1100..0x1106], b"wasm64");
+}
+
+#[test]
+fn pointers_past_4gib() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut ctx = ctx(&dir);
+    let mut mem = vec![0; 0x2000];
+    let fd = open(&mut ctx, &mut mem, "file");
+
+    // the low 32 bits of the pointer are in bounds, so it mustn't be truncated
+    put_iovec(&mut mem, BUF + (1 << 32), 4);
+    assert_eq!(
+        unsafe { hostcalls::fd_write(&mut ctx, &mut mem, fd, IOVS, 1, RESULT) },
+        wasm64::__WASI_EFAULT
+    );
+    assert_eq!(
+        unsafe { hostcalls::fd_write(&mut ctx, &mut mem, fd, IOVS + (1 << 32), 1, RESULT) },
+        wasm64::__WASI_EFAULT
+    );
+    assert_eq!(std::fs::read(dir.path().join("file")).unwrap(), b"");
+}
+
+#[test]
+fn sockets_are_not_supported() {
+    let dir = tempfile::tempdir().unwrap();
+    let ctx = ctx(&dir);
+    let mut mem = vec![0; 0x2000];
+    assert_eq!(
+        unsafe { hostcalls::sock_shutdown(&ctx, &mut mem, DIR, 0) },
+        wasm64::__WASI_ENOSYS
+    );
+}
@@ -74,6 +74,8 @@ win_error_expand! {
     ERROR_LABEL_TOO_LONG,
     /// The requested resource is in use.
     ERROR_BUSY,
+    /// The system cannot move the file to a different disk drive.
+    ERROR_NOT_SAME_DEVICE,
     /// The file name, directory name, or volume label syntax is incorrect.
     ERROR_INVALID_NAME,
     /// The process cannot access the file because it is being used by another process.